@@ -1,12 +1,19 @@
 #![warn(clippy::all)]
 #![warn(rust_2018_idioms)]
 
+use std::collections::BTreeSet;
 use std::fs;
-use std::path::PathBuf;
-use std::sync::mpsc::{channel, Receiver, Sender};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
 use std::time::Duration;
 
-use notify::{Config, RecommendedWatcher, RecursiveMode, Watcher};
+use notify::{Config, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+/// How long to wait for more events after a change before reporting it. A single save often
+/// fires several events, this reports each changed file once.
+const DEBOUNCE: Duration = Duration::from_millis(50);
 
 /// Using `notify` to watch for changes to files in any directories
 /// where the files resides.
@@ -14,47 +21,119 @@ use notify::{Config, RecommendedWatcher, RecursiveMode, Watcher};
 /// `files` - Which files to watch
 ///
 pub fn watch_all(sender: Sender<PathBuf>, files: Vec<PathBuf>) {
+    watch_until(sender, files, Arc::new(AtomicBool::new(false)));
+}
+
+/// Same as `watch_all` but returns once `stop` is set, or when the receiving end of `sender`
+/// is dropped. Used to replace a watcher when the set of files changes.
+pub fn watch_until(sender: Sender<PathBuf>, files: Vec<PathBuf>, stop: Arc<AtomicBool>) {
     let (tx, rx) = channel();
     let mut watcher = RecommendedWatcher::new(tx, Config::default()).unwrap();
 
-    let file_paths: Vec<PathBuf> = files
+    let file_paths: BTreeSet<PathBuf> = files
         .iter()
         .filter_map(|p| fs::canonicalize(p).ok())
         .collect();
 
+    // Watch the directories instead of the files. Editors that save by renaming a new file
+    // over the old one replace the watched file, so a watch on the file itself would stop
+    // reporting anything after the first save.
+    let directories: BTreeSet<&Path> = file_paths.iter().filter_map(|p| p.parent()).collect();
+
     println!("Watching files shaders in:");
-    for path in &file_paths {
+    for directory in &directories {
         watcher
-            .watch(path.as_path(), RecursiveMode::Recursive)
+            .watch(directory, RecursiveMode::NonRecursive)
             .unwrap(); // TODO: Replace with GLSLWatcherError
-        println!("   {:?}", path);
+        println!("   {:?}", directory);
     }
 
-    watch_loop(sender, rx, file_paths);
+    watch_loop(sender, rx, file_paths, stop);
 }
 
 fn watch_loop(
     sender: Sender<PathBuf>,
     watch_receiver: Receiver<notify::Result<notify::Event>>,
-    _directories: Vec<PathBuf>,
+    files: BTreeSet<PathBuf>,
+    stop: Arc<AtomicBool>,
 ) {
-    // TODO: Handle the different types of event for example removing file(s)
-    // EventKind::Any => {}
-    // EventKind::Access(_) => {}
-    // EventKind::Create(_) => {}
-    // EventKind::Modify(_) => {}
-    // EventKind::Remove(_) => {}
-    // EventKind::Other => {}
-    loop {
-        while let Ok(res) = watch_receiver.recv() {
-            if let Ok(event) = res {
-                let path = event.paths.first().unwrap();
-                sender
-                    .send(path.to_owned().canonicalize().unwrap())
-                    .unwrap();
+    let mut changed: BTreeSet<PathBuf> = BTreeSet::new();
+
+    while !stop.load(Ordering::Relaxed) {
+        let timeout = match changed.is_empty() {
+            true => Duration::from_millis(100),
+            false => DEBOUNCE,
+        };
+        match watch_receiver.recv_timeout(timeout) {
+            Ok(Ok(event)) => {
+                // a rename over a watched file shows up as a create or a rename to its path,
+                // removals are skipped as there's nothing to reload until the file is back
+                if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                    continue;
+                }
+                changed.extend(
+                    event
+                        .paths
+                        .iter()
+                        .filter_map(|p| p.canonicalize().ok())
+                        .filter(|p| files.contains(p)),
+                );
+            }
+            Ok(Err(_)) => {}
+            Err(RecvTimeoutError::Timeout) => {
+                for path in std::mem::take(&mut changed) {
+                    if sender.send(path).is_err() {
+                        // nobody is listening anymore
+                        return;
+                    }
+                }
             }
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc::channel;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::watch_until;
+
+    #[test]
+    fn rename_over_watched_file_is_reported() {
+        let dir = std::env::temp_dir().join(format!("glsl-watcher-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let shader = dir.join("shader.glsl");
+        fs::write(&shader, "void main() {}").unwrap();
+        let shader = shader.canonicalize().unwrap();
+
+        let (sender, receiver) = channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let watcher = {
+            let (files, stop) = (vec![shader.clone()], stop.clone());
+            thread::spawn(move || watch_until(sender, files, stop))
+        };
+        thread::sleep(Duration::from_millis(200));
+
+        // save twice the way vim and VS Code do, the second save must be seen as well
+        for _ in 0..2 {
+            let temp = dir.join("shader.glsl.tmp");
+            fs::write(&temp, "void main() { }").unwrap();
+            fs::rename(&temp, &shader).unwrap();
+
+            let changed = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+            assert_eq!(changed, shader);
+            // the save is reported once
+            assert!(receiver.recv_timeout(Duration::from_millis(200)).is_err());
         }
 
-        std::thread::sleep(Duration::from_millis(10));
+        stop.store(true, Ordering::Relaxed);
+        watcher.join().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,14 @@
+// Built-in shader shown when skuggbox is started without any shader files
+
+uniform float iTime;
+uniform vec2 iResolution;
+
+void mainImage(out vec4 fragColor, in vec2 fragCoord) {
+    vec2 uv = fragCoord / iResolution.xy;
+    vec3 top = vec3(0.13, 0.15, 0.22);
+    vec3 bottom = vec3(0.04, 0.05, 0.08);
+    vec3 col = mix(bottom, top, uv.y);
+    // a slow glow drifting along the horizon
+    col += 0.06 * (0.5 + 0.5 * cos(iTime * 0.5 + uv.x * 3.0 + vec3(0.0, 2.0, 4.0)));
+    fragColor = vec4(col, 1.0);
+}
@@ -0,0 +1,20 @@
+#version 330 core
+
+out vec4 fragColor;
+
+uniform float iTime;
+uniform vec2 iResolution;
+uniform sampler2D iChannel0;
+
+void main(void) {
+    vec2 uv = gl_FragCoord.xy / iResolution.xy;
+
+    // previous frame of this buffer, slowly fading out
+    vec3 previous = texture(iChannel0, uv).rgb * 0.98;
+
+    vec2 pos = 0.5 + 0.3 * vec2(cos(iTime), sin(iTime * 1.3));
+    float spot = smoothstep(0.03, 0.0, length((uv - pos) * vec2(iResolution.x / iResolution.y, 1.0)));
+    vec3 col = 0.5 + 0.5 * cos(iTime + vec3(0, 2, 4));
+
+    fragColor = vec4(max(previous, col * spot), 1.0);
+}
@@ -0,0 +1,11 @@
+#version 330 core
+
+out vec4 fragColor;
+
+uniform vec2 iResolution;
+uniform sampler2D iChannel0;
+
+void main(void) {
+    vec2 uv = gl_FragCoord.xy / iResolution.xy;
+    fragColor = vec4(texture(iChannel0, uv).rgb, 1.0);
+}
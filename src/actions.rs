@@ -1,16 +1,14 @@
-use crate::camera::OrbitCamera;
-use crate::{seek, AppState, Mouse, PlayMode, PlaybackControl, PreProcessorConfig, ShaderService};
+use crate::camera::{camera_file, CameraKind};
+use crate::camera_path::{camera_path_file, CameraPath, CameraPathMode};
+use crate::capture::screenshot_path;
+use crate::{
+    presets_file, seek, step_frame, uniforms_file, AppState, ChannelConfig, DrawMode, Mouse,
+    PlayMode, PlaybackControl, PreProcessorConfig, RenderRegion, ShaderService, UniformGraph,
+};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 use winit::event_loop::ControlFlow;
 
-/// First person camera movement
-pub enum CameraMovement {
-    MoveForward,
-    MoveBackward,
-    StrafeLeft,
-    StrafeRight,
-    Reset,
-}
-
 pub enum Action {
     AppExit,
     TimePlay,
@@ -19,17 +17,85 @@ pub enum Action {
     TimeStop,
     TimeForward(f32),
     TimeRewind(f32),
+    TimeSet(f32),
+    /// Move playback this many frames at `AppState::step_fps`, only while paused
+    StepFrame(i32),
+    /// Loop playback between an in and out point
+    SetLoop(f32, f32),
+    ClearLoop,
+    SetTimeScale(f32),
+    SetBpm(f32),
+    /// Set the tempo from the interval between taps
+    TapTempo,
+    SetRenderScale(f32),
+    /// Show the average of the frames since the last change instead of the latest frame
+    ToggleAccumulation,
+    ResetAccumulation,
+    /// Only render part of the frame, `None` renders all of it again
+    SetRenderRegion(Option<RenderRegion>),
+    SetDrawMode(DrawMode),
+    /// Plot a uniform over time in the graph window, `None` closes it
+    GraphUniform(Option<String>),
+    SetActiveShader(usize),
+    /// Show a channel or buffer on its own instead of the final shader, `None` to go back
+    SoloChannel(Option<usize>),
+    /// The window's close button, asks first when there are unsaved uniform edits
     WindowClose,
+    /// Keep running after `WindowClose` asked
+    CancelExit,
+    /// Save the custom uniforms and quit, keeps running when saving fails
+    SaveUniformsAndExit,
     WindowResize((i32, i32)),
     // size
     CameraToggleIntegration(bool),
+    /// Switch between the dev camera and the camera of the shader
+    ToggleCameraIntegration,
     CameraReset,
-    CameraMove(CameraMovement),
-    UIToggleVisible,
+    SetCameraKind(CameraKind),
+    /// Save the dev camera next to the shader, see `--restore-camera`
+    SaveCamera,
+    /// Save the custom uniform values next to the shader, restored on the next start
+    SaveUniforms,
+    /// Snapshot the custom uniform values as a named preset
+    SavePreset(String),
+    ApplyPreset(String),
+    DeletePreset(String),
+    RenamePreset(String, String),
+    /// Blend from the active preset towards another one, 0.0 to 1.0
+    MorphPreset(String, f32),
+    /// Set the components of a custom uniform
+    SetUniform(String, Vec<f32>),
+    /// Start or stop recording the camera path
+    CameraPathToggleRecording,
+    /// Start or stop following the recorded camera path
+    CameraPathTogglePlayback,
+    CameraPathClear,
+    CameraPathSave,
+    CameraPathLoad,
+    /// Show/hide the UI overlay
+    ToggleUi,
+    UIToggleStats,
+    /// Show/hide the window listing the files of the active shader
+    UIToggleShaderInfo,
+    ToggleVsync,
+    SetFpsCap(Option<f32>),
     ToggleFullscreen,
-    Screenshot,
+    /// Save the next frame to a PNG, to a timestamped file without a path
+    Screenshot(Option<PathBuf>),
+    /// Save the last few seconds as a GIF
+    SaveGif,
     PrintSource,
+    /// Log the values of all uniforms, see `AppState::dump_uniforms`
+    DumpUniforms,
+    /// Save the active shader with its includes inlined, see `flatten_source`
+    SaveFlattened(PathBuf),
     TakeSnapshot,
+    /// Rebuild all shaders from disk, for when the file watcher missed a change
+    ReloadShaders,
+    /// Replace the running shaders with a shader file
+    OpenFile(PathBuf),
+    /// Load an image into the first free channel
+    AddChannel(PathBuf),
 }
 
 pub fn handle_actions(
@@ -40,11 +106,23 @@ pub fn handle_actions(
 ) {
     for action in actions.drain(..) {
         match action {
-            Action::AppExit => {
+            Action::WindowClose if app_state.confirm_quit && app_state.uniforms_changed => {
+                app_state.show_quit_dialog = true;
+                app_state.ui_visible = true;
+            }
+            Action::AppExit | Action::WindowClose => {
                 log::info!("Bye now...");
                 app_state.is_running = false;
                 *control_flow = ControlFlow::Exit
             }
+            Action::SaveUniformsAndExit => {
+                // the dialog stays open with the error toast when saving fails
+                if app_state.save_uniforms() {
+                    log::info!("Bye now...");
+                    app_state.is_running = false;
+                    *control_flow = ControlFlow::Exit
+                }
+            }
             Action::TimePlay => {
                 app_state.timer.start();
                 app_state.play_mode = PlayMode::Playing;
@@ -58,6 +136,7 @@ pub fn handle_actions(
                     &app_state.modifier,
                     PlaybackControl::Stop,
                 );
+                app_state.frame_count = 0;
                 app_state.play_mode = PlayMode::Paused;
                 log::debug!("Stopped");
             }
@@ -86,72 +165,283 @@ pub fn handle_actions(
                     PlaybackControl::Rewind(time),
                 )
             }
-            Action::WindowClose => {}
+            // seeking keeps the current play mode
+            Action::TimeSet(time) => {
+                app_state.playback_time = seek(
+                    app_state.playback_time,
+                    &app_state.modifier,
+                    PlaybackControl::Set(time),
+                );
+                if app_state.playback_time == 0.0 {
+                    app_state.frame_count = 0;
+                }
+            }
+            Action::StepFrame(frames) => {
+                if matches!(app_state.play_mode, PlayMode::Paused) {
+                    let (time, frame) =
+                        step_frame(app_state.playback_time, app_state.step_fps, frames);
+                    app_state.playback_time = time;
+                    app_state.frame_count = frame;
+                }
+            }
+            Action::SetLoop(start, end) => {
+                let start = f32::max(start, 0.0);
+                app_state.loop_start = Some(start);
+                app_state.loop_end = Some(f32::max(end, start));
+            }
+            Action::ClearLoop => {
+                app_state.loop_start = None;
+                app_state.loop_end = None;
+            }
+            Action::SetTimeScale(time_scale) => {
+                app_state.time_scale = time_scale;
+            }
+            Action::SetBpm(bpm) => {
+                app_state.bpm = bpm;
+            }
+            Action::TapTempo => {
+                if let Some(bpm) = app_state.tap_tempo.tap(Instant::now()) {
+                    app_state.bpm = bpm;
+                    log::debug!("Tap tempo {:.1} BPM", bpm);
+                }
+            }
+            Action::SetRenderScale(render_scale) => {
+                app_state.render_scale = render_scale;
+            }
+            Action::ToggleAccumulation => {
+                app_state.accumulate = !app_state.accumulate;
+                app_state.reset_accumulation = true;
+                app_state.sample_count = 0;
+            }
+            Action::ResetAccumulation => {
+                app_state.reset_accumulation = true;
+            }
+            Action::SetRenderRegion(region) => {
+                app_state.render_region = region;
+                app_state.selecting_region = false;
+            }
+            Action::SetDrawMode(draw_mode) => {
+                app_state.draw_mode = draw_mode;
+            }
+            Action::GraphUniform(name) => {
+                app_state.uniform_graph = name.map(|name| UniformGraph::new(&name));
+            }
+            Action::SetActiveShader(index) => {
+                if index < shader_service.shaders.len() {
+                    app_state.active_shader = index;
+                }
+            }
+            Action::SoloChannel(index) => {
+                app_state.solo_channel = index;
+            }
+            Action::CancelExit => {
+                app_state.show_quit_dialog = false;
+            }
             Action::WindowResize((width, height)) => {
                 app_state.width = width;
                 app_state.height = height;
             }
-            Action::CameraToggleIntegration(use_camera_integration) => match use_camera_integration
-            {
-                true => {
-                    if !shader_service.use_camera_integration {
-                        log::info!("Enabling camera integration. Please use '#pragma skuggbox(camera)' in your shader");
-                        shader_service.use_camera_integration = true;
-                        shader_service.reload(PreProcessorConfig {
-                            use_camera_integration: true,
-                        });
-                        //.expect("Expected successful shader reload");
-                    }
+            Action::CameraToggleIntegration(use_camera_integration) => {
+                set_camera_integration(shader_service, use_camera_integration)
+            }
+            Action::ToggleCameraIntegration => {
+                let use_camera_integration = !shader_service.use_camera_integration;
+                set_camera_integration(shader_service, use_camera_integration)
+            }
+            Action::CameraReset => {
+                app_state.camera = app_state.camera_kind.camera();
+                app_state.mouse = Mouse::default();
+            }
+            Action::SetCameraKind(camera_kind) => {
+                app_state.camera = camera_kind.camera();
+                app_state.camera_kind = camera_kind;
+            }
+            Action::SaveCamera => app_state.save_camera(),
+            Action::SaveUniforms => {
+                app_state.save_uniforms();
+            }
+            Action::SavePreset(name) => {
+                app_state.presets.save(&name, &app_state.custom_uniforms);
+                app_state.save_presets();
+                app_state.show_toast(format!("Saved preset {}", name));
+            }
+            Action::ApplyPreset(name) => {
+                if app_state
+                    .presets
+                    .apply(&name, &mut app_state.custom_uniforms)
+                {
+                    app_state.uniforms_changed = true;
                 }
-                false => {
-                    if shader_service.use_camera_integration {
-                        log::info!("Disabling camera integration");
-                        shader_service.use_camera_integration = false;
-                        shader_service.reload(PreProcessorConfig {
-                            use_camera_integration: false,
-                        });
-                        //.expect("Expected successful shader reload");
-                    }
+            }
+            Action::DeletePreset(name) => {
+                app_state.presets.delete(&name);
+                app_state.save_presets();
+            }
+            Action::RenamePreset(from, to) => match app_state.presets.rename(&from, &to) {
+                Ok(()) => app_state.save_presets(),
+                Err(e) => app_state.show_toast(e),
+            },
+            Action::MorphPreset(name, amount) => {
+                let uniforms = &mut app_state.custom_uniforms;
+                app_state.presets.morph(&name, amount, uniforms);
+                app_state.uniforms_changed = true;
+            }
+            Action::SetUniform(name, values) => {
+                if let Err(e) = app_state.set_uniform(&name, &values) {
+                    log::warn!("{}", e);
+                }
+            }
+            Action::CameraPathToggleRecording => match app_state.camera_path.mode {
+                CameraPathMode::Recording => {
+                    app_state.camera_path.mode = CameraPathMode::Live;
+                    log::info!(
+                        "Recorded {} camera keyframes",
+                        app_state.camera_path.keyframes.len()
+                    );
+                }
+                _ => {
+                    app_state.camera_path.start_recording();
+                    log::info!("Recording camera path");
                 }
             },
-            Action::CameraReset => {
-                app_state.camera = Box::from(OrbitCamera::default());
-                app_state.mouse = Mouse::default();
+            Action::CameraPathTogglePlayback => {
+                app_state.camera_path.mode = match app_state.camera_path.mode {
+                    CameraPathMode::Playback => CameraPathMode::Live,
+                    _ => CameraPathMode::Playback,
+                };
+            }
+            Action::CameraPathClear => {
+                app_state.camera_path.clear();
+            }
+            Action::CameraPathSave => match &app_state.camera_path_file {
+                Some(file) => match app_state.camera_path.save(file) {
+                    Ok(()) => log::info!("Saved camera path to {}", file.display()),
+                    Err(e) => log::error!("Failed to save camera path: {}", e),
+                },
+                None => log::warn!("No camera path file, pass one with --camera-path"),
+            },
+            Action::CameraPathLoad => match &app_state.camera_path_file {
+                Some(file) => match CameraPath::load(file) {
+                    Ok(camera_path) => {
+                        log::info!("Loaded {} camera keyframes", camera_path.keyframes.len());
+                        app_state.camera_path = camera_path;
+                        app_state.camera_path.mode = CameraPathMode::Playback;
+                    }
+                    Err(e) => log::error!("Failed to load camera path: {}", e),
+                },
+                None => log::warn!("No camera path file, pass one with --camera-path"),
+            },
+            Action::ToggleUi => {
+                // the UI is forced open while there's a shader error to show
+                app_state.ui_visible = !app_state.ui_visible || app_state.shader_error.is_some();
+                log::debug!("Action::ToggleUi {:?}", app_state.ui_visible);
+            }
+            Action::UIToggleStats => {
+                app_state.show_stats = !app_state.show_stats;
+            }
+            Action::UIToggleShaderInfo => {
+                app_state.show_shader_info = !app_state.show_shader_info;
             }
-            Action::UIToggleVisible => {
-                app_state.ui_visible = !app_state.ui_visible;
-                log::debug!("Action::UIToggleVisible {:?}", app_state.ui_visible);
+            Action::ToggleVsync => {
+                app_state.vsync = !app_state.vsync;
+            }
+            Action::SetFpsCap(fps_cap) => {
+                app_state.fps_cap = fps_cap;
             }
             Action::ToggleFullscreen => {
                 app_state.is_fullscreen = !app_state.is_fullscreen;
             }
-            Action::Screenshot => {
-                log::debug!("Take screenshot. To be implemented.");
+            Action::Screenshot(path) => {
+                app_state.take_screenshot = Some(path.unwrap_or_else(screenshot_path));
             }
+            Action::SaveGif => app_state.save_gif_recording(),
             Action::PrintSource => {
                 shader_service.source();
             }
-            Action::CameraMove(camera_movement) => match camera_movement {
-                CameraMovement::MoveForward => {
-                    app_state.camera_pos.z += 0.2;
-                }
-                CameraMovement::MoveBackward => {
-                    app_state.camera_pos.z -= 0.2;
-                }
-                CameraMovement::StrafeLeft => {
-                    app_state.camera_pos.x -= 0.2;
+            Action::DumpUniforms => {
+                log::info!("Uniforms:\n{}", app_state.dump_uniforms());
+            }
+            Action::SaveFlattened(path) => {
+                match shader_service.save_flattened(app_state.active_shader, &path) {
+                    Ok(()) => app_state.show_toast(format!("Saved {}", file_name(&path))),
+                    Err(e) => app_state.show_toast(format!("Failed to save {}", e)),
                 }
-                CameraMovement::StrafeRight => {
-                    app_state.camera_pos.x += 0.2;
+            }
+
+            Action::TakeSnapshot => shader_service.save_snapshot(),
+            Action::ReloadShaders => {
+                log::info!("Reloading all shaders");
+                shader_service.reload(shader_service.pre_processor_config());
+                // re-evaluated when the shaders are compiled in the next frame
+                shader_service.last_error = None;
+                // atomic saves can leave the watcher on a file that no longer exists
+                shader_service.watch();
+            }
+            Action::OpenFile(path) => {
+                // keep the edits of the old shader in its own sidecar files
+                if app_state.uniforms_changed {
+                    app_state.save_uniforms();
                 }
-                CameraMovement::Reset => {
-                    app_state.camera_pos.x = 0.0;
-                    app_state.camera_pos.y = 0.0;
-                    app_state.camera_pos.z = 0.0;
+                app_state.save_camera();
+
+                if app_state.camera_file.is_some() {
+                    app_state.camera_file = Some(camera_file(&path));
+                    app_state.restore_camera();
                 }
+                app_state.uniforms_file = Some(uniforms_file(&path));
+                // start from the new shader's defaults instead of carrying values over by name
+                app_state.custom_uniforms.clear();
+                app_state.uniforms_changed = false;
+                app_state.restore_uniforms_on_compile = true;
+                app_state.presets_file = Some(presets_file(&path));
+                app_state.load_presets();
+                app_state.camera_path_file = Some(camera_path_file(&path));
+                shader_service.open_files(vec![path.clone()]);
+                app_state.active_shader = 0;
+                app_state.frame_count = 0;
+                app_state.reset_accumulation = true;
+                app_state.show_welcome = false;
+                app_state.show_toast(format!("Opened {}", file_name(&path)));
+            }
+            Action::AddChannel(path) => match shader_service.free_channel() {
+                Some(index) => match shader_service.load_channel(index, &ChannelConfig::new(&path))
+                {
+                    Ok(()) => app_state.show_toast(format!(
+                        "Loaded {} into iChannel{}",
+                        file_name(&path),
+                        index
+                    )),
+                    Err(e) => app_state.show_toast(format!("Failed to load {}", e)),
+                },
+                None => app_state.show_toast("All channels are in use"),
             },
-
-            Action::TakeSnapshot => shader_service.save_snapshot(),
         }
     }
 }
+
+/// The preprocessor injects the camera code, so switching recompiles the shaders. Only the
+/// shaders are rebuilt, playback time and custom uniforms carry on.
+fn set_camera_integration(shader_service: &mut ShaderService, use_camera_integration: bool) {
+    if shader_service.use_camera_integration == use_camera_integration {
+        return;
+    }
+    match use_camera_integration {
+        true => log::info!(
+            "Enabling camera integration. Please use '#pragma skuggbox(camera)' in your shader"
+        ),
+        false => log::info!("Disabling camera integration"),
+    }
+    shader_service.use_camera_integration = use_camera_integration;
+    shader_service.reload(PreProcessorConfig {
+        use_camera_integration,
+        ..shader_service.pre_processor_config()
+    });
+}
+
+/// File name for messages in the UI, the whole path if there's no name
+fn file_name(path: &Path) -> String {
+    path.file_name().map_or_else(
+        || path.display().to_string(),
+        |name| name.to_string_lossy().to_string(),
+    )
+}
@@ -1,11 +1,24 @@
+use glow::HasContext;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit::platform::run_return::EventLoopExtRunReturn;
 
+use crate::camera::{camera_file, CameraSmoothing};
+use crate::camera_path::{camera_path_file, CameraPath, CameraPathMode};
+use crate::capture::{convert_alpha, read_pixels, save_png, save_screenshot};
+use crate::framebuffer::Framebuffer;
+use crate::gif_recorder::GifRecorder;
 use crate::renderer::Renderer;
 use crate::{
-    handle_actions, handle_events, top_bar, Action, AppConfig, AppState, AppWindow, PlayMode,
-    ShaderService,
+    describe_uniform, handle_actions, handle_events, key_bank_values, limit_frame_rate, mux_audio,
+    presets_file, quit_dialog, render_region, required_glsl_version, shader_info, toast, top_bar,
+    uniform_graph, uniforms_file, uniforms_panel, welcome, Action, AppConfig, AppState, AppWindow,
+    AudioInput, AudioLevel, AudioTrack, AudioTrackInput, BenchmarkConfig, BenchmarkReport,
+    ControlInput, ExportAlpha, ExportConfig, GlInfo, KeyboardTexture, MidiInput, OscInput,
+    PlayMode, PresetTransition, Presets, ShaderService, BUILTIN_SHADER, CHANNEL_COUNT,
 };
 use ui_backend::Ui;
 
@@ -19,8 +32,51 @@ pub struct App {
 
 impl App {
     pub fn from_config(config: AppConfig) -> Self {
-        let app_state = AppState::default();
+        let mut app_state = AppState {
+            max_time: config.max_time,
+            accumulate: config.accumulate,
+            key_bank: config
+                .key_bank
+                .then(|| key_bank_values(config.key_values.as_deref())),
+            pause_on_error: config.pause_on_error,
+            confirm_quit: !config.no_confirm_quit,
+            vsync: config.vsync,
+            fps_cap: config.fps_cap,
+            fixed_size: config.width.zip(config.height),
+            bpm: config.bpm,
+            step_fps: config.step_fps,
+            max_delta_time: config.max_delta_time,
+            // the desktop shows through a transparent window where nothing is drawn
+            clear_color: match config.transparent {
+                true => [0.0, 0.0, 0.0, 0.0],
+                false => config.clear_color,
+            },
+            // validated in main
+            gif_recorder: GifRecorder::new(config.gif_settings().unwrap_or_default()),
+            presets: Presets::new(PresetTransition {
+                seconds: config.preset_fade,
+                easing: config.preset_easing,
+            }),
+            camera: config.camera.camera(),
+            camera_kind: config.camera,
+            camera_smoothing: CameraSmoothing::new(config.camera_smoothing),
+            camera_path: config
+                .camera_path
+                .as_deref()
+                .map(load_camera_path)
+                .unwrap_or_default(),
+            camera_path_file: config.camera_path.clone(),
+            ..Default::default()
+        };
+        app_state.start_at(config.time, config.paused);
         let (app_window, event_loop) = AppWindow::new(config, &app_state);
+        // the window is created with a logical size, render at its size in physical pixels
+        if let Some(window) = &app_window.window {
+            let size = window.inner_size();
+            app_state.width = size.width as i32;
+            app_state.height = size.height as i32;
+            app_state.pixel_ratio = window.scale_factor() as f32;
+        }
         let ui = None;
         Self {
             event_loop,
@@ -31,6 +87,11 @@ impl App {
         }
     }
 
+    /// Version and driver strings of the OpenGL context, once it's created
+    pub fn gl_info(&self) -> Option<GlInfo> {
+        self.app_window.gl_info.clone()
+    }
+
     pub fn run(&mut self, config: AppConfig) {
         let App {
             event_loop,
@@ -45,19 +106,136 @@ impl App {
         let gl = app_window.create_window_context();
         let mut ui = Ui::new(event_loop, gl.clone());
 
-        let shader_files = config.files.unwrap();
-        log::debug!("Shader files: {:?}", shader_files);
-        let mut shader_service = ShaderService::new(gl.clone(), shader_files);
+        let mut shader_service =
+            match create_shader_service(gl.clone(), &config, app_window.gl_info.as_ref()) {
+                Some(shader_service) => shader_service,
+                None => return,
+            };
+
+        // without shaders the built-in one is running, explain how to load one
+        app_state.show_welcome =
+            config.passes.is_none() && config.files.as_deref().unwrap_or_default().is_empty();
+
+        // sidecar files go next to the shader, a shader from stdin has none
+        let shader_file = shader_service
+            .shaders
+            .first()
+            .and_then(|shader| shader.file());
+        if config.restore_camera {
+            app_state.camera_file = shader_file.map(camera_file);
+            app_state.restore_camera();
+        }
+
+        app_state.uniforms_file = shader_file.map(uniforms_file);
+        app_state.presets_file = shader_file.map(presets_file);
+        app_state.load_presets();
+        if app_state.camera_path_file.is_none() {
+            app_state.camera_path_file = shader_file.map(camera_path_file);
+        }
+
+        let audio_level = AudioLevel::new(config.audio_attack, config.audio_release);
+        let mut audio_input = match config.audio_input || config.audio_level {
+            true if config.audio_input && config.audio_channel >= CHANNEL_COUNT => {
+                log::error!("Audio channel must be less than {}", CHANNEL_COUNT);
+                None
+            }
+            true => match AudioInput::start(gl.as_ref(), config.audio_input, audio_level) {
+                Ok(audio_input) => {
+                    if config.audio_input {
+                        shader_service.set_channel(config.audio_channel, audio_input.channel());
+                    }
+                    Some(audio_input)
+                }
+                Err(e) => {
+                    log::error!("Failed to set up audio input: {}", e);
+                    None
+                }
+            },
+            false => None,
+        };
+        let keyboard_texture = match config.keyboard_channel {
+            Some(channel) if channel >= CHANNEL_COUNT => {
+                log::error!("Keyboard channel must be less than {}", CHANNEL_COUNT);
+                None
+            }
+            Some(channel) => match KeyboardTexture::new(gl.as_ref()) {
+                Ok(keyboard_texture) => {
+                    shader_service.set_channel(channel, keyboard_texture.channel());
+                    Some(keyboard_texture)
+                }
+                Err(e) => {
+                    log::error!("Failed to create keyboard texture: {}", e);
+                    None
+                }
+            },
+            None => None,
+        };
+        let midi_input = match config.midi {
+            true => match MidiInput::start() {
+                Ok(midi_input) => {
+                    app_state.midi.enabled = true;
+                    Some(midi_input)
+                }
+                Err(e) => {
+                    log::error!("Failed to set up MIDI input: {}", e);
+                    None
+                }
+            },
+            false => None,
+        };
+        let osc_input = config
+            .osc_port
+            .and_then(|port| match OscInput::start(port) {
+                Ok(osc_input) => Some(osc_input),
+                Err(e) => {
+                    log::error!("Failed to listen for OSC messages on port {}: {}", port, e);
+                    None
+                }
+            });
+        let control_input = config.stdin_control.then(ControlInput::start);
         shader_service.watch();
         let _ = shader_service.run(gl.as_ref());
+        app_state.set_custom_uniforms(shader_service.custom_uniforms());
+        app_state.restore_uniforms();
 
-        let renderer = Renderer::new(gl.clone());
+        let mut renderer = Renderer::new(gl.clone());
 
         log::debug!("MainLoop: Start");
 
         while app_state.is_running {
-            let _ = shader_service.run(gl.as_ref());
-            app_state.shader_error = shader_service.last_error.clone();
+            let frame_start = Instant::now();
+            if let Ok(true) = shader_service.run(gl.as_ref()) {
+                // restart iFrame so accumulation buffers start over cleanly
+                app_state.frame_count = 0;
+                app_state.reset_accumulation = true;
+                app_state.set_custom_uniforms(shader_service.custom_uniforms());
+                if app_state.restore_uniforms_on_compile {
+                    app_state.restore_uniforms_on_compile = false;
+                    app_state.restore_uniforms();
+                }
+            }
+            app_state.set_shader_error(shader_service.last_error.clone());
+
+            if let Some(midi_input) = &midi_input {
+                for change in midi_input.receiver.try_iter() {
+                    app_state.midi.apply(change, &mut app_state.custom_uniforms);
+                    app_state.uniforms_changed = true;
+                }
+            }
+            if let Some(osc_input) = &osc_input {
+                for message in osc_input.receiver.try_iter() {
+                    message.apply(&mut app_state.custom_uniforms);
+                    app_state.uniforms_changed = true;
+                }
+            }
+            if let Some(control_input) = &control_input {
+                actions.extend(control_input.receiver.try_iter());
+            }
+            // frame times keep going while paused, unlike the playback delta
+            let frame_time = app_state.frame_times.last();
+            app_state
+                .presets
+                .update(frame_time, &mut app_state.custom_uniforms);
 
             // force UI open if we have a shader error
             if app_state.shader_error.is_some() {
@@ -66,8 +244,7 @@ impl App {
 
             if matches!(app_state.play_mode, PlayMode::Playing) {
                 app_state.timer.start();
-                // TODO(mathias): Remove this. Only use `app_state.timer.delta_time`
-                app_state.delta_time = app_state.timer.delta_time;
+                app_state.update_delta_time();
             }
 
             event_loop.run_return(|event, _window_target, control_flow| {
@@ -77,9 +254,42 @@ impl App {
 
                 let _repaint_after = ui.run(app_window.window.as_ref().unwrap(), |egui_ctx| {
                     egui::TopBottomPanel::top("view_top").show(egui_ctx, |ui| {
-                        top_bar(ui, app_state, &mut actions, &shader_service);
+                        let gl_info = app_window.gl_info.as_ref();
+                        top_bar(ui, app_state, &mut actions, &shader_service, gl_info);
                     });
 
+                    egui::Window::new("No shader loaded")
+                        .open(&mut app_state.show_welcome)
+                        .collapsible(false)
+                        .resizable(false)
+                        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                        .show(egui_ctx, |ui| welcome(ui, &mut actions));
+
+                    toast(egui_ctx, app_state);
+
+                    let uniforms_file = app_state.uniforms_file.clone();
+                    egui::Window::new("Unsaved uniforms")
+                        .open(&mut app_state.show_quit_dialog)
+                        .collapsible(false)
+                        .resizable(false)
+                        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                        .show(egui_ctx, |ui| {
+                            quit_dialog(ui, uniforms_file.as_deref(), &mut actions)
+                        });
+
+                    let active_shader = app_state.active_shader;
+                    egui::Window::new("Shader files")
+                        .open(&mut app_state.show_shader_info)
+                        .show(egui_ctx, |ui| {
+                            shader_info(ui, &shader_service, active_shader)
+                        });
+
+                    if !app_state.custom_uniforms.is_empty() {
+                        egui::SidePanel::right("view_uniforms").show(egui_ctx, |ui| {
+                            uniforms_panel(ui, app_state, &mut actions);
+                        });
+                    }
+
                     if let Some(error) = &app_state.shader_error {
                         let mut error = format!("{}", error);
                         egui::TopBottomPanel::bottom("view_bottom").show(egui_ctx, |ui| {
@@ -95,6 +305,9 @@ impl App {
                             });
                         });
                     }
+
+                    render_region(egui_ctx, app_state, &mut actions);
+                    uniform_graph(egui_ctx, app_state, &mut actions);
                 });
 
                 handle_events(&event, control_flow, &mut ui, app_state, &mut actions);
@@ -102,8 +315,31 @@ impl App {
                 handle_actions(&mut actions, app_state, &mut shader_service, control_flow);
             });
 
+            app_window.set_vsync(app_state.vsync);
+            app_window.resize_surface(app_state.width, app_state.height);
+            if app_state.is_fullscreen != app_window.is_fullscreen() {
+                app_window.set_fullscreen(app_state.is_fullscreen);
+            }
+
+            if let Some(audio_input) = &mut audio_input {
+                audio_input.update(gl.as_ref(), app_state.frame_times.last());
+                app_state.audio_level = audio_input.level();
+            }
+            app_state.update_uniform_graph(app_state.frame_times.last());
+            if let Some(keyboard_texture) = &keyboard_texture {
+                keyboard_texture.update(gl.as_ref(), &app_state.keyboard);
+            }
+
             // Render the OpenGL scene
-            renderer.draw(app_state, &shader_service);
+            renderer.draw(app_state, &mut shader_service);
+
+            // Capture the scene before the UI is painted on top of it
+            if let Some(path) = app_state.take_screenshot.take() {
+                save_screenshot(gl.as_ref(), app_state.width, app_state.height, &path);
+            }
+            app_state
+                .gif_recorder
+                .capture(gl.as_ref(), app_state.width, app_state.height);
 
             // Render UI on top of OpenGL scene
             if app_state.ui_visible && app_window.window.is_some() {
@@ -113,10 +349,323 @@ impl App {
             }
 
             app_window.swap_buffers();
+            if let Some(fps_cap) = app_state.fps_cap {
+                limit_frame_rate(frame_start, fps_cap);
+            }
+            app_state.frame_times.tick();
+            app_state.mouse.end_frame();
+            app_state.keyboard.end_frame();
 
             app_state.timer.stop();
         }
 
         log::debug!("MainLoop: Exit");
+        if let Some(keyboard_texture) = &keyboard_texture {
+            keyboard_texture.delete(gl.as_ref());
+        }
+        app_state.save_camera();
+    }
+
+    /// Render the frames in the export time range offscreen and save them as numbered PNGs.
+    /// Time advances exactly `1.0 / fps` per frame so the output is reproducible.
+    pub fn export(&mut self, config: AppConfig, export: ExportConfig) {
+        let App {
+            app_window,
+            app_state,
+            ..
+        } = self;
+
+        let gl = app_window.create_window_context();
+
+        let mut shader_service =
+            match create_shader_service(gl.clone(), &config, app_window.gl_info.as_ref()) {
+                Some(shader_service) => shader_service,
+                None => return,
+            };
+        if let Err(e) = shader_service.run(gl.as_ref()) {
+            log::error!("Export: Shader failed to compile: {}", e);
+            return;
+        }
+        app_state.set_custom_uniforms(shader_service.custom_uniforms());
+        // the saved uniform values, so the frames match the tweaked shader
+        app_state.uniforms_file = shader_service
+            .shaders
+            .first()
+            .and_then(|shader| shader.file())
+            .map(uniforms_file);
+        app_state.restore_uniforms();
+
+        if let Err(e) = fs::create_dir_all(&export.out_dir) {
+            log::error!("Export: Failed to create {:?}: {}", export.out_dir, e);
+            return;
+        }
+
+        // the size rendered in the window, so the frames match what was seen
+        let (width, height) = app_state.render_size();
+        let target = match Framebuffer::new(gl.as_ref(), width, height) {
+            Ok(target) => target,
+            Err(e) => {
+                log::error!("Export: Failed to create framebuffer: {}", e);
+                return;
+            }
+        };
+
+        let mut renderer = Renderer::new(gl.clone());
+        let frame_count = export.frame_count();
+        log::info!(
+            "Export: Rendering {} frames at {}x{} into {:?}",
+            frame_count,
+            width,
+            height,
+            export.out_dir
+        );
+
+        // transparent where the shader writes no alpha, instead of the background color
+        if export.alpha != ExportAlpha::Opaque {
+            app_state.clear_color = [0.0, 0.0, 0.0, 0.0];
+        }
+        let mut audio = match &export.audio {
+            Some(path) => match export_audio_input(gl.as_ref(), &config, path) {
+                Ok(audio) => {
+                    if config.audio_input {
+                        shader_service.set_channel(config.audio_channel, audio.channel());
+                    }
+                    Some(audio)
+                }
+                Err(e) => {
+                    log::error!("Export: {}", e);
+                    return;
+                }
+            },
+            None => None,
+        };
+
+        app_state.delta_time = 1.0 / export.fps;
+        for frame in 0..frame_count {
+            app_state.playback_time = export.start + frame as f32 / export.fps;
+            if let Some(audio) = &mut audio {
+                audio.update(gl.as_ref(), app_state.playback_time, app_state.delta_time);
+                app_state.audio_level = audio.level();
+            }
+            renderer.draw_to(app_state, &mut shader_service, Some(&target));
+
+            target.bind(gl.as_ref());
+            let mut pixels = read_pixels(gl.as_ref(), width, height);
+            convert_alpha(&mut pixels, export.alpha);
+            let path = export.out_dir.join(format!("frame_{:05}.png", frame + 1));
+            if let Err(e) = save_png(&path, width, height, &pixels) {
+                log::error!("Export: Failed to save {:?}: {}", path, e);
+                return;
+            }
+        }
+
+        target.delete(gl.as_ref());
+        if let Some(audio) = &audio {
+            audio.delete(gl.as_ref());
+        }
+        if let Some(path) = &export.audio {
+            mux_audio(&export, path);
+        }
+        log::info!("Export: Done");
+    }
+
+    /// Render the benchmark shader offscreen as fast as possible and print frame time
+    /// statistics to stdout. Each frame waits on `gl.finish()` so the GPU work is measured too.
+    pub fn benchmark(&mut self, config: AppConfig, benchmark: BenchmarkConfig) {
+        let App {
+            app_window,
+            app_state,
+            ..
+        } = self;
+
+        let gl = app_window.create_window_context();
+
+        let config = AppConfig {
+            files: Some(vec![benchmark.file.clone()]),
+            passes: None,
+            ..config
+        };
+        let mut shader_service =
+            match create_shader_service(gl.clone(), &config, app_window.gl_info.as_ref()) {
+                Some(shader_service) => shader_service,
+                None => return,
+            };
+        if let Err(e) = shader_service.run(gl.as_ref()) {
+            log::error!("Benchmark: Shader failed to compile: {}", e);
+            return;
+        }
+        app_state.set_custom_uniforms(shader_service.custom_uniforms());
+
+        app_state.width = benchmark.width;
+        app_state.height = benchmark.height;
+        let target = match Framebuffer::new(gl.as_ref(), benchmark.width, benchmark.height) {
+            Ok(target) => target,
+            Err(e) => {
+                log::error!("Benchmark: Failed to create framebuffer: {}", e);
+                return;
+            }
+        };
+
+        let mut renderer = Renderer::new(gl.clone());
+        log::info!(
+            "Benchmark: Rendering {} frames of {:?} at {}x{}",
+            benchmark.frames,
+            benchmark.file,
+            benchmark.width,
+            benchmark.height
+        );
+
+        // time advances at a fixed 60 fps so every run renders the same frames
+        app_state.delta_time = 1.0 / 60.0;
+        // warm up once so driver-side shader compilation isn't counted as a frame
+        renderer.draw_to(app_state, &mut shader_service, Some(&target));
+        unsafe { gl.finish() };
+        app_state.frame_count = 0;
+
+        let mut durations = Vec::with_capacity(benchmark.frames as usize);
+        for frame in 0..benchmark.frames {
+            app_state.playback_time = frame as f32 * app_state.delta_time;
+
+            let frame_start = Instant::now();
+            renderer.draw_to(app_state, &mut shader_service, Some(&target));
+            unsafe { gl.finish() };
+            durations.push(frame_start.elapsed().as_secs_f32());
+        }
+
+        target.delete(gl.as_ref());
+
+        if let Some(report) = BenchmarkReport::from_durations(&durations) {
+            println!("{}", report);
+        }
+    }
+
+    /// Compile the shader offscreen and print every active uniform with its type and whether
+    /// skuggbox sets it, so it's clear which uniforms get sliders
+    pub fn list_uniforms(&mut self, config: AppConfig, file: PathBuf) {
+        let gl = self.app_window.create_window_context();
+
+        let config = AppConfig {
+            files: Some(vec![file.clone()]),
+            passes: None,
+            ..config
+        };
+        let mut shader_service =
+            match create_shader_service(gl.clone(), &config, self.app_window.gl_info.as_ref()) {
+                Some(shader_service) => shader_service,
+                None => return,
+            };
+        if let Err(e) = shader_service.run(gl.as_ref()) {
+            log::error!("List uniforms: Shader failed to compile: {}", e);
+            return;
+        }
+        let program = match shader_service.shaders.first().and_then(|s| s.program) {
+            Some(program) => program,
+            None => return,
+        };
+
+        let custom_uniforms = shader_service
+            .custom_uniforms()
+            .into_iter()
+            .map(|uniform| (uniform.name.clone(), uniform))
+            .collect();
+        println!("Active uniforms of {:?}", file);
+        unsafe {
+            for index in 0..gl.get_active_uniforms(program) {
+                if let Some(uniform) = gl.get_active_uniform(program, index) {
+                    println!("{}", describe_uniform(&uniform, &custom_uniforms));
+                }
+            }
+        }
+    }
+}
+
+/// Read the `--export-audio` track, with the audio texture when `--audio-input` asks for it
+fn export_audio_input(
+    gl: &glow::Context,
+    config: &AppConfig,
+    path: &Path,
+) -> Result<AudioTrackInput, String> {
+    if config.audio_input && config.audio_channel >= CHANNEL_COUNT {
+        return Err(format!("Audio channel must be less than {}", CHANNEL_COUNT));
+    }
+    log::info!("Export: Reading audio from {:?}", path);
+    let track = AudioTrack::load(path)?;
+    let level = AudioLevel::new(config.audio_attack, config.audio_release);
+    AudioTrackInput::new(gl, track, config.audio_input, level)
+}
+
+/// Set up the shaders from either a pass manifest or the shader files, and load the channels.
+fn create_shader_service(
+    gl: Arc<glow::Context>,
+    config: &AppConfig,
+    gl_info: Option<&GlInfo>,
+) -> Option<ShaderService> {
+    let pre_processor_config = config.pre_processor_config();
+
+    let mut shader_service = match &config.passes {
+        Some(manifest) => {
+            match ShaderService::from_manifest(gl.clone(), manifest, pre_processor_config) {
+                Ok(shader_service) => shader_service,
+                Err(e) => {
+                    log::error!("Failed to read pass manifest {:?}: {}", manifest, e);
+                    return None;
+                }
+            }
+        }
+        None => {
+            let shader_files = match &config.files {
+                Some(files) if !files.is_empty() => files.clone(),
+                _ => vec![PathBuf::from(BUILTIN_SHADER)],
+            };
+            log::debug!("Shader files: {:?}", shader_files);
+            ShaderService::new(gl.clone(), shader_files, pre_processor_config)
+        }
+    };
+    shader_service.load_channels(config.channel_files());
+    shader_service.debouncer.interval = Duration::from_millis(config.reload_debounce);
+
+    if let Some(gl_info) = gl_info {
+        warn_unsupported_glsl_versions(gl_info, &shader_service);
+    }
+
+    Some(shader_service)
+}
+
+/// Warn about shaders declaring a newer `#version` than the context supports, they will fail
+/// to compile with errors that don't always say why
+fn warn_unsupported_glsl_versions(gl_info: &GlInfo, shader_service: &ShaderService) {
+    let supported = match gl_info.glsl_version() {
+        Some(supported) => supported,
+        None => return,
+    };
+    for shader in &shader_service.shaders {
+        match required_glsl_version(&shader.content.shader_src) {
+            Some(required) if required > supported => log::warn!(
+                "{:?} needs GLSL {} but the context only supports {}",
+                shader.get_main_shader_path(),
+                required,
+                gl_info.shading_language_version
+            ),
+            _ => {}
+        }
+    }
+}
+
+/// A camera path passed with `--camera-path` is played back right away, so exported frames
+/// follow it too
+fn load_camera_path(file: &Path) -> CameraPath {
+    if !file.exists() {
+        return CameraPath::default();
+    }
+    match CameraPath::load(file) {
+        Ok(mut camera_path) => {
+            log::info!("Playing back camera path {}", file.display());
+            camera_path.mode = CameraPathMode::Playback;
+            camera_path
+        }
+        Err(e) => {
+            log::error!("Failed to load camera path: {}", e);
+            CameraPath::default()
+        }
     }
 }
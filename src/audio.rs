@@ -0,0 +1,418 @@
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::SizedSample;
+use glow::HasContext;
+use rustfft::num_complex::Complex;
+use rustfft::FftPlanner;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use crate::{spawn_guarded, AudioTrack, Channel, ChannelSource};
+
+/// Width of the audio texture. Row 0 holds the spectrum and row 1 the waveform, like Shadertoy.
+pub const AUDIO_TEXTURE_WIDTH: usize = 512;
+const FFT_SIZE: usize = AUDIO_TEXTURE_WIDTH * 2;
+
+/// Decibel range mapped to 0..1 in the spectrum, same defaults as the WebAudio analyser
+const MIN_DECIBELS: f32 = -100.0;
+const MAX_DECIBELS: f32 = -30.0;
+const SMOOTHING: f32 = 0.8;
+
+/// Analysed audio, both rows are in the range 0..255. The rows are empty when only the level
+/// is captured.
+pub struct AudioFrame {
+    pub spectrum: Vec<u8>,
+    pub waveform: Vec<u8>,
+    /// RMS of the samples that came in since the last frame
+    pub level: f32,
+}
+
+/// Root mean square of the samples, 0.0 for silence
+pub fn rms(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+}
+
+/// Follows the audio level with separate attack and release times, so the level jumps up
+/// on a beat and decays slowly instead of flickering with every buffer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioLevel {
+    /// Seconds to rise most of the way to a louder level
+    pub attack: f32,
+    /// Seconds to fall most of the way to a quieter level
+    pub release: f32,
+    pub level: f32,
+}
+
+impl AudioLevel {
+    pub fn new(attack: f32, release: f32) -> Self {
+        Self {
+            attack,
+            release,
+            level: 0.0,
+        }
+    }
+
+    /// Move towards `target` by the time since the last update
+    pub fn update(&mut self, target: f32, delta_time: f32) -> f32 {
+        let time = match target > self.level {
+            true => self.attack,
+            false => self.release,
+        };
+        let amount = match time > 0.0 {
+            true => 1.0 - (-delta_time / time).exp(),
+            false => 1.0,
+        };
+        self.level += (target - self.level) * amount;
+        self.level
+    }
+}
+
+/// Captures the default audio input on a separate thread and keeps a texture with its
+/// spectrum and waveform, and the level, up to date.
+pub struct AudioInput {
+    receiver: Receiver<AudioFrame>,
+    texture: glow::Texture,
+    level: AudioLevel,
+    /// Last level received from the capture thread
+    target_level: f32,
+}
+
+impl AudioInput {
+    /// Create the audio texture and start capturing. If there is no input device the texture
+    /// simply stays silent and the level stays at 0.0. Without `spectrum` only the level is
+    /// captured, which skips the FFT.
+    pub fn start(gl: &glow::Context, spectrum: bool, level: AudioLevel) -> Result<Self, String> {
+        let texture = create_audio_texture(gl)?;
+
+        let (sender, receiver): (Sender<AudioFrame>, Receiver<AudioFrame>) = channel();
+        let _ = spawn_guarded("audio capture", move || capture(sender, spectrum));
+
+        Ok(Self {
+            receiver,
+            texture,
+            level,
+            target_level: 0.0,
+        })
+    }
+
+    /// The audio texture, to be bound to one of the `iChannelN` samplers
+    pub fn channel(&self) -> Channel {
+        audio_channel(self.texture)
+    }
+
+    /// Smoothed level of the audio input, the `iAudioLevel` uniform
+    pub fn level(&self) -> f32 {
+        self.level.level
+    }
+
+    /// Upload the most recent audio frame, if any arrived since the last call, and move the
+    /// level on by the frame time. This method should be called from the GL-thread.
+    pub fn update(&mut self, gl: &glow::Context, delta_time: f32) {
+        let frame = self.receiver.try_iter().last();
+        if let Some(frame) = &frame {
+            self.target_level = frame.level;
+        }
+        self.level.update(self.target_level, delta_time);
+
+        if let Some(frame) = frame.filter(|frame| !frame.spectrum.is_empty()) {
+            upload_frame(gl, self.texture, frame);
+        }
+    }
+}
+
+/// Analyses an audio track at the playback time of each frame, in place of the live input
+/// while exporting
+pub struct AudioTrackInput {
+    track: AudioTrack,
+    /// `None` when only the level is needed
+    analyser: Option<Analyser>,
+    texture: glow::Texture,
+    level: AudioLevel,
+}
+
+impl AudioTrackInput {
+    pub fn new(
+        gl: &glow::Context,
+        track: AudioTrack,
+        spectrum: bool,
+        level: AudioLevel,
+    ) -> Result<Self, String> {
+        Ok(Self {
+            track,
+            analyser: spectrum.then(Analyser::new),
+            texture: create_audio_texture(gl)?,
+            level,
+        })
+    }
+
+    pub fn channel(&self) -> Channel {
+        audio_channel(self.texture)
+    }
+
+    /// Smoothed level of the track, the `iAudioLevel` uniform
+    pub fn level(&self) -> f32 {
+        self.level.level
+    }
+
+    /// Analyse the samples of the frame ending at `time`, call once per frame in order.
+    /// This method should be called from the GL-thread.
+    pub fn update(&mut self, gl: &glow::Context, time: f32, delta_time: f32) {
+        let samples = self.track.slice(time - delta_time, time);
+        let level = rms(samples);
+        self.level.update(level, delta_time);
+
+        if let Some(analyser) = &mut self.analyser {
+            // the whole FFT window, a frame can be shorter than that
+            let window = FFT_SIZE as f32 / self.track.sample_rate as f32;
+            analyser.push(self.track.slice(time - window, time));
+            upload_frame(gl, self.texture, analyser.analyse(level));
+        }
+    }
+
+    pub fn delete(&self, gl: &glow::Context) {
+        unsafe { gl.delete_texture(self.texture) };
+    }
+}
+
+fn audio_channel(texture: glow::Texture) -> Channel {
+    Channel {
+        texture,
+        target: glow::TEXTURE_2D,
+        width: AUDIO_TEXTURE_WIDTH as u32,
+        height: 2,
+        source: ChannelSource::Audio,
+    }
+}
+
+/// A silent audio texture
+fn create_audio_texture(gl: &glow::Context) -> Result<glow::Texture, String> {
+    unsafe {
+        let texture = gl.create_texture()?;
+        gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+        let silence = [0u8; AUDIO_TEXTURE_WIDTH * 2];
+        gl.tex_image_2d(
+            glow::TEXTURE_2D,
+            0,
+            glow::R8 as i32,
+            AUDIO_TEXTURE_WIDTH as i32,
+            2,
+            0,
+            glow::RED,
+            glow::UNSIGNED_BYTE,
+            Some(&silence),
+        );
+        gl.tex_parameter_i32(
+            glow::TEXTURE_2D,
+            glow::TEXTURE_MIN_FILTER,
+            glow::LINEAR as i32,
+        );
+        gl.tex_parameter_i32(
+            glow::TEXTURE_2D,
+            glow::TEXTURE_MAG_FILTER,
+            glow::LINEAR as i32,
+        );
+        gl.tex_parameter_i32(
+            glow::TEXTURE_2D,
+            glow::TEXTURE_WRAP_S,
+            glow::CLAMP_TO_EDGE as i32,
+        );
+        gl.tex_parameter_i32(
+            glow::TEXTURE_2D,
+            glow::TEXTURE_WRAP_T,
+            glow::CLAMP_TO_EDGE as i32,
+        );
+        gl.bind_texture(glow::TEXTURE_2D, None);
+        Ok(texture)
+    }
+}
+
+/// Upload the spectrum and waveform rows of `frame`
+fn upload_frame(gl: &glow::Context, texture: glow::Texture, frame: AudioFrame) {
+    let mut pixels = frame.spectrum;
+    pixels.extend(frame.waveform);
+
+    unsafe {
+        gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+        gl.tex_sub_image_2d(
+            glow::TEXTURE_2D,
+            0,
+            0,
+            0,
+            AUDIO_TEXTURE_WIDTH as i32,
+            2,
+            glow::RED,
+            glow::UNSIGNED_BYTE,
+            glow::PixelUnpackData::Slice(&pixels),
+        );
+        gl.bind_texture(glow::TEXTURE_2D, None);
+    }
+}
+
+/// Runs on the audio thread. Returns when there's no input device or the receiver is gone.
+fn capture(sender: Sender<AudioFrame>, spectrum: bool) {
+    let host = cpal::default_host();
+    let device = match host.default_input_device() {
+        Some(device) => device,
+        None => {
+            log::warn!("No audio input device found, the audio channel will stay silent");
+            return;
+        }
+    };
+
+    let config = match device.default_input_config() {
+        Ok(config) => config,
+        Err(e) => {
+            log::warn!("Failed to get audio input config: {}", e);
+            return;
+        }
+    };
+
+    let (sample_sender, sample_receiver) = channel::<Vec<f32>>();
+    let stream = match config.sample_format() {
+        cpal::SampleFormat::F32 => build_stream::<f32>(&device, &config.into(), sample_sender),
+        cpal::SampleFormat::I16 => build_stream::<i16>(&device, &config.into(), sample_sender),
+        cpal::SampleFormat::U16 => build_stream::<u16>(&device, &config.into(), sample_sender),
+        format => Err(format!("unsupported sample format {:?}", format)),
+    };
+
+    let stream =
+        match stream.and_then(|stream| stream.play().map(|_| stream).map_err(|e| e.to_string())) {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::warn!("Failed to start audio input: {}", e);
+                return;
+            }
+        };
+
+    log::info!("Capturing audio from {:?}", device.name());
+
+    let mut analyser = Analyser::new();
+    while let Ok(samples) = sample_receiver.recv() {
+        let frame = match spectrum {
+            true => {
+                analyser.push(&samples);
+                analyser.analyse(rms(&samples))
+            }
+            false => AudioFrame {
+                spectrum: vec![],
+                waveform: vec![],
+                level: rms(&samples),
+            },
+        };
+        if sender.send(frame).is_err() {
+            break;
+        }
+    }
+
+    drop(stream);
+}
+
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    sender: Sender<Vec<f32>>,
+) -> Result<cpal::Stream, String>
+where
+    T: SizedSample,
+    f32: cpal::FromSample<T>,
+{
+    let channels = config.channels as usize;
+    device
+        .build_input_stream(
+            config,
+            move |data: &[T], _: &cpal::InputCallbackInfo| {
+                // mix down to mono
+                let samples = data
+                    .chunks(channels)
+                    .map(|frame| {
+                        frame.iter().map(|s| s.to_sample::<f32>()).sum::<f32>() / channels as f32
+                    })
+                    .collect();
+                let _ = sender.send(samples);
+            },
+            |e| log::error!("Audio input error: {}", e),
+            None,
+        )
+        .map_err(|e| e.to_string())
+}
+
+/// Keeps the most recent samples and turns them into spectrum and waveform rows
+struct Analyser {
+    samples: Vec<f32>,
+    spectrum: Vec<f32>,
+    fft: std::sync::Arc<dyn rustfft::Fft<f32>>,
+}
+
+impl Analyser {
+    fn new() -> Self {
+        Self {
+            samples: vec![0.0; FFT_SIZE],
+            spectrum: vec![0.0; AUDIO_TEXTURE_WIDTH],
+            fft: FftPlanner::new().plan_fft_forward(FFT_SIZE),
+        }
+    }
+
+    fn push(&mut self, samples: &[f32]) {
+        self.samples.extend_from_slice(samples);
+        let overflow = self.samples.len().saturating_sub(FFT_SIZE);
+        self.samples.drain(..overflow);
+    }
+
+    fn analyse(&mut self, level: f32) -> AudioFrame {
+        // Hann window to reduce spectral leakage
+        let mut buffer: Vec<Complex<f32>> = self
+            .samples
+            .iter()
+            .enumerate()
+            .map(|(i, s)| {
+                let window =
+                    0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / FFT_SIZE as f32).cos();
+                Complex::new(s * window, 0.0)
+            })
+            .collect();
+        self.fft.process(&mut buffer);
+
+        let spectrum = self
+            .spectrum
+            .iter_mut()
+            .zip(buffer.iter())
+            .map(|(smoothed, bin)| {
+                *smoothed =
+                    SMOOTHING * *smoothed + (1.0 - SMOOTHING) * bin.norm() / FFT_SIZE as f32;
+                let decibels = 20.0 * smoothed.max(1e-10).log10();
+                let value = (decibels - MIN_DECIBELS) / (MAX_DECIBELS - MIN_DECIBELS);
+                (value.clamp(0.0, 1.0) * 255.0) as u8
+            })
+            .collect();
+
+        let waveform = self.samples[FFT_SIZE - AUDIO_TEXTURE_WIDTH..]
+            .iter()
+            .map(|s| ((s * 0.5 + 0.5).clamp(0.0, 1.0) * 255.0) as u8)
+            .collect();
+
+        AudioFrame {
+            spectrum,
+            waveform,
+            level,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rms, AudioLevel};
+
+    #[test]
+    fn audio_level_follows_rms() {
+        assert_eq!(rms(&[]), 0.0);
+        assert_eq!(rms(&[0.5, -0.5, 0.5, -0.5]), 0.5);
+
+        let mut level = AudioLevel::new(0.0, 1.0);
+        assert_eq!(level.update(0.5, 0.016), 0.5);
+        // releases slowly
+        let released = level.update(0.0, 1.0);
+        assert!(released > 0.15 && released < 0.2);
+        assert_eq!(AudioLevel::new(0.1, 0.1).update(0.0, 0.016), 0.0);
+    }
+}
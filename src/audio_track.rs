@@ -0,0 +1,282 @@
+use std::convert::TryInto;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use which::which;
+
+use crate::ExportConfig;
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+/// A decoded audio file mixed down to mono. Exports analyse it at the time of each frame
+/// instead of the live input, so the same time range always renders the same.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioTrack {
+    pub sample_rate: u32,
+    pub samples: Vec<f32>,
+}
+
+impl AudioTrack {
+    /// Read a WAV file. Other formats are decoded with ffmpeg, if it's installed.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let is_wav = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("wav"));
+        let bytes = match is_wav {
+            true => std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?,
+            false => decode_with_ffmpeg(path)?,
+        };
+        Self::from_wav(&bytes).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Parse an 8, 16, 24 or 32 bit integer or a 32 bit float WAV file
+    pub fn from_wav(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < 12 || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+            return Err("not a WAV file".to_string());
+        }
+
+        let mut format = None;
+        let mut chunks = &bytes[12..];
+        while chunks.len() >= 8 {
+            let id = &chunks[..4];
+            let size = match u32::from_le_bytes(chunks[4..8].try_into().unwrap()) {
+                // a WAV streamed to a pipe, e.g. by ffmpeg, doesn't know its data size
+                u32::MAX if id == b"data" => chunks.len() - 8,
+                size => size as usize,
+            };
+            let data = chunks
+                .get(8..8 + size)
+                .ok_or_else(|| "truncated WAV file".to_string())?;
+
+            match id {
+                b"fmt " if data.len() >= 16 => {
+                    let u16_at = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+                    let mut tag = u16_at(0);
+                    if tag == WAVE_FORMAT_EXTENSIBLE && data.len() >= 26 {
+                        // the sub format GUID starts with the actual format tag
+                        tag = u16_at(24);
+                    }
+                    let sample_rate = u32::from_le_bytes(data[4..8].try_into().unwrap());
+                    format = Some((tag, u16_at(2) as usize, sample_rate, u16_at(14)));
+                }
+                b"data" => {
+                    let (tag, channels, sample_rate, bits) =
+                        format.ok_or_else(|| "WAV data before its format".to_string())?;
+                    let samples = decode_samples(data, tag, bits)?;
+                    let channels = channels.max(1);
+                    let samples = samples
+                        .chunks_exact(channels)
+                        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+                        .collect();
+                    return Ok(Self {
+                        sample_rate,
+                        samples,
+                    });
+                }
+                _ => {}
+            }
+            // chunks are padded to an even size
+            chunks = chunks.get(8 + size + size % 2..).unwrap_or_default();
+        }
+        Err("WAV file without data".to_string())
+    }
+
+    /// Samples from `start` to `end` seconds, cut short at the ends of the track
+    pub fn slice(&self, start: f32, end: f32) -> &[f32] {
+        let index = |time: f32| {
+            ((time.max(0.0) * self.sample_rate as f32) as usize).min(self.samples.len())
+        };
+        let (start, end) = (index(start), index(end));
+        &self.samples[start..end.max(start)]
+    }
+}
+
+fn decode_samples(data: &[u8], tag: u16, bits: u16) -> Result<Vec<f32>, String> {
+    let samples = match (tag, bits) {
+        (WAVE_FORMAT_PCM, 8) => data.iter().map(|s| (*s as f32 - 128.0) / 128.0).collect(),
+        (WAVE_FORMAT_PCM, 16) => data
+            .chunks_exact(2)
+            .map(|s| i16::from_le_bytes([s[0], s[1]]) as f32 / 32768.0)
+            .collect(),
+        (WAVE_FORMAT_PCM, 24) => data
+            .chunks_exact(3)
+            .map(|s| i32::from_le_bytes([0, s[0], s[1], s[2]]) as f32 / 2147483648.0)
+            .collect(),
+        (WAVE_FORMAT_PCM, 32) => data
+            .chunks_exact(4)
+            .map(|s| i32::from_le_bytes(s.try_into().unwrap()) as f32 / 2147483648.0)
+            .collect(),
+        (WAVE_FORMAT_IEEE_FLOAT, 32) => data
+            .chunks_exact(4)
+            .map(|s| f32::from_le_bytes(s.try_into().unwrap()))
+            .collect(),
+        _ => return Err(format!("unsupported WAV format {} with {} bits", tag, bits)),
+    };
+    Ok(samples)
+}
+
+/// Decode any audio file ffmpeg knows into a mono float WAV in memory
+fn decode_with_ffmpeg(path: &Path) -> Result<Vec<u8>, String> {
+    let ffmpeg = which("ffmpeg").map_err(|_| {
+        format!(
+            "{}: only WAV files can be read without ffmpeg",
+            path.display()
+        )
+    })?;
+    let output = Command::new(ffmpeg)
+        .args(["-v", "error", "-i"])
+        .arg(path)
+        .args(["-ac", "1", "-c:a", "pcm_f32le", "-f", "wav", "-"])
+        .output()
+        .map_err(|e| format!("Failed to run ffmpeg: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "ffmpeg failed to decode {}: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(output.stdout)
+}
+
+/// Where `mux_audio` writes the video, next to the exported frames
+pub fn export_video_path(export: &ExportConfig) -> PathBuf {
+    export.out_dir.join("video.mp4")
+}
+
+/// ffmpeg arguments encoding the exported frames into a video with the audio track, starting
+/// the audio at the first exported frame
+pub fn mux_args(export: &ExportConfig, audio: &Path) -> Vec<String> {
+    let frames = export.out_dir.join("frame_%05d.png");
+    [
+        "-y",
+        "-v",
+        "error",
+        "-framerate",
+        &export.fps.to_string(),
+        "-i",
+        &frames.display().to_string(),
+        "-ss",
+        &export.start.to_string(),
+        "-i",
+        &audio.display().to_string(),
+        "-map",
+        "0:v",
+        "-map",
+        "1:a",
+        "-c:v",
+        "libx264",
+        "-pix_fmt",
+        "yuv420p",
+        "-c:a",
+        "aac",
+        "-shortest",
+        &export_video_path(export).display().to_string(),
+    ]
+    .iter()
+    .map(|arg| arg.to_string())
+    .collect()
+}
+
+/// Encode the exported frames with the audio track using ffmpeg. Without ffmpeg the command
+/// to run is logged instead.
+pub fn mux_audio(export: &ExportConfig, audio: &Path) {
+    let args = mux_args(export, audio);
+    let ffmpeg = match which("ffmpeg") {
+        Ok(ffmpeg) => ffmpeg,
+        Err(_) => {
+            log::warn!(
+                "Export: ffmpeg not found, add the audio with: ffmpeg {}",
+                args.join(" ")
+            );
+            return;
+        }
+    };
+    match Command::new(ffmpeg).args(&args).output() {
+        Ok(output) if output.status.success() => {
+            log::info!("Export: Saved {:?}", export_video_path(export));
+        }
+        Ok(output) => log::error!(
+            "Export: ffmpeg failed to add the audio: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Err(e) => log::error!("Export: Failed to run ffmpeg: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{mux_args, AudioTrack};
+    use crate::{ExportAlpha, ExportConfig};
+
+    fn wav(channels: u16, samples: &[i16]) -> Vec<u8> {
+        let data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        let mut bytes = b"RIFF".to_vec();
+        bytes.extend((36 + data.len() as u32).to_le_bytes());
+        bytes.extend(b"WAVEfmt ");
+        bytes.extend(16u32.to_le_bytes());
+        bytes.extend(1u16.to_le_bytes());
+        bytes.extend(channels.to_le_bytes());
+        bytes.extend(4u32.to_le_bytes());
+        bytes.extend((4 * 2 * channels as u32).to_le_bytes());
+        bytes.extend((2 * channels).to_le_bytes());
+        bytes.extend(16u16.to_le_bytes());
+        bytes.extend(b"data");
+        bytes.extend((data.len() as u32).to_le_bytes());
+        bytes.extend(data);
+        bytes
+    }
+
+    #[test]
+    fn read_wav_as_mono() {
+        let track =
+            AudioTrack::from_wav(&wav(2, &[16384, 0, -16384, -16384, 0, 0, 8192, 8192])).unwrap();
+        assert_eq!(track.sample_rate, 4);
+        assert_eq!(track.samples, vec![0.25, -0.5, 0.0, 0.25]);
+
+        assert_eq!(track.slice(0.25, 0.75), &[-0.5, 0.0]);
+        assert_eq!(track.slice(-1.0, 0.25), &[0.25]);
+        assert!(track.slice(2.0, 3.0).is_empty());
+
+        assert!(AudioTrack::from_wav(b"RIFF....WAVE").is_err());
+        assert!(AudioTrack::from_wav(b"not a wav file").is_err());
+    }
+
+    #[test]
+    fn read_streamed_wav() {
+        let mut bytes = wav(1, &[16384, -16384]);
+        // the sizes a stream writes before it knows them
+        bytes[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        bytes[40..44].copy_from_slice(&u32::MAX.to_le_bytes());
+        let track = AudioTrack::from_wav(&bytes).unwrap();
+        assert_eq!(track.samples, vec![0.5, -0.5]);
+
+        // any other size past the end is still an error
+        bytes[40..44].copy_from_slice(&100u32.to_le_bytes());
+        assert!(AudioTrack::from_wav(&bytes).is_err());
+    }
+
+    #[test]
+    fn mux_frames_with_audio() {
+        let export = ExportConfig {
+            start: 2.5,
+            end: 5.0,
+            fps: 30.0,
+            out_dir: PathBuf::from("frames"),
+            alpha: ExportAlpha::Opaque,
+            audio: None,
+        };
+        let args = mux_args(&export, Path::new("song.wav"));
+        let arg_after = |flag: &str| {
+            let index = args.iter().position(|arg| arg == flag).unwrap();
+            args[index + 1].clone()
+        };
+        assert_eq!(arg_after("-framerate"), "30");
+        assert_eq!(arg_after("-ss"), "2.5");
+        assert_eq!(args.last().unwrap(), "frames/video.mp4");
+    }
+}
@@ -0,0 +1,90 @@
+use std::fmt::Formatter;
+
+/// Frame time statistics of a benchmark run, all durations in seconds
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchmarkReport {
+    pub frames: usize,
+    pub total: f32,
+    pub min: f32,
+    pub max: f32,
+    pub average: f32,
+    pub p50: f32,
+    pub p95: f32,
+    pub p99: f32,
+}
+
+impl BenchmarkReport {
+    /// Summarize the frame durations. Returns `None` when there are none.
+    pub fn from_durations(durations: &[f32]) -> Option<Self> {
+        if durations.is_empty() {
+            return None;
+        }
+
+        let mut sorted = durations.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let total: f32 = sorted.iter().sum();
+
+        Some(Self {
+            frames: sorted.len(),
+            total,
+            min: sorted[0],
+            max: sorted[sorted.len() - 1],
+            average: total / sorted.len() as f32,
+            p50: percentile(&sorted, 50.0),
+            p95: percentile(&sorted, 95.0),
+            p99: percentile(&sorted, 99.0),
+        })
+    }
+
+    /// Frames rendered per second over the whole run
+    pub fn fps(&self) -> f32 {
+        if self.total > 0.0 {
+            self.frames as f32 / self.total
+        } else {
+            0.0
+        }
+    }
+}
+
+impl std::fmt::Display for BenchmarkReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let ms = |seconds: f32| seconds * 1000.0;
+        writeln!(f, "frames: {}", self.frames)?;
+        writeln!(f, "min:    {:.3} ms", ms(self.min))?;
+        writeln!(f, "max:    {:.3} ms", ms(self.max))?;
+        writeln!(f, "avg:    {:.3} ms", ms(self.average))?;
+        writeln!(f, "p50:    {:.3} ms", ms(self.p50))?;
+        writeln!(f, "p95:    {:.3} ms", ms(self.p95))?;
+        writeln!(f, "p99:    {:.3} ms", ms(self.p99))?;
+        write!(f, "fps:    {:.1}", self.fps())
+    }
+}
+
+/// Nearest-rank percentile of already sorted values
+fn percentile(sorted: &[f32], percent: f32) -> f32 {
+    let rank = (percent / 100.0 * sorted.len() as f32).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BenchmarkReport;
+
+    #[test]
+    fn report_from_durations() {
+        assert_eq!(BenchmarkReport::from_durations(&[]), None);
+
+        // 1..=100 ms in reverse order
+        let durations: Vec<f32> = (1..=100).rev().map(|ms| ms as f32 / 1000.0).collect();
+        let report = BenchmarkReport::from_durations(&durations).unwrap();
+
+        assert_eq!(report.frames, 100);
+        assert_eq!(report.min, 0.001);
+        assert_eq!(report.max, 0.1);
+        assert_eq!(report.p50, 0.05);
+        assert_eq!(report.p95, 0.095);
+        assert_eq!(report.p99, 0.099);
+        assert!((report.average - 0.0505).abs() < 1e-6);
+        assert!((report.fps() - 100.0 / 5.05).abs() < 1e-3);
+    }
+}
@@ -1,16 +1,917 @@
-use std::path::PathBuf;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
 
-use clap::Parser;
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use serde::Deserialize;
+
+use crate::camera::CameraKind;
+use crate::gif_recorder::GifSettings;
+use crate::Easing;
+use crate::{is_stdin_shader, ChannelConfig, PreProcessorConfig, Prelude, DEFAULT_GLSL_VERSION};
 
 #[derive(Parser, Debug, Clone)]
 #[command(about = "skuggbox", long_about = "Skuggbox GLSL shader viewer")]
 pub struct AppConfig {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Shader files, `-` reads a shader from stdin
     #[arg(short, long, name = "SHADER_FILES")]
     pub files: Option<Vec<PathBuf>>,
 
+    /// Keep the window above other windows
     #[arg(short, long)]
     pub always_on_top: bool,
 
+    /// Open the window without a title bar and borders
+    #[arg(long)]
+    pub borderless: bool,
+
+    /// Let the desktop show through where the shader writes alpha below 1, the background is
+    /// cleared to transparent
+    #[arg(long)]
+    pub transparent: bool,
+
+    /// Pass mouse clicks through the window to the ones below it, e.g. for live wallpapers
+    #[arg(long)]
+    pub click_through: bool,
+
     #[arg(short, long, name = "NEW_FILE")]
     pub new: Option<PathBuf>,
+
+    /// Project config file, defaults to `skuggbox.toml` in the working directory if it exists.
+    /// Command line flags take precedence over its values.
+    #[arg(long, name = "CONFIG_FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Manifest listing the render passes of a multi-pass setup
+    #[arg(long, name = "PASS_MANIFEST")]
+    pub passes: Option<PathBuf>,
+
+    /// Capture the default audio input and expose its spectrum and waveform as a texture
+    #[arg(long)]
+    pub audio_input: bool,
+
+    /// Channel the audio texture is bound to
+    #[arg(long, default_value_t = 0, name = "AUDIO_CHANNEL")]
+    pub audio_channel: usize,
+
+    /// Capture the default audio input only for the `iAudioLevel` uniform, without the
+    /// spectrum texture
+    #[arg(long)]
+    pub audio_level: bool,
+
+    /// Seconds `iAudioLevel` takes to rise to a louder level
+    #[arg(long, default_value_t = 0.01, name = "ATTACK_SECONDS")]
+    pub audio_attack: f32,
+
+    /// Seconds `iAudioLevel` takes to fall to a quieter level
+    #[arg(long, default_value_t = 0.3, name = "RELEASE_SECONDS")]
+    pub audio_release: f32,
+
+    /// Map MIDI controllers to custom uniforms, needs the `midi` feature
+    #[arg(long)]
+    pub midi: bool,
+
+    /// UDP port to receive OSC messages on, `/skuggbox/uniform/<name>` sets a custom uniform
+    #[arg(long, name = "PORT")]
+    pub osc_port: Option<u16>,
+
+    /// Read commands like `set time 3.2` or `screenshot out.png` from stdin, see
+    /// `CONTROL_COMMANDS`
+    #[arg(long)]
+    pub stdin_control: bool,
+
+    /// Number keys 0-9 set the `iKey` uniform instead of their usual action
+    #[arg(long)]
+    pub key_bank: bool,
+
+    /// Comma separated values the number keys 0-9 set `iKey` to with `--key-bank`, keys
+    /// without a value set their own number
+    #[arg(long, value_delimiter = ',', name = "KEY_VALUES")]
+    pub key_values: Option<Vec<f32>>,
+
+    /// Channel a Shadertoy style keyboard texture is bound to
+    #[arg(long, name = "KEYBOARD_CHANNEL")]
+    pub keyboard_channel: Option<usize>,
+
+    /// Render frames offscreen at a fixed time step and save them as numbered PNGs, then exit
+    #[arg(long, num_args = 4, value_names = ["START", "END", "FPS", "OUT_DIR"])]
+    pub export_frames: Option<Vec<String>>,
+
+    /// How `--export-frames` treats the alpha the shader writes
+    #[arg(long, value_enum, default_value_t = ExportAlpha::Opaque, name = "ALPHA")]
+    pub export_alpha: ExportAlpha,
+
+    /// Audio file for `--export-frames`. `iAudioLevel` and the audio channel follow it
+    /// instead of the live input, and it's muxed with the frames into a video with ffmpeg.
+    #[arg(long, name = "AUDIO_FILE")]
+    pub export_audio: Option<PathBuf>,
+
+    /// Render a shader offscreen as fast as possible, print frame time statistics and exit
+    #[arg(long, name = "BENCHMARK_FILE")]
+    pub benchmark: Option<PathBuf>,
+
+    /// Compile a shader offscreen, print its active uniforms and whether skuggbox drives them,
+    /// then exit
+    #[arg(long, name = "LIST_UNIFORMS_FILE")]
+    pub list_uniforms: Option<PathBuf>,
+
+    /// Number of frames rendered by `--benchmark`
+    #[arg(long, default_value_t = 600, name = "FRAMES")]
+    pub frames: u32,
+
+    /// Fixed render resolution of `--benchmark`
+    #[arg(long, default_value = "1280x720", name = "WIDTHxHEIGHT")]
+    pub resolution: String,
+
+    /// Render at a fixed width, independent of the window size, needs `--height` too.
+    /// The image is letterboxed to fit the window.
+    #[arg(long, requires = "HEIGHT", value_parser = clap::value_parser!(i32).range(1..), name = "WIDTH")]
+    pub width: Option<i32>,
+
+    /// Render at a fixed height, needs `--width` too
+    #[arg(long, requires = "WIDTH", value_parser = clap::value_parser!(i32).range(1..), name = "HEIGHT")]
+    pub height: Option<i32>,
+
+    /// Preprocessor define added after the `#version` line, e.g. `--define QUALITY=2`.
+    /// Can be given multiple times.
+    #[arg(long = "define", name = "NAME[=VALUE]")]
+    pub defines: Vec<String>,
+
+    /// File inlined into every shader after its `#version` line, like Shadertoy's Common tab.
+    /// Defaults to `common.glsl` next to the shader when there is one.
+    #[arg(long, name = "PRELUDE_FILE")]
+    pub prelude: Option<PathBuf>,
+
+    /// Don't inline `common.glsl` or `--prelude` into the shaders
+    #[arg(long)]
+    pub no_prelude: bool,
+
+    /// GLSL version for shaders without a `#version` line of their own
+    #[arg(long, default_value = DEFAULT_GLSL_VERSION, name = "VERSION")]
+    pub glsl_version: String,
+
+    /// OpenGL version of the context, e.g. `4.3` for compute shaders. Falls back to the
+    /// default context when it can't be created.
+    #[arg(long, value_parser = parse_gl_version, name = "MAJOR.MINOR")]
+    pub gl_version: Option<GlVersion>,
+
+    /// OpenGL profile of the context
+    #[arg(long, value_enum, name = "PROFILE")]
+    pub gl_profile: Option<GlProfile>,
+
+    /// Background behind shaders that don't cover the frame or use alpha, as hex `RRGGBB` or
+    /// `RRGGBBAA`
+    #[arg(long, default_value = "000000", value_parser = parse_hex_color, name = "RRGGBB")]
+    pub clear_color: [f32; 4],
+
+    /// Milliseconds without file changes to wait before reloading the shaders
+    #[arg(long, default_value_t = 100, name = "MILLISECONDS")]
+    pub reload_debounce: u64,
+
+    /// Sync to the display refresh rate
+    #[arg(long, overrides_with = "no_vsync")]
+    pub vsync: bool,
+
+    /// Don't sync to the display refresh rate, even when the config file turns it on
+    #[arg(long, overrides_with = "vsync")]
+    pub no_vsync: bool,
+
+    /// Limit the frame rate, e.g. to keep the GPU cool
+    #[arg(long, name = "FPS")]
+    pub fps_cap: Option<f32>,
+
+    /// Seconds of frames kept for a GIF, F8 saves them
+    #[arg(long, default_value_t = 3.0, name = "GIF_SECONDS")]
+    pub gif_seconds: f32,
+
+    /// Frame rate of the GIF recordings
+    #[arg(long, default_value_t = 15.0, name = "GIF_FPS")]
+    pub gif_fps: f32,
+
+    /// Width GIF recordings are scaled down to
+    #[arg(long, default_value_t = 480, name = "GIF_WIDTH")]
+    pub gif_width: u32,
+
+    /// Longest frame time playback advances by, slower frames after a stall are clamped to it
+    #[arg(long, default_value_t = 0.1, name = "MAX_DELTA_SECONDS")]
+    pub max_delta_time: f32,
+
+    /// Pause playback while the shader has errors and resume once it compiles again
+    #[arg(long)]
+    pub pause_on_error: bool,
+
+    /// Close the window without asking to save edited uniforms, e.g. for scripted runs
+    #[arg(long)]
+    pub no_confirm_quit: bool,
+
+    /// Tempo of the `iBeat` and `iBar` uniforms, can be tapped in with `B`
+    #[arg(long, default_value_t = 120.0, name = "BPM")]
+    pub bpm: f32,
+
+    /// Frame rate the `[` and `]` keys step through paused playback at
+    #[arg(long, default_value_t = 60.0, name = "STEP_FPS")]
+    pub step_fps: f32,
+
+    /// Seconds applying a uniform preset fades to its values, 0 switches right away
+    #[arg(long, default_value_t = 1.0, name = "FADE_SECONDS")]
+    pub preset_fade: f32,
+
+    /// Curve of the fade to a uniform preset
+    #[arg(long, value_enum, default_value_t = Easing::Smoothstep, name = "EASING")]
+    pub preset_easing: Easing,
+
+    /// Dev camera to start with
+    #[arg(long, value_enum, default_value_t = CameraKind::Orbit, name = "CAMERA")]
+    pub camera: CameraKind,
+
+    /// Seconds the dev camera takes to ease into where the input puts it, 0 follows the input
+    /// directly
+    #[arg(long, default_value_t = 0.0, name = "SMOOTHING_SECONDS")]
+    pub camera_smoothing: f32,
+
+    /// Restore the dev camera from `<shader>.camera.json` on start and save it there on exit.
+    /// Off by default so exports don't depend on where the camera was left.
+    #[arg(long)]
+    pub restore_camera: bool,
+
+    /// JSON file the camera path is saved to and loaded from, played back on start when it
+    /// exists. Defaults to `<shader>.camera-path.json` next to the shader, which is only loaded
+    /// on request.
+    #[arg(long, name = "CAMERA_PATH_FILE")]
+    pub camera_path: Option<PathBuf>,
+
+    /// Show the running average of the frames for path tracers, see `iSampleCount`
+    #[arg(long)]
+    pub accumulate: bool,
+
+    /// Length in seconds of the time scrubber in the UI
+    #[arg(long, default_value_t = 60.0, name = "SECONDS")]
+    pub max_time: f32,
+
+    /// Start with playback paused, e.g. with `--time` to look at a single frame
+    #[arg(long)]
+    pub paused: bool,
+
+    /// Playback time in seconds to start at
+    #[arg(long, default_value_t = 0.0, name = "START_TIME")]
+    pub time: f32,
+
+    /// Image bound to the `iChannel0` sampler, optionally followed by options like `,rgba16f`
+    #[arg(long, name = "CHANNEL0_FILE")]
+    pub channel0: Option<ChannelConfig>,
+
+    /// Image bound to the `iChannel1` sampler, optionally followed by options like `,rgba16f`
+    #[arg(long, name = "CHANNEL1_FILE")]
+    pub channel1: Option<ChannelConfig>,
+
+    /// Image bound to the `iChannel2` sampler, optionally followed by options like `,rgba16f`
+    #[arg(long, name = "CHANNEL2_FILE")]
+    pub channel2: Option<ChannelConfig>,
+
+    /// Image bound to the `iChannel3` sampler, optionally followed by options like `,rgba16f`
+    #[arg(long, name = "CHANNEL3_FILE")]
+    pub channel3: Option<ChannelConfig>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Download a shader from Shadertoy by its ID and save it as a skuggbox shader
+    Import {
+        /// ID of the shader, the last part of its URL
+        id: String,
+
+        /// Shadertoy API key, falls back to the SHADERTOY_API_KEY environment variable
+        #[arg(long)]
+        api_key: Option<String>,
+
+        /// Where to save the shader, defaults to <ID>.glsl
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+    },
+}
+
+/// Config file used when there's no `--config`
+pub const CONFIG_FILE: &str = "skuggbox.toml";
+
+/// Project settings read from a TOML config file. Relative paths are relative to the file.
+///
+/// ```toml
+/// files = ["shaders/scene.glsl"]
+/// channel0 = "textures/noise.png"
+/// defines = ["QUALITY=2"]
+/// # fixed render size, like --width and --height
+/// resolution = "1920x1080"
+/// vsync = true
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ConfigFile {
+    pub files: Option<Vec<PathBuf>>,
+    pub passes: Option<PathBuf>,
+    pub channel0: Option<ChannelConfig>,
+    pub channel1: Option<ChannelConfig>,
+    pub channel2: Option<ChannelConfig>,
+    pub channel3: Option<ChannelConfig>,
+    pub defines: Vec<String>,
+    pub resolution: Option<String>,
+    pub vsync: bool,
+    pub glsl_version: Option<String>,
+    pub prelude: Option<PathBuf>,
+}
+
+impl ConfigFile {
+    /// Read and parse a config file, returns `Ok(None)` when it doesn't exist
+    pub fn load(path: &Path) -> Result<Option<Self>, String> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        let mut config_file: Self =
+            toml::from_str(&content).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let resolve = |path: &mut Option<PathBuf>| {
+            if let Some(path) = path {
+                *path = dir.join(&path);
+            }
+        };
+        for file in config_file.files.iter_mut().flatten() {
+            *file = dir.join(&file);
+        }
+        let resolve_channel = |channel: &mut Option<ChannelConfig>| {
+            if let Some(channel) = channel {
+                channel.path = dir.join(&channel.path);
+            }
+        };
+        resolve(&mut config_file.passes);
+        resolve(&mut config_file.prelude);
+        resolve_channel(&mut config_file.channel0);
+        resolve_channel(&mut config_file.channel1);
+        resolve_channel(&mut config_file.channel2);
+        resolve_channel(&mut config_file.channel3);
+
+        if let Some(resolution) = &config_file.resolution {
+            parse_resolution(resolution).map_err(|e| format!("{}: {}", path.display(), e))?;
+        }
+
+        Ok(Some(config_file))
+    }
+}
+
+/// OpenGL context version requested with `--gl-version`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GlVersion {
+    pub major: u8,
+    pub minor: u8,
+}
+
+impl std::fmt::Display for GlVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+fn parse_gl_version(version: &str) -> Result<GlVersion, String> {
+    let parse = |part: &str| part.parse::<u8>().ok();
+    match version.split_once('.') {
+        Some((major, minor)) => match (parse(major), parse(minor)) {
+            (Some(major), Some(minor)) => Ok(GlVersion { major, minor }),
+            _ => Err(format!("expected MAJOR.MINOR, got '{}'", version)),
+        },
+        None => Err(format!("expected MAJOR.MINOR, got '{}'", version)),
+    }
+}
+
+/// A size as `WIDTHxHEIGHT`, both greater than zero
+fn parse_resolution(resolution: &str) -> Result<(i32, i32), String> {
+    let size = resolution
+        .split_once('x')
+        .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)));
+    match size {
+        Some((width, height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(format!(
+            "resolution must be WIDTHxHEIGHT, got '{}'",
+            resolution
+        )),
+    }
+}
+
+/// A color as `RRGGBB` or `RRGGBBAA`, with or without a leading `#`
+fn parse_hex_color(color: &str) -> Result<[f32; 4], String> {
+    let hex = color.trim_start_matches('#');
+    let channel = |index: usize| {
+        hex.get(index * 2..index * 2 + 2)
+            .and_then(|channel| u8::from_str_radix(channel, 16).ok())
+            .map(|channel| channel as f32 / 255.0)
+    };
+    match (hex.len(), channel(0), channel(1), channel(2)) {
+        (6, Some(r), Some(g), Some(b)) => Ok([r, g, b, 1.0]),
+        (8, Some(r), Some(g), Some(b)) => match channel(3) {
+            Some(a) => Ok([r, g, b, a]),
+            None => Err(format!("expected RRGGBB or RRGGBBAA, got '{}'", color)),
+        },
+        _ => Err(format!("expected RRGGBB or RRGGBBAA, got '{}'", color)),
+    }
+}
+
+/// OpenGL context profile requested with `--gl-profile`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum GlProfile {
+    Core,
+    Compatibility,
+}
+
+/// Alpha of exported frames, requested with `--export-alpha`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportAlpha {
+    /// Alpha is ignored and frames are saved fully opaque
+    #[default]
+    Opaque,
+    /// Frames are cleared to transparent and saved with the alpha the shader writes
+    Straight,
+    /// Like `Straight` for shaders that write color already multiplied by alpha, the color
+    /// is divided by alpha again as PNGs store straight alpha
+    Premultiplied,
+}
+
+/// Time range and output directory of an offline frame export
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportConfig {
+    pub start: f32,
+    pub end: f32,
+    pub fps: f32,
+    pub out_dir: PathBuf,
+    pub alpha: ExportAlpha,
+    /// Audio track the audio uniforms follow, muxed into a video with the frames
+    pub audio: Option<PathBuf>,
+}
+
+impl ExportConfig {
+    /// Number of frames in the time range
+    pub fn frame_count(&self) -> u32 {
+        ((self.end - self.start) * self.fps).ceil().max(0.0) as u32
+    }
+}
+
+/// Shader, frame count and fixed resolution of a headless benchmark run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BenchmarkConfig {
+    pub file: PathBuf,
+    pub frames: u32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl AppConfig {
+    /// Parse the command line and fill in what it leaves out from the config file.
+    /// Exits on invalid arguments like `AppConfig::parse`.
+    pub fn parse_with_config_file() -> Result<Self, String> {
+        Self::parse_with_config_file_from(std::env::args_os())
+    }
+
+    pub fn parse_with_config_file_from<I, T>(args: I) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let matches = Self::command().get_matches_from(args);
+        let mut config = Self::from_arg_matches(&matches)
+            .map_err(|e| format!("Invalid command line arguments: {}", e))?;
+
+        let path = config
+            .config
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(CONFIG_FILE));
+        match ConfigFile::load(&path).map_err(|e| format!("Invalid config file {}", e))? {
+            Some(config_file) => {
+                log::info!("Using config file {}", path.display());
+                let from_command_line =
+                    |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+                config.merge(config_file, from_command_line);
+            }
+            None if config.config.is_some() => {
+                log::warn!("Config file {} not found", path.display());
+            }
+            None => {}
+        }
+
+        Ok(config)
+    }
+
+    /// Take the values of the config file that weren't given on the command line.
+    /// `from_command_line` tells if the argument with the given id was.
+    fn merge(&mut self, config_file: ConfigFile, from_command_line: impl Fn(&str) -> bool) {
+        self.files = self.files.take().or(config_file.files);
+        self.passes = self.passes.take().or(config_file.passes);
+        self.prelude = self.prelude.take().or(config_file.prelude);
+        self.channel0 = self.channel0.take().or(config_file.channel0);
+        self.channel1 = self.channel1.take().or(config_file.channel1);
+        self.channel2 = self.channel2.take().or(config_file.channel2);
+        self.channel3 = self.channel3.take().or(config_file.channel3);
+        self.vsync |= config_file.vsync && !self.no_vsync;
+
+        // defines from the command line replace the ones of the same name
+        let defined_here: Vec<String> = self.defines().into_iter().map(|(name, _)| name).collect();
+        let mut defines: Vec<String> = config_file
+            .defines
+            .into_iter()
+            .filter(|define| {
+                let name = define
+                    .split_once('=')
+                    .map_or(define.as_str(), |(name, _)| name);
+                !defined_here.iter().any(|defined| defined == name)
+            })
+            .collect();
+        defines.append(&mut self.defines);
+        self.defines = defines;
+
+        // the fixed render size, validated when the file was loaded
+        if self.width.is_none() {
+            if let Some(Ok((width, height))) =
+                config_file.resolution.as_deref().map(parse_resolution)
+            {
+                self.width = Some(width);
+                self.height = Some(height);
+            }
+        }
+
+        // these have defaults, so only the source tells if they were given
+        if let (Some(glsl_version), false) =
+            (config_file.glsl_version, from_command_line("VERSION"))
+        {
+            self.glsl_version = glsl_version;
+        }
+    }
+
+    /// Parse `--export-frames`. Returns `Ok(None)` when not exporting.
+    pub fn export_config(&self) -> Result<Option<ExportConfig>, String> {
+        let args = match &self.export_frames {
+            Some(args) => args,
+            None => return Ok(None),
+        };
+
+        let parse = |name: &str, value: &str| {
+            value
+                .parse::<f32>()
+                .map_err(|_| format!("{} must be a number, got '{}'", name, value))
+        };
+        let export = ExportConfig {
+            start: parse("START", &args[0])?,
+            end: parse("END", &args[1])?,
+            fps: parse("FPS", &args[2])?,
+            out_dir: PathBuf::from(&args[3]),
+            alpha: self.export_alpha,
+            audio: self.export_audio.clone(),
+        };
+
+        if export.fps <= 0.0 {
+            return Err("FPS must be greater than zero".to_string());
+        }
+        if export.end < export.start {
+            return Err("END must not be before START".to_string());
+        }
+
+        Ok(Some(export))
+    }
+
+    /// Length, frame rate and width of the GIFs saved with F8
+    pub fn gif_settings(&self) -> Result<GifSettings, String> {
+        if self.gif_seconds <= 0.0 {
+            return Err("--gif-seconds must be greater than zero".to_string());
+        }
+        if self.gif_fps <= 0.0 {
+            return Err("--gif-fps must be greater than zero".to_string());
+        }
+        if self.gif_width == 0 {
+            return Err("--gif-width must be greater than zero".to_string());
+        }
+        Ok(GifSettings {
+            seconds: self.gif_seconds,
+            fps: self.gif_fps,
+            width: self.gif_width,
+        })
+    }
+
+    /// Parse `--benchmark` and `--resolution`. Returns `Ok(None)` when not benchmarking.
+    pub fn benchmark_config(&self) -> Result<Option<BenchmarkConfig>, String> {
+        let file = match &self.benchmark {
+            Some(file) => file.clone(),
+            None => return Ok(None),
+        };
+
+        let (width, height) = parse_resolution(&self.resolution)?;
+
+        if self.frames == 0 {
+            return Err("FRAMES must be greater than zero".to_string());
+        }
+
+        Ok(Some(BenchmarkConfig {
+            file,
+            frames: self.frames,
+            width,
+            height,
+        }))
+    }
+
+    /// Split `--define` arguments into `(name, value)` pairs, the value is empty for flags
+    pub fn defines(&self) -> Vec<(String, String)> {
+        self.defines
+            .iter()
+            .map(|define| match define.split_once('=') {
+                Some((name, value)) => (name.to_string(), value.to_string()),
+                None => (define.to_string(), String::new()),
+            })
+            .collect()
+    }
+
+    /// What's inlined into every shader after its `#version` line
+    pub fn prelude(&self) -> Prelude {
+        match (&self.prelude, self.no_prelude) {
+            (_, true) => Prelude::None,
+            (Some(file), false) => Prelude::File(file.clone()),
+            (None, false) => Prelude::Common,
+        }
+    }
+
+    /// How the shaders are pre-processed
+    pub fn pre_processor_config(&self) -> PreProcessorConfig {
+        PreProcessorConfig {
+            use_camera_integration: false,
+            defines: self.defines(),
+            default_version: self.glsl_version.clone(),
+            prelude: self.prelude(),
+        }
+    }
+
+    /// Whether a shader is read from stdin with `-f -`
+    pub fn reads_shader_from_stdin(&self) -> bool {
+        self.files
+            .iter()
+            .flatten()
+            .any(|file| is_stdin_shader(file))
+    }
+
+    /// Image files and their options per channel, indexed by channel number
+    pub fn channel_files(&self) -> Vec<Option<ChannelConfig>> {
+        vec![
+            self.channel0.clone(),
+            self.channel1.clone(),
+            self.channel2.clone(),
+            self.channel3.clone(),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use std::path::PathBuf;
+
+    use super::{AppConfig, ConfigFile, ExportAlpha, GifSettings, GlProfile, GlVersion};
+    use crate::{ChannelConfig, Prelude, TextureFormat};
+
+    #[test]
+    fn export_config_from_args() {
+        let config =
+            AppConfig::parse_from(["skuggbox", "--export-frames", "1", "3", "30", "frames"]);
+        let export = config.export_config().unwrap().unwrap();
+        assert_eq!(export.start, 1.0);
+        assert_eq!(export.end, 3.0);
+        assert_eq!(export.out_dir, PathBuf::from("frames"));
+        assert_eq!(export.frame_count(), 60);
+        assert_eq!(export.alpha, ExportAlpha::Opaque);
+
+        let config = AppConfig::parse_from([
+            "skuggbox",
+            "--export-frames",
+            "0",
+            "1",
+            "30",
+            "out",
+            "--export-alpha",
+            "premultiplied",
+            "--export-audio",
+            "song.wav",
+        ]);
+        let export = config.export_config().unwrap().unwrap();
+        assert_eq!(export.alpha, ExportAlpha::Premultiplied);
+        assert_eq!(export.audio, Some(PathBuf::from("song.wav")));
+
+        let config = AppConfig::parse_from(["skuggbox", "--export-frames", "0", "1", "0", "out"]);
+        assert!(config.export_config().is_err());
+
+        let config = AppConfig::parse_from(["skuggbox"]);
+        assert_eq!(config.export_config(), Ok(None));
+    }
+
+    #[test]
+    fn benchmark_config_from_args() {
+        let config = AppConfig::parse_from([
+            "skuggbox",
+            "--benchmark",
+            "shader.glsl",
+            "--frames",
+            "100",
+            "--resolution",
+            "640x360",
+        ]);
+        let benchmark = config.benchmark_config().unwrap().unwrap();
+        assert_eq!(benchmark.file, PathBuf::from("shader.glsl"));
+        assert_eq!(benchmark.frames, 100);
+        assert_eq!((benchmark.width, benchmark.height), (640, 360));
+
+        let config = AppConfig::parse_from(["skuggbox", "--benchmark", "a.glsl"]);
+        let benchmark = config.benchmark_config().unwrap().unwrap();
+        assert_eq!(benchmark.frames, 600);
+        assert_eq!((benchmark.width, benchmark.height), (1280, 720));
+
+        let config =
+            AppConfig::parse_from(["skuggbox", "--benchmark", "a.glsl", "--resolution", "640"]);
+        assert!(config.benchmark_config().is_err());
+
+        let config = AppConfig::parse_from(["skuggbox"]);
+        assert_eq!(config.benchmark_config(), Ok(None));
+    }
+
+    #[test]
+    fn gif_settings_from_args() {
+        let config = AppConfig::parse_from(["skuggbox"]);
+        assert_eq!(config.gif_settings(), Ok(GifSettings::default()));
+
+        let config = AppConfig::parse_from(["skuggbox", "--gif-seconds", "5", "--gif-fps", "20"]);
+        let gif = config.gif_settings().unwrap();
+        assert_eq!((gif.seconds, gif.fps), (5.0, 20.0));
+
+        let config = AppConfig::parse_from(["skuggbox", "--gif-fps", "0"]);
+        assert!(config.gif_settings().is_err());
+        let config = AppConfig::parse_from(["skuggbox", "--gif-seconds", "0"]);
+        assert!(config.gif_settings().is_err());
+    }
+
+    #[test]
+    fn gl_context_from_args() {
+        let config =
+            AppConfig::parse_from(["skuggbox", "--gl-version", "4.3", "--gl-profile", "core"]);
+        assert_eq!(config.gl_version, Some(GlVersion { major: 4, minor: 3 }));
+        assert_eq!(config.gl_profile, Some(GlProfile::Core));
+
+        assert!(AppConfig::try_parse_from(["skuggbox", "--gl-version", "4"]).is_err());
+        assert!(AppConfig::try_parse_from(["skuggbox", "--gl-version", "four.3"]).is_err());
+    }
+
+    #[test]
+    fn clear_color_from_args() {
+        let config = AppConfig::parse_from(["skuggbox"]);
+        assert_eq!(config.clear_color, [0.0, 0.0, 0.0, 1.0]);
+
+        let config = AppConfig::parse_from(["skuggbox", "--clear-color", "#ff0000"]);
+        assert_eq!(config.clear_color, [1.0, 0.0, 0.0, 1.0]);
+
+        let config = AppConfig::parse_from(["skuggbox", "--clear-color", "00ff0000"]);
+        assert_eq!(config.clear_color, [0.0, 1.0, 0.0, 0.0]);
+
+        assert!(AppConfig::try_parse_from(["skuggbox", "--clear-color", "fff"]).is_err());
+        assert!(AppConfig::try_parse_from(["skuggbox", "--clear-color", "gg0000"]).is_err());
+    }
+
+    #[test]
+    fn overlay_window_from_args() {
+        let config = AppConfig::parse_from(["skuggbox"]);
+        assert!(!config.always_on_top && !config.borderless);
+        assert!(!config.transparent && !config.click_through);
+
+        let config = AppConfig::parse_from([
+            "skuggbox",
+            "-a",
+            "--borderless",
+            "--transparent",
+            "--click-through",
+        ]);
+        assert!(config.always_on_top && config.borderless);
+        assert!(config.transparent && config.click_through);
+    }
+
+    #[test]
+    fn start_time_from_args() {
+        let config = AppConfig::parse_from(["skuggbox"]);
+        assert!(!config.paused);
+        assert_eq!(config.time, 0.0);
+
+        let config = AppConfig::parse_from(["skuggbox", "--paused", "--time", "12.5"]);
+        assert!(config.paused);
+        assert_eq!(config.time, 12.5);
+    }
+
+    #[test]
+    fn prelude_from_args() {
+        let config = AppConfig::parse_from(["skuggbox"]);
+        assert_eq!(config.prelude(), Prelude::Common);
+
+        let config = AppConfig::parse_from(["skuggbox", "--prelude", "lib/math.glsl"]);
+        assert_eq!(
+            config.prelude(),
+            Prelude::File(PathBuf::from("lib/math.glsl"))
+        );
+
+        let config = AppConfig::parse_from(["skuggbox", "--prelude", "x.glsl", "--no-prelude"]);
+        assert_eq!(config.prelude(), Prelude::None);
+    }
+
+    #[test]
+    fn key_bank_from_args() {
+        let config = AppConfig::parse_from(["skuggbox", "--key-bank", "--key-values", "0,0.1,1"]);
+        assert!(config.key_bank);
+        assert_eq!(config.key_values, Some(vec![0.0, 0.1, 1.0]));
+    }
+
+    #[test]
+    fn defines_from_args() {
+        let config =
+            AppConfig::parse_from(["skuggbox", "--define", "QUALITY=2", "--define", "DEBUG"]);
+        assert_eq!(
+            config.defines(),
+            vec![
+                ("QUALITY".to_string(), "2".to_string()),
+                ("DEBUG".to_string(), String::new())
+            ]
+        );
+    }
+
+    #[test]
+    fn command_line_overrides_config_file() {
+        let dir = std::env::temp_dir().join(format!("skuggbox_config_test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("skuggbox.toml");
+        std::fs::write(
+            &path,
+            r#"
+            files = ["scene.glsl"]
+            channel0 = "noise.png"
+            channel1 = "/textures/rock.exr,rgba32f"
+            defines = ["QUALITY=2", "DEBUG"]
+            resolution = "1920x1080"
+            vsync = true
+            "#,
+        )
+        .unwrap();
+
+        let config = AppConfig::parse_with_config_file_from([
+            "skuggbox",
+            "--config",
+            path.to_str().unwrap(),
+            "--channel0",
+            "other.png",
+            "--define",
+            "QUALITY=3",
+            "--width",
+            "640",
+            "--height",
+            "360",
+        ])
+        .unwrap();
+        let no_vsync = AppConfig::parse_with_config_file_from([
+            "skuggbox",
+            "--config",
+            path.to_str().unwrap(),
+            "--no-vsync",
+        ])
+        .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(!no_vsync.vsync);
+        assert_eq!((no_vsync.width, no_vsync.height), (Some(1920), Some(1080)));
+        assert_eq!(no_vsync.resolution, "1280x720");
+
+        assert_eq!(config.files, Some(vec![dir.join("scene.glsl")]));
+        assert_eq!(config.channel0, Some(ChannelConfig::new("other.png")));
+        assert_eq!(
+            config.channel1,
+            Some(ChannelConfig {
+                format: Some(TextureFormat::Rgba32F),
+                ..ChannelConfig::new("/textures/rock.exr")
+            })
+        );
+        assert_eq!(config.defines, vec!["DEBUG", "QUALITY=3"]);
+        assert_eq!((config.width, config.height), (Some(640), Some(360)));
+        assert!(config.vsync);
+        assert_eq!(config.glsl_version, crate::DEFAULT_GLSL_VERSION);
+    }
+
+    #[test]
+    fn missing_config_file_is_ignored() {
+        let path =
+            std::env::temp_dir().join(format!("skuggbox_missing-{}.toml", std::process::id()));
+        assert_eq!(ConfigFile::load(&path), Ok(None));
+
+        let config = AppConfig::parse_with_config_file_from([
+            "skuggbox",
+            "--config",
+            path.to_str().unwrap(),
+        ])
+        .unwrap();
+        assert_eq!(config.files, None);
+    }
 }
@@ -0,0 +1,151 @@
+use std::io::BufRead;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use crate::{spawn_guarded, Action};
+
+/// The commands `--stdin-control` reads, one per line. Empty lines and lines starting with `#`
+/// are ignored.
+pub const CONTROL_COMMANDS: &str = "\
+play | pause | toggle | stop
+set time <seconds>
+set speed <time scale>
+set bpm <bpm>
+set uniform <name> <value>...
+step <frames>
+reload
+open <shader file>
+screenshot [png file]
+preset <name>
+accumulate
+reset accumulation
+uniforms
+quit";
+
+/// Parse a line of the control grammar in `CONTROL_COMMANDS`. Returns `Ok(None)` for lines
+/// without a command.
+pub fn parse_command(line: &str) -> Result<Option<Action>, String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    let (command, rest) = match line.split_once(char::is_whitespace) {
+        Some((command, rest)) => (command, rest.trim()),
+        None => (line, ""),
+    };
+    let args: Vec<&str> = rest.split_whitespace().collect();
+
+    let action = match (command, args.as_slice()) {
+        ("play", []) => Action::TimePlay,
+        ("pause", []) => Action::TimePause,
+        ("toggle", []) => Action::TogglePlayPause,
+        ("stop", []) => Action::TimeStop,
+        ("set", ["time", seconds]) => Action::TimeSet(number(seconds)?),
+        ("set", ["speed", scale]) => Action::SetTimeScale(number(scale)?),
+        ("set", ["bpm", bpm]) => Action::SetBpm(number(bpm)?),
+        ("set", ["uniform", name, values @ ..]) if !values.is_empty() => {
+            let values = values.iter().map(|v| number(v)).collect::<Result<_, _>>()?;
+            Action::SetUniform(name.to_string(), values)
+        }
+        ("step", [frames]) => Action::StepFrame(
+            frames
+                .parse()
+                .map_err(|_| format!("Not a number: {}", frames))?,
+        ),
+        ("reload", []) => Action::ReloadShaders,
+        // paths run to the end of the line so they can contain spaces
+        ("open", [_, ..]) => Action::OpenFile(PathBuf::from(rest)),
+        ("screenshot", []) => Action::Screenshot(None),
+        ("screenshot", [_, ..]) => Action::Screenshot(Some(PathBuf::from(rest))),
+        ("preset", [_, ..]) => Action::ApplyPreset(rest.to_string()),
+        ("accumulate", []) => Action::ToggleAccumulation,
+        ("reset", ["accumulation"]) => Action::ResetAccumulation,
+        ("uniforms", []) => Action::DumpUniforms,
+        ("quit", []) => Action::AppExit,
+        _ => return Err(format!("Unknown command: {}", line)),
+    };
+    Ok(Some(action))
+}
+
+fn number(arg: &str) -> Result<f32, String> {
+    arg.parse().map_err(|_| format!("Not a number: {}", arg))
+}
+
+/// Reads control commands from stdin on a background thread
+pub struct ControlInput {
+    pub receiver: Receiver<Action>,
+}
+
+impl ControlInput {
+    pub fn start() -> Self {
+        log::info!("Reading commands from stdin");
+        let (sender, receiver): (Sender<Action>, Receiver<Action>) = channel();
+        let _ = spawn_guarded("control", move || read_commands(sender));
+
+        Self { receiver }
+    }
+}
+
+/// Runs on the control thread. Returns when stdin closes or the receiver is gone.
+fn read_commands(sender: Sender<Action>) {
+    for line in std::io::stdin().lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                log::warn!("Failed to read from stdin: {}", e);
+                return;
+            }
+        };
+        match parse_command(&line) {
+            Ok(Some(action)) => {
+                if sender.send(action).is_err() {
+                    return;
+                }
+            }
+            Ok(None) => {}
+            Err(e) => log::warn!("{}, the commands are:\n{}", e, CONTROL_COMMANDS),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::parse_command;
+    use crate::Action;
+
+    #[test]
+    fn parse_control_commands() {
+        assert!(matches!(
+            parse_command("pause"),
+            Ok(Some(Action::TimePause))
+        ));
+        assert!(
+            matches!(parse_command(" set time 3.2 "), Ok(Some(Action::TimeSet(t))) if t == 3.2)
+        );
+        assert!(matches!(
+            parse_command("step -2"),
+            Ok(Some(Action::StepFrame(-2)))
+        ));
+        assert!(matches!(
+            parse_command("set uniform offset 0.5 1"),
+            Ok(Some(Action::SetUniform(name, values))) if name == "offset" && values == [0.5, 1.0]
+        ));
+        assert!(matches!(
+            parse_command("screenshot my shots/out.png"),
+            Ok(Some(Action::Screenshot(Some(path)))) if path == PathBuf::from("my shots/out.png")
+        ));
+        assert!(matches!(
+            parse_command("screenshot"),
+            Ok(Some(Action::Screenshot(None)))
+        ));
+
+        assert!(matches!(parse_command(""), Ok(None)));
+        assert!(matches!(parse_command("# comment"), Ok(None)));
+        assert!(parse_command("set time soon").is_err());
+        assert!(parse_command("pause now").is_err());
+        assert!(parse_command("set uniform speed").is_err());
+        assert!(parse_command("jump").is_err());
+    }
+}
@@ -0,0 +1,84 @@
+use glow::HasContext;
+use regex::Regex;
+
+/// Strings describing the OpenGL context that was actually created
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GlInfo {
+    pub version: String,
+    pub renderer: String,
+    pub vendor: String,
+    pub shading_language_version: String,
+}
+
+impl GlInfo {
+    pub fn query(gl: &glow::Context) -> Self {
+        unsafe {
+            Self {
+                version: gl.get_parameter_string(glow::VERSION),
+                renderer: gl.get_parameter_string(glow::RENDERER),
+                vendor: gl.get_parameter_string(glow::VENDOR),
+                shading_language_version: gl.get_parameter_string(glow::SHADING_LANGUAGE_VERSION),
+            }
+        }
+    }
+
+    pub fn log(&self) {
+        log::info!("OpenGL version: {}", self.version);
+        log::info!("OpenGL renderer: {}", self.renderer);
+        log::info!("OpenGL vendor: {}", self.vendor);
+        log::info!("GLSL version: {}", self.shading_language_version);
+    }
+
+    /// Highest supported GLSL version as written in `#version`, e.g. 460 for "4.60 NVIDIA"
+    /// or 300 for "OpenGL ES GLSL ES 3.00"
+    pub fn glsl_version(&self) -> Option<u32> {
+        let pattern = Regex::new(r"(\d+)\.(\d+)").unwrap();
+        let captures = pattern.captures(&self.shading_language_version)?;
+        let major: u32 = captures[1].parse().ok()?;
+        let minor: u32 = captures[2].parse().ok()?;
+        // "4.6" and "4.60" both mean 460
+        let minor = if captures[2].len() == 1 {
+            minor * 10
+        } else {
+            minor
+        };
+        Some(major * 100 + minor)
+    }
+}
+
+/// The number of the `#version` line of a shader, e.g. 430 for `#version 430 core`
+pub fn required_glsl_version(source: &str) -> Option<u32> {
+    let pattern = Regex::new(r"(?m)^\s*#version\s+(\d+)").unwrap();
+    pattern
+        .captures(source)
+        .and_then(|captures| captures[1].parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{required_glsl_version, GlInfo};
+
+    fn gl_info(shading_language_version: &str) -> GlInfo {
+        GlInfo {
+            shading_language_version: shading_language_version.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn glsl_version_from_driver_string() {
+        assert_eq!(gl_info("4.60 NVIDIA").glsl_version(), Some(460));
+        assert_eq!(gl_info("4.6").glsl_version(), Some(460));
+        assert_eq!(gl_info("OpenGL ES GLSL ES 3.00").glsl_version(), Some(300));
+        assert_eq!(gl_info("").glsl_version(), None);
+    }
+
+    #[test]
+    fn glsl_version_from_shader() {
+        assert_eq!(
+            required_glsl_version("#version 430 core\nvoid main() {}"),
+            Some(430)
+        );
+        assert_eq!(required_glsl_version("void main() {}"), None);
+    }
+}
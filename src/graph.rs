@@ -0,0 +1,87 @@
+use std::collections::VecDeque;
+
+/// Built-in float uniforms that can be graphed, custom float uniforms can be as well
+pub const GRAPH_BUILTINS: [&str; 7] = [
+    "iTime",
+    "iTimeDelta",
+    "iFrame",
+    "iBeat",
+    "iAudioLevel",
+    "iKey",
+    "iSampleCount",
+];
+
+/// Values of a uniform over the last seconds, sampled once per frame
+#[derive(Debug)]
+pub struct UniformGraph {
+    pub uniform: String,
+    /// How many seconds of history are kept
+    pub seconds: f32,
+    /// `(age, value)` pairs, the oldest first. The age is the wall time since the graph started.
+    samples: VecDeque<(f32, f32)>,
+    elapsed: f32,
+}
+
+impl UniformGraph {
+    pub fn new(uniform: &str) -> Self {
+        Self {
+            uniform: uniform.to_string(),
+            seconds: 5.0,
+            samples: VecDeque::new(),
+            elapsed: 0.0,
+        }
+    }
+
+    /// Add the value of a frame that took `delta_time` seconds, dropping what's too old
+    pub fn push(&mut self, delta_time: f32, value: f32) {
+        self.elapsed += delta_time;
+        self.samples.push_back((self.elapsed, value));
+        while self
+            .samples
+            .front()
+            .is_some_and(|(time, _)| *time < self.elapsed - self.seconds)
+        {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Samples as `(seconds ago, value)`, the oldest first
+    pub fn samples(&self) -> impl Iterator<Item = (f32, f32)> + '_ {
+        self.samples
+            .iter()
+            .map(move |(time, value)| (self.elapsed - time, *value))
+    }
+
+    /// Lowest and highest value in the history, `None` when empty
+    pub fn range(&self) -> Option<(f32, f32)> {
+        self.samples.iter().fold(None, |range, (_, value)| {
+            let (min, max) = range.unwrap_or((*value, *value));
+            Some((min.min(*value), max.max(*value)))
+        })
+    }
+
+    pub fn last(&self) -> Option<f32> {
+        self.samples.back().map(|(_, value)| *value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UniformGraph;
+
+    #[test]
+    fn graph_keeps_the_last_seconds() {
+        let mut graph = UniformGraph::new("iAudioLevel");
+        graph.seconds = 1.0;
+        assert_eq!(graph.range(), None);
+
+        for value in [0.5, 2.0, -1.0, 0.25, 0.75] {
+            graph.push(0.4, value);
+        }
+        let samples: Vec<(f32, f32)> = graph.samples().collect();
+        assert_eq!(samples.len(), 3);
+        assert_eq!(samples.last(), Some(&(0.0, 0.75)));
+        assert_eq!(graph.range(), Some((-1.0, 0.75)));
+        assert_eq!(graph.last(), Some(0.75));
+    }
+}
@@ -5,8 +5,9 @@ use winit::{
 };
 
 use crate::{
-    state::{AppState, PlayMode},
-    Action, ActionModifier, CameraMovement, WindowEventHandler,
+    is_shader_file,
+    state::{wrap_loop, AppState, PlayMode},
+    Action, ActionModifier, WindowEventHandler,
 };
 
 pub fn handle_events<T>(
@@ -22,7 +23,7 @@ pub fn handle_events<T>(
         Event::WindowEvent { event, .. } => {
             match event {
                 WindowEvent::CloseRequested => {
-                    actions.push(Action::AppExit);
+                    actions.push(Action::WindowClose);
                 }
 
                 WindowEvent::Resized(size) => {
@@ -30,6 +31,16 @@ pub fn handle_events<T>(
                     actions.push(Action::WindowResize((size.width, size.height)))
                 }
 
+                // e.g. moved to a monitor with another DPI, not always followed by a resize
+                WindowEvent::ScaleFactorChanged {
+                    scale_factor,
+                    new_inner_size,
+                } => {
+                    app_state.pixel_ratio = *scale_factor as f32;
+                    let size = new_inner_size.to_logical::<i32>(1.0);
+                    actions.push(Action::WindowResize((size.width, size.height)))
+                }
+
                 WindowEvent::ModifiersChanged(modifier_state) => {
                     let internal =
                         i32::from(modifier_state.shift()) + 2 * i32::from(modifier_state.ctrl());
@@ -41,84 +52,55 @@ pub fn handle_events<T>(
                     }
                 }
 
-                WindowEvent::KeyboardInput { input, .. } => {
-                    if input.state == ElementState::Pressed {
-                        if let Some(keycode) = input.virtual_keycode {
-                            match keycode {
-                                VirtualKeyCode::Escape => {
-                                    actions.push(Action::AppExit);
-                                }
-
-                                // Timeline controls
-                                VirtualKeyCode::Space => {
-                                    actions.push(Action::TogglePlayPause);
-                                }
-                                VirtualKeyCode::Right => {
-                                    actions.push(Action::TimeForward(1.0));
-                                }
-                                VirtualKeyCode::Left => {
-                                    actions.push(Action::TimeRewind(1.0));
-                                }
-                                VirtualKeyCode::Key0 => {
-                                    actions.push(Action::TimeStop);
-                                }
-
-                                // Movement controls
-                                VirtualKeyCode::A => {
-                                    actions.push(Action::CameraMove(CameraMovement::StrafeLeft));
-                                }
-                                VirtualKeyCode::D => {
-                                    actions.push(Action::CameraMove(CameraMovement::StrafeRight));
-                                }
-                                VirtualKeyCode::S => {
-                                    actions.push(Action::CameraMove(CameraMovement::MoveBackward));
-                                }
-                                VirtualKeyCode::W => {
-                                    actions.push(Action::CameraMove(CameraMovement::MoveForward));
-                                }
-                                VirtualKeyCode::X => {
-                                    actions.push(Action::CameraMove(CameraMovement::Reset));
-                                }
-
-                                // Feature controls
-                                VirtualKeyCode::Key1 => {
-                                    actions.push(Action::CameraToggleIntegration(false));
-                                }
-                                VirtualKeyCode::Key2 => {
-                                    actions.push(Action::CameraToggleIntegration(true));
-                                }
-                                VirtualKeyCode::Period => {
-                                    // reset all camera settings
-                                    actions.push(Action::CameraReset);
-                                }
-
-                                // UI controls
-                                VirtualKeyCode::Tab => actions.push(Action::UIToggleVisible),
-                                VirtualKeyCode::F11 => actions.push(Action::ToggleFullscreen),
-
-                                VirtualKeyCode::P => actions.push(Action::PrintSource),
-                                VirtualKeyCode::F12 => actions.push(Action::TakeSnapshot),
-                                _ => {}
-                            }
-                        }
-                    }
-                }
+                WindowEvent::Focused(false) => app_state.keyboard.release_all(),
+
+                // shaders replace the running one, anything else is tried as a channel image
+                WindowEvent::DroppedFile(path) => match is_shader_file(path) {
+                    true => actions.push(Action::OpenFile(path.clone())),
+                    false => actions.push(Action::AddChannel(path.clone())),
+                },
+
+                WindowEvent::KeyboardInput { input, .. } => handle_key(
+                    input.virtual_keycode,
+                    input.state,
+                    ui.egui_ctx.wants_keyboard_input(),
+                    app_state,
+                    actions,
+                ),
 
                 _ => {}
             }
 
             app_state.mouse.handle_window_events(event);
-            app_state.camera.handle_window_events(event);
-            app_state
-                .camera
-                .handle_mouse(&app_state.mouse, app_state.delta_time);
+
+            // the camera only takes input that isn't meant for the UI on top of the view
+            let for_ui = match event {
+                WindowEvent::KeyboardInput { .. } => ui.egui_ctx.wants_keyboard_input(),
+                _ => ui.egui_ctx.is_pointer_over_area(),
+            };
+            if !for_ui {
+                app_state.camera.handle_window_events(event);
+                app_state
+                    .camera
+                    .handle_mouse(&app_state.mouse, app_state.timer.delta_time);
+            }
 
             let _event_response = ui.on_event(event);
         }
 
         Event::MainEventsCleared => {
+            // the frame duration, so the camera keeps moving while playback is paused or slowed
+            let frame_time = app_state.frame_times.last();
+            app_state.camera.update(&app_state.keyboard, frame_time);
+            app_state.update_camera_smoothing(frame_time);
+
             if matches!(app_state.play_mode, PlayMode::Playing) {
                 app_state.playback_time += app_state.delta_time;
+                app_state.playback_time = wrap_loop(
+                    app_state.playback_time,
+                    app_state.loop_start,
+                    app_state.loop_end,
+                );
             }
 
             *control_flow = ControlFlow::Exit;
@@ -128,3 +110,176 @@ pub fn handle_events<T>(
         _ => (),
     }
 }
+
+/// Keys go to the keyboard texture, the key bank and the hotkeys. While typing into the UI
+/// presses are left to egui, releases always count so keys held while clicking into the UI
+/// don't stay down.
+fn handle_key(
+    keycode: Option<VirtualKeyCode>,
+    state: ElementState,
+    ui_wants_keyboard: bool,
+    app_state: &mut AppState,
+    actions: &mut Vec<Action>,
+) {
+    let keycode = match keycode {
+        Some(keycode) => keycode,
+        None => return,
+    };
+    if state == ElementState::Released {
+        app_state.keyboard.key_up(keycode);
+        return;
+    }
+    if ui_wants_keyboard {
+        return;
+    }
+    app_state.keyboard.key_down(keycode);
+
+    // with a key bank the number keys set `iKey` instead of their usual action
+    if let Some(key_value) = app_state.key_bank_value(keycode) {
+        app_state.key_value = key_value;
+        return;
+    }
+
+    match keycode {
+        VirtualKeyCode::Escape => {
+            // like closing the window, so unsaved uniform edits are asked about
+            actions.push(Action::WindowClose);
+        }
+
+        // Timeline controls
+        VirtualKeyCode::Space => {
+            actions.push(Action::TogglePlayPause);
+        }
+        VirtualKeyCode::Right => {
+            actions.push(Action::TimeForward(1.0));
+        }
+        VirtualKeyCode::Left => {
+            actions.push(Action::TimeRewind(1.0));
+        }
+        VirtualKeyCode::Key0 => {
+            actions.push(Action::TimeStop);
+        }
+        // key repeat keeps stepping while held, `.` is taken by the camera
+        VirtualKeyCode::RBracket => actions.push(Action::StepFrame(1)),
+        VirtualKeyCode::LBracket => actions.push(Action::StepFrame(-1)),
+        VirtualKeyCode::B => actions.push(Action::TapTempo),
+
+        // Feature controls
+        VirtualKeyCode::Key1 => {
+            actions.push(Action::CameraToggleIntegration(false));
+        }
+        VirtualKeyCode::Key2 => {
+            actions.push(Action::CameraToggleIntegration(true));
+        }
+        VirtualKeyCode::R => {
+            actions.push(Action::CameraPathToggleRecording);
+        }
+        VirtualKeyCode::F6 => {
+            actions.push(Action::CameraPathTogglePlayback);
+        }
+        VirtualKeyCode::Period => {
+            // reset all camera settings
+            actions.push(Action::CameraReset);
+        }
+
+        // UI controls
+        VirtualKeyCode::Tab => actions.push(Action::ToggleUi),
+        VirtualKeyCode::F2 => actions.push(Action::DumpUniforms),
+        VirtualKeyCode::F3 => actions.push(Action::UIToggleStats),
+        VirtualKeyCode::F11 => actions.push(Action::ToggleFullscreen),
+
+        VirtualKeyCode::F5 => actions.push(Action::ReloadShaders),
+        VirtualKeyCode::F8 => actions.push(Action::SaveGif),
+        VirtualKeyCode::F9 => actions.push(Action::Screenshot(None)),
+        VirtualKeyCode::P => actions.push(Action::PrintSource),
+        VirtualKeyCode::F12 => actions.push(Action::TakeSnapshot),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use winit::event::{ElementState, VirtualKeyCode};
+
+    use super::handle_key;
+    use crate::{key_bank_values, state::AppState, Action};
+
+    #[test]
+    fn typing_into_the_ui_triggers_no_hotkeys() {
+        let mut app_state = AppState {
+            key_bank: Some(key_bank_values(None)),
+            ..Default::default()
+        };
+        let mut actions = vec![];
+        for key in [
+            VirtualKeyCode::B,
+            VirtualKeyCode::R,
+            VirtualKeyCode::Space,
+            VirtualKeyCode::Escape,
+            VirtualKeyCode::Key3,
+        ] {
+            handle_key(
+                Some(key),
+                ElementState::Pressed,
+                true,
+                &mut app_state,
+                &mut actions,
+            );
+        }
+        assert!(actions.is_empty());
+        assert_eq!(app_state.key_value, 0.0);
+        assert!(!app_state.keyboard.is_down(VirtualKeyCode::B));
+
+        handle_key(
+            Some(VirtualKeyCode::Key3),
+            ElementState::Pressed,
+            false,
+            &mut app_state,
+            &mut actions,
+        );
+        assert_eq!(app_state.key_value, 3.0);
+        handle_key(
+            Some(VirtualKeyCode::Space),
+            ElementState::Pressed,
+            false,
+            &mut app_state,
+            &mut actions,
+        );
+        assert_eq!(actions.len(), 1);
+    }
+
+    #[test]
+    fn releases_reach_the_keyboard_while_typing() {
+        let mut app_state = AppState::default();
+        let mut actions = vec![];
+        handle_key(
+            Some(VirtualKeyCode::W),
+            ElementState::Pressed,
+            false,
+            &mut app_state,
+            &mut actions,
+        );
+        handle_key(
+            Some(VirtualKeyCode::W),
+            ElementState::Released,
+            true,
+            &mut app_state,
+            &mut actions,
+        );
+        assert!(!app_state.keyboard.is_down(VirtualKeyCode::W));
+    }
+
+    #[test]
+    fn escape_closes_like_the_window() {
+        let mut app_state = AppState::default();
+        let mut actions = vec![];
+        handle_key(
+            Some(VirtualKeyCode::Escape),
+            ElementState::Pressed,
+            false,
+            &mut app_state,
+            &mut actions,
+        );
+        assert!(matches!(actions[..], [Action::WindowClose]));
+    }
+}
@@ -0,0 +1,304 @@
+use glow::HasContext;
+use winit::event::VirtualKeyCode;
+
+use crate::{Channel, ChannelSource};
+
+/// Width of the keyboard texture, one column per JavaScript key code like Shadertoy.
+/// Row 0 holds the keys that are down, row 1 the keys pressed this frame and row 2 toggles
+/// that flip on every press.
+pub const KEYBOARD_TEXTURE_WIDTH: usize = 256;
+const ROWS: usize = 3;
+
+/// Keyboard state in the layout of the keyboard texture, values are 0 or 255
+pub struct KeyboardState {
+    pixels: [u8; KEYBOARD_TEXTURE_WIDTH * ROWS],
+}
+
+impl Default for KeyboardState {
+    fn default() -> Self {
+        Self {
+            pixels: [0; KEYBOARD_TEXTURE_WIDTH * ROWS],
+        }
+    }
+}
+
+impl KeyboardState {
+    pub fn key_down(&mut self, key: VirtualKeyCode) {
+        if let Some(code) = key_code(key) {
+            // key repeat doesn't count as another press
+            if self.pixels[code] == 0 {
+                self.pixels[KEYBOARD_TEXTURE_WIDTH + code] = 255;
+                self.pixels[KEYBOARD_TEXTURE_WIDTH * 2 + code] ^= 255;
+            }
+            self.pixels[code] = 255;
+        }
+    }
+
+    pub fn key_up(&mut self, key: VirtualKeyCode) {
+        if let Some(code) = key_code(key) {
+            self.pixels[code] = 0;
+        }
+    }
+
+    pub fn is_down(&self, key: VirtualKeyCode) -> bool {
+        key_code(key).is_some_and(|code| self.pixels[code] != 0)
+    }
+
+    /// Release all keys, e.g. when the window loses focus and the key up events go elsewhere
+    pub fn release_all(&mut self) {
+        self.pixels[..KEYBOARD_TEXTURE_WIDTH].fill(0);
+    }
+
+    /// Call once per frame after rendering, presses only last a single frame
+    pub fn end_frame(&mut self) {
+        self.pixels[KEYBOARD_TEXTURE_WIDTH..KEYBOARD_TEXTURE_WIDTH * 2].fill(0);
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+}
+
+/// The keyboard texture bound to one of the `iChannelN` samplers
+pub struct KeyboardTexture {
+    texture: glow::Texture,
+}
+
+impl KeyboardTexture {
+    pub fn new(gl: &glow::Context) -> Result<Self, String> {
+        let texture = unsafe {
+            let texture = gl.create_texture()?;
+            gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+            gl.tex_image_2d(
+                glow::TEXTURE_2D,
+                0,
+                glow::R8 as i32,
+                KEYBOARD_TEXTURE_WIDTH as i32,
+                ROWS as i32,
+                0,
+                glow::RED,
+                glow::UNSIGNED_BYTE,
+                Some(KeyboardState::default().pixels()),
+            );
+            // keys are looked up with texelFetch, never interpolate between them
+            gl.tex_parameter_i32(
+                glow::TEXTURE_2D,
+                glow::TEXTURE_MIN_FILTER,
+                glow::NEAREST as i32,
+            );
+            gl.tex_parameter_i32(
+                glow::TEXTURE_2D,
+                glow::TEXTURE_MAG_FILTER,
+                glow::NEAREST as i32,
+            );
+            gl.bind_texture(glow::TEXTURE_2D, None);
+            texture
+        };
+
+        Ok(Self { texture })
+    }
+
+    pub fn channel(&self) -> Channel {
+        Channel {
+            texture: self.texture,
+            target: glow::TEXTURE_2D,
+            width: KEYBOARD_TEXTURE_WIDTH as u32,
+            height: ROWS as u32,
+            source: ChannelSource::Keyboard,
+        }
+    }
+
+    /// Upload the keyboard state. This method should be called from the GL-thread.
+    pub fn update(&self, gl: &glow::Context, keyboard: &KeyboardState) {
+        unsafe {
+            gl.bind_texture(glow::TEXTURE_2D, Some(self.texture));
+            gl.tex_sub_image_2d(
+                glow::TEXTURE_2D,
+                0,
+                0,
+                0,
+                KEYBOARD_TEXTURE_WIDTH as i32,
+                ROWS as i32,
+                glow::RED,
+                glow::UNSIGNED_BYTE,
+                glow::PixelUnpackData::Slice(keyboard.pixels()),
+            );
+            gl.bind_texture(glow::TEXTURE_2D, None);
+        }
+    }
+
+    pub fn delete(&self, gl: &glow::Context) {
+        unsafe { gl.delete_texture(self.texture) };
+    }
+}
+
+/// The number of a number key, on the top row or the numpad
+pub fn number_key(key: VirtualKeyCode) -> Option<usize> {
+    use VirtualKeyCode::*;
+
+    let number = match key {
+        Key0 | Numpad0 => 0,
+        Key1 | Numpad1 => 1,
+        Key2 | Numpad2 => 2,
+        Key3 | Numpad3 => 3,
+        Key4 | Numpad4 => 4,
+        Key5 | Numpad5 => 5,
+        Key6 | Numpad6 => 6,
+        Key7 | Numpad7 => 7,
+        Key8 | Numpad8 => 8,
+        Key9 | Numpad9 => 9,
+        _ => return None,
+    };
+    Some(number)
+}
+
+/// What the number keys 0-9 set `iKey` to, the key's own number unless `values` has one
+pub fn key_bank_values(values: Option<&[f32]>) -> [f32; 10] {
+    std::array::from_fn(|key| {
+        values
+            .and_then(|values| values.get(key))
+            .copied()
+            .unwrap_or(key as f32)
+    })
+}
+
+/// The JavaScript key code Shadertoy shaders use to look up a key
+fn key_code(key: VirtualKeyCode) -> Option<usize> {
+    use VirtualKeyCode::*;
+
+    let code = match key {
+        Back => 8,
+        Tab => 9,
+        Return | NumpadEnter => 13,
+        LShift | RShift => 16,
+        LControl | RControl => 17,
+        LAlt | RAlt => 18,
+        Pause => 19,
+        Capital => 20,
+        Escape => 27,
+        Space => 32,
+        PageUp => 33,
+        PageDown => 34,
+        End => 35,
+        Home => 36,
+        Left => 37,
+        Up => 38,
+        Right => 39,
+        Down => 40,
+        Insert => 45,
+        Delete => 46,
+        Key0 => 48,
+        Key1 => 49,
+        Key2 => 50,
+        Key3 => 51,
+        Key4 => 52,
+        Key5 => 53,
+        Key6 => 54,
+        Key7 => 55,
+        Key8 => 56,
+        Key9 => 57,
+        A => 65,
+        B => 66,
+        C => 67,
+        D => 68,
+        E => 69,
+        F => 70,
+        G => 71,
+        H => 72,
+        I => 73,
+        J => 74,
+        K => 75,
+        L => 76,
+        M => 77,
+        N => 78,
+        O => 79,
+        P => 80,
+        Q => 81,
+        R => 82,
+        S => 83,
+        T => 84,
+        U => 85,
+        V => 86,
+        W => 87,
+        X => 88,
+        Y => 89,
+        Z => 90,
+        Numpad0 => 96,
+        Numpad1 => 97,
+        Numpad2 => 98,
+        Numpad3 => 99,
+        Numpad4 => 100,
+        Numpad5 => 101,
+        Numpad6 => 102,
+        Numpad7 => 103,
+        Numpad8 => 104,
+        Numpad9 => 105,
+        NumpadMultiply => 106,
+        NumpadAdd => 107,
+        NumpadSubtract => 109,
+        NumpadDecimal => 110,
+        NumpadDivide => 111,
+        F1 => 112,
+        F2 => 113,
+        F3 => 114,
+        F4 => 115,
+        F5 => 116,
+        F6 => 117,
+        F7 => 118,
+        F8 => 119,
+        F9 => 120,
+        F10 => 121,
+        F11 => 122,
+        F12 => 123,
+        Semicolon => 186,
+        Equals => 187,
+        Comma => 188,
+        Minus => 189,
+        Period => 190,
+        Slash => 191,
+        Grave => 192,
+        LBracket => 219,
+        Backslash => 220,
+        RBracket => 221,
+        Apostrophe => 222,
+        _ => return None,
+    };
+    Some(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use winit::event::VirtualKeyCode;
+
+    use super::{KeyboardState, KEYBOARD_TEXTURE_WIDTH};
+
+    const A: usize = 65;
+
+    #[test]
+    fn keyboard_rows_match_shadertoy() {
+        let mut keyboard = KeyboardState::default();
+        let row = |keyboard: &KeyboardState, row: usize| {
+            keyboard.pixels()[row * KEYBOARD_TEXTURE_WIDTH + A]
+        };
+
+        keyboard.key_down(VirtualKeyCode::A);
+        assert_eq!(row(&keyboard, 0), 255);
+        assert_eq!(row(&keyboard, 1), 255);
+        assert_eq!(row(&keyboard, 2), 255);
+
+        // held down with key repeat
+        keyboard.end_frame();
+        keyboard.key_down(VirtualKeyCode::A);
+        assert_eq!(row(&keyboard, 0), 255);
+        assert_eq!(row(&keyboard, 1), 0);
+        assert_eq!(row(&keyboard, 2), 255);
+
+        keyboard.key_up(VirtualKeyCode::A);
+        keyboard.key_down(VirtualKeyCode::A);
+        assert_eq!(row(&keyboard, 1), 255);
+        assert_eq!(row(&keyboard, 2), 0);
+
+        keyboard.release_all();
+        assert_eq!(row(&keyboard, 0), 0);
+    }
+}
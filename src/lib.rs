@@ -2,14 +2,25 @@
 
 pub mod actions;
 pub mod app;
+pub mod audio;
+pub mod audio_track;
+pub mod benchmark;
 
 pub mod config;
+pub mod control;
 pub mod event;
+pub mod gl_info;
+pub mod graph;
 pub mod input;
+pub mod keyboard;
+pub mod midi;
 pub mod minime;
 pub mod mouse;
+pub mod osc;
+pub mod presets;
 pub mod render;
 pub mod shader;
+pub mod shadertoy;
 pub mod state;
 pub mod timer;
 pub mod ui;
@@ -18,13 +29,24 @@ pub mod window;
 
 pub use actions::*;
 pub use app::*;
+pub use audio::*;
+pub use audio_track::*;
+pub use benchmark::*;
 pub use config::*;
+pub use control::*;
 pub use event::*;
+pub use gl_info::*;
+pub use graph::*;
 pub use input::*;
+pub use keyboard::*;
+pub use midi::*;
 pub use minime::*;
 pub use mouse::*;
+pub use osc::*;
+pub use presets::*;
 pub use render::*;
 pub use shader::*;
+pub use shadertoy::*;
 pub use state::*;
 pub use timer::*;
 pub use ui::*;
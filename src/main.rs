@@ -2,14 +2,15 @@ extern crate gl;
 extern crate glutin;
 extern crate winit;
 
+use std::env;
 use std::fs;
 use std::path::PathBuf;
 use std::process::exit;
 
-use clap::Parser;
 use simple_logger::SimpleLogger;
 
-use skuggbox::{app::App, config::AppConfig};
+use skuggbox::shadertoy::import_shader;
+use skuggbox::{app::App, config::AppConfig, config::Command};
 
 /// Creates a new default shader at the gives path
 fn create_new_default_shader(path: PathBuf) -> std::io::Result<u64> {
@@ -23,9 +24,48 @@ fn create_new_default_shader(path: PathBuf) -> std::io::Result<u64> {
 fn main() -> anyhow::Result<(), anyhow::Error> {
     SimpleLogger::new().init().unwrap();
 
-    // Parse command line arguments using `structopt`
-    let config = AppConfig::parse();
+    // Parse command line arguments, filling in the rest from skuggbox.toml
+    let config = match AppConfig::parse_with_config_file() {
+        Ok(config) => config,
+        Err(err) => {
+            log::error!("{}", err);
+            exit(1);
+        }
+    };
+
+    if let Some(Command::Import { id, api_key, out }) = &config.command {
+        let api_key = match api_key
+            .clone()
+            .or_else(|| env::var("SHADERTOY_API_KEY").ok())
+        {
+            Some(api_key) => api_key,
+            None => {
+                log::error!("A Shadertoy API key is required, use --api-key or SHADERTOY_API_KEY");
+                exit(1);
+            }
+        };
+        let out = out
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(format!("{}.glsl", id)));
+        if let Err(err) = import_shader(id, &api_key, &out) {
+            log::error!("Import failed: {}", err);
+            exit(1);
+        }
+        return Ok(());
+    }
+
+    if config.stdin_control && config.reads_shader_from_stdin() {
+        log::error!("-f - and --stdin-control both read from stdin, use one of them");
+        exit(1);
+    }
+
+    if let Err(err) = config.gif_settings() {
+        log::error!("Invalid GIF settings: {}", err);
+        exit(1);
+    }
+
     let mut app = App::from_config(config.clone());
+    let has_shaders = config.files.is_some() || config.passes.is_some();
 
     if let Some(new_file) = config.clone().new {
         log::info!("creating new shader at {:?}", new_file);
@@ -36,11 +76,49 @@ fn main() -> anyhow::Result<(), anyhow::Error> {
                 exit(1);
             }
         }
+        if !has_shaders {
+            return Ok(());
+        }
+    }
+
+    match config.benchmark_config() {
+        Ok(Some(benchmark)) => {
+            app.benchmark(config, benchmark);
+            return Ok(());
+        }
+        Ok(None) => {}
+        Err(err) => {
+            log::error!("Invalid --benchmark: {}", err);
+            exit(1);
+        }
+    }
+
+    if let Some(file) = config.list_uniforms.clone() {
+        app.list_uniforms(config, file);
+        return Ok(());
     }
 
-    if config.files.is_some() {
-        log::info!("loading existing shader");
-        app.run(config);
+    match config.export_config() {
+        Ok(Some(_)) if !has_shaders => {
+            log::error!("--export-frames needs a shader, use -f or --passes");
+            exit(1);
+        }
+        Ok(Some(export)) => {
+            log::info!("exporting frames");
+            app.export(config, export);
+        }
+        Ok(None) if has_shaders => {
+            log::info!("loading existing shader");
+            app.run(config);
+        }
+        Ok(None) => {
+            log::info!("No shader files given, starting with the built-in shader");
+            app.run(config);
+        }
+        Err(err) => {
+            log::error!("Invalid --export-frames: {}", err);
+            exit(1);
+        }
     }
     Ok(())
 }
@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+use std::sync::mpsc::Receiver;
+
+use crate::Uniform;
+
+/// A MIDI control change message, sent by knobs and faders
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControlChange {
+    pub channel: u8,
+    pub controller: u8,
+    /// 0..=127
+    pub value: u8,
+}
+
+impl ControlChange {
+    /// Parse a raw MIDI message, returns `None` for anything but a control change
+    pub fn parse(message: &[u8]) -> Option<Self> {
+        match message {
+            [status, controller, value, ..] if status & 0xF0 == 0xB0 => Some(Self {
+                channel: status & 0x0F,
+                controller: *controller,
+                value: *value,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Drives one component of a custom uniform with a MIDI controller
+#[derive(Debug, Clone, PartialEq)]
+pub struct MidiMapping {
+    pub uniform: String,
+    pub component: usize,
+    pub controller: u8,
+    /// Values 0..=127 are mapped onto this range
+    pub range: (f32, f32),
+}
+
+/// Mappings from MIDI controllers to custom uniforms
+#[derive(Debug, Default)]
+pub struct MidiState {
+    /// Whether MIDI input is running, the UI only shows MIDI controls when it is
+    pub enabled: bool,
+    pub mappings: Vec<MidiMapping>,
+    /// The last control change received, shown in the UI
+    pub last_change: Option<ControlChange>,
+    /// Uniform component to bind to the next controller that's touched ("MIDI learn")
+    pub learn: Option<(String, usize)>,
+}
+
+impl MidiState {
+    /// Apply a control change to the mapped uniforms, or bind it when learning
+    pub fn apply(&mut self, change: ControlChange, uniforms: &mut BTreeMap<String, Uniform>) {
+        self.last_change = Some(change);
+
+        if let Some((name, component)) = self.learn.take() {
+            if let Some(uniform) = uniforms.get(&name) {
+                self.mappings
+                    .retain(|m| !(m.uniform == name && m.component == component));
+                self.mappings.push(MidiMapping {
+                    range: uniform.slider_range(),
+                    uniform: name,
+                    component,
+                    controller: change.controller,
+                });
+            }
+        }
+
+        for mapping in &self.mappings {
+            if mapping.controller != change.controller {
+                continue;
+            }
+            let value = uniforms
+                .get_mut(&mapping.uniform)
+                .and_then(|uniform| uniform.value.as_mut())
+                .and_then(|value| value.components_mut().get_mut(mapping.component));
+            if let Some(value) = value {
+                let (min, max) = mapping.range;
+                *value = min + change.value as f32 / 127.0 * (max - min);
+            }
+        }
+    }
+
+    /// Controller bound to a uniform component, if any
+    pub fn controller(&self, uniform: &str, component: usize) -> Option<u8> {
+        self.mappings
+            .iter()
+            .find(|m| m.uniform == uniform && m.component == component)
+            .map(|m| m.controller)
+    }
+}
+
+/// Receives control changes from all connected MIDI inputs on midir's threads
+pub struct MidiInput {
+    pub receiver: Receiver<ControlChange>,
+    #[cfg(feature = "midi")]
+    _connections: Vec<midir::MidiInputConnection<()>>,
+}
+
+impl MidiInput {
+    /// Connect to every MIDI input port. Without any device connected this succeeds and
+    /// simply never receives anything.
+    #[cfg(feature = "midi")]
+    pub fn start() -> Result<Self, String> {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let input = midir::MidiInput::new("skuggbox").map_err(|e| e.to_string())?;
+        let ports = input.ports();
+        if ports.is_empty() {
+            log::warn!("No MIDI input device found");
+        }
+
+        let mut connections = vec![];
+        for port in &ports {
+            // every connection needs its own client
+            let input = midir::MidiInput::new("skuggbox").map_err(|e| e.to_string())?;
+            let name = input.port_name(port).unwrap_or_default();
+            let sender = sender.clone();
+            let connection = input.connect(
+                port,
+                "skuggbox-input",
+                move |_timestamp, message, _| {
+                    if let Some(change) = ControlChange::parse(message) {
+                        let _ = sender.send(change);
+                    }
+                },
+                (),
+            );
+            match connection {
+                Ok(connection) => {
+                    log::info!("Listening to MIDI input {}", name);
+                    connections.push(connection);
+                }
+                Err(e) => log::warn!("Failed to connect to MIDI input {}: {}", name, e),
+            }
+        }
+
+        Ok(Self {
+            receiver,
+            _connections: connections,
+        })
+    }
+
+    #[cfg(not(feature = "midi"))]
+    pub fn start() -> Result<Self, String> {
+        Err("skuggbox was built without the `midi` feature".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{ControlChange, MidiState};
+    use crate::{GLSLType, GLSLValue, Uniform};
+
+    #[test]
+    fn parse_control_change() {
+        assert_eq!(
+            ControlChange::parse(&[0xB1, 21, 64]),
+            Some(ControlChange {
+                channel: 1,
+                controller: 21,
+                value: 64
+            })
+        );
+        // note on
+        assert_eq!(ControlChange::parse(&[0x90, 60, 100]), None);
+        assert_eq!(ControlChange::parse(&[0xB0]), None);
+    }
+
+    #[test]
+    fn learn_and_apply_control_change() {
+        let mut uniforms = BTreeMap::new();
+        uniforms.insert(
+            "speed".to_string(),
+            Uniform {
+                name: "speed".to_string(),
+                glsl_type: GLSLType::Float,
+                value: Some(GLSLValue::Float(0.0)),
+                range: Some((0.0, 10.0)),
+                color: None,
+            },
+        );
+        let change = |value| ControlChange {
+            channel: 0,
+            controller: 21,
+            value,
+        };
+
+        let mut midi = MidiState {
+            learn: Some(("speed".to_string(), 0)),
+            ..Default::default()
+        };
+        midi.apply(change(127), &mut uniforms);
+        assert_eq!(midi.controller("speed", 0), Some(21));
+        assert_eq!(uniforms["speed"].value, Some(GLSLValue::Float(10.0)));
+
+        midi.apply(change(0), &mut uniforms);
+        assert_eq!(uniforms["speed"].value, Some(GLSLValue::Float(0.0)));
+        assert_eq!(midi.learn, None);
+    }
+}
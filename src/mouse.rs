@@ -1,4 +1,4 @@
-use glam::{Vec2, Vec3};
+use glam::{Vec2, Vec3, Vec4};
 use winit::event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent};
 
 use crate::event::WindowEventHandler;
@@ -6,6 +6,7 @@ use crate::event::WindowEventHandler;
 #[derive(Debug)]
 pub struct Mouse {
     pub pos: Vec2,
+    /// Cursor position at the end of the previous frame, see `drag_delta`
     pub last_pos: Vec2,
     pub delta: Vec2,
     /// Only keep track of the direction the mouse is going in range -1 to 1
@@ -19,6 +20,17 @@ pub struct Mouse {
     /// y: vertical rotation
     /// z: zoom level
     pub dir: Vec3,
+    /// Accumulated scroll, positive when scrolling up. Exposed as `iMouseWheel`
+    pub wheel: f32,
+
+    /// Cursor position in window pixels, origin at the top left
+    pub cursor: Vec2,
+    /// Last cursor position while the left button was held down
+    pub drag_pos: Vec2,
+    /// Cursor position when the left button was last pressed
+    pub click_pos: Vec2,
+    /// True during the frame the left button was pressed, see `end_frame`
+    pub is_click_frame: bool,
 
     pub is_lmb_down: bool,
     pub is_mmb_down: bool,
@@ -34,6 +46,11 @@ impl Default for Mouse {
             delta: Vec2::new(0.0, 0.0),
 
             dir: Vec3::default(),
+            wheel: 0.0,
+            cursor: Vec2::ZERO,
+            drag_pos: Vec2::ZERO,
+            click_pos: Vec2::ZERO,
+            is_click_frame: false,
             is_lmb_down: false,
             is_mmb_down: false,
             is_rmb_down: false,
@@ -42,10 +59,64 @@ impl Default for Mouse {
     }
 }
 
+impl Mouse {
+    /// The `iMouse` uniform following Shadertoy's convention, in pixels with the origin at the
+    /// bottom left of a window `height` pixels high:
+    ///
+    /// - `xy`: cursor position while the left button is held, kept after it's released
+    /// - `z`: x of the last click, positive while the button is held and negative after
+    /// - `w`: y of the last click, positive only during the frame of the click and negative after
+    ///
+    /// Everything is zero until the first click.
+    pub fn shadertoy_uniform(&self, height: f32) -> Vec4 {
+        if self.click_pos == Vec2::ZERO && !self.is_lmb_down {
+            return Vec4::ZERO;
+        }
+
+        let drag_y = height - self.drag_pos.y;
+        let click_y = height - self.click_pos.y;
+
+        let z = match self.is_lmb_down {
+            true => self.click_pos.x,
+            false => -self.click_pos.x,
+        };
+        let w = match self.is_click_frame {
+            true => click_y,
+            false => -click_y,
+        };
+
+        Vec4::new(self.drag_pos.x, drag_y, z, w)
+    }
+
+    /// The `iMouseDelta` uniform, how far the cursor moved since the previous frame while
+    /// dragging with the left button. In pixels with y pointing up, zero on frames without
+    /// movement and on the frame of the click.
+    pub fn drag_delta(&self) -> Vec2 {
+        if !self.is_lmb_down || self.is_click_frame {
+            return Vec2::ZERO;
+        }
+        Vec2::new(
+            self.cursor.x - self.last_pos.x,
+            self.last_pos.y - self.cursor.y,
+        )
+    }
+
+    /// Call after rendering a frame
+    pub fn end_frame(&mut self) {
+        self.is_click_frame = false;
+        self.last_pos = self.cursor;
+    }
+}
+
 impl WindowEventHandler for Mouse {
     fn handle_window_events(&mut self, event: &WindowEvent<'_>) -> bool {
         match event {
             WindowEvent::CursorMoved { position, .. } => {
+                self.cursor = Vec2::new(position.x as f32, position.y as f32);
+                if self.is_lmb_down {
+                    self.drag_pos = self.cursor;
+                }
+
                 if self.is_rmb_down {
                     self.delta = Vec2::new(
                         position.x as f32 - self.pos.x,
@@ -73,6 +144,11 @@ impl WindowEventHandler for Mouse {
                 }
 
                 self.is_lmb_down = *button == MouseButton::Left && *state == ElementState::Pressed;
+                if self.is_lmb_down {
+                    self.click_pos = self.cursor;
+                    self.drag_pos = self.cursor;
+                    self.is_click_frame = true;
+                }
                 self.is_mmb_down =
                     *button == MouseButton::Middle && *state == ElementState::Pressed;
                 self.is_rmb_down = *button == MouseButton::Right && *state == ElementState::Pressed;
@@ -81,10 +157,12 @@ impl WindowEventHandler for Mouse {
             }
 
             WindowEvent::MouseWheel { delta, .. } => {
-                self.dir.z -= match delta {
+                let scroll = match delta {
                     MouseScrollDelta::LineDelta(_, y) => *y,
                     MouseScrollDelta::PixelDelta(pos) => pos.y as f32,
                 };
+                self.dir.z -= scroll;
+                self.wheel += scroll;
                 true
             }
 
@@ -113,3 +191,60 @@ fn vec2_to_dir(v: Vec2) -> Vec2 {
         },
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use glam::{Vec2, Vec4};
+
+    use super::Mouse;
+
+    #[test]
+    fn shadertoy_mouse_convention() {
+        let mut mouse = Mouse::default();
+        assert_eq!(mouse.shadertoy_uniform(100.0), Vec4::ZERO);
+
+        // click at (10, 20) from the top left and drag to (30, 40)
+        mouse.is_lmb_down = true;
+        mouse.is_click_frame = true;
+        mouse.click_pos = Vec2::new(10.0, 20.0);
+        mouse.drag_pos = Vec2::new(30.0, 40.0);
+        assert_eq!(
+            mouse.shadertoy_uniform(100.0),
+            Vec4::new(30.0, 60.0, 10.0, 80.0)
+        );
+
+        mouse.end_frame();
+        assert_eq!(
+            mouse.shadertoy_uniform(100.0),
+            Vec4::new(30.0, 60.0, 10.0, -80.0)
+        );
+
+        mouse.is_lmb_down = false;
+        assert_eq!(
+            mouse.shadertoy_uniform(100.0),
+            Vec4::new(30.0, 60.0, -10.0, -80.0)
+        );
+    }
+
+    #[test]
+    fn drag_delta_per_frame() {
+        let mut mouse = Mouse::default();
+        mouse.cursor = Vec2::new(10.0, 20.0);
+        mouse.end_frame();
+
+        mouse.is_lmb_down = true;
+        mouse.is_click_frame = true;
+        assert_eq!(mouse.drag_delta(), Vec2::ZERO);
+        mouse.end_frame();
+
+        mouse.cursor = Vec2::new(15.0, 10.0);
+        assert_eq!(mouse.drag_delta(), Vec2::new(5.0, 10.0));
+        mouse.end_frame();
+        // no movement this frame
+        assert_eq!(mouse.drag_delta(), Vec2::ZERO);
+
+        mouse.cursor = Vec2::new(20.0, 10.0);
+        mouse.is_lmb_down = false;
+        assert_eq!(mouse.drag_delta(), Vec2::ZERO);
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::net::UdpSocket;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rosc::{OscError, OscPacket, OscType};
+
+use crate::{spawn_guarded, Uniform};
+
+/// Messages to `/skuggbox/uniform/<name>` set the custom uniform `<name>`
+pub const OSC_UNIFORM_PREFIX: &str = "/skuggbox/uniform/";
+
+/// An OSC message with its numeric arguments, arrays are flattened
+#[derive(Debug, Clone, PartialEq)]
+pub struct OscMessage {
+    pub address: String,
+    pub values: Vec<f32>,
+}
+
+impl OscMessage {
+    /// Set the uniform the address points to. Each value sets one component, so vec uniforms
+    /// take several floats or a float array.
+    pub fn apply(&self, uniforms: &mut BTreeMap<String, Uniform>) {
+        let name = match self.address.strip_prefix(OSC_UNIFORM_PREFIX) {
+            Some(name) => name,
+            None => return,
+        };
+        let value = uniforms
+            .get_mut(name)
+            .and_then(|uniform| uniform.value.as_mut());
+        if let Some(value) = value {
+            for (component, value) in value.components_mut().iter_mut().zip(&self.values) {
+                *component = *value;
+            }
+        }
+    }
+}
+
+/// How often the OSC thread checks if it should stop while no packets arrive
+const RECEIVE_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Listens for OSC messages on a UDP port on a background thread, until it's dropped
+pub struct OscInput {
+    pub receiver: Receiver<OscMessage>,
+    stop: Arc<AtomicBool>,
+}
+
+impl OscInput {
+    pub fn start(port: u16) -> Result<Self, String> {
+        let socket = UdpSocket::bind(("0.0.0.0", port)).map_err(|e| e.to_string())?;
+        socket
+            .set_read_timeout(Some(RECEIVE_TIMEOUT))
+            .map_err(|e| e.to_string())?;
+        log::info!("Listening for OSC messages on port {}", port);
+
+        let (sender, receiver): (Sender<OscMessage>, Receiver<OscMessage>) = channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let _ = spawn_guarded("OSC", move || receive(socket, sender, thread_stop));
+
+        Ok(Self { receiver, stop })
+    }
+}
+
+impl Drop for OscInput {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Runs on the OSC thread. Returns when stopped or the receiver is gone.
+fn receive(socket: UdpSocket, sender: Sender<OscMessage>, stop: Arc<AtomicBool>) {
+    let mut buffer = [0u8; 65536];
+    while !stop.load(Ordering::Relaxed) {
+        let size = match socket.recv_from(&mut buffer) {
+            Ok((size, _)) => size,
+            // the read timed out, check whether to stop
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                continue;
+            }
+            Err(e) => {
+                log::warn!("Failed to receive OSC packet: {}", e);
+                continue;
+            }
+        };
+        let messages = match decode_packet(&buffer[..size]) {
+            Ok(messages) => messages,
+            Err(e) => {
+                log::warn!("Ignoring malformed OSC packet: {}", e);
+                continue;
+            }
+        };
+        for message in messages {
+            if sender.send(message).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Decode an OSC packet, a message or a bundle of packets, into its messages.
+/// Only numeric arguments are kept, others are skipped.
+pub fn decode_packet(packet: &[u8]) -> Result<Vec<OscMessage>, OscError> {
+    let (rest, packet) = rosc::decoder::decode_udp(packet)?;
+    // rosc stops at a bundle element it can't read and returns what's left
+    if !rest.is_empty() {
+        return Err(OscError::BadPacket("Unreadable bytes at the end"));
+    }
+    let mut messages = vec![];
+    push_messages(packet, &mut messages);
+    Ok(messages)
+}
+
+fn push_messages(packet: OscPacket, messages: &mut Vec<OscMessage>) {
+    match packet {
+        OscPacket::Message(message) => {
+            let mut values = vec![];
+            push_values(message.args, &mut values);
+            messages.push(OscMessage {
+                address: message.addr,
+                values,
+            });
+        }
+        // time tags are ignored, everything is applied right away
+        OscPacket::Bundle(bundle) => {
+            for packet in bundle.content {
+                push_messages(packet, messages);
+            }
+        }
+    }
+}
+
+/// Arrays are flattened into `values`
+fn push_values(args: Vec<OscType>, values: &mut Vec<f32>) {
+    for arg in args {
+        match arg {
+            OscType::Float(value) => values.push(value),
+            OscType::Int(value) => values.push(value as f32),
+            OscType::Double(value) => values.push(value as f32),
+            OscType::Long(value) => values.push(value as f32),
+            OscType::Bool(value) => values.push(if value { 1.0 } else { 0.0 }),
+            OscType::Array(array) => push_values(array.content, values),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{decode_packet, OscMessage};
+    use crate::{GLSLType, GLSLValue, Uniform};
+
+    fn message(address: &str, type_tags: &str, args: &[u8]) -> Vec<u8> {
+        let mut packet = vec![];
+        for string in [address, type_tags] {
+            packet.extend(string.as_bytes());
+            packet.resize((packet.len() + 4) & !3, 0);
+        }
+        packet.extend(args);
+        packet
+    }
+
+    #[test]
+    fn decode_message_and_bundle() {
+        let args: Vec<u8> = [0.5f32.to_be_bytes(), 2i32.to_be_bytes()].concat();
+        let packet = message("/skuggbox/uniform/pos", ",f[i]", &args);
+
+        assert_eq!(
+            decode_packet(&packet).unwrap(),
+            vec![OscMessage {
+                address: "/skuggbox/uniform/pos".to_string(),
+                values: vec![0.5, 2.0],
+            }]
+        );
+
+        let mut bundle = b"#bundle\0".to_vec();
+        bundle.extend([0, 0, 0, 0, 0, 0, 0, 1]);
+        bundle.extend((packet.len() as i32).to_be_bytes());
+        bundle.extend(&packet);
+        assert_eq!(decode_packet(&bundle).unwrap().len(), 1);
+
+        assert!(decode_packet(&packet[..packet.len() - 2]).is_err());
+    }
+
+    #[test]
+    fn reject_invalid_sizes() {
+        let blob = message("/skuggbox/uniform/pos", ",b", &(-1i32).to_be_bytes());
+        assert!(decode_packet(&blob).is_err());
+        let blob = message("/skuggbox/uniform/pos", ",b", &i32::MAX.to_be_bytes());
+        assert!(decode_packet(&blob).is_err());
+
+        let mut bundle = b"#bundle\0".to_vec();
+        bundle.extend([0, 0, 0, 0, 0, 0, 0, 1]);
+        bundle.extend((-4i32).to_be_bytes());
+        assert!(decode_packet(&bundle).is_err());
+    }
+
+    #[test]
+    fn apply_message_to_uniform() {
+        let mut uniforms = BTreeMap::new();
+        uniforms.insert(
+            "pos".to_string(),
+            Uniform {
+                name: "pos".to_string(),
+                glsl_type: GLSLType::Vec2,
+                value: Some(GLSLValue::Vec2([0.0, 0.0])),
+                range: None,
+                color: None,
+            },
+        );
+
+        OscMessage {
+            address: "/skuggbox/uniform/pos".to_string(),
+            values: vec![0.25, 0.75],
+        }
+        .apply(&mut uniforms);
+        assert_eq!(uniforms["pos"].value, Some(GLSLValue::Vec2([0.25, 0.75])));
+
+        OscMessage {
+            address: "/other/pos".to_string(),
+            values: vec![1.0],
+        }
+        .apply(&mut uniforms);
+        assert_eq!(uniforms["pos"].value, Some(GLSLValue::Vec2([0.25, 0.75])));
+    }
+}
@@ -0,0 +1,296 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{apply_uniform_values, uniform_values, Uniform, UniformValues};
+
+/// Sidecar file the presets of a shader are saved to, `shader.glsl` -> `shader.presets.json`
+pub fn presets_file(shader_path: &Path) -> PathBuf {
+    shader_path.with_extension("presets.json")
+}
+
+/// Curve of the fade between the old values and a preset
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Easing {
+    Linear,
+    /// Starts and ends slowly
+    #[default]
+    Smoothstep,
+}
+
+impl Easing {
+    /// Map the linear progress `t` in 0..=1 onto the curve
+    pub fn apply(self, t: f32) -> f32 {
+        match self {
+            Easing::Linear => t,
+            Easing::Smoothstep => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+/// How applying a preset fades to its values
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PresetTransition {
+    /// Length of the fade, 0.0 snaps to the preset right away
+    pub seconds: f32,
+    pub easing: Easing,
+}
+
+impl Default for PresetTransition {
+    fn default() -> Self {
+        Self {
+            seconds: 1.0,
+            easing: Easing::default(),
+        }
+    }
+}
+
+/// A preset being faded in by `Presets::update`
+#[derive(Debug)]
+struct Fade {
+    from: UniformValues,
+    to: UniformValues,
+    elapsed: f32,
+}
+
+/// Named snapshots of the custom uniform values to switch and morph between
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Presets {
+    pub presets: BTreeMap<String, UniformValues>,
+    /// The preset applied last, morphing starts from it
+    #[serde(skip)]
+    pub active: Option<String>,
+    /// The preset morphed towards and how far, 0.0 is `active` and 1.0 is the target
+    #[serde(skip)]
+    pub morph: Option<(String, f32)>,
+    #[serde(skip)]
+    pub transition: PresetTransition,
+    #[serde(skip)]
+    fade: Option<Fade>,
+}
+
+impl Presets {
+    pub fn new(transition: PresetTransition) -> Self {
+        Self {
+            transition,
+            ..Default::default()
+        }
+    }
+
+    /// Snapshot the current values as `name`, replacing a preset with that name
+    pub fn save(&mut self, name: &str, uniforms: &BTreeMap<String, Uniform>) {
+        self.presets
+            .insert(name.to_string(), uniform_values(uniforms));
+        self.active = Some(name.to_string());
+        self.morph = None;
+        self.fade = None;
+    }
+
+    pub fn delete(&mut self, name: &str) {
+        self.presets.remove(name);
+        if self.active.as_deref() == Some(name) {
+            self.active = None;
+        }
+        if self.morph.as_ref().is_some_and(|(to, _)| to == name) {
+            self.morph = None;
+        }
+    }
+
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<(), String> {
+        if to.is_empty() {
+            return Err("Preset names can't be empty".to_string());
+        }
+        if self.presets.contains_key(to) {
+            return Err(format!("There already is a preset named {}", to));
+        }
+        let values = self
+            .presets
+            .remove(from)
+            .ok_or_else(|| format!("No preset named {}", from))?;
+        self.presets.insert(to.to_string(), values);
+
+        if self.active.as_deref() == Some(from) {
+            self.active = Some(to.to_string());
+        }
+        if let Some((target, _)) = &mut self.morph {
+            if target == from {
+                *target = to.to_string();
+            }
+        }
+        Ok(())
+    }
+
+    /// Fade the uniforms to the values of `name` over the transition time, see `update`.
+    /// Returns false when there's no such preset.
+    pub fn apply(&mut self, name: &str, uniforms: &mut BTreeMap<String, Uniform>) -> bool {
+        let values = match self.presets.get(name) {
+            Some(values) => values.clone(),
+            None => return false,
+        };
+        self.active = Some(name.to_string());
+        self.morph = None;
+
+        if self.transition.seconds > 0.0 {
+            self.fade = Some(Fade {
+                from: uniform_values(uniforms),
+                to: values,
+                elapsed: 0.0,
+            });
+        } else {
+            self.fade = None;
+            apply_uniform_values(&values, uniforms);
+        }
+        true
+    }
+
+    /// Advance the fade started by `apply` by the frame time, call once per frame. Returns
+    /// true while the uniforms are changing.
+    pub fn update(&mut self, delta_time: f32, uniforms: &mut BTreeMap<String, Uniform>) -> bool {
+        let fade = match &mut self.fade {
+            Some(fade) => fade,
+            None => return false,
+        };
+        fade.elapsed += delta_time;
+        let t = (fade.elapsed / self.transition.seconds.max(f32::EPSILON)).min(1.0);
+        let values = blend(&fade.from, &fade.to, self.transition.easing.apply(t));
+        apply_uniform_values(&values, uniforms);
+
+        if t >= 1.0 {
+            self.fade = None;
+        }
+        true
+    }
+
+    /// Blend the uniforms between the active preset and `to`. Uniforms only one of the presets
+    /// knows about are left alone.
+    pub fn morph(&mut self, to: &str, amount: f32, uniforms: &mut BTreeMap<String, Uniform>) {
+        let from = match self.active.as_ref().and_then(|name| self.presets.get(name)) {
+            Some(from) => from,
+            None => return,
+        };
+        let to_values = match self.presets.get(to) {
+            Some(to_values) => to_values,
+            None => return,
+        };
+
+        apply_uniform_values(&blend(from, to_values, amount), uniforms);
+        self.morph = Some((to.to_string(), amount));
+        self.fade = None;
+    }
+
+    pub fn save_to(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, json).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json =
+            std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        serde_json::from_str(&json).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+/// Values `t` of the way from `from` to `to`. Uniforms only one of them has are left out.
+fn blend(from: &UniformValues, to: &UniformValues, t: f32) -> UniformValues {
+    from.iter()
+        .filter_map(|(name, a)| {
+            let b = to.get(name).filter(|b| b.len() == a.len())?;
+            let value = a.iter().zip(b).map(|(a, b)| a + (b - a) * t);
+            Some((name.clone(), value.collect()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{Easing, PresetTransition, Presets};
+    use crate::{custom_uniforms, GLSLValue, Uniform};
+
+    fn uniforms() -> BTreeMap<String, Uniform> {
+        custom_uniforms("uniform float speed; // range 0..10\nuniform vec2 offset;")
+            .into_iter()
+            .map(|uniform| (uniform.name.clone(), uniform))
+            .collect()
+    }
+
+    fn set(uniforms: &mut BTreeMap<String, Uniform>, speed: f32, offset: [f32; 2]) {
+        uniforms.get_mut("speed").unwrap().value = Some(GLSLValue::Float(speed));
+        uniforms.get_mut("offset").unwrap().value = Some(GLSLValue::Vec2(offset));
+    }
+
+    #[test]
+    fn save_apply_and_morph_presets() {
+        let mut uniforms = uniforms();
+        let mut presets = Presets::new(PresetTransition {
+            seconds: 0.0,
+            easing: Easing::Linear,
+        });
+
+        set(&mut uniforms, 2.0, [0.0, 0.0]);
+        presets.save("calm", &uniforms);
+        set(&mut uniforms, 10.0, [1.0, 0.5]);
+        presets.save("wild", &uniforms);
+
+        assert!(presets.apply("calm", &mut uniforms));
+        assert_eq!(uniforms["speed"].value, Some(GLSLValue::Float(2.0)));
+        assert!(!presets.apply("missing", &mut uniforms));
+
+        presets.morph("wild", 0.5, &mut uniforms);
+        assert_eq!(uniforms["speed"].value, Some(GLSLValue::Float(6.0)));
+        assert_eq!(uniforms["offset"].value, Some(GLSLValue::Vec2([0.5, 0.25])));
+    }
+
+    #[test]
+    fn fade_to_preset() {
+        let mut uniforms = uniforms();
+        let mut presets = Presets::default();
+        set(&mut uniforms, 10.0, [1.0, 1.0]);
+        presets.save("wild", &uniforms);
+        set(&mut uniforms, 0.0, [0.0, 0.0]);
+
+        presets.transition.easing = Easing::Linear;
+        presets.apply("wild", &mut uniforms);
+        assert_eq!(uniforms["speed"].value, Some(GLSLValue::Float(0.0)));
+        assert!(presets.update(0.25, &mut uniforms));
+        assert_eq!(uniforms["speed"].value, Some(GLSLValue::Float(2.5)));
+        assert!(presets.update(1.0, &mut uniforms));
+        assert_eq!(uniforms["speed"].value, Some(GLSLValue::Float(10.0)));
+        assert!(!presets.update(0.1, &mut uniforms));
+
+        assert_eq!(Easing::Smoothstep.apply(0.5), 0.5);
+        assert!(Easing::Smoothstep.apply(0.1) < 0.1);
+    }
+
+    #[test]
+    fn rename_and_delete_presets() {
+        let mut presets = Presets::default();
+        presets.save("a", &uniforms());
+        presets.save("b", &uniforms());
+
+        assert!(presets.rename("a", "b").is_err());
+        assert!(presets.rename("a", "").is_err());
+        presets.rename("b", "c").unwrap();
+        assert_eq!(presets.active.as_deref(), Some("c"));
+
+        presets.delete("c");
+        assert_eq!(presets.active, None);
+        assert_eq!(presets.presets.keys().collect::<Vec<_>>(), vec!["a"]);
+    }
+
+    #[test]
+    fn save_and_load_presets() {
+        let mut presets = Presets::default();
+        presets.save("a", &uniforms());
+
+        let file =
+            std::env::temp_dir().join(format!("skuggbox_presets_test-{}.json", std::process::id()));
+        presets.save_to(&file).unwrap();
+        let loaded = Presets::load(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+
+        assert_eq!(loaded.presets, presets.presets);
+    }
+}
@@ -1,14 +1,48 @@
 use glam::{Mat4, Vec2, Vec3, Vec4};
+use serde::{Deserialize, Serialize};
 use std::f32::consts::PI;
+use std::path::{Path, PathBuf};
 use winit::event::{ElementState, MouseScrollDelta, VirtualKeyCode, WindowEvent};
 
 use crate::event::WindowEventHandler;
 use crate::mouse::Mouse;
+use crate::KeyboardState;
 
 pub trait CameraModel: WindowEventHandler {
     fn handle_mouse(&mut self, mouse: &Mouse, delta_time: f32);
 
+    /// Called once per frame with the keys held down and the frame duration in seconds
+    fn update(&mut self, _keyboard: &KeyboardState, _delta_time: f32) {}
+
     fn calculate_uniform_data(&mut self) -> Mat4;
+
+    /// Everything needed to put the camera back where it is, see `SavedCamera`
+    fn save(&self) -> SavedCamera;
+
+    /// Movement speed in units per second, for cameras that move on their own
+    fn speed_mut(&mut self) -> Option<&mut f32> {
+        None
+    }
+}
+
+/// The dev cameras to choose from
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum CameraKind {
+    /// Orbit around a target, drag with the right mouse button to rotate and scroll to zoom
+    #[default]
+    Orbit,
+    /// Fly with WASD, drag with the right mouse button to look around and scroll to
+    /// change the speed
+    Fly,
+}
+
+impl CameraKind {
+    pub fn camera(self) -> Box<dyn CameraModel> {
+        match self {
+            CameraKind::Orbit => Box::from(OrbitCamera::default()),
+            CameraKind::Fly => Box::from(FlyCamera::default()),
+        }
+    }
 }
 
 pub struct OrbitCamera {
@@ -32,6 +66,75 @@ impl Default for OrbitCamera {
     }
 }
 
+/// A dev camera as saved to disk, so it can be restored the next time the shader is opened
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum SavedCamera {
+    Orbit {
+        target: [f32; 3],
+        angle: [f32; 2],
+        zoom: f32,
+    },
+    Fly {
+        position: [f32; 3],
+        yaw: f32,
+        pitch: f32,
+        speed: f32,
+    },
+}
+
+impl SavedCamera {
+    pub fn kind(&self) -> CameraKind {
+        match self {
+            SavedCamera::Orbit { .. } => CameraKind::Orbit,
+            SavedCamera::Fly { .. } => CameraKind::Fly,
+        }
+    }
+
+    pub fn camera(&self) -> Box<dyn CameraModel> {
+        match *self {
+            SavedCamera::Orbit {
+                target,
+                angle,
+                zoom,
+            } => Box::from(OrbitCamera {
+                target: target.into(),
+                angle: angle.into(),
+                zoom,
+                ..Default::default()
+            }),
+            SavedCamera::Fly {
+                position,
+                yaw,
+                pitch,
+                speed,
+            } => Box::from(FlyCamera {
+                pos: position.into(),
+                yaw,
+                pitch,
+                speed,
+                ..Default::default()
+            }),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, json).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json =
+            std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        serde_json::from_str(&json).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+/// Sidecar file the camera of a shader is saved to, `shader.glsl` -> `shader.camera.json`
+pub fn camera_file(shader_path: &Path) -> PathBuf {
+    shader_path.with_extension("camera.json")
+}
+
 impl CameraModel for OrbitCamera {
     fn handle_mouse(&mut self, mouse: &Mouse, delta_time: f32) {
         if mouse.is_rmb_down {
@@ -52,19 +155,227 @@ impl CameraModel for OrbitCamera {
         self.pos.y = (self.angle.y * 1.53).sin() * self.zoom;
         self.pos.z = (self.angle.x * PI).cos() * self.zoom;
 
-        let up = Vec3::new(0.0, 1.0, 0.0);
         let forward = (self.target - self.pos).normalize();
-        let side = Vec3::cross(up, forward);
+        camera_transform(self.pos, forward)
+    }
+
+    fn save(&self) -> SavedCamera {
+        SavedCamera::Orbit {
+            target: self.target.to_array(),
+            angle: self.angle.to_array(),
+            zoom: self.zoom,
+        }
+    }
+}
 
-        Mat4::from_cols(
-            Vec4::new(side.x, side.y, side.z, 0.0),
-            Vec4::new(up.x, up.y, up.z, 0.0),
-            Vec4::new(forward.x, forward.y, forward.z, 0.0),
-            Vec4::new(self.pos.x, self.pos.y, self.pos.z, 1.0),
+/// First person camera moving along its own axes
+pub struct FlyCamera {
+    pos: Vec3,
+    /// Rotation around the y axis in radians, 0 looks along +z
+    yaw: f32,
+    /// Rotation up and down in radians
+    pitch: f32,
+    /// Movement speed in units per second, held shift moves faster
+    pub speed: f32,
+    /// Cursor position while looking around with the right mouse button
+    last_cursor: Option<Vec2>,
+}
+
+impl FlyCamera {
+    /// Radians per pixel of mouse movement
+    const LOOK_SENSITIVITY: f32 = 0.005;
+    const FAST_FACTOR: f32 = 4.0;
+
+    fn forward(&self) -> Vec3 {
+        Vec3::new(
+            self.yaw.sin() * self.pitch.cos(),
+            self.pitch.sin(),
+            self.yaw.cos() * self.pitch.cos(),
         )
     }
 }
 
+impl Default for FlyCamera {
+    fn default() -> Self {
+        Self {
+            pos: Vec3::new(0.0, 0.0, -5.0),
+            yaw: 0.0,
+            pitch: 0.0,
+            speed: 2.0,
+            last_cursor: None,
+        }
+    }
+}
+
+impl CameraModel for FlyCamera {
+    fn handle_mouse(&mut self, mouse: &Mouse, _delta_time: f32) {
+        if !mouse.is_rmb_down {
+            self.last_cursor = None;
+            return;
+        }
+
+        // the look direction follows the cursor directly, independent of the frame rate
+        if let Some(last_cursor) = self.last_cursor {
+            let delta = mouse.cursor - last_cursor;
+            self.yaw += delta.x * Self::LOOK_SENSITIVITY;
+            self.pitch = (self.pitch - delta.y * Self::LOOK_SENSITIVITY).clamp(-1.5, 1.5);
+        }
+        self.last_cursor = Some(mouse.cursor);
+    }
+
+    fn update(&mut self, keyboard: &KeyboardState, delta_time: f32) {
+        let forward = self.forward();
+        let side = Vec3::cross(Vec3::Y, forward).normalize_or_zero();
+
+        let mut direction = Vec3::ZERO;
+        for (key, axis) in [
+            (VirtualKeyCode::W, forward),
+            (VirtualKeyCode::S, -forward),
+            (VirtualKeyCode::D, side),
+            (VirtualKeyCode::A, -side),
+        ] {
+            if keyboard.is_down(key) {
+                direction += axis;
+            }
+        }
+
+        let fast =
+            keyboard.is_down(VirtualKeyCode::LShift) || keyboard.is_down(VirtualKeyCode::RShift);
+        let speed = match fast {
+            true => self.speed * Self::FAST_FACTOR,
+            false => self.speed,
+        };
+        self.pos += direction.normalize_or_zero() * speed * delta_time;
+    }
+
+    fn calculate_uniform_data(&mut self) -> Mat4 {
+        camera_transform(self.pos, self.forward())
+    }
+
+    fn save(&self) -> SavedCamera {
+        SavedCamera::Fly {
+            position: self.pos.to_array(),
+            yaw: self.yaw,
+            pitch: self.pitch,
+            speed: self.speed,
+        }
+    }
+
+    fn speed_mut(&mut self) -> Option<&mut f32> {
+        Some(&mut self.speed)
+    }
+}
+
+impl WindowEventHandler for FlyCamera {
+    fn handle_window_events(&mut self, event: &WindowEvent<'_>) -> bool {
+        match event {
+            // scrolling changes the speed in steps of 10%, for small and large scenes alike
+            WindowEvent::MouseWheel { delta, .. } => {
+                let steps = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(pos) => pos.y as f32 / 50.0,
+                };
+                self.speed = (self.speed * 1.1f32.powf(steps)).clamp(0.01, 1000.0);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Eases the camera towards where the input puts it with a critically damped spring, so
+/// navigation and recorded camera paths move smoothly instead of jumping with every event
+#[derive(Debug, Default)]
+pub struct CameraSmoothing {
+    /// Roughly the seconds the camera takes to catch up, 0.0 turns smoothing off
+    pub time: f32,
+    /// Smoothed position and forward direction with their velocities
+    state: Option<SmoothedCamera>,
+}
+
+#[derive(Debug)]
+struct SmoothedCamera {
+    position: Vec3,
+    forward: Vec3,
+    position_velocity: Vec3,
+    forward_velocity: Vec3,
+}
+
+impl CameraSmoothing {
+    pub fn new(time: f32) -> Self {
+        Self { time, state: None }
+    }
+
+    /// Move the smoothed camera towards `target` by the frame time. Starts at the target.
+    pub fn update(&mut self, target: &Mat4, delta_time: f32) {
+        if self.time <= 0.0 {
+            self.state = None;
+            return;
+        }
+        let position = target.w_axis.truncate();
+        let forward = target.z_axis.truncate();
+        let state = self.state.get_or_insert(SmoothedCamera {
+            position,
+            forward,
+            position_velocity: Vec3::ZERO,
+            forward_velocity: Vec3::ZERO,
+        });
+        state.position = smooth_damp(
+            state.position,
+            position,
+            &mut state.position_velocity,
+            self.time,
+            delta_time,
+        );
+        state.forward = smooth_damp(
+            state.forward,
+            forward,
+            &mut state.forward_velocity,
+            self.time,
+            delta_time,
+        );
+    }
+
+    /// The smoothed transform, `None` when smoothing is off or hasn't started
+    pub fn transform(&self) -> Option<Mat4> {
+        let state = self.state.as_ref()?;
+        let forward = state.forward.try_normalize()?;
+        Some(camera_transform(state.position, forward))
+    }
+}
+
+/// Critically damped spring from `current` towards `target`, stable for any time step.
+/// See Game Programming Gems 4, chapter 1.10.
+fn smooth_damp(
+    current: Vec3,
+    target: Vec3,
+    velocity: &mut Vec3,
+    smooth_time: f32,
+    delta_time: f32,
+) -> Vec3 {
+    let omega = 2.0 / smooth_time;
+    let x = omega * delta_time;
+    let decay = 1.0 / (1.0 + x + 0.48 * x * x + 0.235 * x * x * x);
+    let change = current - target;
+    let temp = (*velocity + omega * change) * delta_time;
+    *velocity = (*velocity - omega * temp) * decay;
+    target + (change + temp) * decay
+}
+
+/// Camera to world transform of a camera at `pos` looking along `forward`, with the y axis up.
+/// This is the layout of `sbCameraTransform`.
+pub fn camera_transform(pos: Vec3, forward: Vec3) -> Mat4 {
+    let up = Vec3::new(0.0, 1.0, 0.0);
+    let side = Vec3::cross(up, forward);
+
+    Mat4::from_cols(
+        Vec4::new(side.x, side.y, side.z, 0.0),
+        Vec4::new(up.x, up.y, up.z, 0.0),
+        Vec4::new(forward.x, forward.y, forward.z, 0.0),
+        Vec4::new(pos.x, pos.y, pos.z, 1.0),
+    )
+}
+
 impl WindowEventHandler for OrbitCamera {
     fn handle_window_events(&mut self, event: &WindowEvent<'_>) -> bool {
         match event {
@@ -110,3 +421,93 @@ impl WindowEventHandler for OrbitCamera {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use glam::Vec3;
+    use winit::event::VirtualKeyCode;
+
+    use super::{
+        camera_file, camera_transform, CameraKind, CameraModel, CameraSmoothing, FlyCamera,
+        SavedCamera,
+    };
+    use crate::KeyboardState;
+
+    #[test]
+    fn fly_camera_moves_at_its_speed() {
+        let mut camera = FlyCamera::default();
+        let mut keyboard = KeyboardState::default();
+
+        keyboard.key_down(VirtualKeyCode::W);
+        camera.update(&keyboard, 0.5);
+        assert_eq!(camera.pos, Vec3::new(0.0, 0.0, -4.0));
+
+        // shift moves four times as fast
+        keyboard.key_down(VirtualKeyCode::LShift);
+        for _ in 0..4 {
+            camera.update(&keyboard, 0.125);
+        }
+        assert_eq!(camera.pos, Vec3::new(0.0, 0.0, 0.0));
+
+        keyboard.key_up(VirtualKeyCode::W);
+        keyboard.key_down(VirtualKeyCode::D);
+        camera.update(&keyboard, 0.125);
+        assert_eq!(camera.pos, Vec3::new(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn smoothing_catches_up_with_the_camera() {
+        let start = camera_transform(Vec3::ZERO, Vec3::Z);
+        let target = camera_transform(Vec3::new(10.0, 0.0, 0.0), Vec3::Z);
+
+        let mut smoothing = CameraSmoothing::new(0.0);
+        smoothing.update(&target, 0.016);
+        assert_eq!(smoothing.transform(), None);
+
+        let mut smoothing = CameraSmoothing::new(0.2);
+        smoothing.update(&start, 0.016);
+        assert_eq!(smoothing.transform(), Some(start));
+
+        // the same time in small or large steps ends up in about the same place
+        let mut x = vec![];
+        for steps in [4, 64] {
+            let mut smoothing = CameraSmoothing::new(0.2);
+            smoothing.update(&start, 0.0);
+            for _ in 0..steps {
+                smoothing.update(&target, 0.2 / steps as f32);
+            }
+            x.push(smoothing.transform().unwrap().w_axis.x);
+        }
+        assert!(x[0] > 5.0 && x[0] < 10.0);
+        assert!((x[0] - x[1]).abs() < 0.2);
+    }
+
+    #[test]
+    fn save_and_restore_camera() {
+        let mut camera = FlyCamera::default();
+        let mut keyboard = KeyboardState::default();
+        keyboard.key_down(VirtualKeyCode::D);
+        camera.update(&keyboard, 1.0);
+
+        let file =
+            std::env::temp_dir().join(format!("skuggbox_camera_test-{}.json", std::process::id()));
+        camera.save().save(&file).unwrap();
+        let saved = SavedCamera::load(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+
+        assert_eq!(saved.kind(), CameraKind::Fly);
+        assert_eq!(saved, camera.save());
+        let mut restored = saved.camera();
+        assert_eq!(
+            restored.calculate_uniform_data(),
+            camera.calculate_uniform_data()
+        );
+
+        assert_eq!(
+            camera_file(Path::new("shaders/scene.glsl")),
+            PathBuf::from("shaders/scene.camera.json")
+        );
+    }
+}
@@ -0,0 +1,172 @@
+use std::path::{Path, PathBuf};
+
+use glam::{Mat4, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::camera::camera_transform;
+
+/// Sidecar file the camera path of a shader is saved to, `shader.glsl` ->
+/// `shader.camera-path.json`
+pub fn camera_path_file(shader_path: &Path) -> PathBuf {
+    shader_path.with_extension("camera-path.json")
+}
+
+/// Where the camera transform comes from
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CameraPathMode {
+    /// The camera follows the mouse and keyboard
+    #[default]
+    Live,
+    /// The camera follows the mouse and keyboard and every frame is added to the path
+    Recording,
+    /// The camera follows the recorded path, input is ignored
+    Playback,
+}
+
+/// Camera position and direction at a point in playback time
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CameraKeyframe {
+    pub time: f32,
+    pub position: [f32; 3],
+    pub forward: [f32; 3],
+}
+
+impl CameraKeyframe {
+    pub fn from_transform(time: f32, transform: &Mat4) -> Self {
+        Self {
+            time,
+            position: transform.w_axis.truncate().to_array(),
+            forward: transform.z_axis.truncate().to_array(),
+        }
+    }
+
+    pub fn transform(&self) -> Mat4 {
+        camera_transform(self.position.into(), self.forward.into())
+    }
+}
+
+/// A recorded camera move, keyed on playback time so it replays the same way every time
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CameraPath {
+    /// Sorted by time
+    pub keyframes: Vec<CameraKeyframe>,
+    #[serde(skip)]
+    pub mode: CameraPathMode,
+    /// Time of the last recorded keyframe, everything passed over since then is replaced
+    #[serde(skip)]
+    last_recorded: Option<f32>,
+}
+
+impl CameraPath {
+    pub fn start_recording(&mut self) {
+        self.mode = CameraPathMode::Recording;
+        self.last_recorded = None;
+    }
+
+    pub fn clear(&mut self) {
+        self.keyframes.clear();
+        self.last_recorded = None;
+    }
+
+    /// Add the transform at `time`. Recording over an existing part of the path replaces it,
+    /// so a move can be redone by rewinding.
+    pub fn record(&mut self, time: f32, transform: &Mat4) {
+        let from = match self.last_recorded {
+            Some(last) if last < time => last,
+            _ => time,
+        };
+        self.keyframes
+            .retain(|k| k.time != time && !(from < k.time && k.time <= time));
+
+        let index = self.keyframes.partition_point(|k| k.time < time);
+        self.keyframes
+            .insert(index, CameraKeyframe::from_transform(time, transform));
+        self.last_recorded = Some(time);
+    }
+
+    /// Transform at `time`, interpolated between the surrounding keyframes. Before the first
+    /// and after the last keyframe the camera holds still.
+    pub fn sample(&self, time: f32) -> Option<Mat4> {
+        let index = self.keyframes.partition_point(|k| k.time <= time);
+        let before = index.checked_sub(1).map(|i| &self.keyframes[i]);
+        match (before, self.keyframes.get(index)) {
+            (Some(a), Some(b)) => {
+                let t = (time - a.time) / (b.time - a.time);
+                let position = Vec3::from(a.position).lerp(b.position.into(), t);
+                let forward = Vec3::from(a.forward).lerp(b.forward.into(), t);
+                Some(camera_transform(
+                    position,
+                    forward.try_normalize().unwrap_or(a.forward.into()),
+                ))
+            }
+            (Some(keyframe), None) | (None, Some(keyframe)) => Some(keyframe.transform()),
+            (None, None) => None,
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, json).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json =
+            std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut camera_path: Self =
+            serde_json::from_str(&json).map_err(|e| format!("{}: {}", path.display(), e))?;
+        camera_path
+            .keyframes
+            .sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        Ok(camera_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Mat4, Vec3};
+
+    use super::CameraPath;
+    use crate::camera::camera_transform;
+
+    fn at(x: f32) -> Mat4 {
+        camera_transform(Vec3::new(x, 0.0, 0.0), Vec3::Z)
+    }
+
+    #[test]
+    fn record_and_sample_path() {
+        let mut path = CameraPath::default();
+        assert_eq!(path.sample(1.0), None);
+
+        path.start_recording();
+        for (time, x) in [(0.0, 0.0), (1.0, 2.0), (2.0, 4.0)] {
+            path.record(time, &at(x));
+        }
+        assert_eq!(path.sample(0.5), Some(at(1.0)));
+        assert_eq!(path.sample(-1.0), Some(at(0.0)));
+        assert_eq!(path.sample(3.0), Some(at(4.0)));
+
+        // rewind and record over the end of the path
+        path.record(1.5, &at(10.0));
+        path.record(2.5, &at(20.0));
+        let times: Vec<f32> = path.keyframes.iter().map(|k| k.time).collect();
+        assert_eq!(times, vec![0.0, 1.0, 1.5, 2.5]);
+    }
+
+    #[test]
+    fn save_and_load_path() {
+        let mut path = CameraPath::default();
+        path.record(0.0, &at(1.0));
+        path.record(1.0, &at(2.0));
+
+        let file = std::env::temp_dir().join(format!(
+            "skuggbox_camera_path_test-{}.json",
+            std::process::id()
+        ));
+        path.save(&file).unwrap();
+        let loaded = CameraPath::load(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+
+        assert_eq!(loaded.keyframes, path.keyframes);
+    }
+}
@@ -0,0 +1,108 @@
+use glow::HasContext;
+use std::path::{Path, PathBuf};
+use time::format_description;
+
+use crate::ExportAlpha;
+
+/// Read back the currently bound framebuffer as tightly packed RGBA rows, top row first
+pub fn read_pixels(gl: &glow::Context, width: i32, height: i32) -> Vec<u8> {
+    let row_size = width as usize * 4;
+    let mut pixels = vec![0u8; row_size * height as usize];
+
+    unsafe {
+        gl.pixel_store_i32(glow::PACK_ALIGNMENT, 1);
+        gl.read_pixels(
+            0,
+            0,
+            width,
+            height,
+            glow::RGBA,
+            glow::UNSIGNED_BYTE,
+            glow::PixelPackData::Slice(&mut pixels),
+        );
+        macros::check_for_gl_error!(gl, "read_pixels");
+    }
+
+    // OpenGL returns the bottom row first
+    pixels
+        .chunks_exact(row_size)
+        .rev()
+        .flatten()
+        .copied()
+        .collect()
+}
+
+/// Turn the pixels the shader rendered into the straight alpha PNGs store
+pub fn convert_alpha(pixels: &mut [u8], alpha: ExportAlpha) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        match (alpha, pixel[3]) {
+            (ExportAlpha::Opaque, _) => pixel[3] = 255,
+            (ExportAlpha::Straight, _) | (ExportAlpha::Premultiplied, 0) => {}
+            (ExportAlpha::Premultiplied, a) => {
+                for channel in &mut pixel[..3] {
+                    *channel = (*channel as u32 * 255 / a as u32).min(255) as u8;
+                }
+            }
+        }
+    }
+}
+
+/// Save RGBA pixels as a PNG
+pub fn save_png(path: &Path, width: i32, height: i32, pixels: &[u8]) -> image::ImageResult<()> {
+    image::save_buffer(
+        path,
+        pixels,
+        width as u32,
+        height as u32,
+        image::ColorType::Rgba8,
+    )
+}
+
+/// `screenshot-<datetime>.png` in the current working directory
+pub fn screenshot_path() -> PathBuf {
+    timestamped_path("screenshot", "png")
+}
+
+/// `<name>-<datetime>.<extension>` in the current working directory
+pub fn timestamped_path(name: &str, extension: &str) -> PathBuf {
+    let format = format_description::parse("[year][month][day]_[hour][minute][second]").unwrap();
+    let datetime = time::OffsetDateTime::now_local()
+        .unwrap_or_else(|_| time::OffsetDateTime::now_utc())
+        .format(&format)
+        .unwrap();
+
+    PathBuf::from(format!("{}-{}.{}", name, datetime, extension))
+}
+
+/// Grab the default framebuffer and write it to a PNG
+pub fn save_screenshot(gl: &glow::Context, width: i32, height: i32, path: &Path) {
+    let pixels = read_pixels(gl, width, height);
+
+    match save_png(path, width, height, &pixels) {
+        Ok(_) => log::info!("Screenshot: Saved to {:?}", path),
+        Err(e) => log::error!("Screenshot: Failed to save {:?}: {}", path, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::convert_alpha;
+    use crate::ExportAlpha;
+
+    #[test]
+    fn convert_exported_alpha() {
+        let rendered = [100, 50, 0, 128, 10, 20, 30, 0];
+
+        let mut pixels = rendered;
+        convert_alpha(&mut pixels, ExportAlpha::Opaque);
+        assert_eq!(pixels, [100, 50, 0, 255, 10, 20, 30, 255]);
+
+        let mut pixels = rendered;
+        convert_alpha(&mut pixels, ExportAlpha::Straight);
+        assert_eq!(pixels, rendered);
+
+        let mut pixels = rendered;
+        convert_alpha(&mut pixels, ExportAlpha::Premultiplied);
+        assert_eq!(pixels, [199, 99, 0, 128, 10, 20, 30, 0]);
+    }
+}
@@ -0,0 +1,122 @@
+use glow::HasContext;
+
+use crate::program::{compile_shader, link_program};
+use crate::VERTEX_SHADER;
+
+/// Shows the storage texture as is, one texel per pixel
+const DISPLAY_SHADER: &str = r#"#version 330 core
+uniform sampler2D image;
+out vec4 fragColor;
+void main() {
+    fragColor = texelFetch(image, ivec2(gl_FragCoord.xy), 0);
+}"#;
+
+/// Storage texture a compute shader writes into on image unit 0, together with the program
+/// that draws it to the screen
+pub struct ComputeTarget {
+    pub texture: glow::Texture,
+    pub width: i32,
+    pub height: i32,
+    display_program: glow::Program,
+}
+
+impl ComputeTarget {
+    pub fn new(gl: &glow::Context, width: i32, height: i32) -> Result<Self, String> {
+        unsafe {
+            let vert_shader = compile_shader(gl, glow::VERTEX_SHADER, VERTEX_SHADER)?;
+            let frag_shader = compile_shader(gl, glow::FRAGMENT_SHADER, DISPLAY_SHADER)?;
+            let display_program = link_program(gl, &[vert_shader, frag_shader]);
+            gl.delete_shader(vert_shader);
+            gl.delete_shader(frag_shader);
+            let display_program = display_program?;
+
+            let texture = gl.create_texture()?;
+            gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+            allocate_storage(gl, width, height);
+            gl.tex_parameter_i32(
+                glow::TEXTURE_2D,
+                glow::TEXTURE_MIN_FILTER,
+                glow::NEAREST as i32,
+            );
+            gl.tex_parameter_i32(
+                glow::TEXTURE_2D,
+                glow::TEXTURE_MAG_FILTER,
+                glow::NEAREST as i32,
+            );
+            gl.bind_texture(glow::TEXTURE_2D, None);
+            macros::check_for_gl_error!(gl, "compute_target_create");
+
+            Ok(Self {
+                texture,
+                width,
+                height,
+                display_program,
+            })
+        }
+    }
+
+    /// Reallocate the texture if the size differs from the current one
+    pub fn ensure_size(&mut self, gl: &glow::Context, width: i32, height: i32) {
+        if self.width == width && self.height == height {
+            return;
+        }
+        unsafe {
+            gl.bind_texture(glow::TEXTURE_2D, Some(self.texture));
+            allocate_storage(gl, width, height);
+            gl.bind_texture(glow::TEXTURE_2D, None);
+        }
+        self.width = width;
+        self.height = height;
+    }
+
+    /// Bind the texture to image unit 0 for the compute shader to write into
+    pub fn bind_image(&self, gl: &glow::Context) {
+        unsafe {
+            gl.bind_image_texture(
+                0,
+                self.texture,
+                0,
+                false,
+                0,
+                glow::READ_WRITE,
+                glow::RGBA32F,
+            );
+        }
+    }
+
+    /// Draw the texture into the currently bound framebuffer
+    pub fn display(&self, gl: &glow::Context) {
+        unsafe {
+            gl.use_program(Some(self.display_program));
+            gl.active_texture(glow::TEXTURE0);
+            gl.bind_texture(glow::TEXTURE_2D, Some(self.texture));
+            let location = gl.get_uniform_location(self.display_program, "image");
+            gl.uniform_1_i32(location.as_ref(), 0);
+
+            gl.clear(glow::COLOR_BUFFER_BIT);
+            gl.draw_arrays(glow::TRIANGLES, 0, 3);
+            macros::check_for_gl_error!(gl, "compute_display");
+        }
+    }
+
+    pub fn delete(&self, gl: &glow::Context) {
+        unsafe {
+            gl.delete_program(self.display_program);
+            gl.delete_texture(self.texture);
+        }
+    }
+}
+
+unsafe fn allocate_storage(gl: &glow::Context, width: i32, height: i32) {
+    gl.tex_image_2d(
+        glow::TEXTURE_2D,
+        0,
+        glow::RGBA32F as i32,
+        width,
+        height,
+        0,
+        glow::RGBA,
+        glow::FLOAT,
+        None,
+    );
+}
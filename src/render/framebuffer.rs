@@ -0,0 +1,216 @@
+use glow::HasContext;
+
+use crate::TextureFormat;
+
+/// A texture backed framebuffer object used as an offscreen render target
+pub struct Framebuffer {
+    pub fbo: glow::Framebuffer,
+    /// One texture per color attachment, `textures[N]` is written by `layout(location = N) out`
+    pub textures: Vec<glow::Texture>,
+    pub width: i32,
+    pub height: i32,
+    pub format: TextureFormat,
+}
+
+impl Framebuffer {
+    /// An 8 bit RGBA framebuffer, which is what the screen and exported images use
+    pub fn new(gl: &glow::Context, width: i32, height: i32) -> Result<Self, String> {
+        Self::with_format(gl, width, height, TextureFormat::Rgba8)
+    }
+
+    pub fn with_format(
+        gl: &glow::Context,
+        width: i32,
+        height: i32,
+        format: TextureFormat,
+    ) -> Result<Self, String> {
+        Self::with_outputs(gl, width, height, format, 1)
+    }
+
+    /// A framebuffer with `outputs` color attachments of the same format, all drawn to at once
+    pub fn with_outputs(
+        gl: &glow::Context,
+        width: i32,
+        height: i32,
+        format: TextureFormat,
+        outputs: usize,
+    ) -> Result<Self, String> {
+        unsafe {
+            let mut textures = vec![];
+            for _ in 0..outputs {
+                let texture = gl.create_texture()?;
+                gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+                allocate_texture(gl, width, height, format);
+                gl.tex_parameter_i32(
+                    glow::TEXTURE_2D,
+                    glow::TEXTURE_MIN_FILTER,
+                    glow::LINEAR as i32,
+                );
+                gl.tex_parameter_i32(
+                    glow::TEXTURE_2D,
+                    glow::TEXTURE_MAG_FILTER,
+                    glow::LINEAR as i32,
+                );
+                gl.tex_parameter_i32(
+                    glow::TEXTURE_2D,
+                    glow::TEXTURE_WRAP_S,
+                    glow::CLAMP_TO_EDGE as i32,
+                );
+                gl.tex_parameter_i32(
+                    glow::TEXTURE_2D,
+                    glow::TEXTURE_WRAP_T,
+                    glow::CLAMP_TO_EDGE as i32,
+                );
+                textures.push(texture);
+            }
+            gl.bind_texture(glow::TEXTURE_2D, None);
+
+            let fbo = gl.create_framebuffer()?;
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(fbo));
+            let attachments: Vec<u32> = (0..outputs as u32)
+                .map(|index| glow::COLOR_ATTACHMENT0 + index)
+                .collect();
+            for (attachment, texture) in attachments.iter().zip(&textures) {
+                gl.framebuffer_texture_2d(
+                    glow::FRAMEBUFFER,
+                    *attachment,
+                    glow::TEXTURE_2D,
+                    Some(*texture),
+                    0,
+                );
+            }
+            // part of the framebuffer state, so this holds whenever it's bound
+            gl.draw_buffers(&attachments);
+            let status = gl.check_framebuffer_status(glow::FRAMEBUFFER);
+
+            // start out with a blank buffer instead of whatever happens to be in memory
+            gl.clear_color(0.0, 0.0, 0.0, 0.0);
+            gl.clear(glow::COLOR_BUFFER_BIT);
+            gl.bind_framebuffer(glow::FRAMEBUFFER, None);
+            macros::check_for_gl_error!(gl, "framebuffer_create");
+
+            if status != glow::FRAMEBUFFER_COMPLETE {
+                gl.delete_framebuffer(fbo);
+                for texture in textures {
+                    gl.delete_texture(texture);
+                }
+                return Err(format!("Framebuffer incomplete: 0x{:X}", status));
+            }
+
+            Ok(Self {
+                fbo,
+                textures,
+                width,
+                height,
+                format,
+            })
+        }
+    }
+
+    /// Reallocate the texture storage. The previous content is lost.
+    pub fn resize(&mut self, gl: &glow::Context, width: i32, height: i32) {
+        unsafe {
+            for texture in &self.textures {
+                gl.bind_texture(glow::TEXTURE_2D, Some(*texture));
+                allocate_texture(gl, width, height, self.format);
+            }
+            gl.bind_texture(glow::TEXTURE_2D, None);
+
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(self.fbo));
+            gl.clear_color(0.0, 0.0, 0.0, 0.0);
+            gl.clear(glow::COLOR_BUFFER_BIT);
+            gl.bind_framebuffer(glow::FRAMEBUFFER, None);
+        }
+        self.width = width;
+        self.height = height;
+    }
+
+    /// Bind as the render target and set the viewport to cover it
+    pub fn bind(&self, gl: &glow::Context) {
+        unsafe {
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(self.fbo));
+            gl.viewport(0, 0, self.width, self.height);
+        }
+    }
+
+    pub fn delete(&self, gl: &glow::Context) {
+        unsafe {
+            gl.delete_framebuffer(self.fbo);
+            for texture in &self.textures {
+                gl.delete_texture(*texture);
+            }
+        }
+    }
+}
+
+/// Two framebuffers that are swapped every frame so a pass can read its own previous output
+pub struct DoubleBuffer {
+    buffers: [Framebuffer; 2],
+    front: usize,
+}
+
+impl DoubleBuffer {
+    pub fn new(
+        gl: &glow::Context,
+        width: i32,
+        height: i32,
+        format: TextureFormat,
+        outputs: usize,
+    ) -> Result<Self, String> {
+        Ok(Self {
+            buffers: [
+                Framebuffer::with_outputs(gl, width, height, format, outputs)?,
+                Framebuffer::with_outputs(gl, width, height, format, outputs)?,
+            ],
+            front: 0,
+        })
+    }
+
+    /// The most recently completed frame. This is what other passes sample from.
+    pub fn front(&self) -> &Framebuffer {
+        &self.buffers[self.front]
+    }
+
+    /// The buffer to render the next frame into
+    pub fn back(&self) -> &Framebuffer {
+        &self.buffers[1 - self.front]
+    }
+
+    /// Make the back buffer the front buffer, called when a pass is done rendering
+    pub fn swap(&mut self) {
+        self.front = 1 - self.front;
+    }
+
+    /// Resize both buffers if the size differs from the current one
+    pub fn ensure_size(&mut self, gl: &glow::Context, width: i32, height: i32) {
+        for buffer in self.buffers.iter_mut() {
+            if buffer.width != width || buffer.height != height {
+                buffer.resize(gl, width, height);
+            }
+        }
+    }
+
+    pub fn delete(&self, gl: &glow::Context) {
+        for buffer in &self.buffers {
+            buffer.delete(gl);
+        }
+    }
+}
+
+unsafe fn allocate_texture(gl: &glow::Context, width: i32, height: i32, format: TextureFormat) {
+    let data_type = match format.is_float() {
+        true => glow::FLOAT,
+        false => glow::UNSIGNED_BYTE,
+    };
+    gl.tex_image_2d(
+        glow::TEXTURE_2D,
+        0,
+        format.internal_format() as i32,
+        width,
+        height,
+        0,
+        glow::RGBA,
+        data_type,
+        None,
+    );
+}
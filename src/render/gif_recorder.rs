@@ -0,0 +1,203 @@
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::path::Path;
+use std::time::Instant;
+
+use image::imageops::FilterType;
+use image::RgbaImage;
+
+use crate::capture::read_pixels;
+
+/// Length, frame rate and size of a GIF recording
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GifSettings {
+    pub seconds: f32,
+    pub fps: f32,
+    /// Frames are scaled down to this width, keeping the aspect ratio
+    pub width: u32,
+}
+
+impl Default for GifSettings {
+    fn default() -> Self {
+        Self {
+            seconds: 3.0,
+            fps: 15.0,
+            width: 480,
+        }
+    }
+}
+
+/// Keeps the last `seconds` of rendered frames in memory, so a GIF of what just happened can
+/// be saved at any time
+pub struct GifRecorder {
+    pub settings: GifSettings,
+    /// Scaled down frames with the time they were captured at, oldest first
+    frames: VecDeque<(f32, RgbaImage)>,
+    started: Instant,
+}
+
+impl GifRecorder {
+    pub fn new(settings: GifSettings) -> Self {
+        Self {
+            settings,
+            frames: VecDeque::new(),
+            started: Instant::now(),
+        }
+    }
+
+    /// Grab the default framebuffer when the next frame of the GIF is due
+    pub fn capture(&mut self, gl: &glow::Context, width: i32, height: i32) {
+        let time = self.started.elapsed().as_secs_f32();
+        if !self.is_due(time) {
+            return;
+        }
+        let pixels = read_pixels(gl, width, height);
+        if let Some(frame) = RgbaImage::from_raw(width as u32, height as u32, pixels) {
+            self.push(time, scale_to_width(&frame, self.settings.width));
+        }
+    }
+
+    fn is_due(&self, time: f32) -> bool {
+        match self.frames.back() {
+            Some((last, _)) => time - last >= 1.0 / self.settings.fps,
+            None => true,
+        }
+    }
+
+    /// Add a frame and drop the ones older than `seconds`
+    fn push(&mut self, time: f32, frame: RgbaImage) {
+        // A GIF has one size, the frames from before a resize can't go in it
+        if let Some((_, last)) = self.frames.back() {
+            if last.dimensions() != frame.dimensions() {
+                self.frames.clear();
+            }
+        }
+        self.frames.push_back((time, frame));
+        let max_frames = (self.settings.seconds * self.settings.fps).round().max(1.0) as usize;
+        while self.frames.len() > max_frames {
+            self.frames.pop_front();
+        }
+    }
+
+    /// Take the buffered frames, oldest first. Recording starts over from an empty buffer.
+    pub fn take_frames(&mut self) -> Vec<RgbaImage> {
+        self.frames.drain(..).map(|(_, frame)| frame).collect()
+    }
+}
+
+/// Scale down so the frame is at most `width` wide, small GIFs are the point
+fn scale_to_width(frame: &RgbaImage, width: u32) -> RgbaImage {
+    if frame.width() <= width {
+        return frame.clone();
+    }
+    let height = (frame.height() as u64 * width as u64 / frame.width() as u64).max(1) as u32;
+    image::imageops::resize(frame, width, height, FilterType::Triangle)
+}
+
+/// Encode frames as a looping GIF, each frame gets its own quantized palette
+pub fn save_gif(path: &Path, frames: Vec<RgbaImage>, fps: f32) -> Result<(), String> {
+    let (width, height) = match frames.first() {
+        Some(frame) => frame.dimensions(),
+        None => return Err("no frames recorded".to_string()),
+    };
+    let (width, height) = match (u16::try_from(width), u16::try_from(height)) {
+        (Ok(width), Ok(height)) => (width, height),
+        _ => {
+            return Err(format!(
+                "{}x{} is too large, GIFs are at most {} pixels wide and high",
+                width,
+                height,
+                u16::MAX
+            ))
+        }
+    };
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    let mut encoder = gif::Encoder::new(file, width, height, &[]).map_err(|e| e.to_string())?;
+    encoder
+        .set_repeat(gif::Repeat::Infinite)
+        .map_err(|e| e.to_string())?;
+
+    // GIF delays are in hundredths of a second
+    let delay = (100.0 / fps).round() as u16;
+    for frame in frames {
+        let mut pixels = frame.into_raw();
+        let mut frame = gif::Frame::from_rgba_speed(width, height, &mut pixels, 10);
+        frame.delay = delay;
+        encoder.write_frame(&frame).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use super::{save_gif, scale_to_width, GifRecorder, GifSettings};
+
+    #[test]
+    fn scale_frames_down_to_width() {
+        let frame = RgbaImage::new(1920, 1080);
+        let scaled = scale_to_width(&frame, 480);
+        assert_eq!(scaled.dimensions(), (480, 270));
+
+        let small = RgbaImage::new(320, 200);
+        assert_eq!(scale_to_width(&small, 480).dimensions(), (320, 200));
+    }
+
+    #[test]
+    fn save_looping_gif() {
+        let frames = vec![
+            RgbaImage::from_pixel(8, 4, Rgba([255, 0, 0, 255])),
+            RgbaImage::from_pixel(8, 4, Rgba([0, 0, 255, 255])),
+        ];
+        let path =
+            std::env::temp_dir().join(format!("skuggbox_gif_test-{}.gif", std::process::id()));
+        save_gif(&path, frames, 10.0).unwrap();
+
+        let file = std::fs::File::open(&path).unwrap();
+        let mut decoder = gif::DecodeOptions::new().read_info(file).unwrap();
+        let mut count = 0;
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            assert_eq!(frame.delay, 10);
+            count += 1;
+        }
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(count, 2);
+        assert_eq!((decoder.width(), decoder.height()), (8, 4));
+
+        assert!(save_gif(&path, vec![], 10.0).is_err());
+        let too_wide = vec![RgbaImage::new(u16::MAX as u32 + 1, 1)];
+        assert!(save_gif(&path, too_wide, 10.0).is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn keep_the_last_seconds_of_frames() {
+        let mut recorder = GifRecorder::new(GifSettings {
+            seconds: 1.0,
+            fps: 4.0,
+            width: 480,
+        });
+        assert!(recorder.is_due(0.0));
+        for i in 0..10 {
+            let time = i as f32 * 0.25;
+            recorder.push(time, RgbaImage::from_pixel(2, 2, Rgba([i, 0, 0, 255])));
+            assert!(!recorder.is_due(time + 0.1));
+            assert!(recorder.is_due(time + 0.25));
+        }
+
+        let frames = recorder.take_frames();
+        let reds: Vec<u8> = frames
+            .iter()
+            .map(|frame| frame.get_pixel(0, 0)[0])
+            .collect();
+        assert_eq!(reds, vec![6, 7, 8, 9]);
+        assert!(recorder.take_frames().is_empty());
+
+        // frames from before a resize are dropped
+        recorder.push(0.0, RgbaImage::new(2, 2));
+        recorder.push(0.25, RgbaImage::new(4, 2));
+        assert_eq!(recorder.take_frames().len(), 1);
+    }
+}
@@ -0,0 +1,77 @@
+use glow::HasContext;
+
+/// Number of queries in flight. Results are read back this many frames after they were
+/// issued, by then the GPU is usually done with them so reading doesn't stall.
+const QUERY_COUNT: usize = 3;
+
+/// Measures GPU time with `GL_TIME_ELAPSED` queries that are read back a few frames later
+pub struct GpuTimer {
+    queries: Vec<glow::Query>,
+    /// Whether the query at the same index was issued and not yet read back
+    pending: [bool; QUERY_COUNT],
+    next: usize,
+    /// Query begun in this frame
+    active: Option<usize>,
+    /// Most recent result in nanoseconds
+    pub elapsed: Option<u32>,
+}
+
+impl GpuTimer {
+    /// Returns `None` when the context doesn't support timer queries
+    pub fn new(gl: &glow::Context) -> Option<Self> {
+        let version = gl.version();
+        let supported = (version.major, version.minor) >= (3, 3)
+            || gl.supported_extensions().contains("GL_ARB_timer_query");
+        if !supported || version.is_embedded {
+            return None;
+        }
+
+        let queries = (0..QUERY_COUNT)
+            .map(|_| unsafe { gl.create_query() })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| log::warn!("Failed to create timer queries: {}", e))
+            .ok()?;
+
+        Some(Self {
+            queries,
+            pending: [false; QUERY_COUNT],
+            next: 0,
+            active: None,
+            elapsed: None,
+        })
+    }
+
+    /// Read back the oldest query and start a new one. When the oldest result isn't ready
+    /// yet this frame isn't measured rather than waiting for it.
+    pub fn begin(&mut self, gl: &glow::Context) {
+        let index = self.next;
+        let query = self.queries[index];
+
+        unsafe {
+            if self.pending[index] {
+                if gl.get_query_parameter_u32(query, glow::QUERY_RESULT_AVAILABLE) == 0 {
+                    return;
+                }
+                self.elapsed = Some(gl.get_query_parameter_u32(query, glow::QUERY_RESULT));
+                self.pending[index] = false;
+            }
+
+            gl.begin_query(glow::TIME_ELAPSED, query);
+        }
+        self.active = Some(index);
+    }
+
+    pub fn end(&mut self, gl: &glow::Context) {
+        if let Some(index) = self.active.take() {
+            unsafe { gl.end_query(glow::TIME_ELAPSED) };
+            self.pending[index] = true;
+            self.next = (index + 1) % QUERY_COUNT;
+        }
+    }
+
+    pub fn delete(&self, gl: &glow::Context) {
+        for query in &self.queries {
+            unsafe { gl.delete_query(*query) };
+        }
+    }
+}
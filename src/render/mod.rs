@@ -1,3 +1,9 @@
 pub mod camera;
+pub mod camera_path;
+pub mod capture;
+pub mod compute;
+pub mod framebuffer;
+pub mod gif_recorder;
+pub mod gpu_timer;
 pub mod renderer;
 pub mod uniforms;
@@ -1,10 +1,29 @@
-use crate::{AppState, ShaderService};
+use crate::compute::ComputeTarget;
+use crate::framebuffer::{DoubleBuffer, Framebuffer};
+use crate::gpu_timer::GpuTimer;
+use crate::{
+    beat, local_date, sampler_type, AppState, DrawMode, GLSLValue, Pass, ShaderService,
+    SkuggboxShader, SoloTexture, TextureFormat, BEATS_PER_BAR, CHANNEL_COUNT,
+};
 use glow::{HasContext, VertexArray};
 use std::sync::Arc;
 
+/// A texture bound to one of the `iChannelN` samplers together with its target and size
+type ChannelInput = Option<(glow::Texture, u32, [f32; 3])>;
+
 pub struct Renderer {
     gl: Arc<glow::Context>,
     vertex_array: VertexArray,
+    /// Intermediate target used when `AppState::render_scale` isn't 1.0
+    scaled_target: Option<Framebuffer>,
+    /// Measures the GPU time of a frame while the frame rate overlay is shown
+    gpu_timer: Option<GpuTimer>,
+    /// Output texture of compute shaders, created when the first one is rendered
+    compute_target: Option<ComputeTarget>,
+    /// Read framebuffer the solo texture is attached to for blitting
+    solo_fbo: glow::Framebuffer,
+    /// Float target holding the average of the frames while accumulating
+    accumulation_target: Option<Framebuffer>,
 }
 
 impl Renderer {
@@ -13,75 +32,642 @@ impl Renderer {
             gl.create_vertex_array()
                 .expect("Cannot create vertex array")
         };
-        Self { gl, vertex_array }
+        let solo_fbo = unsafe { gl.create_framebuffer().expect("Cannot create framebuffer") };
+        let gpu_timer = GpuTimer::new(&gl);
+        Self {
+            gl,
+            vertex_array,
+            scaled_target: None,
+            gpu_timer,
+            compute_target: None,
+            solo_fbo,
+            accumulation_target: None,
+        }
+    }
+
+    /// Render to the screen. With a render scale other than 1.0, a fixed size or a render
+    /// region the scene is rendered into an intermediate framebuffer at that size first and
+    /// then stretched onto the screen, see `AppState::output_rect`. The intermediate
+    /// framebuffer keeps the previous frame around the render region.
+    pub fn draw(&mut self, state: &mut AppState, shader_service: &mut ShaderService) {
+        let gl = self.gl.clone();
+        // taken out for the duration of the frame so `self` can be borrowed for drawing
+        let mut gpu_timer = self.gpu_timer.take();
+        let measure = state.show_stats && gpu_timer.is_some();
+
+        if let Some(gpu_timer) = gpu_timer.as_mut().filter(|_| measure) {
+            gpu_timer.begin(&gl);
+        }
+        // the float target is large, don't keep it around once accumulation is turned off
+        if !state.accumulate {
+            if let Some(target) = self.accumulation_target.take() {
+                target.delete(&gl);
+            }
+        }
+        self.draw_scaled(state, shader_service);
+        if let Some(gpu_timer) = gpu_timer.as_mut().filter(|_| measure) {
+            gpu_timer.end(&gl);
+            state.gpu_time = gpu_timer.elapsed;
+        } else {
+            state.gpu_time = None;
+        }
+
+        self.gpu_timer = gpu_timer;
     }
 
-    pub fn draw(&self, state: &mut AppState, shader_service: &ShaderService) {
+    fn draw_scaled(&mut self, state: &mut AppState, shader_service: &mut ShaderService) {
+        if state.render_scale == 1.0 && state.fixed_size.is_none() && state.render_region.is_none()
+        {
+            self.draw_to(state, shader_service, None);
+            return;
+        }
+
         let gl = self.gl.clone();
+        let (width, height) = state.render_size();
+        let target = match self.scaled_target.take() {
+            Some(mut target) => {
+                if target.width != width || target.height != height {
+                    target.resize(&gl, width, height);
+                }
+                target
+            }
+            None => match Framebuffer::new(&gl, width, height) {
+                Ok(target) => target,
+                Err(e) => {
+                    log::error!("Failed to create scaled render target: {}", e);
+                    state.render_scale = 1.0;
+                    state.fixed_size = None;
+                    self.draw_to(state, shader_service, None);
+                    return;
+                }
+            },
+        };
+
+        self.draw_to(state, shader_service, Some(&target));
+
+        let (x, y, output_width, output_height) = state.output_rect();
         unsafe {
-            gl.bind_vertex_array(Some(self.vertex_array));
+            gl.bind_framebuffer(glow::READ_FRAMEBUFFER, Some(target.fbo));
+            gl.bind_framebuffer(glow::DRAW_FRAMEBUFFER, None);
+            if state.fixed_size.is_some() {
+                // black bars around the letterboxed image
+                gl.viewport(0, 0, state.width, state.height);
+                gl.clear_color(0.0, 0.0, 0.0, 1.0);
+                gl.clear(glow::COLOR_BUFFER_BIT);
+            }
+            gl.blit_framebuffer(
+                0,
+                0,
+                width,
+                height,
+                x,
+                y,
+                x + output_width,
+                y + output_height,
+                glow::COLOR_BUFFER_BIT,
+                glow::LINEAR,
+            );
+            gl.bind_framebuffer(glow::FRAMEBUFFER, None);
+            gl.viewport(0, 0, state.width, state.height);
+            macros::check_for_gl_error!(&gl, "blit_scaled_target");
+        }
 
-            gl.clear_color(0.1, 0.2, 0.1, 1.0);
+        self.scaled_target = Some(target);
+    }
+
+    /// Render into `target`, or to the screen when `target` is `None`
+    pub fn draw_to(
+        &mut self,
+        state: &mut AppState,
+        shader_service: &mut ShaderService,
+        target: Option<&Framebuffer>,
+    ) {
+        match target {
+            Some(target) => target.bind(&self.gl),
+            // follow the window size, it may have been resized since the last frame
+            None => unsafe { self.gl.viewport(0, 0, state.width, state.height) },
+        }
+
+        unsafe {
+            self.gl.bind_vertex_array(Some(self.vertex_array));
+
+            let [r, g, b, a] = state.clear_color;
+            self.gl.clear_color(r, g, b, a);
+        }
+        shader_service.bind_storage_buffers(&self.gl);
+
+        if shader_service.passes.is_empty() {
+            if let Some(solo) = solo_texture(state, shader_service) {
+                unsafe { self.draw_solo(state, &solo, target) };
+            } else if let Some(shader) = shader_service.shaders.get(state.active_shader) {
+                let inputs = channel_inputs(shader_service, None);
+                if shader.is_compute() {
+                    unsafe { self.draw_compute(state, shader, &inputs) };
+                } else if state.accumulate {
+                    unsafe { self.draw_accumulated(state, shader, &inputs, target) };
+                } else {
+                    unsafe { self.draw_shader(state, shader, &inputs) };
+                }
+            }
+        } else {
+            self.draw_passes(state, shader_service, target);
+        }
+
+        state.frame_count += 1;
+    }
 
-            if let Some(shader) = shader_service.shaders.get(0) {
-                // kick shader to gpu
-                gl.use_program(shader.program);
+    /// Render all passes in order. Buffer passes render into their back buffer which is then
+    /// swapped to the front, so passes sampling themselves read the previous frame.
+    fn draw_passes(
+        &mut self,
+        state: &mut AppState,
+        shader_service: &mut ShaderService,
+        output: Option<&Framebuffer>,
+    ) {
+        let gl = self.gl.clone();
+        let gl = gl.as_ref();
+        let last_pass = shader_service.passes.len() - 1;
+        let (width, height) = state.render_size();
 
-                // set uniforms
-                if let Some(resolution) = shader.locations.resolution {
-                    gl.uniform_2_f32(Some(&resolution), state.width as f32, state.height as f32)
+        for index in 0..shader_service.passes.len() {
+            // buffers keep updating while one of them is shown
+            if index == last_pass {
+                if let Some(solo) = solo_texture(state, shader_service) {
+                    if output.is_none() {
+                        unsafe {
+                            gl.bind_framebuffer(glow::FRAMEBUFFER, None);
+                            gl.viewport(0, 0, width, height);
+                        }
+                    }
+                    unsafe { self.draw_solo(state, &solo, output) };
+                    continue;
                 }
+            }
+
+            let inputs = channel_inputs(shader_service, Some(&shader_service.passes[index]));
 
-                if let Some(time) = shader.locations.time {
-                    gl.uniform_1_f32(Some(&time), state.playback_time)
+            let pass = &mut shader_service.passes[index];
+            let shader = match shader_service.shaders.get(pass.shader_index) {
+                Some(shader) => shader,
+                None => continue,
+            };
+
+            if index == last_pass && state.accumulate {
+                unsafe { self.draw_accumulated(state, shader, &inputs, output) };
+                continue;
+            }
+            if index == last_pass {
+                match output {
+                    Some(output) => output.bind(gl),
+                    None => unsafe {
+                        gl.bind_framebuffer(glow::FRAMEBUFFER, None);
+                        gl.viewport(0, 0, width, height);
+                    },
                 }
+                unsafe { self.draw_shader(state, shader, &inputs) };
+                continue;
+            }
 
-                if let Some(delta_time) = shader.locations.time_delta {
-                    gl.uniform_1_f32(Some(&delta_time), state.delta_time)
+            if pass.target.is_none() {
+                match DoubleBuffer::new(gl, width, height, pass.format, pass.outputs) {
+                    Ok(target) => pass.target = Some(target),
+                    Err(e) => {
+                        log::error!("Failed to create buffer for pass {}: {}", pass.name, e);
+                        continue;
+                    }
                 }
+            }
+
+            if let Some(target) = &mut pass.target {
+                target.ensure_size(gl, width, height);
+                target.back().bind(gl);
+                unsafe { self.draw_shader(state, shader, &inputs) };
+                target.swap();
+            }
+        }
+    }
 
-                // Mouse uniforms
-                if let Some(mouse) = shader.locations.mouse {
-                    let x = state.mouse.pos.x;
-                    let y = state.mouse.pos.y;
+    /// Set all uniforms, bind the channel textures and render a full screen triangle
+    /// into the currently bound framebuffer.
+    unsafe fn draw_shader(
+        &self,
+        state: &mut AppState,
+        shader: &SkuggboxShader,
+        inputs: &[ChannelInput; CHANNEL_COUNT],
+    ) {
+        let gl = self.gl.as_ref();
 
-                    let left_mouse = if state.mouse.is_lmb_down { 1.0 } else { 0.0 };
-                    let right_mouse = if state.mouse.is_rmb_down { 1.0 } else { 0.0 };
+        // nothing has compiled yet, e.g. when starting with a broken shader
+        if shader.program.is_none() {
+            gl.clear(glow::COLOR_BUFFER_BIT);
+            return;
+        }
+
+        self.set_uniforms(state, shader, inputs);
+        self.draw_region(state, shader, true);
+    }
 
-                    gl.uniform_4_f32(Some(&mouse), x, y, left_mouse, right_mouse);
-                };
+    /// Render the full screen triangle into the currently bound framebuffer, only into the
+    /// render region when there is one
+    unsafe fn draw_region(&self, state: &AppState, shader: &SkuggboxShader, clear: bool) {
+        let gl = self.gl.as_ref();
 
-                if let Some(mouse_dir) = shader.locations.mouse_dir {
-                    gl.uniform_3_f32(
-                        Some(&mouse_dir),
-                        state.mouse.dir.x,
-                        state.mouse.dir.y,
-                        state.mouse.dir.z,
-                    );
+        // iResolution stays the whole frame, so the region looks the same as in a full render
+        let (width, height) = state.render_size();
+        let region = state
+            .render_region
+            .map(|region| region.pixels(width, height));
+        if let Some((x, y, width, height)) = region {
+            gl.enable(glow::SCISSOR_TEST);
+            gl.scissor(x, y, width, height);
+        }
+
+        // actually render
+        if clear {
+            gl.clear(glow::COLOR_BUFFER_BIT);
+            macros::check_for_gl_error!(gl, "clear");
+        }
+        match state.draw_mode {
+            DrawMode::Filled => {}
+            DrawMode::Wireframe => gl.polygon_mode(glow::FRONT_AND_BACK, glow::LINE),
+            DrawMode::Points => {
+                gl.polygon_mode(glow::FRONT_AND_BACK, glow::POINT);
+                gl.enable(glow::PROGRAM_POINT_SIZE);
+            }
+        }
+        gl.draw_arrays(glow::TRIANGLES, 0, shader.vertex_count());
+        macros::check_for_gl_error!(gl, "draw_arrays");
+        // the UI is drawn filled
+        if state.draw_mode != DrawMode::Filled {
+            gl.polygon_mode(glow::FRONT_AND_BACK, glow::FILL);
+            gl.disable(glow::PROGRAM_POINT_SIZE);
+        }
+
+        if region.is_some() {
+            gl.disable(glow::SCISSOR_TEST);
+        }
+    }
+
+    /// Blend the shader into the accumulation target with a weight of 1 / samples, so it holds
+    /// the running average of the samples since the last reset, and copy that into `output`,
+    /// or the screen when `output` is `None`
+    unsafe fn draw_accumulated(
+        &mut self,
+        state: &mut AppState,
+        shader: &SkuggboxShader,
+        inputs: &[ChannelInput; CHANNEL_COUNT],
+        output: Option<&Framebuffer>,
+    ) {
+        let gl = self.gl.clone();
+        let (width, height) = state.render_size();
+        let output_fbo = output.map(|output| output.fbo);
+
+        let target = match self.accumulation_target.take() {
+            Some(target) if target.width == width && target.height == height => target,
+            stale => {
+                // the samples of another size can't be averaged in, start on a new target
+                if let Some(stale) = stale {
+                    stale.delete(&gl);
+                }
+                match Framebuffer::with_format(&gl, width, height, TextureFormat::Rgba32F) {
+                    Ok(target) => target,
+                    Err(e) => {
+                        log::error!("Failed to create accumulation target: {}", e);
+                        state.accumulate = false;
+                        return;
+                    }
                 }
+            }
+        };
+
+        target.bind(&gl);
+        if shader.program.is_none() {
+            gl.clear(glow::COLOR_BUFFER_BIT);
+        } else {
+            let samples = state.next_sample();
+            self.set_uniforms(state, shader, inputs);
+            gl.enable(glow::BLEND);
+            gl.blend_func(glow::CONSTANT_ALPHA, glow::ONE_MINUS_CONSTANT_ALPHA);
+            gl.blend_color(0.0, 0.0, 0.0, 1.0 / samples as f32);
+            self.draw_region(state, shader, false);
+            gl.disable(glow::BLEND);
+        }
+
+        gl.bind_framebuffer(glow::READ_FRAMEBUFFER, Some(target.fbo));
+        gl.bind_framebuffer(glow::DRAW_FRAMEBUFFER, output_fbo);
+        gl.blit_framebuffer(
+            0,
+            0,
+            width,
+            height,
+            0,
+            0,
+            width,
+            height,
+            glow::COLOR_BUFFER_BIT,
+            glow::NEAREST,
+        );
+        gl.bind_framebuffer(glow::FRAMEBUFFER, output_fbo);
+        gl.viewport(0, 0, width, height);
+        macros::check_for_gl_error!(&gl, "draw_accumulated");
+
+        self.accumulation_target = Some(target);
+    }
+
+    /// Stretch `solo` over `target`, or the screen when `target` is `None`, in place of a shader
+    unsafe fn draw_solo(&self, state: &AppState, solo: &SoloTexture, target: Option<&Framebuffer>) {
+        let gl = self.gl.as_ref();
+        let (width, height) = state.render_size();
+        let target = target.map(|target| target.fbo);
 
-                if let Some(sb_camera_transform) = shader.locations.sb_camera_transform {
-                    let camera = state.camera.calculate_uniform_data();
-                    let f32_arr = camera.to_cols_array();
-                    gl.uniform_matrix_4_f32_slice(Some(&sb_camera_transform), false, &f32_arr);
+        gl.bind_framebuffer(glow::DRAW_FRAMEBUFFER, target);
+        gl.clear(glow::COLOR_BUFFER_BIT);
+        gl.bind_framebuffer(glow::READ_FRAMEBUFFER, Some(self.solo_fbo));
+        gl.framebuffer_texture_2d(
+            glow::READ_FRAMEBUFFER,
+            glow::COLOR_ATTACHMENT0,
+            glow::TEXTURE_2D,
+            Some(solo.texture),
+            0,
+        );
+        gl.blit_framebuffer(
+            0,
+            0,
+            solo.width,
+            solo.height,
+            0,
+            0,
+            width,
+            height,
+            glow::COLOR_BUFFER_BIT,
+            glow::LINEAR,
+        );
+        gl.framebuffer_texture_2d(
+            glow::READ_FRAMEBUFFER,
+            glow::COLOR_ATTACHMENT0,
+            glow::TEXTURE_2D,
+            None,
+            0,
+        );
+        gl.bind_framebuffer(glow::READ_FRAMEBUFFER, target);
+        macros::check_for_gl_error!(gl, "draw_solo");
+    }
+
+    /// Run a compute shader with one invocation per pixel of the render size, writing into
+    /// the compute target, and draw the result into the currently bound framebuffer.
+    unsafe fn draw_compute(
+        &mut self,
+        state: &mut AppState,
+        shader: &SkuggboxShader,
+        inputs: &[ChannelInput; CHANNEL_COUNT],
+    ) {
+        let gl = self.gl.clone();
+
+        if shader.program.is_none() {
+            gl.clear(glow::COLOR_BUFFER_BIT);
+            return;
+        }
+
+        let (width, height) = state.render_size();
+        match &mut self.compute_target {
+            Some(target) => target.ensure_size(&gl, width, height),
+            None => match ComputeTarget::new(&gl, width, height) {
+                Ok(target) => self.compute_target = Some(target),
+                Err(e) => {
+                    log::error!("Failed to create compute target: {}", e);
+                    return;
                 }
+            },
+        }
+
+        self.set_uniforms(state, shader, inputs);
+
+        if let Some(target) = &self.compute_target {
+            target.bind_image(&gl);
+            let (local_x, local_y) = shader.local_size();
+            gl.dispatch_compute(
+                (width as u32).div_ceil(local_x.max(1)),
+                (height as u32).div_ceil(local_y.max(1)),
+                1,
+            );
+            // make the writes visible to the display pass
+            gl.memory_barrier(glow::TEXTURE_FETCH_BARRIER_BIT);
+            macros::check_for_gl_error!(&gl, "dispatch_compute");
 
-                if let Some(cam_pos) = shader.locations.cam_pos {
-                    let pos = state.camera_pos;
-                    gl.uniform_3_f32(Some(&cam_pos), pos.x, pos.y, pos.z);
+            target.display(&gl);
+        }
+    }
+
+    /// Use the shader's program and set the built-in and custom uniforms and channel textures
+    unsafe fn set_uniforms(
+        &self,
+        state: &mut AppState,
+        shader: &SkuggboxShader,
+        inputs: &[ChannelInput; CHANNEL_COUNT],
+    ) {
+        let gl = self.gl.as_ref();
+
+        // kick shader to gpu
+        gl.use_program(shader.program);
+
+        // set uniforms
+        // the size actually rendered at, which differs from the window with a render scale
+        if let Some(resolution) = shader.locations.resolution {
+            let (width, height) = state.render_size();
+            // z is the pixel aspect ratio, pixels are always square
+            match shader.locations.resolution_type {
+                glow::FLOAT_VEC3 => {
+                    gl.uniform_3_f32(Some(&resolution), width as f32, height as f32, 1.0)
                 }
+                _ => gl.uniform_2_f32(Some(&resolution), width as f32, height as f32),
+            }
+        }
+
+        if let Some(time) = shader.locations.time {
+            gl.uniform_1_f32(Some(&time), state.playback_time)
+        }
 
-                if let Some(sb_color_a) = shader.locations.sb_color_a {
-                    let col = state.scene_vars.color_a;
-                    gl.uniform_3_f32(Some(&sb_color_a), col[0], col[1], col[2]);
+        if let Some(delta_time) = shader.locations.time_delta {
+            gl.uniform_1_f32(Some(&delta_time), state.delta_time)
+        }
+
+        if let Some(frame) = shader.locations.frame {
+            gl.uniform_1_i32(Some(&frame), state.frame_count as i32)
+        }
+
+        // wall clock date, updated even when playback is paused
+        if let Some(date) = shader.locations.date {
+            let [year, month, day, seconds] = local_date();
+            gl.uniform_4_f32(Some(&date), year, month, day, seconds)
+        }
+
+        if let Some(location) = shader.locations.pixel_ratio {
+            gl.uniform_1_f32(Some(&location), state.pixel_ratio);
+        }
+
+        // tempo synced uniforms follow the playback time, so they pause and seek with it
+        let beats = beat(state.playback_time, state.bpm);
+        if let Some(location) = shader.locations.beat {
+            gl.uniform_1_f32(Some(&location), beats);
+        }
+
+        if let Some(location) = shader.locations.bar {
+            gl.uniform_1_f32(Some(&location), beats / BEATS_PER_BAR);
+        }
+
+        if let Some(location) = shader.locations.audio_level {
+            gl.uniform_1_f32(Some(&location), state.audio_level);
+        }
+
+        if let Some(location) = shader.locations.key {
+            gl.uniform_1_f32(Some(&location), state.key_value);
+        }
+
+        if let Some(location) = shader.locations.sample_count {
+            gl.uniform_1_i32(Some(&location), state.sample_count as i32);
+        }
+
+        // Mouse uniforms, see `Mouse::shadertoy_uniform`
+        if let Some(mouse) = shader.locations.mouse {
+            let m = state.mouse_uniform();
+            gl.uniform_4_f32(Some(&mouse), m.x, m.y, m.z, m.w);
+        };
+
+        if let Some(mouse_dir) = shader.locations.mouse_dir {
+            gl.uniform_3_f32(
+                Some(&mouse_dir),
+                state.mouse.dir.x,
+                state.mouse.dir.y,
+                state.mouse.dir.z,
+            );
+        }
+
+        if let Some(mouse_delta) = shader.locations.mouse_delta {
+            let delta = state.mouse_delta_uniform();
+            gl.uniform_2_f32(Some(&mouse_delta), delta.x, delta.y);
+        }
+
+        if let Some(mouse_wheel) = shader.locations.mouse_wheel {
+            gl.uniform_1_f32(Some(&mouse_wheel), state.mouse.wheel);
+        }
+
+        let locations = &shader.locations;
+        if locations.sb_camera_transform.is_some()
+            || locations.sb_camera_position.is_some()
+            || locations.sb_camera_inv_transform.is_some()
+            || locations.cam_pos.is_some()
+        {
+            let camera = state.camera_transform();
+
+            if let Some(sb_camera_transform) = &locations.sb_camera_transform {
+                let f32_arr = camera.to_cols_array();
+                gl.uniform_matrix_4_f32_slice(Some(sb_camera_transform), false, &f32_arr);
+            }
+
+            // `iCamPos` is the older name of the same position
+            let pos = camera.w_axis;
+            for location in locations
+                .sb_camera_position
+                .iter()
+                .chain(&locations.cam_pos)
+            {
+                gl.uniform_3_f32(Some(location), pos.x, pos.y, pos.z);
+            }
+
+            if let Some(sb_camera_inv_transform) = &locations.sb_camera_inv_transform {
+                let f32_arr = camera.inverse().to_cols_array();
+                gl.uniform_matrix_4_f32_slice(Some(sb_camera_inv_transform), false, &f32_arr);
+            }
+        }
+
+        if let Some(sb_color_a) = shader.locations.sb_color_a {
+            let col = state.scene_vars.color_a;
+            gl.uniform_3_f32(Some(&sb_color_a), col[0], col[1], col[2]);
+        }
+
+        // uniforms declared in the shader and edited from the UI
+        for uniform in state.custom_uniforms.values() {
+            let location = match shader.locations.custom.get(&uniform.name) {
+                Some(location) => Some(location),
+                None => continue,
+            };
+            match &uniform.upload_value() {
+                Some(GLSLValue::Float(v)) => gl.uniform_1_f32(location, *v),
+                Some(GLSLValue::Vec2(v)) => gl.uniform_2_f32_slice(location, v),
+                Some(GLSLValue::Vec3(v)) => gl.uniform_3_f32_slice(location, v),
+                Some(GLSLValue::Vec4(v)) => gl.uniform_4_f32_slice(location, v),
+                Some(GLSLValue::Int(v)) => gl.uniform_1_i32(location, *v),
+                Some(GLSLValue::Bool(v)) => gl.uniform_1_i32(location, *v as i32),
+                None => {}
+            }
+        }
+
+        // texture channels
+        let mut channel_resolution = [0.0; CHANNEL_COUNT * 3];
+        let mut cube_map_bound = false;
+        for (index, input) in inputs.iter().enumerate() {
+            if let Some((texture, target, resolution)) = input {
+                // a cube map can't be sampled as a sampler2D or the other way around, a
+                // warning is logged when the shader is compiled
+                let declared = shader.locations.channel_types[index];
+                if declared == 0 || declared == sampler_type(*target) {
+                    gl.active_texture(glow::TEXTURE0 + index as u32);
+                    gl.bind_texture(*target, Some(*texture));
+                    channel_resolution[index * 3..index * 3 + 3].copy_from_slice(resolution);
+                    cube_map_bound |= *target == glow::TEXTURE_CUBE_MAP;
                 }
+            }
 
-                // actually render
-                gl.clear(glow::COLOR_BUFFER_BIT);
-                macros::check_for_gl_error!(&gl, "clear");
-                gl.draw_arrays(glow::TRIANGLE_STRIP, 0, 3);
-                macros::check_for_gl_error!(&gl, "draw_arrays");
+            if let Some(location) = &shader.locations.channels[index] {
+                gl.uniform_1_i32(Some(location), index as i32);
             }
         }
+        gl.active_texture(glow::TEXTURE0);
+        // filter cube maps across the edges of the faces, this makes the wrap modes irrelevant
+        match cube_map_bound {
+            true => gl.enable(glow::TEXTURE_CUBE_MAP_SEAMLESS),
+            false => gl.disable(glow::TEXTURE_CUBE_MAP_SEAMLESS),
+        }
+
+        if let Some(resolution) = shader.locations.channel_resolution {
+            gl.uniform_3_f32_slice(Some(&resolution), &channel_resolution);
+        }
+
+        // none of the channel sources has a clock of its own, they all follow playback
+        if let Some(channel_time) = shader.locations.channel_time {
+            gl.uniform_1_f32_slice(Some(&channel_time), &[state.playback_time; CHANNEL_COUNT]);
+        }
     }
 }
+
+/// The texture picked with `Action::SoloChannel`, if it's still there
+fn solo_texture(state: &AppState, shader_service: &ShaderService) -> Option<SoloTexture> {
+    let index = state.solo_channel?;
+    shader_service.solo_textures().into_iter().nth(index)
+}
+
+/// Collect the textures for each `iChannelN`. Buffers from other passes take precedence over
+/// images loaded on the same channel.
+fn channel_inputs(
+    shader_service: &ShaderService,
+    pass: Option<&Pass>,
+) -> [ChannelInput; CHANNEL_COUNT] {
+    std::array::from_fn(|index| {
+        let buffer = pass
+            .and_then(|pass| pass.inputs[index])
+            .and_then(|(source, output)| {
+                let front = shader_service.passes[source].target.as_ref()?.front();
+                Some((
+                    *front.textures.get(output)?,
+                    glow::TEXTURE_2D,
+                    [front.width as f32, front.height as f32, 1.0],
+                ))
+            });
+
+        buffer.or_else(|| {
+            shader_service.channels[index]
+                .as_ref()
+                .map(|channel| (channel.texture, channel.target, channel.resolution()))
+        })
+    })
+}
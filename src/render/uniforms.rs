@@ -1,9 +1,143 @@
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
-#[derive(Debug, PartialEq, Eq)]
+/// Uniforms set by skuggbox itself. These are never treated as custom uniforms.
+pub const BUILTIN_UNIFORMS: &[&str] = &[
+    "iResolution",
+    "iTime",
+    "iTimeDelta",
+    "iFrame",
+    "iDate",
+    "iPixelRatio",
+    "iBeat",
+    "iBar",
+    "iAudioLevel",
+    "iSampleCount",
+    "iKey",
+    "iMouse",
+    "iMouseDir",
+    "iMouseDelta",
+    "iMouseWheel",
+    "iCamPos",
+    "iChannelResolution",
+    "iChannelTime",
+    "sbCameraTransform",
+    "sbCameraPosition",
+    "sbCameraInvTransform",
+    "sbColorA",
+];
+
+/// Sidecar file the custom uniform values of a shader are saved to,
+/// `shader.glsl` -> `shader.uniforms.json`
+pub fn uniforms_file(shader_path: &Path) -> PathBuf {
+    shader_path.with_extension("uniforms.json")
+}
+
+/// The components of every custom uniform set from skuggbox, keyed by name
+pub type UniformValues = BTreeMap<String, Vec<f32>>;
+
+pub fn uniform_values(uniforms: &BTreeMap<String, Uniform>) -> UniformValues {
+    uniforms
+        .iter()
+        .filter_map(|(name, uniform)| {
+            let mut value = uniform.value.clone()?;
+            Some((name.clone(), value.components_mut().to_vec()))
+        })
+        .collect()
+}
+
+/// Set the uniforms to `values`. Uniforms that were removed or changed type since the values
+/// were taken are skipped.
+pub fn apply_uniform_values(values: &UniformValues, uniforms: &mut BTreeMap<String, Uniform>) {
+    for (name, saved) in values {
+        let value = uniforms
+            .get_mut(name)
+            .and_then(|uniform| uniform.value.as_mut());
+        if let Some(value) = value {
+            let components = value.components_mut();
+            if components.len() == saved.len() {
+                components.copy_from_slice(saved);
+            }
+        }
+    }
+}
+
+/// Save the values of the custom uniforms as `{ "name": [components] }`
+pub fn save_uniform_values(
+    path: &Path,
+    uniforms: &BTreeMap<String, Uniform>,
+) -> Result<(), String> {
+    let json =
+        serde_json::to_string_pretty(&uniform_values(uniforms)).map_err(|e| e.to_string())?;
+    std::fs::write(path, json).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Restore values saved with `save_uniform_values`, see `apply_uniform_values`
+pub fn load_uniform_values(
+    path: &Path,
+    uniforms: &mut BTreeMap<String, Uniform>,
+) -> Result<(), String> {
+    let json = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let values: UniformValues =
+        serde_json::from_str(&json).map_err(|e| format!("{}: {}", path.display(), e))?;
+    apply_uniform_values(&values, uniforms);
+    Ok(())
+}
+
+/// One line of `--list-uniforms`: the name and type of an active uniform and how skuggbox
+/// sets it
+pub fn describe_uniform(
+    uniform: &glow::ActiveUniform,
+    custom_uniforms: &BTreeMap<String, Uniform>,
+) -> String {
+    // arrays are reported by their first element
+    let name = uniform.name.trim_end_matches("[0]");
+    let driven_by = if BUILTIN_UNIFORMS.contains(&name) {
+        "built-in"
+    } else if name.starts_with("iChannel") {
+        "channel texture"
+    } else {
+        match custom_uniforms.get(name).and_then(|u| u.value.as_ref()) {
+            Some(_) => "custom, slider in the UI",
+            None => "not set by skuggbox",
+        }
+    };
+
+    let mut glsl_type = gl_type_name(uniform.utype).to_string();
+    if uniform.size > 1 {
+        glsl_type = format!("{}[{}]", glsl_type, uniform.size);
+    }
+    format!("{:<24} {:<14} {}", name, glsl_type, driven_by)
+}
+
+/// GLSL name of a uniform type reported by `glGetActiveUniform`
+fn gl_type_name(utype: u32) -> &'static str {
+    match utype {
+        glow::FLOAT => "float",
+        glow::FLOAT_VEC2 => "vec2",
+        glow::FLOAT_VEC3 => "vec3",
+        glow::FLOAT_VEC4 => "vec4",
+        glow::INT => "int",
+        glow::INT_VEC2 => "ivec2",
+        glow::INT_VEC3 => "ivec3",
+        glow::INT_VEC4 => "ivec4",
+        glow::UNSIGNED_INT => "uint",
+        glow::BOOL => "bool",
+        glow::FLOAT_MAT2 => "mat2",
+        glow::FLOAT_MAT3 => "mat3",
+        glow::FLOAT_MAT4 => "mat4",
+        glow::SAMPLER_2D => "sampler2D",
+        glow::SAMPLER_3D => "sampler3D",
+        glow::SAMPLER_CUBE => "samplerCube",
+        glow::IMAGE_2D => "image2D",
+        _ => "other",
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 #[allow(dead_code)]
 pub enum GLSLType {
     // primitives
@@ -35,19 +169,85 @@ impl FromStr for GLSLType {
 }
 
 /// hmm... might be overkill
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum GLSLValue {
     Int(i32),
     Float(f32),
     Bool(bool),
+    Vec2([f32; 2]),
+    Vec3([f32; 3]),
+    Vec4([f32; 4]),
 }
 
-#[derive(Debug)]
-#[allow(dead_code)]
+impl GLSLValue {
+    /// Zero value of a float based type
+    pub fn zero(glsl_type: &GLSLType) -> Option<Self> {
+        match glsl_type {
+            GLSLType::Float => Some(GLSLValue::Float(0.0)),
+            GLSLType::Vec2 => Some(GLSLValue::Vec2([0.0; 2])),
+            GLSLType::Vec3 => Some(GLSLValue::Vec3([0.0; 3])),
+            GLSLType::Vec4 => Some(GLSLValue::Vec4([0.0; 4])),
+            _ => None,
+        }
+    }
+
+    /// The float components, empty for non float types
+    pub fn components_mut(&mut self) -> &mut [f32] {
+        match self {
+            GLSLValue::Float(value) => std::slice::from_mut(value),
+            GLSLValue::Vec2(value) => value,
+            GLSLValue::Vec3(value) => value,
+            GLSLValue::Vec4(value) => value,
+            GLSLValue::Int(_) | GLSLValue::Bool(_) => &mut [],
+        }
+    }
+}
+
+/// How a color picked in the UI is uploaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// Upload the picked sRGB values as they are
+    Srgb,
+    /// Convert the picked sRGB values to linear before uploading
+    Linear,
+}
+
+#[derive(Debug, Clone)]
 pub struct Uniform {
-    name: String,
-    glsl_type: GLSLType,
-    value: Option<GLSLValue>,
+    pub name: String,
+    pub glsl_type: GLSLType,
+    pub value: Option<GLSLValue>,
+    /// Slider range, read from a trailing `// range <min>..<max>` comment
+    pub range: Option<(f32, f32)>,
+    /// Edited with a color picker instead of sliders, see `color_space`
+    pub color: Option<ColorSpace>,
+}
+
+impl Uniform {
+    /// Lower and upper bound for the UI, defaults to 0..1
+    pub fn slider_range(&self) -> (f32, f32) {
+        self.range.unwrap_or((0.0, 1.0))
+    }
+
+    /// The value to send to the shader, with colors converted to their color space
+    pub fn upload_value(&self) -> Option<GLSLValue> {
+        let mut value = self.value.clone()?;
+        if self.color == Some(ColorSpace::Linear) {
+            // alpha is never gamma encoded
+            for component in value.components_mut().iter_mut().take(3) {
+                *component = srgb_to_linear(*component);
+            }
+        }
+        Some(value)
+    }
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
 }
 
 #[derive(Debug)]
@@ -77,6 +277,72 @@ pub fn read_uniforms(shader_path: PathBuf) -> Vec<Uniform> {
     uniforms
 }
 
+/// Find the float and vector uniforms in pre-processed shader source which can be edited from
+/// the UI. Built-in uniforms are skipped and every uniform starts out at zero, or at the lower
+/// bound of its range when zero is outside of it.
+pub fn custom_uniforms(source: &str) -> Vec<Uniform> {
+    let mut uniforms: Vec<Uniform> = vec![];
+
+    for line in source.lines() {
+        let mut uniform = match extract_uniform(line.to_string()) {
+            Ok(uniform) => uniform,
+            Err(_) => continue,
+        };
+
+        if BUILTIN_UNIFORMS.contains(&uniform.name.as_str())
+            || uniform.name.contains('[')
+            || uniforms.iter().any(|u| u.name == uniform.name)
+        {
+            continue;
+        }
+
+        uniform.range = extract_range(line);
+        uniform.color = color_space(&uniform, line);
+        uniform.value = GLSLValue::zero(&uniform.glsl_type);
+        let (min, max) = uniform.slider_range();
+        if let Some(value) = &mut uniform.value {
+            for component in value.components_mut() {
+                *component = component.clamp(min, max);
+            }
+            uniforms.push(uniform);
+        }
+    }
+
+    uniforms
+}
+
+/// `vec3` and `vec4` uniforms named `*color`/`*col`, or annotated with `// color`, are colors.
+/// Adding `linear` to the comment, e.g. `// color linear`, uploads them as linear.
+fn color_space(uniform: &Uniform, line: &str) -> Option<ColorSpace> {
+    if !matches!(uniform.glsl_type, GLSLType::Vec3 | GLSLType::Vec4) {
+        return None;
+    }
+
+    let words: Vec<&str> = line
+        .split_once("//")
+        .map(|(_, comment)| comment.split_whitespace().collect())
+        .unwrap_or_default();
+    let name = uniform.name.to_lowercase();
+
+    if words.contains(&"color") || name.ends_with("color") || name.ends_with("col") {
+        match words.contains(&"linear") {
+            true => Some(ColorSpace::Linear),
+            false => Some(ColorSpace::Srgb),
+        }
+    } else {
+        None
+    }
+}
+
+/// Parse `// range <min>..<max>` at the end of a line
+fn extract_range(line: &str) -> Option<(f32, f32)> {
+    let (_, comment) = line.split_once("//")?;
+    let range = comment.trim().strip_prefix("range")?.trim();
+    let (min, max) = range.split_once("..")?;
+    let (min, max) = (min.trim().parse().ok()?, max.trim().parse().ok()?);
+    (min < max).then_some((min, max))
+}
+
 fn is_uniform(line: String) -> bool {
     line.trim().starts_with("uniform")
 }
@@ -109,14 +375,20 @@ fn extract_uniform(line: String) -> Result<Uniform, UniformError> {
         name: uniform_name,
         glsl_type: uniform_type,
         value: None,
+        range: None,
+        color: None,
     })
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
     use std::str::FromStr;
 
-    use super::{extract_uniform, is_uniform, GLSLType};
+    use super::{
+        custom_uniforms, describe_uniform, extract_range, extract_uniform, is_uniform,
+        load_uniform_values, save_uniform_values, ColorSpace, GLSLType, GLSLValue,
+    };
 
     #[test]
     fn is_uniform_line() {
@@ -162,4 +434,122 @@ mod tests {
         assert_eq!(GLSLType::from_str("vec3").unwrap(), GLSLType::Vec3);
         assert_eq!(GLSLType::from_str("vec4").unwrap(), GLSLType::Vec4);
     }
+
+    #[test]
+    fn extract_range_from_comment() {
+        assert_eq!(
+            extract_range("uniform float a; // range 0..1"),
+            Some((0.0, 1.0))
+        );
+        assert_eq!(
+            extract_range("uniform float a; //range -2.5 .. 4"),
+            Some((-2.5, 4.0))
+        );
+        assert_eq!(extract_range("uniform float a; // range 1..0"), None);
+        assert_eq!(extract_range("uniform float a;"), None);
+    }
+
+    #[test]
+    fn custom_uniforms_from_source() {
+        let source = "
+            uniform vec2 iResolution;
+            uniform sampler2D iChannel0;
+            uniform float speed; // range 1..4
+            uniform vec3 offset;
+            uniform float speed;
+        ";
+        let uniforms = custom_uniforms(source);
+
+        assert_eq!(uniforms.len(), 2);
+        assert_eq!(uniforms[0].name, "speed");
+        assert_eq!(uniforms[0].value, Some(GLSLValue::Float(1.0)));
+        assert_eq!(uniforms[1].name, "offset");
+        assert_eq!(uniforms[1].glsl_type, GLSLType::Vec3);
+        assert_eq!(uniforms[1].value, Some(GLSLValue::Vec3([0.0; 3])));
+    }
+
+    #[test]
+    fn color_uniforms() {
+        let source = "
+            uniform vec3 baseColor;
+            uniform vec4 skyCol;
+            uniform vec3 tint; // color linear
+            uniform vec3 offset;
+            uniform float bgColor;
+        ";
+        let uniforms = custom_uniforms(source);
+        let colors: Vec<_> = uniforms.iter().map(|u| u.color).collect();
+        assert_eq!(
+            colors,
+            [
+                Some(ColorSpace::Srgb),
+                Some(ColorSpace::Srgb),
+                Some(ColorSpace::Linear),
+                None,
+                None
+            ]
+        );
+
+        let mut tint = uniforms[2].clone();
+        tint.value = Some(GLSLValue::Vec3([1.0, 0.5, 0.0]));
+        match tint.upload_value() {
+            Some(GLSLValue::Vec3([r, g, b])) => {
+                assert_eq!(r, 1.0);
+                assert!((g - 0.214).abs() < 0.001);
+                assert_eq!(b, 0.0);
+            }
+            value => panic!("unexpected value {:?}", value),
+        }
+    }
+
+    #[test]
+    fn save_and_load_uniform_values() {
+        let uniforms = |source: &str| -> BTreeMap<_, _> {
+            custom_uniforms(source)
+                .into_iter()
+                .map(|uniform| (uniform.name.clone(), uniform))
+                .collect()
+        };
+        let mut saved = uniforms("uniform float speed;\nuniform vec2 offset;");
+        saved.get_mut("speed").unwrap().value = Some(GLSLValue::Float(2.5));
+        saved.get_mut("offset").unwrap().value = Some(GLSLValue::Vec2([0.25, 0.5]));
+
+        let file = std::env::temp_dir().join(format!(
+            "skuggbox_uniforms_test-{}.json",
+            std::process::id()
+        ));
+        save_uniform_values(&file, &saved).unwrap();
+
+        // offset became a vec3 since it was saved
+        let mut loaded = uniforms("uniform float speed;\nuniform vec3 offset;");
+        load_uniform_values(&file, &mut loaded).unwrap();
+        std::fs::remove_file(&file).unwrap();
+
+        assert_eq!(loaded["speed"].value, Some(GLSLValue::Float(2.5)));
+        assert_eq!(loaded["offset"].value, Some(GLSLValue::Vec3([0.0; 3])));
+    }
+
+    #[test]
+    fn describe_active_uniforms() {
+        let custom: BTreeMap<_, _> = custom_uniforms("uniform float speed;\nuniform int mode;")
+            .into_iter()
+            .map(|uniform| (uniform.name.clone(), uniform))
+            .collect();
+        let describe = |name: &str, utype: u32, size: i32| {
+            let uniform = glow::ActiveUniform {
+                size,
+                utype,
+                name: name.to_string(),
+            };
+            describe_uniform(&uniform, &custom)
+        };
+
+        let line = describe("iTime", glow::FLOAT, 1);
+        assert!(line.starts_with("iTime "));
+        assert!(line.ends_with(" float          built-in"));
+        assert!(describe("iChannelTime[0]", glow::FLOAT, 4).contains(" float[4] "));
+        assert!(describe("iChannel0", glow::SAMPLER_2D, 1).ends_with("channel texture"));
+        assert!(describe("speed", glow::FLOAT, 1).ends_with("slider in the UI"));
+        assert!(describe("mode", glow::INT, 1).ends_with("not set by skuggbox"));
+    }
 }
@@ -0,0 +1,635 @@
+use glam::Vec3;
+use glow::HasContext;
+use image::DynamicImage;
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::f32::consts::PI;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::ShaderError;
+
+/// Number of texture channels exposed to the shaders as `iChannel0` to `iChannel3`
+pub const CHANNEL_COUNT: usize = 4;
+
+/// Storage format of a channel texture or a pass buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureFormat {
+    /// 8 bits per component, values are clamped to 0..1
+    Rgba8,
+    /// Half float, enough for HDR images and most feedback effects
+    Rgba16F,
+    /// Full float, for buffers that store positions or accumulate over many frames
+    Rgba32F,
+}
+
+impl TextureFormat {
+    pub fn internal_format(&self) -> u32 {
+        match self {
+            TextureFormat::Rgba8 => glow::RGBA8,
+            TextureFormat::Rgba16F => glow::RGBA16F,
+            TextureFormat::Rgba32F => glow::RGBA32F,
+        }
+    }
+
+    pub fn is_float(&self) -> bool {
+        *self != TextureFormat::Rgba8
+    }
+
+    /// The format images are loaded in when none is given, floats for HDR and EXR images
+    pub fn for_file(path: &Path) -> Self {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default()
+            .to_lowercase();
+        match extension.as_str() {
+            "hdr" | "exr" => TextureFormat::Rgba16F,
+            _ => TextureFormat::Rgba8,
+        }
+    }
+
+    /// Pixels of `image` as RGBA with the data type to upload them with
+    fn pixels(&self, image: DynamicImage) -> (Vec<u8>, u32) {
+        match self {
+            TextureFormat::Rgba8 => (image.into_rgba8().into_raw(), glow::UNSIGNED_BYTE),
+            _ => (
+                bytemuck::cast_slice(image.into_rgba32f().as_raw()).to_vec(),
+                glow::FLOAT,
+            ),
+        }
+    }
+}
+
+impl FromStr for TextureFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "rgba8" => Ok(TextureFormat::Rgba8),
+            "rgba16f" => Ok(TextureFormat::Rgba16F),
+            "rgba32f" => Ok(TextureFormat::Rgba32F),
+            _ => Err(format!(
+                "unknown texture format '{}', expected rgba8, rgba16f or rgba32f",
+                s
+            )),
+        }
+    }
+}
+
+/// Texture filtering, `Linear` interpolates between texels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    Nearest,
+    Linear,
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest" => Ok(Filter::Nearest),
+            "linear" => Ok(Filter::Linear),
+            _ => Err(format!(
+                "unknown filter '{}', expected nearest or linear",
+                s
+            )),
+        }
+    }
+}
+
+/// What happens when a texture is sampled outside of 0..1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wrap {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+impl Wrap {
+    fn gl_wrap(&self) -> u32 {
+        match self {
+            Wrap::Repeat => glow::REPEAT,
+            Wrap::Clamp => glow::CLAMP_TO_EDGE,
+            Wrap::Mirror => glow::MIRRORED_REPEAT,
+        }
+    }
+}
+
+impl FromStr for Wrap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "repeat" => Ok(Wrap::Repeat),
+            "clamp" => Ok(Wrap::Clamp),
+            "mirror" => Ok(Wrap::Mirror),
+            _ => Err(format!(
+                "unknown wrap mode '{}', expected repeat, clamp or mirror",
+                s
+            )),
+        }
+    }
+}
+
+/// How a channel texture is sampled, like the sampler settings of a Shadertoy channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SamplerSettings {
+    pub min_filter: Filter,
+    pub mag_filter: Filter,
+    pub wrap_s: Wrap,
+    pub wrap_t: Wrap,
+    /// Generate mipmaps and sample between them when minifying
+    pub mipmap: bool,
+}
+
+impl Default for SamplerSettings {
+    fn default() -> Self {
+        Self {
+            min_filter: Filter::Linear,
+            mag_filter: Filter::Linear,
+            wrap_s: Wrap::Repeat,
+            wrap_t: Wrap::Repeat,
+            mipmap: false,
+        }
+    }
+}
+
+impl SamplerSettings {
+    fn gl_min_filter(&self) -> u32 {
+        match (self.min_filter, self.mipmap) {
+            (Filter::Nearest, false) => glow::NEAREST,
+            (Filter::Linear, false) => glow::LINEAR,
+            (Filter::Nearest, true) => glow::NEAREST_MIPMAP_NEAREST,
+            (Filter::Linear, true) => glow::LINEAR_MIPMAP_LINEAR,
+        }
+    }
+
+    fn gl_mag_filter(&self) -> u32 {
+        match self.mag_filter {
+            Filter::Nearest => glow::NEAREST,
+            Filter::Linear => glow::LINEAR,
+        }
+    }
+
+    /// Set the filtering and wrapping of `texture` and generate its mipmaps if enabled
+    pub fn apply(&self, gl: &glow::Context, target: u32, texture: glow::Texture) {
+        unsafe {
+            gl.bind_texture(target, Some(texture));
+            gl.tex_parameter_i32(
+                target,
+                glow::TEXTURE_MIN_FILTER,
+                self.gl_min_filter() as i32,
+            );
+            gl.tex_parameter_i32(
+                target,
+                glow::TEXTURE_MAG_FILTER,
+                self.gl_mag_filter() as i32,
+            );
+            gl.tex_parameter_i32(target, glow::TEXTURE_WRAP_S, self.wrap_s.gl_wrap() as i32);
+            gl.tex_parameter_i32(target, glow::TEXTURE_WRAP_T, self.wrap_t.gl_wrap() as i32);
+            if self.mipmap {
+                gl.generate_mipmap(target);
+            }
+            gl.bind_texture(target, None);
+            macros::check_for_gl_error!(gl, "channel_sampler_settings");
+        }
+    }
+
+    /// Parse a `key=value` channel option, returns `Ok(false)` for keys that aren't sampler
+    /// settings
+    fn set(&mut self, key: &str, value: &str) -> Result<bool, String> {
+        match key {
+            "filter" => {
+                self.min_filter = value.parse()?;
+                self.mag_filter = self.min_filter;
+            }
+            "min" => self.min_filter = value.parse()?,
+            "mag" => self.mag_filter = value.parse()?,
+            "wrap" => {
+                self.wrap_s = value.parse()?;
+                self.wrap_t = self.wrap_s;
+            }
+            "wrap-s" => self.wrap_s = value.parse()?,
+            "wrap-t" => self.wrap_t = value.parse()?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+/// A channel as given on the command line or in the config file: a file followed by
+/// comma separated options, e.g. `studio.exr,rgba32f,cube` or `noise.png,filter=nearest,mipmap`.
+/// Commas in the file name are kept as long as the part after them has a `.` or `/`, like the
+/// extension of the file.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct ChannelConfig {
+    pub path: PathBuf,
+    /// Defaults to `TextureFormat::for_file`
+    pub format: Option<TextureFormat>,
+    /// Project an equirectangular panorama onto a cube map
+    pub cube: bool,
+    pub sampler: SamplerSettings,
+}
+
+impl ChannelConfig {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            format: None,
+            cube: false,
+            sampler: SamplerSettings::default(),
+        }
+    }
+
+    pub fn format(&self) -> TextureFormat {
+        self.format
+            .unwrap_or_else(|| TextureFormat::for_file(&self.path))
+    }
+}
+
+impl FromStr for ChannelConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(',').collect();
+        // options are the trailing parts that can't be part of a path
+        let options = parts[1..]
+            .iter()
+            .rev()
+            .take_while(|part| !part.contains(['.', '/', '\\']))
+            .count();
+        let (path, options) = parts.split_at(parts.len() - options);
+
+        let mut config = ChannelConfig::new(path.join(","));
+        for option in options {
+            match option.trim().split_once('=') {
+                Some((key, value)) => {
+                    if !config.sampler.set(key, value)? {
+                        return Err(format!("unknown channel option '{}'", key));
+                    }
+                }
+                None => match option.trim() {
+                    "cube" => config.cube = true,
+                    "mipmap" => config.sampler.mipmap = true,
+                    format => config.format = Some(format.parse()?),
+                },
+            }
+        }
+        Ok(config)
+    }
+}
+
+impl TryFrom<String> for ChannelConfig {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Where the content of a channel texture comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChannelSource {
+    /// Image file loaded from disk
+    Image(PathBuf),
+    /// Cube map from a directory of six faces or an equirectangular panorama
+    CubeMap(PathBuf),
+    /// Spectrum and waveform of the audio input, see `AudioInput`
+    Audio,
+    /// Keyboard state, see `KeyboardState`
+    Keyboard,
+}
+
+/// An OpenGL texture which is bound to one of the `iChannelN` samplers
+pub struct Channel {
+    pub texture: glow::Texture,
+    /// `TEXTURE_2D` for `sampler2D` or `TEXTURE_CUBE_MAP` for `samplerCube`
+    pub target: u32,
+    pub width: u32,
+    pub height: u32,
+    pub source: ChannelSource,
+}
+
+/// File names of the cube map faces in a directory, in the order of the OpenGL face targets
+/// starting at `TEXTURE_CUBE_MAP_POSITIVE_X`. Any image extension works, e.g. `px.png`.
+pub const CUBE_MAP_FACES: [&str; 6] = ["px", "nx", "py", "ny", "pz", "nz"];
+
+impl Channel {
+    /// Load a channel texture from disk. Directories are loaded as cube maps from the six
+    /// faces in `CUBE_MAP_FACES`, files with the `cube` option as cube maps from an
+    /// equirectangular panorama and everything else as a 2D image.
+    pub fn from_file(gl: &glow::Context, config: &ChannelConfig) -> Result<Self, ShaderError> {
+        let path = config.path.as_path();
+        let format = config.format();
+        let channel = if path.is_dir() {
+            Self::cube_map_from_faces(gl, path, format)?
+        } else if config.cube {
+            Self::cube_map_from_panorama(gl, path, format)?
+        } else {
+            Self::image(gl, path, format)?
+        };
+        config.sampler.apply(gl, channel.target, channel.texture);
+
+        Ok(channel)
+    }
+
+    /// Load a PNG/JPG/HDR/EXR image from disk into a RGBA texture
+    fn image(gl: &glow::Context, path: &Path, format: TextureFormat) -> Result<Self, ShaderError> {
+        let image = open_image(path)?;
+
+        // OpenGL expects the first row of pixels to be the bottom one
+        let image = image.flipv();
+        let (width, height) = (image.width(), image.height());
+        let (pixels, data_type) = format.pixels(image);
+
+        let texture = unsafe {
+            let texture = gl
+                .create_texture()
+                .map_err(|error| ShaderError::TextureError { error })?;
+
+            gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+            gl.tex_image_2d(
+                glow::TEXTURE_2D,
+                0,
+                format.internal_format() as i32,
+                width as i32,
+                height as i32,
+                0,
+                glow::RGBA,
+                data_type,
+                Some(&pixels),
+            );
+            macros::check_for_gl_error!(gl, "channel_tex_image_2d");
+            gl.bind_texture(glow::TEXTURE_2D, None);
+
+            texture
+        };
+
+        log::debug!(
+            "Loaded channel {:?} ({}x{} {:?})",
+            path,
+            width,
+            height,
+            format
+        );
+
+        Ok(Self {
+            texture,
+            target: glow::TEXTURE_2D,
+            width,
+            height,
+            source: ChannelSource::Image(path.to_owned()),
+        })
+    }
+
+    /// Load the six square faces of a cube map from `dir`
+    fn cube_map_from_faces(
+        gl: &glow::Context,
+        dir: &Path,
+        format: TextureFormat,
+    ) -> Result<Self, ShaderError> {
+        let mut size = 0;
+        let mut faces = vec![];
+        let mut data_type = glow::UNSIGNED_BYTE;
+        for name in CUBE_MAP_FACES {
+            let path = find_face(dir, name).ok_or_else(|| ShaderError::FileError {
+                error: format!("{:?} has no cube map face named {}", dir, name),
+            })?;
+            // cube map faces start at the top row, no flipping needed
+            let face = open_image(&path)?;
+
+            if face.width() != face.height() || (size != 0 && face.width() != size) {
+                return Err(ShaderError::TextureError {
+                    error: format!(
+                        "cube map faces in {:?} must be square and the same size",
+                        dir
+                    ),
+                });
+            }
+            size = face.width();
+            let (pixels, face_data_type) = format.pixels(face);
+            data_type = face_data_type;
+            faces.push(pixels);
+        }
+
+        let faces: Vec<&[u8]> = faces.iter().map(|face| face.as_slice()).collect();
+        let texture = create_cube_map(gl, size, format.internal_format(), data_type, &faces)?;
+        log::debug!("Loaded cube map {:?} ({}x{})", dir, size, size);
+
+        Ok(Self {
+            texture,
+            target: glow::TEXTURE_CUBE_MAP,
+            width: size,
+            height: size,
+            source: ChannelSource::CubeMap(dir.to_owned()),
+        })
+    }
+
+    /// Project an equirectangular panorama onto the faces of a cube map
+    fn cube_map_from_panorama(
+        gl: &glow::Context,
+        path: &Path,
+        format: TextureFormat,
+    ) -> Result<Self, ShaderError> {
+        let panorama = open_image(path)?.into_rgba32f();
+
+        // a quarter of the panorama keeps about the same number of pixels around the horizon
+        let size = (panorama.width() / 4).max(1);
+        let faces: Vec<Vec<f32>> = (0..CUBE_MAP_FACES.len())
+            .map(|face| {
+                let mut pixels = Vec::with_capacity((size * size * 4) as usize);
+                for y in 0..size {
+                    for x in 0..size {
+                        let s = 2.0 * (x as f32 + 0.5) / size as f32 - 1.0;
+                        let t = 2.0 * (y as f32 + 0.5) / size as f32 - 1.0;
+                        let (u, v) = panorama_uv(cube_map_direction(face, s, t));
+                        let px = ((u * panorama.width() as f32) as u32).min(panorama.width() - 1);
+                        let py = ((v * panorama.height() as f32) as u32).min(panorama.height() - 1);
+                        pixels.extend(panorama.get_pixel(px, py).0);
+                    }
+                }
+                pixels
+            })
+            .collect();
+
+        let faces: Vec<&[u8]> = faces
+            .iter()
+            .map(|face| bytemuck::cast_slice(face))
+            .collect();
+        let texture = create_cube_map(gl, size, format.internal_format(), glow::FLOAT, &faces)?;
+        log::debug!("Loaded panorama {:?} as {}x{} cube map", path, size, size);
+
+        Ok(Self {
+            texture,
+            target: glow::TEXTURE_CUBE_MAP,
+            width: size,
+            height: size,
+            source: ChannelSource::CubeMap(path.to_owned()),
+        })
+    }
+
+    /// Size of the texture as it's exposed through `iChannelResolution`
+    pub fn resolution(&self) -> [f32; 3] {
+        [self.width as f32, self.height as f32, 1.0]
+    }
+
+    pub fn delete(&self, gl: &glow::Context) {
+        unsafe { gl.delete_texture(self.texture) };
+    }
+}
+
+/// The `sampler` type a channel of this texture target is declared as in GLSL
+pub fn sampler_type(target: u32) -> u32 {
+    match target {
+        glow::TEXTURE_CUBE_MAP => glow::SAMPLER_CUBE,
+        _ => glow::SAMPLER_2D,
+    }
+}
+
+fn open_image(path: &Path) -> Result<DynamicImage, ShaderError> {
+    image::open(path).map_err(|e| ShaderError::FileError {
+        error: format!("Err: {:?}, {:?} could not be loaded as an image", e, path),
+    })
+}
+
+fn find_face(dir: &Path, name: &str) -> Option<PathBuf> {
+    std::fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|path| {
+            path.is_file() && path.file_stem().and_then(|stem| stem.to_str()) == Some(name)
+        })
+}
+
+fn create_cube_map(
+    gl: &glow::Context,
+    size: u32,
+    internal_format: u32,
+    data_type: u32,
+    faces: &[&[u8]],
+) -> Result<glow::Texture, ShaderError> {
+    unsafe {
+        let texture = gl
+            .create_texture()
+            .map_err(|error| ShaderError::TextureError { error })?;
+
+        gl.bind_texture(glow::TEXTURE_CUBE_MAP, Some(texture));
+        for (index, face) in faces.iter().enumerate() {
+            gl.tex_image_2d(
+                glow::TEXTURE_CUBE_MAP_POSITIVE_X + index as u32,
+                0,
+                internal_format as i32,
+                size as i32,
+                size as i32,
+                0,
+                glow::RGBA,
+                data_type,
+                Some(face),
+            );
+        }
+        macros::check_for_gl_error!(gl, "cube_map_tex_image_2d");
+
+        gl.bind_texture(glow::TEXTURE_CUBE_MAP, None);
+
+        Ok(texture)
+    }
+}
+
+/// Direction through the texel at `s`, `t` in -1..1 of a cube map face, following the face
+/// layout of the OpenGL spec
+fn cube_map_direction(face: usize, s: f32, t: f32) -> Vec3 {
+    let direction = match face {
+        0 => Vec3::new(1.0, -t, -s),
+        1 => Vec3::new(-1.0, -t, s),
+        2 => Vec3::new(s, 1.0, t),
+        3 => Vec3::new(s, -1.0, -t),
+        4 => Vec3::new(s, -t, 1.0),
+        _ => Vec3::new(-s, -t, -1.0),
+    };
+    direction.normalize()
+}
+
+/// Position in 0..1 of a direction in an equirectangular panorama, with the top row up and
+/// the center of the image towards -z
+fn panorama_uv(direction: Vec3) -> (f32, f32) {
+    let u = 0.5 + direction.x.atan2(-direction.z) / (2.0 * PI);
+    let v = direction.y.clamp(-1.0, 1.0).acos() / PI;
+    (u, v)
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use std::path::PathBuf;
+
+    use super::{
+        cube_map_direction, panorama_uv, ChannelConfig, Filter, SamplerSettings, TextureFormat,
+        Wrap,
+    };
+
+    #[test]
+    fn parse_channel_config() {
+        let config: ChannelConfig = "textures/noise.png".parse().unwrap();
+        assert_eq!(config, ChannelConfig::new("textures/noise.png"));
+        assert_eq!(config.format(), TextureFormat::Rgba8);
+
+        let config: ChannelConfig = "studio.HDR,cube".parse().unwrap();
+        assert_eq!(config.path, PathBuf::from("studio.HDR"));
+        assert!(config.cube);
+        assert_eq!(config.format(), TextureFormat::Rgba16F);
+
+        let config: ChannelConfig = "feedback.png,rgba32f".parse().unwrap();
+        assert_eq!(config.format(), TextureFormat::Rgba32F);
+
+        assert!("noise.png,rgb565".parse::<ChannelConfig>().is_err());
+
+        let config: ChannelConfig = "shots, 2023/noise,blue.png,rgba32f".parse().unwrap();
+        assert_eq!(config.path, PathBuf::from("shots, 2023/noise,blue.png"));
+        assert_eq!(config.format(), TextureFormat::Rgba32F);
+    }
+
+    #[test]
+    fn parse_sampler_settings() {
+        let config: ChannelConfig = "noise.png".parse().unwrap();
+        assert_eq!(config.sampler, SamplerSettings::default());
+
+        let config: ChannelConfig = "noise.png,filter=nearest,wrap=clamp,wrap-t=mirror,mipmap"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            config.sampler,
+            SamplerSettings {
+                min_filter: Filter::Nearest,
+                mag_filter: Filter::Nearest,
+                wrap_s: Wrap::Clamp,
+                wrap_t: Wrap::Mirror,
+                mipmap: true,
+            }
+        );
+        assert_eq!(config.sampler.gl_min_filter(), glow::NEAREST_MIPMAP_NEAREST);
+
+        assert!("noise.png,wrap=border".parse::<ChannelConfig>().is_err());
+        assert!("noise.png,anisotropy=16".parse::<ChannelConfig>().is_err());
+    }
+
+    #[test]
+    fn cube_map_face_directions() {
+        assert_eq!(cube_map_direction(0, 0.0, 0.0), Vec3::X);
+        assert_eq!(cube_map_direction(3, 0.0, 0.0), -Vec3::Y);
+        assert_eq!(cube_map_direction(4, 0.0, 0.0), Vec3::Z);
+        // the first row of the side faces is the top
+        assert!(cube_map_direction(5, 0.0, -1.0).y > 0.0);
+    }
+
+    #[test]
+    fn panorama_positions() {
+        assert_eq!(panorama_uv(-Vec3::Z), (0.5, 0.5));
+        assert_eq!(panorama_uv(Vec3::Y).1, 0.0);
+        assert_eq!(panorama_uv(Vec3::X), (0.75, 0.5));
+    }
+}
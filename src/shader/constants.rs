@@ -21,3 +21,6 @@ pub const VERTEX_SHADER: &str = r#"#version 330 core
                 vert = verts[gl_VertexID];
                 gl_Position = vec4(vert, 0.0, 1.0);
             }"#;
+
+/// Shown when skuggbox is started without any shader files
+pub const FALLBACK_SHADER: &str = include_str!("../../shaders/fallback.glsl");
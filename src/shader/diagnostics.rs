@@ -0,0 +1,129 @@
+use regex::Regex;
+use std::fmt::Formatter;
+
+use crate::SourceLocation;
+
+/// A compile error or warning, pointing at the file the user wrote when the line is known
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub location: Option<SourceLocation>,
+    pub message: String,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.location {
+            Some(location) => write!(
+                f,
+                "{}:{}: {}",
+                location.file.display(),
+                location.line,
+                self.message
+            ),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Parse an OpenGL info log and map the reported line numbers back to the original files
+/// through `source_map`, which holds the origin of each line of the pre-processed source.
+///
+/// Handles the common driver formats:
+/// * Mesa: `0:42(5): error: ...`
+/// * NVIDIA: `0(42) : error C0000: ...`
+/// * AMD/Apple: `ERROR: 0:42: ...`
+pub fn parse_info_log(info_log: &str, source_map: &[SourceLocation]) -> Vec<Diagnostic> {
+    let pattern =
+        Regex::new(r"^(ERROR|WARNING)?:?\s*\d+(?::(\d+)|\((\d+)\))(?:\(\d+\))?\s*:?\s*(.*)$")
+            .unwrap();
+
+    info_log
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| match pattern.captures(line) {
+            Some(captures) => {
+                let line_number = captures
+                    .get(2)
+                    .or_else(|| captures.get(3))
+                    .and_then(|m| m.as_str().parse::<usize>().ok())
+                    .unwrap_or_default();
+                let message = captures.get(4).map_or("", |m| m.as_str());
+                let message = match captures.get(1) {
+                    Some(severity) => format!("{}: {}", severity.as_str().to_lowercase(), message),
+                    None => message.to_string(),
+                };
+
+                Diagnostic {
+                    location: line_number
+                        .checked_sub(1)
+                        .and_then(|index| source_map.get(index))
+                        .cloned(),
+                    message,
+                }
+            }
+            None => Diagnostic {
+                location: None,
+                message: line.to_string(),
+            },
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::parse_info_log;
+    use crate::SourceLocation;
+
+    fn source_map() -> Vec<SourceLocation> {
+        vec![
+            SourceLocation {
+                file: PathBuf::from("main.glsl"),
+                line: 1,
+            },
+            SourceLocation {
+                file: PathBuf::from("lib/noise.glsl"),
+                line: 7,
+            },
+        ]
+    }
+
+    #[test]
+    fn parse_mesa_info_log() {
+        let diagnostics = parse_info_log("0:2(5): error: `foo' undeclared\n", &source_map());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "lib/noise.glsl:7: error: `foo' undeclared"
+        );
+    }
+
+    #[test]
+    fn parse_nvidia_info_log() {
+        let diagnostics = parse_info_log("0(1) : error C0000: syntax error", &source_map());
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "main.glsl:1: error C0000: syntax error"
+        );
+    }
+
+    #[test]
+    fn parse_amd_info_log() {
+        let diagnostics = parse_info_log("ERROR: 0:2: 'x' : undeclared identifier", &source_map());
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "lib/noise.glsl:7: error: 'x' : undeclared identifier"
+        );
+    }
+
+    #[test]
+    fn parse_unknown_info_log() {
+        let diagnostics = parse_info_log("link failed\n0:99: error: out of range", &source_map());
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].location, None);
+        assert_eq!(diagnostics[0].message, "link failed");
+        assert_eq!(diagnostics[1].location, None);
+    }
+}
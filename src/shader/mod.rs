@@ -1,11 +1,19 @@
+pub mod channel;
 pub mod constants;
+pub mod diagnostics;
+pub mod pass;
 pub mod pre_processor;
 pub mod program;
 pub mod service;
 pub mod skuggbox_shader;
+pub mod storage_buffer;
 
+pub use channel::*;
 pub use constants::*;
+pub use diagnostics::*;
+pub use pass::*;
 pub use pre_processor::*;
 pub use program::*;
 pub use service::*;
 pub use skuggbox_shader::*;
+pub use storage_buffer::*;
@@ -0,0 +1,231 @@
+use std::path::{Path, PathBuf};
+
+use crate::framebuffer::DoubleBuffer;
+use crate::{ShaderError, TextureFormat, CHANNEL_COUNT};
+
+/// Offscreen buffers are half floats unless the manifest says otherwise, so feedback and
+/// accumulation don't band or clamp to 0..1
+pub const DEFAULT_PASS_FORMAT: TextureFormat = TextureFormat::Rgba16F;
+
+/// Most color attachments a buffer pass can write to at once, OpenGL 3.3 guarantees 8
+pub const MAX_PASS_OUTPUTS: usize = 4;
+
+/// A single render pass as read from a pass manifest
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PassDescription {
+    pub name: String,
+    pub file: PathBuf,
+    /// Name and output of the pass each `iChannelN` samples from
+    pub inputs: [Option<(String, usize)>; CHANNEL_COUNT],
+    pub format: TextureFormat,
+    /// Number of color attachments the pass renders to
+    pub outputs: usize,
+}
+
+/// A render pass in a multi-pass setup, similar to Shadertoy's Buffer A-D.
+/// Every pass but the last one renders into an offscreen buffer, the last one renders to the screen.
+pub struct Pass {
+    pub name: String,
+    /// Index into `ShaderService::shaders`
+    pub shader_index: usize,
+    /// Index and output of the pass each `iChannelN` samples from
+    pub inputs: [Option<(usize, usize)>; CHANNEL_COUNT],
+    /// Format of the offscreen target
+    pub format: TextureFormat,
+    /// Color attachments of the offscreen target, written with `layout(location = N) out`
+    pub outputs: usize,
+    /// Offscreen target, created on first use. Always `None` for the final pass.
+    pub target: Option<DoubleBuffer>,
+}
+
+/// Parse a pass manifest. Each non-empty line describes a pass:
+///
+/// ```text
+/// # <name> <shader file> [iChannelN=<pass name>[:<output>]]... [format=<rgba8|rgba16f|rgba32f>] [outputs=<n>]
+/// buffer_a  buffer_a.glsl  iChannel0=buffer_a  format=rgba32f
+/// gbuffer   gbuffer.glsl   outputs=2
+/// image     image.glsl     iChannel0=buffer_a  iChannel1=gbuffer:1
+/// ```
+///
+/// Buffers are `DEFAULT_PASS_FORMAT` when no format is given. A pass with several outputs
+/// renders to that many color attachments, inputs sample the first one unless another is
+/// picked with `:<output>`. Shader files are relative to `base_dir`. Lines starting with `#`
+/// or `//` are comments. The last pass is the one rendered to the screen.
+pub fn parse_pass_manifest(
+    source: &str,
+    base_dir: &Path,
+) -> Result<Vec<PassDescription>, ShaderError> {
+    let mut passes: Vec<PassDescription> = vec![];
+
+    for (line_number, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("//") {
+            continue;
+        }
+
+        let manifest_error = |error: String| ShaderError::ManifestError {
+            error: format!("line {}: {}", line_number + 1, error),
+        };
+
+        let mut parts = line.split_whitespace();
+        let name = parts.next().unwrap_or_default().to_string();
+        let file = parts
+            .next()
+            .ok_or_else(|| manifest_error(format!("pass '{}' is missing a shader file", name)))?;
+
+        if passes.iter().any(|pass| pass.name == name) {
+            return Err(manifest_error(format!("pass '{}' is declared twice", name)));
+        }
+
+        let mut inputs: [Option<(String, usize)>; CHANNEL_COUNT] = Default::default();
+        let mut format = DEFAULT_PASS_FORMAT;
+        let mut outputs = 1;
+        for input in parts {
+            let (channel, source) = input
+                .split_once('=')
+                .ok_or_else(|| manifest_error(format!("invalid input '{}'", input)))?;
+            if channel == "format" {
+                format = source.parse().map_err(manifest_error)?;
+                continue;
+            }
+            if channel == "outputs" {
+                outputs = source
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|outputs| (1..=MAX_PASS_OUTPUTS).contains(outputs))
+                    .ok_or_else(|| {
+                        manifest_error(format!(
+                            "outputs must be 1 to {}, got '{}'",
+                            MAX_PASS_OUTPUTS, source
+                        ))
+                    })?;
+                continue;
+            }
+            let index = channel
+                .strip_prefix("iChannel")
+                .and_then(|index| index.parse::<usize>().ok())
+                .filter(|index| *index < CHANNEL_COUNT)
+                .ok_or_else(|| manifest_error(format!("invalid channel '{}'", channel)))?;
+            let source = match source.split_once(':') {
+                Some((pass, output)) => {
+                    let output = output
+                        .parse::<usize>()
+                        .map_err(|_| manifest_error(format!("invalid output '{}'", output)))?;
+                    (pass.to_string(), output)
+                }
+                None => (source.to_string(), 0),
+            };
+            inputs[index] = Some(source);
+        }
+
+        passes.push(PassDescription {
+            name,
+            file: base_dir.join(file),
+            inputs,
+            format,
+            outputs,
+        });
+    }
+
+    if passes.is_empty() {
+        return Err(ShaderError::ManifestError {
+            error: "no passes declared".to_string(),
+        });
+    }
+
+    // every input must refer to an output of a declared pass
+    for pass in &passes {
+        for (input, output) in pass.inputs.iter().flatten() {
+            let error = match passes.iter().find(|p| &p.name == input) {
+                None => format!("pass '{}' reads from unknown pass '{}'", pass.name, input),
+                Some(source) if *output >= source.outputs => format!(
+                    "pass '{}' reads output {} of pass '{}' which has {}",
+                    pass.name, output, input, source.outputs
+                ),
+                Some(_) => continue,
+            };
+            return Err(ShaderError::ManifestError { error });
+        }
+    }
+
+    let last = passes.last().unwrap();
+    if last.outputs > 1 {
+        return Err(ShaderError::ManifestError {
+            error: format!(
+                "pass '{}' renders to the screen and can't have several outputs",
+                last.name
+            ),
+        });
+    }
+
+    Ok(passes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{parse_pass_manifest, DEFAULT_PASS_FORMAT};
+    use crate::{ShaderError, TextureFormat};
+
+    #[test]
+    fn parse_manifest() {
+        let manifest = "
+            # feedback setup
+            buffer_a buffer_a.glsl iChannel0=buffer_a format=rgba32f
+            image image.glsl iChannel1=buffer_a
+        ";
+        let passes = parse_pass_manifest(manifest, Path::new("shaders")).unwrap();
+
+        assert_eq!(passes.len(), 2);
+        assert_eq!(passes[0].name, "buffer_a");
+        assert_eq!(passes[0].file, PathBuf::from("shaders/buffer_a.glsl"));
+        assert_eq!(passes[0].inputs[0], Some(("buffer_a".to_string(), 0)));
+        assert_eq!(passes[1].inputs[0], None);
+        assert_eq!(passes[1].inputs[1], Some(("buffer_a".to_string(), 0)));
+        assert_eq!(passes[0].format, TextureFormat::Rgba32F);
+        assert_eq!(passes[1].format, DEFAULT_PASS_FORMAT);
+        assert_eq!(passes[0].outputs, 1);
+    }
+
+    #[test]
+    fn parse_manifest_with_several_outputs() {
+        let manifest = "
+            gbuffer gbuffer.glsl outputs=3
+            image image.glsl iChannel0=gbuffer iChannel1=gbuffer:2
+        ";
+        let passes = parse_pass_manifest(manifest, Path::new(".")).unwrap();
+        assert_eq!(passes[0].outputs, 3);
+        assert_eq!(passes[1].inputs[0], Some(("gbuffer".to_string(), 0)));
+        assert_eq!(passes[1].inputs[1], Some(("gbuffer".to_string(), 2)));
+
+        let missing_output = "gbuffer gbuffer.glsl outputs=2\nimage image.glsl iChannel0=gbuffer:2";
+        assert!(parse_pass_manifest(missing_output, Path::new(".")).is_err());
+
+        let too_many = "gbuffer gbuffer.glsl outputs=9\nimage image.glsl";
+        assert!(parse_pass_manifest(too_many, Path::new(".")).is_err());
+
+        let screen_outputs = "image image.glsl outputs=2";
+        assert!(parse_pass_manifest(screen_outputs, Path::new(".")).is_err());
+    }
+
+    #[test]
+    fn parse_manifest_errors() {
+        let unknown_input = "image image.glsl iChannel0=buffer_b";
+        assert!(matches!(
+            parse_pass_manifest(unknown_input, Path::new(".")),
+            Err(ShaderError::ManifestError { .. })
+        ));
+
+        let invalid_channel = "image image.glsl iChannel4=image";
+        assert!(parse_pass_manifest(invalid_channel, Path::new(".")).is_err());
+
+        let invalid_format = "image image.glsl format=rgb8";
+        assert!(parse_pass_manifest(invalid_format, Path::new(".")).is_err());
+
+        let missing_file = "image";
+        assert!(parse_pass_manifest(missing_file, Path::new(".")).is_err());
+
+        assert!(parse_pass_manifest("// nothing", Path::new(".")).is_err());
+    }
+}
@@ -1,23 +1,207 @@
-use crate::{Part, ShaderContent, SKUGGBOX_CAMERA};
-use std::collections::HashSet;
+use crate::{GlProfile, Part, ShaderContent, SourceLocation, SKUGGBOX_CAMERA};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::collections::{BTreeMap, HashSet};
 /// Utility functions to read shader content
 /// and produce the necessary pieces to construct a
 use std::default::Default;
 use std::fs::File;
 use std::io::Read;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
+use crate::is_builtin_shader;
 use crate::shader::ShaderError;
 use crate::utils::pragma_shader_name;
 
+/// Added to shaders that define `mainImage` but no `main`
+const SHADERTOY_ENTRY_POINT: &str = "out vec4 skuggbox_FragColor;
+void main() {
+    mainImage(skuggbox_FragColor, gl_FragCoord.xy);
+}";
+
+static MAIN_IMAGE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\bvoid\s+mainImage\s*\(").unwrap());
+static MAIN: Lazy<Regex> = Lazy::new(|| Regex::new(r"\bvoid\s+main\s*\(").unwrap());
+
+/// Used for shaders that don't declare a `#version` themselves
+pub const DEFAULT_GLSL_VERSION: &str = "330 core";
+
+/// File the default prelude is read from, next to the main shader
+pub const COMMON_PRELUDE: &str = "common.glsl";
+
+/// A line of shader source together with where it originally came from
+type MappedLine = (String, SourceLocation);
+
 #[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd)]
 pub enum PragmaDirective {
     Camera(String),
 }
 
+/// Code inlined into every shader after its `#version` line, like Shadertoy's Common tab
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum Prelude {
+    /// `COMMON_PRELUDE` next to the main shader, when there is one
+    #[default]
+    Common,
+    File(PathBuf),
+    None,
+}
+
 #[derive(Clone)]
 pub struct PreProcessorConfig {
     pub use_camera_integration: bool,
+    /// `(name, value)` pairs injected as `#define name value` after the `#version` line
+    pub defines: Vec<(String, String)>,
+    /// Version injected as `#version <default_version>` into shaders without one
+    pub default_version: String,
+    pub prelude: Prelude,
+}
+
+impl Default for PreProcessorConfig {
+    fn default() -> Self {
+        Self {
+            use_camera_integration: false,
+            defines: vec![],
+            default_version: DEFAULT_GLSL_VERSION.to_string(),
+            prelude: Prelude::default(),
+        }
+    }
+}
+
+/// Where the pre-processor reads the main shader and its includes from
+pub trait IncludeResolver {
+    fn exists(&self, path: &Path) -> bool;
+    fn read(&self, path: &Path) -> Result<String, ShaderError>;
+    /// Key identifying the file, so `lib/../a.glsl` and `a.glsl` are the same file
+    fn canonical(&self, path: &Path) -> PathBuf;
+}
+
+/// Reads the shader files from disk
+pub struct FileResolver;
+
+impl IncludeResolver for FileResolver {
+    fn exists(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn read(&self, path: &Path) -> Result<String, ShaderError> {
+        read_file(path.to_owned())
+    }
+
+    fn canonical(&self, path: &Path) -> PathBuf {
+        canonical_path(path)
+    }
+}
+
+/// Shader files kept in memory by path, e.g. to test the pre-processor without the disk.
+/// Paths are canonical once `.` and `..` are resolved.
+impl IncludeResolver for BTreeMap<PathBuf, String> {
+    fn exists(&self, path: &Path) -> bool {
+        self.contains_key(&self.canonical(path))
+    }
+
+    fn read(&self, path: &Path) -> Result<String, ShaderError> {
+        self.get(&self.canonical(path))
+            .cloned()
+            .ok_or_else(|| ShaderError::FileError {
+                error: format!("{:?} does not exist", path),
+            })
+    }
+
+    fn canonical(&self, path: &Path) -> PathBuf {
+        let mut canonical = PathBuf::new();
+        for component in path.components() {
+            match component {
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    canonical.pop();
+                }
+                component => canonical.push(component),
+            }
+        }
+        canonical
+    }
+}
+
+/// Tracks `#ifdef` blocks so includes in inactive blocks aren't inlined. The directives stay
+/// in the source for the GLSL compiler, `#if` and `#elif` expressions aren't evaluated and count
+/// as active.
+#[derive(Debug, Default)]
+struct Conditionals {
+    /// Names from `--define` and the `#define`s read so far
+    defined: HashSet<String>,
+    /// Whether each open block is active, `None` when it can't be told
+    blocks: Vec<Option<bool>>,
+}
+
+impl Conditionals {
+    fn new(defines: &[(String, String)]) -> Self {
+        Self {
+            defined: defines.iter().map(|(name, _)| name.clone()).collect(),
+            blocks: vec![],
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        self.blocks.iter().all(|active| *active != Some(false))
+    }
+
+    /// Follow the directive on `line`, lines without one are ignored
+    fn update(&mut self, line: &str) {
+        let mut words = line.split_whitespace();
+        let (directive, name) = (words.next(), words.next());
+        // `#define NAME(x)` defines `NAME`
+        let name = name.map(|name| name.split('(').next().unwrap_or(name));
+
+        match (directive, name) {
+            (Some("#define"), Some(name)) if self.is_active() => {
+                self.defined.insert(name.to_string());
+            }
+            (Some("#undef"), Some(name)) if self.is_active() => {
+                self.defined.remove(name);
+            }
+            (Some("#ifdef"), Some(name)) => self.blocks.push(Some(self.defined.contains(name))),
+            (Some("#ifndef"), Some(name)) => self.blocks.push(Some(!self.defined.contains(name))),
+            (Some("#if"), _) => self.blocks.push(None),
+            (Some("#elif"), _) => {
+                if let Some(active) = self.blocks.last_mut() {
+                    *active = None;
+                }
+            }
+            (Some("#else"), _) => {
+                if let Some(Some(active)) = self.blocks.last_mut() {
+                    *active = !*active;
+                }
+            }
+            (Some("#endif"), _) => {
+                self.blocks.pop();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// State shared by the files of a shader while its includes are inlined
+struct LoadContext<'a> {
+    resolver: &'a dyn IncludeResolver,
+    /// The files currently being inlined, from the main shader down, to detect circular includes
+    include_stack: Vec<PathBuf>,
+    conditionals: Conditionals,
+    /// Canonical path of the prelude once it's read
+    prelude: Option<PathBuf>,
+    /// Set when a file includes the prelude, which the prelude made a skipped duplicate
+    prelude_included: bool,
+}
+
+impl<'a> LoadContext<'a> {
+    fn new(resolver: &'a dyn IncludeResolver, defines: &[(String, String)]) -> Self {
+        Self {
+            resolver,
+            include_stack: vec![],
+            conditionals: Conditionals::new(defines),
+            prelude: None,
+            prelude_included: false,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -31,6 +215,23 @@ impl PreProcessor {
     }
 
     pub fn load_file(&self, shader_path: &PathBuf) -> ShaderContent {
+        self.load_with(shader_path, None, &FileResolver)
+    }
+
+    /// Pre-process `source` as if it was read from `shader_path`, without touching the disk.
+    /// Includes are still read from files, relative to `shader_path`.
+    pub fn load_source(&self, shader_path: &PathBuf, source: String) -> ShaderContent {
+        self.load_with(shader_path, Some(source), &FileResolver)
+    }
+
+    /// Pre-process the shader at `shader_path`, or its `source` when given, reading the files
+    /// through `resolver`
+    pub fn load_with(
+        &self,
+        shader_path: &PathBuf,
+        source: Option<String>,
+        resolver: &dyn IncludeResolver,
+    ) -> ShaderContent {
         let shader_name = shader_path.file_name().unwrap().to_str().unwrap();
         let shader_id = match shader_name.rsplit_once('.') {
             Some((left, _)) => left.to_string(),
@@ -40,45 +241,127 @@ impl PreProcessor {
         let mut shader_content = ShaderContent {
             shader_id,
             main_shader_path: shader_path.to_owned(),
+            source: source.clone(),
             parts: Default::default(),
+            files: Default::default(),
             shader_src: String::new(),
+            source_map: vec![],
             ready_to_compile: false,
+            error: None,
         };
 
-        let mut loaded_files: HashSet<PathBuf> = HashSet::new();
+        let mut context = LoadContext::new(resolver, &self.config.defines);
+        let mut parts = self
+            .load_prelude(&mut shader_content, &mut context, shader_path)
+            .and_then(|prelude| {
+                let main_part = self.process_part(
+                    &mut shader_content,
+                    &mut context,
+                    shader_path.clone(),
+                    source.clone(),
+                )?;
+                Ok((prelude, main_part))
+            });
 
-        match self.process_part(&mut shader_content, &mut loaded_files, shader_path.clone()) {
-            Ok(main_part) => {
-                let path = match shader_path.canonicalize() {
-                    Ok(x) => x,
-                    Err(_) => shader_path.to_owned(),
-                };
+        // A shader including the prelude file itself decides where it goes. Read it again
+        // without the prelude, so that include isn't skipped as a duplicate.
+        if context.prelude_included {
+            shader_content.parts.clear();
+            shader_content.files.clear();
+            let mut context = LoadContext::new(resolver, &self.config.defines);
+            parts = self
+                .process_part(
+                    &mut shader_content,
+                    &mut context,
+                    shader_path.clone(),
+                    source,
+                )
+                .map(|main_part| (None, main_part));
+        }
+
+        match parts {
+            Ok((prelude, main_part)) => {
+                let path = resolver.canonical(shader_path);
                 shader_content.parts.insert(path, main_part.clone());
-                shader_content.shader_src = main_part.shader_src;
+
+                let lines = main_part
+                    .shader_src
+                    .lines()
+                    .map(str::to_string)
+                    .zip(main_part.source_map)
+                    .collect();
+                let lines = self.process_entry_point(lines);
+                let lines = self.process_version(lines);
+                let lines = self.process_prelude(lines, prelude);
+                let (lines, source_map): (Vec<String>, Vec<SourceLocation>) =
+                    self.process_defines(lines).into_iter().unzip();
+                shader_content.shader_src = lines.join("\n");
+                shader_content.source_map = source_map;
                 shader_content.ready_to_compile = true;
             }
             Err(e) => {
                 log::error!("Error reading shader {:?}: {:?}", shader_path, e);
+                shader_content.error = Some(e);
             }
         }
 
         shader_content
     }
 
+    /// Read the prelude of the shader at `shader_path` with its includes, `None` when there's
+    /// no prelude file. It's read before the main shader, so includes of its includes in the
+    /// main shader are skipped as duplicates. A main shader including the prelude file itself is
+    /// read again without it, see `load_with`.
+    fn load_prelude(
+        &self,
+        shader: &mut ShaderContent,
+        context: &mut LoadContext<'_>,
+        shader_path: &Path,
+    ) -> Result<Option<Part>, ShaderError> {
+        // the built-in shader doesn't need anything from the working directory
+        if is_builtin_shader(shader_path) {
+            return Ok(None);
+        }
+        let resolver = context.resolver;
+        let path = match &self.config.prelude {
+            Prelude::Common => shader_path.with_file_name(COMMON_PRELUDE),
+            Prelude::File(path) if resolver.exists(path) => path.clone(),
+            Prelude::File(path) => {
+                log::warn!("Prelude {:?} does not exist, skipping it", path);
+                return Ok(None);
+            }
+            Prelude::None => return Ok(None),
+        };
+        // opening the prelude itself as the main shader
+        if !resolver.exists(&path) || resolver.canonical(&path) == resolver.canonical(shader_path) {
+            return Ok(None);
+        }
+
+        let part = self.process_part(shader, context, path.clone(), None)?;
+        shader.parts.insert(resolver.canonical(&path), part.clone());
+        context.prelude = Some(resolver.canonical(&path));
+        Ok(Some(part))
+    }
+
+    /// Read a file, or take its `source` when it's in memory, and inline its includes
     fn process_part(
         &self,
         shader: &mut ShaderContent,
-        loaded_files: &mut HashSet<PathBuf>,
+        context: &mut LoadContext<'_>,
         shader_path: PathBuf,
+        source: Option<String>,
     ) -> anyhow::Result<Part, ShaderError> {
-        let result = read_file(shader_path.clone());
-        if result.is_err() {
-            return Err(result.err().unwrap());
-        }
-        let file_contents = result.ok().unwrap();
-
-        // mark the file as read
-        loaded_files.insert(shader_path.clone());
+        let file_contents = match source {
+            Some(source) => source,
+            None => {
+                let file_contents = context.resolver.read(&shader_path)?;
+                // mark the file as read, in-memory sources have nothing to watch
+                shader
+                    .files
+                    .insert(context.resolver.canonical(&shader_path));
+                file_contents
+            }
+        };
 
         let shader_name = shader_path
             .file_name()
@@ -86,77 +369,260 @@ impl PreProcessor {
             .to_str()
             .unwrap()
             .to_string();
-        let shader_source =
-            self.process_includes(shader, loaded_files, &shader_path, file_contents);
+        context
+            .include_stack
+            .push(context.resolver.canonical(&shader_path));
+        let mut includes = vec![];
+        let lines =
+            self.process_includes(shader, context, &mut includes, &shader_path, file_contents);
+        context.include_stack.pop();
 
-        let shader_source = self.process_integrations(shader_source);
+        let lines = self.process_integrations(lines?);
+        let (lines, source_map): (Vec<String>, Vec<SourceLocation>) = lines.into_iter().unzip();
 
         Ok(Part {
             shader_path,
-            shader_src: shader_source,
+            shader_src: lines.join("\n"),
             shader_name,
+            source_map,
+            includes,
         })
     }
 
     fn process_includes(
         &self,
         shader: &mut ShaderContent,
-        loaded_files: &mut HashSet<PathBuf>,
+        context: &mut LoadContext<'_>,
+        includes: &mut Vec<PathBuf>,
         shader_path: &Path,
         source: String,
-    ) -> String {
-        let blocks: Vec<String> = source
-            .lines()
-            .map(|line| {
-                if !is_include_line(line.trim_start()) {
-                    return line.to_string();
-                }
+    ) -> Result<Vec<MappedLine>, ShaderError> {
+        let mut lines: Vec<MappedLine> = vec![];
+
+        for (index, line) in source.lines().enumerate() {
+            let location = SourceLocation {
+                file: shader_path.to_owned(),
+                line: index + 1,
+            };
 
-                let shader_name = pragma_shader_name(line);
-                let base_dir = shader_path.parent().unwrap();
-                let path = base_dir.join(shader_name);
+            if !is_include_line(line.trim_start()) {
+                context.conditionals.update(line);
+                lines.push((line.to_string(), location));
+                continue;
+            }
+
+            // the GLSL compiler would skip the included code, don't read the file at all
+            if !context.conditionals.is_active() {
+                lines.push((format!("// {}", line), location));
+                continue;
+            }
 
-                if loaded_files.contains(&path) {
-                    // TODO(mathias): Output this error in the UI
-                    log::warn!("multiple includes of shader: {:?}", path);
-                    return format!("// {}", line);
+            // includes are relative to the including file, not the working directory
+            let shader_name = pragma_shader_name(line);
+            let base_dir = shader_path.parent().unwrap();
+            let path = base_dir.join(shader_name);
+
+            // compare canonical paths so `lib/../a.glsl` and `a.glsl` are the same file
+            let canonical = context.resolver.canonical(&path);
+            if context.include_stack.contains(&canonical) {
+                let mut chain = context.include_stack.clone();
+                chain.push(canonical);
+                return Err(ShaderError::IncludeCycle { chain });
+            }
+
+            if !context.resolver.exists(&path) {
+                return Err(ShaderError::IncludeNotFound {
+                    file: path,
+                    included_from: shader_path.to_owned(),
+                    line: location.line,
+                });
+            }
+
+            if shader.files.contains(&canonical) {
+                if context.prelude.as_ref() == Some(&canonical) {
+                    context.prelude_included = true;
                 }
+                // TODO(mathias): Output this error in the UI
+                log::warn!("multiple includes of shader: {:?}", path);
+                lines.push((format!("// {}", line), location));
+                continue;
+            }
 
-                match self.process_part(shader, loaded_files, path.clone()) {
-                    Ok(part) => {
-                        let source = part.shader_src.clone();
-                        shader.parts.insert(path.canonicalize().unwrap(), part);
-                        source
-                    }
-                    // TODO(mathias): Output this error in the UI
-                    Err(e) => {
-                        log::warn!("failed to load file: {:?}: {:?}", path, e);
-                        format!("// {}", line)
-                    }
+            match self.process_part(shader, context, path.clone(), None) {
+                Ok(part) => {
+                    lines.extend(
+                        part.shader_src
+                            .lines()
+                            .map(str::to_string)
+                            .zip(part.source_map.iter().cloned()),
+                    );
+                    shader.parts.insert(canonical.clone(), part);
+                    includes.push(canonical);
                 }
-            })
-            .collect();
+                Err(
+                    e @ (ShaderError::IncludeCycle { .. } | ShaderError::IncludeNotFound { .. }),
+                ) => return Err(e),
+                // TODO(mathias): Output this error in the UI
+                Err(e) => {
+                    log::warn!("failed to load file: {:?}: {:?}", path, e);
+                    lines.push((format!("// {}", line), location));
+                }
+            }
+        }
+
+        Ok(lines)
+    }
+
+    /// Make Shadertoy style shaders, which only define
+    /// `mainImage(out vec4 fragColor, in vec2 fragCoord)`, runnable by adding a `main` calling it.
+    /// The added lines map to the last line of the shader.
+    pub fn process_entry_point(&self, mut lines: Vec<MappedLine>) -> Vec<MappedLine> {
+        let defines = |pattern: &Regex| lines.iter().any(|(line, _)| pattern.is_match(line));
+        if !defines(&MAIN_IMAGE) || defines(&MAIN) {
+            return lines;
+        }
+
+        let location = match lines.last() {
+            Some((_, location)) => location.clone(),
+            None => return lines,
+        };
+        lines.extend(
+            SHADERTOY_ENTRY_POINT
+                .lines()
+                .map(|line| (line.to_string(), location.clone())),
+        );
+        lines
+    }
 
-        blocks.join("\n")
+    /// Keep the shader's own `#version`, which may be preceded by comments, and only inject the
+    /// default version when there is none. The injected line maps to the first line of the shader.
+    pub fn process_version(&self, mut lines: Vec<MappedLine>) -> Vec<MappedLine> {
+        if lines.is_empty() || version_line(&lines).is_some() {
+            return lines;
+        }
+
+        let version = format!("#version {}", self.config.default_version);
+        let location = lines[0].1.clone();
+        lines.insert(0, (version, location));
+        lines
     }
 
-    pub fn process_integrations(&self, source: String) -> String {
-        let src = source
+    /// Insert the prelude after the `#version` line, or at the top when there is none. Its lines
+    /// map back to the prelude file.
+    fn process_prelude(
+        &self,
+        mut lines: Vec<MappedLine>,
+        prelude: Option<Part>,
+    ) -> Vec<MappedLine> {
+        let prelude = match prelude {
+            Some(prelude) => prelude,
+            None => return lines,
+        };
+        let index = version_line(&lines).map_or(0, |index| index + 1);
+        let prelude_lines = prelude
+            .shader_src
             .lines()
-            .map(|line| {
+            .map(str::to_string)
+            .zip(prelude.source_map);
+        lines.splice(index..index, prelude_lines);
+        lines
+    }
+
+    /// Insert the configured defines after the `#version` line, or at the top when there is
+    /// none. The defines map back to the line they're inserted after.
+    pub fn process_defines(&self, mut lines: Vec<MappedLine>) -> Vec<MappedLine> {
+        if self.config.defines.is_empty() || lines.is_empty() {
+            return lines;
+        }
+
+        let index = version_line(&lines).map_or(0, |index| index + 1);
+        let location = lines[index.saturating_sub(1)].1.clone();
+
+        let defines = self.config.defines.iter().map(|(name, value)| {
+            let define = format!("#define {} {}", name, value);
+            (define.trim_end().to_string(), location.clone())
+        });
+        lines.splice(index..index, defines);
+        lines
+    }
+
+    /// Replace skuggbox pragmas with their integration code. Injected lines map back to the
+    /// pragma that caused them.
+    pub fn process_integrations(&self, lines: Vec<MappedLine>) -> Vec<MappedLine> {
+        lines
+            .into_iter()
+            .flat_map(|(line, location)| {
                 if self.config.use_camera_integration
                     && line.trim().contains("#pragma skuggbox(camera)")
                 {
                     log::info!("Found camera integration in shader code");
-                    return "#define USE_SKUGGBOX_CAMERA\n".to_string() + SKUGGBOX_CAMERA;
+                    return camera_integration()
+                        .lines()
+                        .map(|line| (line.to_string(), location.clone()))
+                        .collect();
                 }
-                line.to_string()
+                vec![(line, location)]
             })
-            .collect::<Vec<String>>()
-            .join("\n");
+            .collect()
+    }
+}
 
-        src
+/// Code replacing `#pragma skuggbox(camera)` when the camera integration is on
+fn camera_integration() -> String {
+    "#define USE_SKUGGBOX_CAMERA\n".to_string() + SKUGGBOX_CAMERA
+}
+
+/// Pre-processed source made to stand on its own outside skuggbox. Injected camera code is
+/// marked as such since it expects skuggbox to upload `sbCameraTransform`, and pragmas only
+/// skuggbox understands are removed.
+pub fn flatten_source(shader_src: &str) -> String {
+    let integration = camera_integration();
+    let integration: Vec<&str> = integration.lines().collect();
+    let lines: Vec<&str> = shader_src.lines().collect();
+
+    let mut flattened = vec![];
+    let mut index = 0;
+    while index < lines.len() {
+        if lines[index..].starts_with(&integration) {
+            flattened.push("// skuggbox camera integration, set `uniform mat4 sbCameraTransform`");
+            flattened.push("// or remove USE_SKUGGBOX_CAMERA to use the shader's own camera");
+            flattened.extend(&integration);
+            flattened.push("// end of skuggbox camera integration");
+            index += integration.len();
+            continue;
+        }
+        if !lines[index].trim_start().starts_with("#pragma skuggbox") {
+            flattened.push(lines[index]);
+        }
+        index += 1;
     }
+
+    flattened.join("\n") + "\n"
+}
+
+/// Profile named in the `#version` directive, e.g. `compatibility` in
+/// `#version 330 compatibility`. `None` without a directive, without a profile or for `es`.
+pub fn version_profile(shader_src: &str) -> Option<GlProfile> {
+    let directive = shader_src
+        .lines()
+        .map(str::trim_start)
+        .find(|line| line.starts_with("#version"))?;
+    match directive.split_whitespace().nth(2) {
+        Some("core") => Some(GlProfile::Core),
+        Some("compatibility") => Some(GlProfile::Compatibility),
+        _ => None,
+    }
+}
+
+/// Index of the `#version` directive
+fn version_line(lines: &[MappedLine]) -> Option<usize> {
+    lines
+        .iter()
+        .position(|(line, _)| line.trim_start().starts_with("#version"))
+}
+
+fn canonical_path(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_owned())
 }
 
 fn is_include_line(s: &str) -> bool {
@@ -175,3 +641,465 @@ fn read_file(shader_path: PathBuf) -> anyhow::Result<String, ShaderError> {
     file.read_to_string(&mut s).unwrap();
     Ok(s)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+
+    use super::{flatten_source, version_profile, PreProcessor, PreProcessorConfig, Prelude};
+    use crate::{GlProfile, ShaderError};
+
+    fn in_memory(files: &[(&str, &str)]) -> BTreeMap<PathBuf, String> {
+        files
+            .iter()
+            .map(|(path, source)| (PathBuf::from(path), source.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn source_map_points_at_included_files() {
+        let pre_processor = PreProcessor::new(PreProcessorConfig {
+            use_camera_integration: false,
+            ..Default::default()
+        });
+        let shader = pre_processor.load_file(&PathBuf::from("tests/files/main_test.glsl"));
+        let lines: Vec<&str> = shader.shader_src.lines().collect();
+
+        assert!(shader.ready_to_compile);
+        assert_eq!(lines.len(), shader.source_map.len());
+
+        let index = lines.iter().position(|l| *l == "// content of b").unwrap();
+        assert!(shader.source_map[index].file.ends_with("include_b.glsl"));
+        assert_eq!(shader.source_map[index].line, 1);
+
+        let last = shader.source_map.last().unwrap();
+        assert!(last.file.ends_with("main_test.glsl"));
+        assert_eq!(last.line, 5);
+    }
+
+    #[test]
+    fn includes_are_relative_to_the_including_file() {
+        let pre_processor = PreProcessor::new(PreProcessorConfig {
+            use_camera_integration: false,
+            ..Default::default()
+        });
+        let shader = pre_processor.load_file(&PathBuf::from("tests/files/nested/main.glsl"));
+        let lines: Vec<&str> = shader.shader_src.lines().collect();
+
+        assert!(shader.ready_to_compile);
+        assert!(lines.contains(&"// content of noise"));
+        assert!(lines.contains(&"// content of common"));
+        assert!(lines.contains(&"// content of shared"));
+        assert_eq!(shader.parts.len(), 4);
+
+        let tree: Vec<(usize, String)> = shader
+            .include_tree()
+            .into_iter()
+            .map(|(depth, path)| {
+                let name = path.file_name().unwrap().to_string_lossy().to_string();
+                (depth, name)
+            })
+            .collect();
+        assert_eq!(
+            tree,
+            vec![
+                (0, "main.glsl".to_string()),
+                (1, "noise.glsl".to_string()),
+                (2, "common.glsl".to_string()),
+                (3, "shared.glsl".to_string()),
+            ]
+        );
+
+        // noise.glsl includes shared.glsl again after common.glsl did, which is skipped
+        assert_eq!(
+            lines
+                .iter()
+                .filter(|l| **l == "// content of shared")
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn circular_includes_are_reported() {
+        let pre_processor = PreProcessor::new(PreProcessorConfig {
+            use_camera_integration: false,
+            ..Default::default()
+        });
+        let shader = pre_processor.load_file(&PathBuf::from("tests/files/circular/a.glsl"));
+
+        assert!(!shader.ready_to_compile);
+        match shader.error {
+            Some(ShaderError::IncludeCycle { chain }) => {
+                let names: Vec<String> = chain
+                    .iter()
+                    .map(|path| path.file_name().unwrap().to_string_lossy().to_string())
+                    .collect();
+                assert_eq!(names, ["a.glsl", "b.glsl", "a.glsl"]);
+            }
+            error => panic!("unexpected error {:?}", error),
+        }
+    }
+
+    #[test]
+    fn defines_follow_the_version_line() {
+        let pre_processor = PreProcessor::new(PreProcessorConfig {
+            use_camera_integration: false,
+            defines: vec![
+                ("QUALITY".to_string(), "2".to_string()),
+                ("DEBUG".to_string(), String::new()),
+            ],
+            ..Default::default()
+        });
+
+        let shader = pre_processor.load_file(&PathBuf::from("tests/files/version_test.glsl"));
+        let lines: Vec<&str> = shader.shader_src.lines().collect();
+        assert_eq!(lines[0], "#version 330 core");
+        assert_eq!(lines[1], "#define QUALITY 2");
+        assert_eq!(lines[2], "#define DEBUG");
+        assert_eq!(lines.len(), shader.source_map.len());
+        assert_eq!(shader.source_map[3].line, 2);
+
+        let shader = pre_processor.load_file(&PathBuf::from("tests/files/include_b.glsl"));
+        assert!(shader
+            .shader_src
+            .starts_with("#version 330 core\n#define QUALITY 2\n#define DEBUG\n"));
+    }
+
+    #[test]
+    fn default_version_is_injected_once() {
+        let pre_processor = PreProcessor::new(PreProcessorConfig {
+            default_version: "300 es".to_string(),
+            ..Default::default()
+        });
+        let version_count = |src: &str| src.lines().filter(|l| l.starts_with("#version")).count();
+
+        // no version
+        let shader = pre_processor.load_file(&PathBuf::from("tests/files/include_b.glsl"));
+        assert!(shader.shader_src.starts_with("#version 300 es\n"));
+        assert_eq!(shader.source_map[0].line, 1);
+
+        // leading version
+        let shader = pre_processor.load_file(&PathBuf::from("tests/files/version_test.glsl"));
+        assert!(shader.shader_src.starts_with("#version 330 core\n"));
+        assert_eq!(version_count(&shader.shader_src), 1);
+
+        // version preceded by comments and whitespace
+        let shader =
+            pre_processor.load_file(&PathBuf::from("tests/files/version_comment_test.glsl"));
+        assert!(shader.shader_src.contains("\n#version 330 core\n"));
+        assert_eq!(version_count(&shader.shader_src), 1);
+    }
+
+    #[test]
+    fn compatibility_profile_is_kept() {
+        let pre_processor = PreProcessor::new(PreProcessorConfig {
+            defines: vec![("QUALITY".to_string(), "2".to_string())],
+            ..Default::default()
+        });
+
+        let shader = pre_processor.load_file(&PathBuf::from("tests/files/compatibility_test.glsl"));
+        assert!(shader
+            .shader_src
+            .starts_with("#version 330 compatibility\n#define QUALITY 2\n"));
+        assert_eq!(
+            version_profile(&shader.shader_src),
+            Some(GlProfile::Compatibility)
+        );
+
+        assert_eq!(
+            version_profile("#version 330 core\n"),
+            Some(GlProfile::Core)
+        );
+        assert_eq!(version_profile("#version 330\n"), None);
+        assert_eq!(version_profile("void main() {}\n"), None);
+    }
+
+    #[test]
+    fn main_image_gets_an_entry_point() {
+        let pre_processor = PreProcessor::new(PreProcessorConfig::default());
+
+        let shader = pre_processor.load_file(&PathBuf::from("tests/files/shadertoy_test.glsl"));
+        let lines: Vec<&str> = shader.shader_src.lines().collect();
+        assert!(lines.contains(&"    mainImage(skuggbox_FragColor, gl_FragCoord.xy);"));
+        assert_eq!(lines.len(), shader.source_map.len());
+        assert_eq!(shader.source_map.last().unwrap().line, 3);
+
+        // shaders with a main of their own are left alone
+        let shader = pre_processor.load_file(&PathBuf::from("tests/files/version_test.glsl"));
+        assert!(!shader.shader_src.contains("skuggbox_FragColor"));
+    }
+
+    #[test]
+    fn source_map_covers_camera_integration() {
+        let pre_processor = PreProcessor::new(PreProcessorConfig {
+            use_camera_integration: true,
+            ..Default::default()
+        });
+        let shader =
+            pre_processor.load_file(&PathBuf::from("tests/files/camera_integration_test.glsl"));
+
+        assert!(
+            shader.shader_src.lines().count() > 3,
+            "No integration added"
+        );
+        assert_eq!(shader.shader_src.lines().count(), shader.source_map.len());
+        assert_eq!(shader.source_map.last().unwrap().line, 3);
+    }
+
+    #[test]
+    fn flatten_camera_integration() {
+        let path = PathBuf::from("tests/files/camera_integration_test.glsl");
+        let flatten = |use_camera_integration| {
+            let pre_processor = PreProcessor::new(PreProcessorConfig {
+                use_camera_integration,
+                ..Default::default()
+            });
+            flatten_source(&pre_processor.load_file(&path).shader_src)
+        };
+
+        assert_eq!(flatten(false), "#version 330 core\nA\nB\n");
+
+        let flattened = flatten(true);
+        assert!(flattened.contains("// skuggbox camera integration"));
+        assert!(flattened.contains("#define USE_SKUGGBOX_CAMERA\n"));
+        assert!(flattened.ends_with("// end of skuggbox camera integration\nB\n"));
+    }
+
+    #[test]
+    fn missing_include_is_an_error() {
+        let pre_processor = PreProcessor::new(PreProcessorConfig::default());
+        let shader = pre_processor.load_file(&PathBuf::from("tests/files/missing_include.glsl"));
+
+        assert!(!shader.ready_to_compile);
+        match shader.error {
+            Some(ShaderError::IncludeNotFound {
+                file,
+                included_from,
+                line,
+            }) => {
+                assert!(file.ends_with("missing.glsl"));
+                assert!(included_from.ends_with("missing_include.glsl"));
+                assert_eq!(line, 2);
+            }
+            error => panic!("unexpected error {:?}", error),
+        }
+    }
+
+    #[test]
+    fn source_in_memory() {
+        let pre_processor = PreProcessor::new(PreProcessorConfig::default());
+        let source = "#pragma include(tests/files/include_b.glsl)\nvoid main() {}";
+        let shader = pre_processor.load_source(&PathBuf::from("-"), source.to_string());
+
+        assert!(shader.ready_to_compile);
+        assert!(shader.shader_src.contains("// content of b"));
+        assert_eq!(shader.source.as_deref(), Some(source));
+        // only the include is a file that can change
+        assert_eq!(shader.files.len(), 1);
+        assert!(shader
+            .files
+            .iter()
+            .all(|file| file.ends_with("include_b.glsl")));
+    }
+
+    #[test]
+    fn nested_includes_in_memory() {
+        let files = in_memory(&[
+            (
+                "main.glsl",
+                "// main\n#pragma include(lib/noise.glsl)\n// end",
+            ),
+            (
+                "lib/noise.glsl",
+                "// noise\n#pragma include(./../shared.glsl)\n#pragma include(../shared.glsl)",
+            ),
+            ("shared.glsl", "// shared"),
+        ]);
+        let pre_processor = PreProcessor::new(PreProcessorConfig::default());
+        let shader = pre_processor.load_with(&PathBuf::from("main.glsl"), None, &files);
+
+        assert!(shader.ready_to_compile);
+        let lines: Vec<&str> = shader.shader_src.lines().skip(1).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "// main",
+                "// noise",
+                "// shared",
+                "// #pragma include(../shared.glsl)",
+                "// end"
+            ]
+        );
+        let origins: Vec<(PathBuf, usize)> = shader.source_map[1..]
+            .iter()
+            .map(|location| (location.file.clone(), location.line))
+            .collect();
+        assert_eq!(
+            origins,
+            vec![
+                (PathBuf::from("main.glsl"), 1),
+                (PathBuf::from("lib/noise.glsl"), 1),
+                (PathBuf::from("lib/./../shared.glsl"), 1),
+                (PathBuf::from("lib/noise.glsl"), 3),
+                (PathBuf::from("main.glsl"), 3),
+            ]
+        );
+        assert_eq!(shader.files.len(), 3);
+    }
+
+    #[test]
+    fn circular_includes_in_memory() {
+        let files = in_memory(&[
+            ("a.glsl", "#pragma include(b.glsl)"),
+            ("b.glsl", "#pragma include(a.glsl)"),
+        ]);
+        let pre_processor = PreProcessor::new(PreProcessorConfig::default());
+        let shader = pre_processor.load_with(&PathBuf::from("a.glsl"), None, &files);
+
+        assert!(!shader.ready_to_compile);
+        match shader.error {
+            Some(ShaderError::IncludeCycle { chain }) => assert_eq!(
+                chain,
+                ["a.glsl", "b.glsl", "a.glsl"].map(PathBuf::from).to_vec()
+            ),
+            error => panic!("unexpected error {:?}", error),
+        }
+    }
+
+    #[test]
+    fn common_prelude_follows_the_version_line() {
+        let files = in_memory(&[
+            ("shaders/main.glsl", "#version 330 core\nvoid main() {}"),
+            ("shaders/common.glsl", "// common"),
+        ]);
+        let main = PathBuf::from("shaders/main.glsl");
+        let pre_processor = PreProcessor::new(PreProcessorConfig {
+            defines: vec![("DEBUG".to_string(), String::new())],
+            ..Default::default()
+        });
+        let shader = pre_processor.load_with(&main, None, &files);
+
+        assert!(shader.ready_to_compile);
+        let lines: Vec<&str> = shader.shader_src.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "#version 330 core",
+                "#define DEBUG",
+                "// common",
+                "void main() {}"
+            ]
+        );
+        assert_eq!(
+            shader.source_map[2].file,
+            PathBuf::from("shaders/common.glsl")
+        );
+        assert_eq!(shader.source_map[3].file, main);
+        assert_eq!(shader.source_map[3].line, 2);
+
+        // a shader without a prelude file, or opening the prelude itself
+        let files = in_memory(&[
+            ("main.glsl", "void main() {}"),
+            ("common.glsl", "// common"),
+        ]);
+        for path in ["main.glsl", "common.glsl"] {
+            let pre_processor = PreProcessor::new(PreProcessorConfig {
+                prelude: Prelude::File(PathBuf::from("common.glsl")),
+                ..Default::default()
+            });
+            let shader = pre_processor.load_with(&PathBuf::from(path), None, &files);
+            assert_eq!(shader.shader_src.matches("// common").count(), 1);
+        }
+        let pre_processor = PreProcessor::new(PreProcessorConfig {
+            prelude: Prelude::File(PathBuf::from("missing.glsl")),
+            ..Default::default()
+        });
+        let shader = pre_processor.load_with(&PathBuf::from("main.glsl"), None, &files);
+        assert!(shader.ready_to_compile);
+        assert!(!shader.shader_src.contains("// common"));
+
+        // the built-in shader ignores a common.glsl in the working directory
+        let pre_processor = PreProcessor::new(PreProcessorConfig::default());
+        let builtin = PathBuf::from(crate::BUILTIN_SHADER);
+        let shader = pre_processor.load_with(&builtin, Some("void main() {}".to_string()), &files);
+        assert!(shader.ready_to_compile);
+        assert!(!shader.shader_src.contains("// common"));
+    }
+
+    #[test]
+    fn shader_including_the_prelude_keeps_its_order() {
+        let files = in_memory(&[
+            (
+                "shaders/main.glsl",
+                "#version 330 core\nuniform float a;\n#pragma include(common.glsl)\nvoid main() {}",
+            ),
+            (
+                "shaders/common.glsl",
+                "#pragma include(lib.glsl)\nfloat b = a;",
+            ),
+            ("shaders/lib.glsl", "// lib"),
+        ]);
+        let pre_processor = PreProcessor::new(PreProcessorConfig::default());
+        let shader = pre_processor.load_with(&PathBuf::from("shaders/main.glsl"), None, &files);
+
+        assert!(shader.ready_to_compile);
+        let lines: Vec<&str> = shader.shader_src.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "#version 330 core",
+                "uniform float a;",
+                "// lib",
+                "float b = a;",
+                "void main() {}"
+            ]
+        );
+        assert_eq!(shader.files.len(), 3);
+    }
+
+    #[test]
+    fn includes_in_ifdef_blocks() {
+        let main = "#ifdef HIGH_QUALITY
+#pragma include(high.glsl)
+#else
+#pragma include(low.glsl)
+#endif
+#define SHADOWS
+#ifndef SHADOWS
+#pragma include(missing.glsl)
+#endif
+#if __VERSION__ > 300
+#pragma include(extra.glsl)
+#endif";
+        let files = in_memory(&[
+            ("main.glsl", main),
+            ("high.glsl", "// high"),
+            ("low.glsl", "// low"),
+            ("extra.glsl", "// extra"),
+        ]);
+        let included = |defines: Vec<(String, String)>| {
+            let pre_processor = PreProcessor::new(PreProcessorConfig {
+                defines,
+                ..Default::default()
+            });
+            let shader = pre_processor.load_with(&PathBuf::from("main.glsl"), None, &files);
+            assert!(shader.ready_to_compile);
+            // the directives are left for the GLSL compiler
+            assert!(shader
+                .shader_src
+                .contains("#ifndef SHADOWS\n// #pragma include"));
+            shader.files
+        };
+
+        let files_without = included(vec![]);
+        assert!(!files_without.contains(&PathBuf::from("high.glsl")));
+        assert!(files_without.contains(&PathBuf::from("low.glsl")));
+        assert!(files_without.contains(&PathBuf::from("extra.glsl")));
+
+        let files_with = included(vec![("HIGH_QUALITY".to_string(), String::new())]);
+        assert!(files_with.contains(&PathBuf::from("high.glsl")));
+        assert!(!files_with.contains(&PathBuf::from("low.glsl")));
+    }
+}
@@ -1,8 +1,9 @@
-use crate::VERTEX_SHADER;
-use egui::TextBuffer;
+use crate::{Diagnostic, BUILTIN_UNIFORMS, CHANNEL_COUNT, VERTEX_SHADER};
 use glow::{HasContext, Program, UniformLocation};
+use std::collections::BTreeMap;
 use std::ffi::CString;
 use std::fmt::Formatter;
+use std::path::PathBuf;
 
 pub fn cstr_with_len(len: usize) -> CString {
     let mut buffer: Vec<u8> = Vec::with_capacity(len + 1);
@@ -16,35 +17,148 @@ pub fn cstr_to_str(cstr: &CString) -> String {
 
 #[derive(Debug, Clone)]
 pub enum ShaderError {
-    CompilationError { error: String },
-    FileError { error: String },
+    CompilationError {
+        /// The info log as reported by the driver
+        error: String,
+        diagnostics: Vec<Diagnostic>,
+        /// Main file of the shader that failed to compile, when known
+        file: Option<PathBuf>,
+    },
+    FileError {
+        error: String,
+    },
+    TextureError {
+        error: String,
+    },
+    ManifestError {
+        error: String,
+    },
+    StorageBufferError {
+        error: String,
+    },
+    /// The file watcher stopped, changes aren't picked up until it's restarted
+    WatcherError {
+        error: String,
+    },
+    /// A file includes one of the files it's included from. The chain starts with the main
+    /// shader and ends with the file included again.
+    IncludeCycle {
+        chain: Vec<PathBuf>,
+    },
+    /// An include points at a file that doesn't exist, `line` is the include in `included_from`
+    IncludeNotFound {
+        file: PathBuf,
+        included_from: PathBuf,
+        line: usize,
+    },
 }
 
 impl From<String> for ShaderError {
     fn from(err: String) -> Self {
-        ShaderError::CompilationError { error: err }
+        ShaderError::CompilationError {
+            error: err,
+            diagnostics: vec![],
+            file: None,
+        }
     }
 }
 
 impl std::fmt::Display for ShaderError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            ShaderError::CompilationError {
+                error,
+                diagnostics,
+                file,
+            } => {
+                if let Some(file) = file {
+                    writeln!(f, "{}: failed to compile", file.display())?;
+                }
+                // show errors as "file.glsl:42: error ..." when we know where they come from
+                if diagnostics.is_empty() {
+                    write!(f, "{}", error.trim_end())
+                } else {
+                    let lines: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+                    write!(f, "{}", lines.join("\n"))
+                }
+            }
+            ShaderError::FileError { error }
+            | ShaderError::TextureError { error }
+            | ShaderError::ManifestError { error }
+            | ShaderError::StorageBufferError { error }
+            | ShaderError::WatcherError { error } => write!(f, "{}", error),
+            ShaderError::IncludeCycle { chain } => {
+                let files: Vec<String> = chain.iter().map(|p| p.display().to_string()).collect();
+                write!(f, "circular include: {}", files.join(" -> "))
+            }
+            ShaderError::IncludeNotFound {
+                file,
+                included_from,
+                line,
+            } => write!(
+                f,
+                "{}:{}: include not found: {}",
+                included_from.display(),
+                line,
+                file.display()
+            ),
+        }
     }
 }
 
+impl std::error::Error for ShaderError {}
+
+/// Where the uniforms skuggbox sets live in a program. A location is `None` when the shader
+/// doesn't declare the uniform or the compiler optimized it out, and the upload is skipped.
 #[derive(Clone, Default, Debug)]
 pub struct ShaderUniformLocations {
     pub resolution: Option<UniformLocation>,
+    /// `FLOAT_VEC3` when `iResolution` is declared like Shadertoy's vec3, otherwise a vec2
+    pub resolution_type: u32,
     pub time: Option<UniformLocation>,
     pub time_delta: Option<UniformLocation>,
+    /// Number of frames rendered since start, reload or rewind
+    pub frame: Option<UniformLocation>,
+    /// Local date as vec4(year, month - 1, day, seconds since midnight)
+    pub date: Option<UniformLocation>,
+    /// Physical pixels per logical pixel of the window, see `AppState::pixel_ratio`
+    pub pixel_ratio: Option<UniformLocation>,
+    /// Beats since the start at the current tempo, the fraction is the phase of the beat
+    pub beat: Option<UniformLocation>,
+    /// Bars of 4 beats since the start
+    pub bar: Option<UniformLocation>,
+    /// Smoothed RMS level of the audio input, 0.0 without audio capture
+    pub audio_level: Option<UniformLocation>,
+    /// Frames averaged into the accumulated image, see `AppState::next_sample`
+    pub sample_count: Option<UniformLocation>,
+    /// Value of the last number key pressed with `--key-bank`
+    pub key: Option<UniformLocation>,
     pub mouse: Option<UniformLocation>,
     /// Direction of the mouse movement in vec2([-1.0, 0.0, 1.0], [-1.0, 0.0, 1.0])
     pub mouse_dir: Option<UniformLocation>,
+    /// Movement of the cursor since the previous frame while dragging, see `Mouse::drag_delta`
+    pub mouse_delta: Option<UniformLocation>,
+    /// Accumulated mouse wheel scroll
+    pub mouse_wheel: Option<UniformLocation>,
     /// Convenience uniform for quickly getting a-s-d-w movement into the shader.
     /// For more full control over the camera, use the `sb_camera_transform` instead
     pub cam_pos: Option<UniformLocation>,
     pub sb_camera_transform: Option<UniformLocation>,
+    /// World position of the camera, the translation of `sb_camera_transform`
+    pub sb_camera_position: Option<UniformLocation>,
+    /// Inverse of `sb_camera_transform`, from world to camera space
+    pub sb_camera_inv_transform: Option<UniformLocation>,
     pub sb_color_a: Option<UniformLocation>,
+    /// Samplers `iChannel0` to `iChannel3`
+    pub channels: [Option<UniformLocation>; CHANNEL_COUNT],
+    /// GLSL type of each channel sampler, e.g. `SAMPLER_2D` or `SAMPLER_CUBE`, 0 when unused
+    pub channel_types: [u32; CHANNEL_COUNT],
+    /// vec3 array with the size of each channel texture
+    pub channel_resolution: Option<UniformLocation>,
+    /// float array with the playback time of each channel
+    pub channel_time: Option<UniformLocation>,
+    /// Active uniforms that aren't built in, see `custom_uniforms`
+    pub custom: BTreeMap<String, UniformLocation>,
 }
 
 #[derive(Clone)]
@@ -53,26 +167,72 @@ pub struct ShaderProgram {
 }
 
 impl ShaderProgram {
+    /// Link a fragment shader with the built-in full-screen triangle vertex shader
     pub fn from_frag_src(
         gl: &glow::Context,
         fragment_src: String,
+    ) -> anyhow::Result<Program, String> {
+        Self::from_sources(gl, VERTEX_SHADER, &fragment_src)
+    }
+
+    /// Link a program from vertex and fragment shader source
+    pub fn from_sources(
+        gl: &glow::Context,
+        vertex_src: &str,
+        fragment_src: &str,
     ) -> anyhow::Result<Program, String> {
         unsafe {
-            let vert_shader = compile_shader(gl, glow::VERTEX_SHADER, VERTEX_SHADER.as_str())?;
+            let vert_shader = compile_shader(gl, glow::VERTEX_SHADER, vertex_src)?;
             macros::check_for_gl_error!(gl, "vertex_shader_compile");
-            let frag_shader = compile_shader(gl, glow::FRAGMENT_SHADER, fragment_src.as_str())?;
+            let frag_shader = match compile_shader(gl, glow::FRAGMENT_SHADER, fragment_src) {
+                Ok(frag_shader) => frag_shader,
+                Err(err) => {
+                    gl.delete_shader(vert_shader);
+                    return Err(err);
+                }
+            };
             macros::check_for_gl_error!(gl, "fragment_shader_compile");
 
             let shader_sources = vec![vert_shader, frag_shader];
-            let program = link_program(gl, &shader_sources).unwrap();
-            log::debug!("Program created");
-            gl.detach_shader(program, vert_shader);
-            gl.detach_shader(program, frag_shader);
+            let program = link_program(gl, &shader_sources);
+            if let Ok(program) = program {
+                log::debug!("Program created");
+                gl.detach_shader(program, vert_shader);
+                gl.detach_shader(program, frag_shader);
+            }
 
             gl.delete_shader(vert_shader);
             gl.delete_shader(frag_shader);
 
-            Ok(program)
+            program
+        }
+    }
+
+    /// Link a compute shader on its own. Fails with a readable message when the context is
+    /// older than OpenGL 4.3, which introduced compute shaders.
+    pub fn from_compute_src(
+        gl: &glow::Context,
+        compute_src: &str,
+    ) -> anyhow::Result<Program, String> {
+        let version = gl.version();
+        if !supports_compute_shaders(gl) {
+            return Err(format!(
+                "Compute shaders need OpenGL 4.3 or newer, this context is OpenGL {}.{}",
+                version.major, version.minor
+            ));
+        }
+
+        unsafe {
+            let compute_shader = compile_shader(gl, glow::COMPUTE_SHADER, compute_src)?;
+            macros::check_for_gl_error!(gl, "compute_shader_compile");
+
+            let program = link_program(gl, &[compute_shader]);
+            if let Ok(program) = program {
+                gl.detach_shader(program, compute_shader);
+            }
+            gl.delete_shader(compute_shader);
+
+            program
         }
     }
 
@@ -88,21 +248,80 @@ impl ShaderProgram {
         let time = gl.get_uniform_location(program, "iTime");
         let resolution = gl.get_uniform_location(program, "iResolution");
         let time_delta = gl.get_uniform_location(program, "iTimeDelta");
+        let frame = gl.get_uniform_location(program, "iFrame");
+        let date = gl.get_uniform_location(program, "iDate");
+        let pixel_ratio = gl.get_uniform_location(program, "iPixelRatio");
+        let beat = gl.get_uniform_location(program, "iBeat");
+        let bar = gl.get_uniform_location(program, "iBar");
+        let audio_level = gl.get_uniform_location(program, "iAudioLevel");
+        let sample_count = gl.get_uniform_location(program, "iSampleCount");
+        let key = gl.get_uniform_location(program, "iKey");
         let mouse = gl.get_uniform_location(program, "iMouse");
         let mouse_dir = gl.get_uniform_location(program, "iMouseDir");
+        let mouse_delta = gl.get_uniform_location(program, "iMouseDelta");
+        let mouse_wheel = gl.get_uniform_location(program, "iMouseWheel");
         let cam_pos = gl.get_uniform_location(program, "iCamPos");
         let sb_camera_transform = gl.get_uniform_location(program, "sbCameraTransform");
+        let sb_camera_position = gl.get_uniform_location(program, "sbCameraPosition");
+        let sb_camera_inv_transform = gl.get_uniform_location(program, "sbCameraInvTransform");
         let sb_color_a = gl.get_uniform_location(program, "sbColorA");
+        let channels: [Option<UniformLocation>; CHANNEL_COUNT] = std::array::from_fn(|index| {
+            gl.get_uniform_location(program, format!("iChannel{}", index).as_str())
+        });
+        let channel_resolution = gl.get_uniform_location(program, "iChannelResolution");
+        let channel_time = gl.get_uniform_location(program, "iChannelTime");
+        let mut channel_types = [0; CHANNEL_COUNT];
+        let mut resolution_type = 0;
+        let mut custom = BTreeMap::new();
+        for index in 0..gl.get_active_uniforms(program) {
+            if let Some(uniform) = gl.get_active_uniform(program, index) {
+                if uniform.name == "iResolution" {
+                    resolution_type = uniform.utype;
+                }
+                // arrays are reported by their first element
+                let name = uniform.name.trim_end_matches("[0]");
+                if !BUILTIN_UNIFORMS.contains(&name) && !name.starts_with("iChannel") {
+                    if let Some(location) = gl.get_uniform_location(program, name) {
+                        custom.insert(name.to_string(), location);
+                    }
+                }
+                let channel = uniform
+                    .name
+                    .strip_prefix("iChannel")
+                    .and_then(|channel| channel.parse::<usize>().ok());
+                if let Some(channel) = channel.filter(|channel| *channel < CHANNEL_COUNT) {
+                    channel_types[channel] = uniform.utype;
+                }
+            }
+        }
 
         let locations = ShaderUniformLocations {
             resolution,
+            resolution_type,
             time,
             time_delta,
+            frame,
+            date,
+            pixel_ratio,
+            beat,
+            bar,
+            audio_level,
+            sample_count,
+            key,
             mouse,
             mouse_dir,
+            mouse_delta,
+            mouse_wheel,
             cam_pos,
             sb_camera_transform,
+            sb_camera_position,
+            sb_camera_inv_transform,
             sb_color_a,
+            channels,
+            channel_types,
+            channel_resolution,
+            channel_time,
+            custom,
         };
 
         log::debug!("shader locations {:?}", locations);
@@ -111,6 +330,13 @@ impl ShaderProgram {
     }
 }
 
+/// OpenGL 4.3 or the `GL_ARB_compute_shader` extension
+pub fn supports_compute_shaders(gl: &glow::Context) -> bool {
+    let version = gl.version();
+    !version.is_embedded && (version.major, version.minor) >= (4, 3)
+        || gl.supported_extensions().contains("GL_ARB_compute_shader")
+}
+
 pub(crate) unsafe fn compile_shader(
     gl: &glow::Context,
     shader_type: u32,
@@ -125,7 +351,9 @@ pub(crate) unsafe fn compile_shader(
     if gl.get_shader_compile_status(shader) {
         Ok(shader)
     } else {
-        Err(gl.get_shader_info_log(shader))
+        let log = gl.get_shader_info_log(shader);
+        gl.delete_shader(shader);
+        Err(log)
     }
 }
 
@@ -144,6 +372,34 @@ pub(crate) unsafe fn link_program<'a, T: IntoIterator<Item = &'a glow::Shader>>(
     if gl.get_program_link_status(program) {
         Ok(program)
     } else {
-        Err(gl.get_program_info_log(program))
+        // failed reloads would leak a program each
+        let log = gl.get_program_info_log(program);
+        gl.delete_program(program);
+        Err(log)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::ShaderError;
+
+    #[test]
+    fn display_compilation_error() {
+        let error = ShaderError::CompilationError {
+            error: "0:3(1): error: syntax error\n".to_string(),
+            diagnostics: vec![],
+            file: Some(PathBuf::from("shader.glsl")),
+        };
+        assert_eq!(
+            error.to_string(),
+            "shader.glsl: failed to compile\n0:3(1): error: syntax error"
+        );
+
+        let error = ShaderError::FileError {
+            error: "missing.glsl does not exist".to_string(),
+        };
+        assert_eq!(error.to_string(), "missing.glsl does not exist");
     }
 }
@@ -1,16 +1,80 @@
-use std::path::PathBuf;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::Arc;
-use std::{fs, thread};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 use time::format_description;
 
-use crate::shader::PreProcessor;
-use crate::{PreProcessorConfig, ShaderError, SkuggboxShader};
+use crate::shader::{flatten_source, PreProcessor};
+use crate::{
+    custom_uniforms, parse_pass_manifest, sampler_type, spawn_guarded, Channel, ChannelConfig,
+    ChannelSource, Pass, PreProcessorConfig, ShaderError, SkuggboxShader, StorageBuffer, Uniform,
+    CHANNEL_COUNT,
+};
+
+/// Default quiet period after a file change before shaders are reloaded
+pub const DEFAULT_RELOAD_DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// Collects changed files until no new change came in for `interval`, so a save touching
+/// several files, or firing several events, reloads once and not from half-written files
+pub struct ChangeDebouncer {
+    pub interval: Duration,
+    changed: BTreeSet<PathBuf>,
+    last_change: Option<Instant>,
+}
+
+impl ChangeDebouncer {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            changed: BTreeSet::new(),
+            last_change: None,
+        }
+    }
+
+    pub fn push(&mut self, path: PathBuf, now: Instant) {
+        self.changed.insert(path);
+        self.last_change = Some(now);
+    }
+
+    /// The changed files once the quiet period has passed
+    pub fn take_ready(&mut self, now: Instant) -> Option<BTreeSet<PathBuf>> {
+        match self.last_change {
+            Some(last_change) if now.duration_since(last_change) >= self.interval => {
+                self.last_change = None;
+                Some(std::mem::take(&mut self.changed))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Outcome of the latest reload, shown in the top bar
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReloadStatus {
+    /// Not watching for changes, or nothing compiled yet
+    Idle,
+    Ok,
+    Error,
+}
+
+/// A texture that can be shown on its own in place of the final shader, see
+/// `ShaderService::solo_textures`
+pub struct SoloTexture {
+    pub name: String,
+    pub texture: glow::Texture,
+    pub width: i32,
+    pub height: i32,
+}
 
 /// The ShaderService handles the inputted shader files, constructs an OpenGL compatible shader
 /// as well as builds up a pre-processor for inlining include files etc.
 /// It also holds all file data around the used shaders to be used for reloading.
 pub struct ShaderService {
+    gl: Arc<glow::Context>,
     /// All the shader constructs we're using in this setup.
     /// Contains the pre-processor and everything else to build and reload a shader
     pub shaders: Vec<SkuggboxShader>,
@@ -18,88 +82,383 @@ pub struct ShaderService {
     /// Two way channels for listening and reacting to changes in our shader files
     pre_processor: PreProcessor,
     receiver: Option<Receiver<PathBuf>>,
+    /// Delays reloads until the file changes settle down
+    pub debouncer: ChangeDebouncer,
+    /// Files the watcher thread is currently watching
+    watched_files: BTreeSet<PathBuf>,
+    /// Set to stop the current watcher thread
+    watcher_stop: Option<Arc<AtomicBool>>,
+    watcher_thread: Option<JoinHandle<Result<(), String>>>,
+    /// Set when the watcher thread died, shown until the watcher is restarted
+    watcher_error: Option<ShaderError>,
     pub last_error: Option<ShaderError>,
+    /// When shaders were last compiled, after a change or on startup
+    pub last_reload: Option<Instant>,
+    /// Images bound to the `iChannelN` samplers
+    pub channels: [Option<Channel>; CHANNEL_COUNT],
+    /// Render passes when running a multi-pass setup. Empty when rendering a single shader.
+    pub passes: Vec<Pass>,
+    /// Shader storage buffers by binding point
+    pub storage_buffers: BTreeMap<u32, StorageBuffer>,
 }
 
 impl ShaderService {
-    pub fn new(gl: Arc<glow::Context>, shader_files: Vec<PathBuf>) -> Self {
-        let pre_processor_config = PreProcessorConfig {
-            use_camera_integration: false,
-        };
-
+    pub fn new(
+        gl: Arc<glow::Context>,
+        shader_files: Vec<PathBuf>,
+        pre_processor_config: PreProcessorConfig,
+    ) -> Self {
+        let use_camera_integration = pre_processor_config.use_camera_integration;
         let pre_processor = PreProcessor::new(pre_processor_config);
-        let shaders = SkuggboxShader::from_files(gl, &pre_processor, shader_files);
+        let shaders = SkuggboxShader::from_files(gl.clone(), &pre_processor, shader_files);
 
         Self {
+            gl,
             pre_processor,
             shaders,
-            use_camera_integration: false,
+            use_camera_integration,
             receiver: None,
+            debouncer: ChangeDebouncer::new(DEFAULT_RELOAD_DEBOUNCE),
+            watched_files: BTreeSet::new(),
+            watcher_stop: None,
+            watcher_thread: None,
+            watcher_error: None,
             last_error: None,
+            last_reload: None,
+            channels: Default::default(),
+            passes: vec![],
+            storage_buffers: BTreeMap::new(),
+        }
+    }
+
+    /// Set up a multi-pass rendering from a pass manifest, see `parse_pass_manifest` for the format.
+    /// Every pass gets its own shader.
+    pub fn from_manifest(
+        gl: Arc<glow::Context>,
+        manifest: &Path,
+        pre_processor_config: PreProcessorConfig,
+    ) -> Result<Self, ShaderError> {
+        let source = fs::read_to_string(manifest).map_err(|e| ShaderError::FileError {
+            error: format!("Err: {:?}, {:?} is invalid or does not exist", e, manifest),
+        })?;
+        let base_dir = manifest.parent().unwrap_or_else(|| Path::new("."));
+        let descriptions = parse_pass_manifest(&source, base_dir)?;
+
+        let shader_files = descriptions.iter().map(|pass| pass.file.clone()).collect();
+        let mut service = Self::new(gl, shader_files, pre_processor_config);
+
+        service.passes = descriptions
+            .iter()
+            .enumerate()
+            .map(|(shader_index, description)| Pass {
+                name: description.name.clone(),
+                shader_index,
+                inputs: description.inputs.clone().map(|input| {
+                    input.and_then(|(name, output)| {
+                        let pass = descriptions.iter().position(|p| p.name == name)?;
+                        Some((pass, output))
+                    })
+                }),
+                format: description.format,
+                outputs: description.outputs,
+                target: None,
+            })
+            .collect();
+
+        Ok(service)
+    }
+
+    /// Replace the shaders with `shader_files`, e.g. a file opened from the UI. A multi-pass
+    /// setup is dropped and the watcher follows the new files. This method should be called
+    /// from the GL-thread.
+    pub fn open_files(&mut self, shader_files: Vec<PathBuf>) {
+        for shader in &self.shaders {
+            shader.delete();
+        }
+        for target in self.passes.iter().filter_map(|pass| pass.target.as_ref()) {
+            target.delete(&self.gl);
+        }
+        self.passes.clear();
+
+        self.shaders =
+            SkuggboxShader::from_files(self.gl.clone(), &self.pre_processor, shader_files);
+        self.last_error = None;
+        if self.watcher_stop.is_some() {
+            self.watch();
         }
     }
 
+    /// Load images into the channel textures. `channel_files` is indexed by channel number.
+    pub fn load_channels(&mut self, channel_files: Vec<Option<ChannelConfig>>) {
+        for (index, config) in channel_files.iter().enumerate().take(CHANNEL_COUNT) {
+            if let Some(config) = config {
+                if let Err(e) = self.load_channel(index, config) {
+                    log::error!("Failed to load iChannel{}: {}", index, e);
+                }
+            }
+        }
+    }
+
+    /// Load an image into a channel texture, replacing the one that was there
+    pub fn load_channel(
+        &mut self,
+        index: usize,
+        config: &ChannelConfig,
+    ) -> Result<(), ShaderError> {
+        let channel = Channel::from_file(&self.gl, config)?;
+        self.set_channel(index, channel);
+        Ok(())
+    }
+
+    /// Bind a texture to a channel. An image that was bound there is deleted.
+    pub fn set_channel(&mut self, index: usize, channel: Channel) {
+        if let Some(previous) = self.channels[index].replace(channel) {
+            // audio and keyboard textures are owned by their inputs
+            if matches!(
+                previous.source,
+                ChannelSource::Image(_) | ChannelSource::CubeMap(_)
+            ) {
+                previous.delete(&self.gl);
+            }
+        }
+    }
+
+    /// The first channel without a texture
+    pub fn free_channel(&self) -> Option<usize> {
+        self.channels.iter().position(|channel| channel.is_none())
+    }
+
+    /// The 2D `iChannelN` textures followed by the outputs of the buffer passes, in the order
+    /// `Action::SoloChannel` indexes them. Cube maps can't be shown flat and buffers only show
+    /// up once they've been rendered to.
+    pub fn solo_textures(&self) -> Vec<SoloTexture> {
+        let channels = self
+            .channels
+            .iter()
+            .enumerate()
+            .filter_map(|(index, channel)| {
+                let channel = channel.as_ref().filter(|c| c.target == glow::TEXTURE_2D)?;
+                Some(SoloTexture {
+                    name: format!("iChannel{}", index),
+                    texture: channel.texture,
+                    width: channel.width as i32,
+                    height: channel.height as i32,
+                })
+            });
+
+        let buffers = self.passes.iter().flat_map(|pass| {
+            let front = pass.target.as_ref().map(|target| target.front());
+            front.into_iter().flat_map(move |front| {
+                front
+                    .textures
+                    .iter()
+                    .enumerate()
+                    .map(move |(output, texture)| {
+                        let name = match pass.outputs {
+                            1 => pass.name.clone(),
+                            _ => format!("{}:{}", pass.name, output),
+                        };
+                        SoloTexture {
+                            name,
+                            texture: *texture,
+                            width: front.width,
+                            height: front.height,
+                        }
+                    })
+            })
+        });
+
+        channels.chain(buffers).collect()
+    }
+
+    /// Create the storage buffer on `binding`, or upload new data to it. Can be called every
+    /// frame to animate the data on the CPU side. Fails on contexts older than OpenGL 4.3.
+    pub fn set_storage_buffer<T: bytemuck::Pod>(
+        &mut self,
+        gl: &glow::Context,
+        binding: u32,
+        data: &[T],
+    ) -> Result<(), ShaderError> {
+        let bytes = bytemuck::cast_slice(data);
+        match self.storage_buffers.get_mut(&binding) {
+            Some(storage_buffer) => storage_buffer.update(gl, bytes),
+            None => {
+                let storage_buffer = StorageBuffer::new(gl, binding, bytes)?;
+                self.storage_buffers.insert(binding, storage_buffer);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn remove_storage_buffer(&mut self, gl: &glow::Context, binding: u32) {
+        if let Some(storage_buffer) = self.storage_buffers.remove(&binding) {
+            storage_buffer.delete(gl);
+        }
+    }
+
+    /// Bind all storage buffers to their binding points, done before rendering
+    pub fn bind_storage_buffers(&self, gl: &glow::Context) {
+        for storage_buffer in self.storage_buffers.values() {
+            storage_buffer.bind(gl);
+        }
+    }
+
+    /// Watch all shader files, including the included ones, for changes.
+    /// Replaces the previous watcher if there is one.
     pub fn watch(&mut self) {
+        self.stop_watching();
+
         let (sender, receiver): (Sender<PathBuf>, Receiver<PathBuf>) = channel();
+        let stop = Arc::new(AtomicBool::new(false));
 
         self.receiver = Some(receiver);
+        self.watcher_stop = Some(stop.clone());
+        self.watched_files = self.all_shader_files();
 
-        let all_shader_files = self
-            .shaders
+        let all_shader_files = self.watched_files.iter().cloned().collect();
+        self.watcher_thread = Some(spawn_guarded("file watcher", move || {
+            glsl_watcher::watch_until(sender, all_shader_files, stop);
+        }));
+        self.watcher_error = None;
+    }
+
+    /// Notice a watcher thread that ended without being stopped, e.g. after a panic on a
+    /// directory that disappeared. Rendering carries on with the shaders as they are.
+    fn check_watcher(&mut self) {
+        let finished = self
+            .watcher_thread
+            .as_ref()
+            .is_some_and(|thread| thread.is_finished());
+        if !finished {
+            return;
+        }
+        let reason = match self.watcher_thread.take().map(JoinHandle::join) {
+            Some(Ok(Err(message))) => message,
+            _ => "the watcher thread ended".to_string(),
+        };
+        self.stop_watching();
+        self.watcher_error = Some(ShaderError::WatcherError {
+            error: format!(
+                "File watcher stopped ({}), press F5 to reload and restart it",
+                reason
+            ),
+        });
+    }
+
+    fn stop_watching(&mut self) {
+        if let Some(stop) = self.watcher_stop.take() {
+            stop.store(true, Ordering::Relaxed);
+        }
+        self.receiver = None;
+        self.watcher_thread = None;
+    }
+
+    fn all_shader_files(&self) -> BTreeSet<PathBuf> {
+        self.shaders
             .iter()
             .flat_map(|shader| shader.get_all_files())
             .cloned()
-            .collect();
+            .collect()
+    }
 
-        let _ = thread::spawn(move || {
-            glsl_watcher::watch_all(sender, all_shader_files);
-        });
+    /// Restart the watcher if includes were added or removed since it was started
+    fn update_watcher(&mut self) {
+        if self.watcher_stop.is_some() && self.all_shader_files() != self.watched_files {
+            log::debug!("Included files changed, restarting watcher");
+            self.watch();
+        }
     }
 
     /// This method should be called from the GL-thread.
     /// It is basically the same as watching for file changes and the
     /// reload the shaders whenever that happens.
-    pub fn run(&mut self, gl: &glow::Context) -> Result<(), ShaderError> {
+    /// Returns `Ok(true)` if any shader was recompiled.
+    pub fn run(&mut self, gl: &glow::Context) -> Result<bool, ShaderError> {
+        let mut recompiled = false;
+        self.check_watcher();
+
         // pull file updates from the channel
         if let Some(recv) = &self.receiver {
-            if let Ok(changed_path_buf) = recv.try_recv() {
-                for shader in self.shaders.iter_mut() {
-                    if shader.uses_file(&changed_path_buf) {
-                        let main_shader_path = shader.get_main_shader_path();
-                        log::debug!("Reloading shader {:?}", main_shader_path);
-
-                        let reloaded_sahder = self.pre_processor.load_file(main_shader_path);
-                        shader.mark_for_recompilation(reloaded_sahder);
-                    }
-                }
+            let now = Instant::now();
+            for changed_path_buf in recv.try_iter() {
+                self.debouncer.push(changed_path_buf, now);
             }
         };
 
+        if let Some(changed_paths) = self.debouncer.take_ready(Instant::now()) {
+            for shader in self.shaders.iter_mut() {
+                if changed_paths.iter().any(|path| shader.uses_file(path)) {
+                    shader.reload(&self.pre_processor);
+                }
+            }
+            self.update_watcher();
+        }
+
+        let mut failed = false;
         for shader in self.shaders.iter_mut() {
             if shader.ready_to_compile {
+                self.last_reload = Some(Instant::now());
                 match shader.try_to_compile() {
                     Ok(_) => {
                         log::debug!("Shader compiled");
                         shader.find_shader_uniforms(gl);
-                        self.last_error = None;
-                    }
-                    Err(e) => {
-                        self.last_error = Some(e.clone());
-                        return Err(e);
+                        warn_channel_mismatches(shader, &self.channels);
+                        recompiled = true;
                     }
+                    Err(_) => failed = true,
                 }
             }
         }
-        Ok(())
+
+        // a shader that compiles again must not hide the error of another one
+        self.last_error = self
+            .shaders
+            .iter()
+            .find_map(|shader| shader.error.clone())
+            .or_else(|| self.watcher_error.clone());
+        match &self.last_error {
+            Some(e) if failed => Err(e.clone()),
+            _ => Ok(recompiled),
+        }
+    }
+
+    pub fn reload_status(&self) -> ReloadStatus {
+        match (&self.last_error, self.last_reload) {
+            (Some(_), _) => ReloadStatus::Error,
+            (None, Some(_)) if self.watcher_stop.is_some() => ReloadStatus::Ok,
+            _ => ReloadStatus::Idle,
+        }
+    }
+
+    /// Number of files the watcher is following, `None` when not watching
+    pub fn watched_file_count(&self) -> Option<usize> {
+        self.watcher_stop.as_ref().map(|_| self.watched_files.len())
+    }
+
+    pub fn pre_processor_config(&self) -> PreProcessorConfig {
+        self.pre_processor.config.clone()
     }
 
     /// Reloading re-constructs all shaders.
     pub fn reload(&mut self, config: PreProcessorConfig) {
         self.pre_processor.config = config;
         for shader in self.shaders.iter_mut() {
-            let reloaded_shader = self.pre_processor.load_file(shader.get_main_shader_path());
-            shader.mark_for_recompilation(reloaded_shader);
+            shader.reload(&self.pre_processor);
+        }
+        self.update_watcher();
+    }
+
+    /// Custom uniforms declared in any of the shaders, see `custom_uniforms`
+    pub fn custom_uniforms(&self) -> Vec<Uniform> {
+        let mut uniforms: Vec<Uniform> = vec![];
+        for shader in &self.shaders {
+            for uniform in custom_uniforms(&shader.content.shader_src) {
+                if !uniforms.iter().any(|u| u.name == uniform.name) {
+                    uniforms.push(uniform);
+                }
+            }
         }
+        uniforms
     }
 
     pub fn source(&self) {
@@ -109,6 +468,16 @@ impl ShaderService {
         }
     }
 
+    /// Write the pre-processed source of a shader, with its includes inlined, as a single file
+    pub fn save_flattened(&self, index: usize, path: &Path) -> Result<(), String> {
+        let shader = self
+            .shaders
+            .get(index)
+            .ok_or_else(|| "No shader loaded".to_string())?;
+        fs::write(path, flatten_source(&shader.content.shader_src))
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
     /// Saves the current state to disk
     /// Make a `snapshots` dir inside the current directory and save the file with
     /// datetime.glsl
@@ -153,3 +522,99 @@ impl ShaderService {
         }
     }
 }
+
+impl Drop for ShaderService {
+    fn drop(&mut self) {
+        self.stop_watching();
+    }
+}
+
+/// Channels whose texture doesn't match the sampler type the shader declares aren't bound,
+/// e.g. a cube map sampled with a `sampler2D`
+fn warn_channel_mismatches(shader: &SkuggboxShader, channels: &[Option<Channel>]) {
+    for (index, channel) in channels.iter().enumerate() {
+        let declared = shader.locations.channel_types[index];
+        if let Some(channel) = channel {
+            if declared != 0 && declared != sampler_type(channel.target) {
+                let expected = match channel.target {
+                    glow::TEXTURE_CUBE_MAP => "samplerCube",
+                    _ => "sampler2D",
+                };
+                log::warn!(
+                    "{:?}: iChannel{} is a {:?} and must be declared as {}",
+                    shader.get_main_shader_path(),
+                    index,
+                    channel.source,
+                    expected
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+    use std::time::{Duration, Instant};
+
+    use super::ChangeDebouncer;
+    use crate::{PreProcessor, PreProcessorConfig};
+
+    #[test]
+    fn debouncer_waits_for_quiet_period() {
+        let mut debouncer = ChangeDebouncer::new(Duration::from_millis(100));
+        let start = Instant::now();
+        assert_eq!(debouncer.take_ready(start), None);
+
+        debouncer.push(PathBuf::from("main.glsl"), start);
+        debouncer.push(PathBuf::from("lib.glsl"), start + Duration::from_millis(60));
+        debouncer.push(
+            PathBuf::from("main.glsl"),
+            start + Duration::from_millis(80),
+        );
+
+        // 100 ms after the first change but not after the last one
+        assert_eq!(
+            debouncer.take_ready(start + Duration::from_millis(120)),
+            None
+        );
+
+        let changed = debouncer
+            .take_ready(start + Duration::from_millis(180))
+            .unwrap();
+        assert_eq!(changed.len(), 2);
+        assert_eq!(
+            debouncer.take_ready(start + Duration::from_millis(400)),
+            None
+        );
+    }
+
+    #[test]
+    fn editing_an_include_reloads_the_shaders_using_it() {
+        let a_path = PathBuf::from("shaders/a.glsl");
+        let b_path = PathBuf::from("shaders/b.glsl");
+        let mut files: BTreeMap<PathBuf, String> = [
+            ("shaders/lib.glsl", "// lib v1"),
+            ("shaders/a.glsl", "#pragma include(lib.glsl)\n"),
+            ("shaders/b.glsl", "// no includes\n"),
+        ]
+        .iter()
+        .map(|(path, source)| (PathBuf::from(path), source.to_string()))
+        .collect();
+
+        let pre_processor = PreProcessor::new(PreProcessorConfig::default());
+        let a = pre_processor.load_with(&a_path, None, &files);
+        let b = pre_processor.load_with(&b_path, None, &files);
+
+        // only the shader including the file is reloaded
+        let changed = PathBuf::from("shaders/lib.glsl");
+        assert!(a.uses_file(&changed));
+        assert!(!b.uses_file(&changed));
+
+        files.insert(changed, "// lib v2".to_string());
+        let a = pre_processor.load_with(&a_path, None, &files);
+        assert!(a.shader_src.contains("// lib v2"));
+        assert!(!a.shader_src.contains("// lib v1"));
+    }
+}
@@ -1,30 +1,86 @@
-use crate::{PreProcessor, ShaderError, ShaderProgram, ShaderUniformLocations};
-use glow::Program;
-use std::collections::BTreeMap;
-use std::path::PathBuf;
+use crate::program::compile_shader;
+use crate::{
+    parse_info_log, PreProcessor, ShaderError, ShaderProgram, ShaderUniformLocations,
+    FALLBACK_SHADER, VERTEX_SHADER,
+};
+use glow::{HasContext, Program};
+use once_cell::sync::OnceCell;
+use regex::Regex;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
+/// Where a line of the pre-processed shader originally came from
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub struct SourceLocation {
+    pub file: PathBuf,
+    /// 1-based line number in `file`
+    pub line: usize,
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd)]
 pub struct Part {
     pub shader_path: PathBuf,
     pub shader_src: String,
     pub shader_name: String,
+    /// Origin of each line in `shader_src`
+    pub source_map: Vec<SourceLocation>,
+    /// Canonical paths of the parts inlined by this one, in order
+    pub includes: Vec<PathBuf>,
 }
 
 /// The textual components that makes up what we need to process and build an OpenGL shader
 /// The `ShaderContent` shall never have anything to do with actual OpenGL calls but provide what the
 /// shader is called, its text content and where it resides on disk.
-#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd)]
+#[derive(Debug, Clone)]
 pub struct ShaderContent {
     /// The filename constitutes the `shader_id`
     /// <shader_id>.glsl
     pub shader_id: String,
     /// Path to the main shader, full path + filename
     pub main_shader_path: PathBuf,
+    /// Source of the main shader when it isn't read from `main_shader_path`, e.g. from stdin
+    pub source: Option<String>,
     pub parts: BTreeMap<PathBuf, Part>,
+    /// Canonical paths of every file read while pre-processing. Unlike `parts` this includes
+    /// the files of a failed include, so fixing them triggers a reload.
+    pub files: BTreeSet<PathBuf>,
     /// contains the final shader after it's been pre-processed
     pub shader_src: String,
+    /// Origin of each line in `shader_src`, used to point compile errors at the original files
+    pub source_map: Vec<SourceLocation>,
     pub ready_to_compile: bool,
+    /// Why the shader could not be pre-processed, reported when trying to compile it
+    pub error: Option<ShaderError>,
+}
+
+impl ShaderContent {
+    /// Whether `path`, a canonical path like the watcher reports, was read while
+    /// pre-processing, either as the main shader or as one of its includes
+    pub fn uses_file(&self, path: &Path) -> bool {
+        self.files.contains(path)
+    }
+
+    /// Files in the order they're inlined, starting with the main shader, with their include
+    /// depth. Files that failed to load aren't part of the tree.
+    pub fn include_tree(&self) -> Vec<(usize, PathBuf)> {
+        let mut tree = vec![];
+        let main_path = self
+            .main_shader_path
+            .canonicalize()
+            .unwrap_or_else(|_| self.main_shader_path.clone());
+        self.add_to_include_tree(&main_path, 0, &mut tree);
+        tree
+    }
+
+    fn add_to_include_tree(&self, path: &Path, depth: usize, tree: &mut Vec<(usize, PathBuf)>) {
+        if let Some(part) = self.parts.get(path) {
+            tree.push((depth, path.to_owned()));
+            for include in &part.includes {
+                self.add_to_include_tree(include, depth + 1, tree);
+            }
+        }
+    }
 }
 
 /// The SkuggboxShader encapsulates an OpenGL shader program with its uniform locations and
@@ -32,9 +88,14 @@ pub struct ShaderContent {
 pub struct SkuggboxShader {
     gl: Arc<glow::Context>,
     pub content: ShaderContent,
+    /// Companion vertex shader, `<shader_id>.vert` next to the main shader. Without one the
+    /// built-in full-screen triangle is used.
+    pub vertex: Option<ShaderContent>,
     pub program: Option<Program>,
     pub locations: ShaderUniformLocations,
     pub ready_to_compile: bool,
+    /// Error of the last compile attempt, `None` once it compiles again
+    pub error: Option<ShaderError>,
 }
 
 impl SkuggboxShader {
@@ -48,14 +109,17 @@ impl SkuggboxShader {
         shader_files
             .iter()
             .map(|path| {
-                let shader = pre_processor.load_file(path);
-                let ready_to_compile = shader.ready_to_compile;
+                let shader = load_shader(pre_processor, path);
+                let vertex = vertex_shader_path(path).map(|path| pre_processor.load_file(&path));
+                let ready_to_compile = shader.ready_to_compile || shader.error.is_some();
                 Self {
                     gl: gl.clone(),
                     content: shader,
+                    vertex,
                     program: None,
                     locations: ShaderUniformLocations::default(),
                     ready_to_compile,
+                    error: None,
                 }
             })
             .collect()
@@ -63,12 +127,19 @@ impl SkuggboxShader {
 
     /// Returns all files that are part of this shader due to inclusion
     pub fn get_all_files(&self) -> Vec<&PathBuf> {
-        self.content.parts.keys().collect()
+        self.contents()
+            .flat_map(|content| content.files.iter())
+            .collect()
     }
 
     /// Returns true if a file is used by the shader
-    pub fn uses_file(&self, path: &PathBuf) -> bool {
-        self.content.parts.keys().any(|p| p.eq(path))
+    pub fn uses_file(&self, path: &Path) -> bool {
+        self.contents().any(|content| content.uses_file(path))
+    }
+
+    /// The fragment shader content followed by the vertex shader content, if there is one
+    fn contents(&self) -> impl Iterator<Item = &ShaderContent> {
+        std::iter::once(&self.content).chain(self.vertex.as_ref())
     }
 
     /// Return the main shader path from where the inclusion tree starts
@@ -76,25 +147,97 @@ impl SkuggboxShader {
         &self.content.main_shader_path
     }
 
+    /// The file the main shader was read from, which sidecar files like the saved uniforms
+    /// are put next to. `None` for a shader that isn't read from a file, e.g. from stdin.
+    pub fn file(&self) -> Option<&Path> {
+        match self.content.source {
+            Some(_) => None,
+            None => Some(&self.content.main_shader_path),
+        }
+    }
+
     /// Mark the shader so that it's recompiled during the next frame
-    pub fn mark_for_recompilation(&mut self, shader: ShaderContent) {
-        self.ready_to_compile = shader.ready_to_compile;
+    pub fn mark_for_recompilation(&mut self, shader: ShaderContent, vertex: Option<ShaderContent>) {
+        // pre-processing errors are surfaced by `try_to_compile`
+        self.ready_to_compile = shader.ready_to_compile || shader.error.is_some();
         self.content = shader;
+        self.vertex = vertex;
     }
 
-    /// Attempt to recompile the shader. Returns true if the shader was recompiled.
+    /// Load the main shader and its vertex shader again
+    pub fn reload(&mut self, pre_processor: &PreProcessor) {
+        let main_shader_path = self.get_main_shader_path().clone();
+        log::debug!("Reloading shader {:?}", main_shader_path);
+
+        // a shader read from stdin keeps its source, there's no file to read it again from
+        let shader = match self.content.source.clone() {
+            Some(source) => pre_processor.load_source(&main_shader_path, source),
+            None => pre_processor.load_file(&main_shader_path),
+        };
+        let vertex =
+            vertex_shader_path(&main_shader_path).map(|path| pre_processor.load_file(&path));
+        self.mark_for_recompilation(shader, vertex);
+    }
+
+    /// Number of vertices drawn, 3 for the full-screen triangle. A vertex shader can ask for
+    /// more with a `// vertices: N` comment and generate them from `gl_VertexID`.
+    pub fn vertex_count(&self) -> i32 {
+        match &self.vertex {
+            Some(vertex) => declared_vertex_count(&vertex.shader_src).unwrap_or(3),
+            None => 3,
+        }
+    }
+
+    /// Compute shaders are recognized by their `.comp` extension
+    pub fn is_compute(&self) -> bool {
+        is_compute_shader(&self.content.main_shader_path)
+    }
+
+    /// Work group size declared with `layout(local_size_x = X, local_size_y = Y) in;`,
+    /// used to cover the output texture with work groups
+    pub fn local_size(&self) -> (u32, u32) {
+        declared_local_size(&self.content.shader_src)
+    }
+
+    /// Delete the compiled program. This method should be called from the GL-thread.
+    pub fn delete(&self) {
+        if let Some(program) = self.program {
+            unsafe { self.gl.delete_program(program) };
+        }
+    }
+
+    /// Attempt to recompile the shader. On failure the previously compiled program is kept,
+    /// so the last working version keeps rendering while the error is fixed.
     pub fn try_to_compile(&mut self) -> Result<(), ShaderError> {
         if !self.ready_to_compile {
             return Ok(());
         }
-
-        // TODO(mathias): Free / delete the old programs
-        // self.shader_program.free();
         self.ready_to_compile = false;
 
-        match ShaderProgram::from_frag_src(&self.gl, self.content.shader_src.clone()) {
+        let result = self.compile();
+        self.error = result.clone().err();
+        result
+    }
+
+    fn compile(&mut self) -> Result<(), ShaderError> {
+        if let Some(error) = self.contents().find_map(|content| content.error.clone()) {
+            return Err(error);
+        }
+
+        let program = if self.is_compute() {
+            ShaderProgram::from_compute_src(&self.gl, &self.content.shader_src)
+        } else {
+            let vertex_src = match &self.vertex {
+                Some(vertex) => vertex.shader_src.as_str(),
+                None => VERTEX_SHADER,
+            };
+            ShaderProgram::from_sources(&self.gl, vertex_src, &self.content.shader_src)
+        };
+        match program {
             Ok(program) => {
-                self.program = Some(program);
+                if let Some(previous) = self.program.replace(program) {
+                    unsafe { self.gl.delete_program(previous) };
+                }
 
                 log::debug!(
                     "Fragment shader size: {:?} bytes",
@@ -104,11 +247,30 @@ impl SkuggboxShader {
             }
             Err(err) => {
                 log::warn!("{:?}", err);
-                Err(ShaderError::CompilationError { error: err })
+                let content = self.failed_content();
+                Err(ShaderError::CompilationError {
+                    diagnostics: parse_info_log(&err, &content.source_map),
+                    error: err,
+                    file: Some(content.main_shader_path.clone()),
+                })
             }
         }
     }
 
+    /// The part that failed to compile, so its line numbers are mapped to the right file.
+    /// The info log doesn't say which stage failed, so the vertex shader is checked on its own.
+    fn failed_content(&self) -> &ShaderContent {
+        if let Some(vertex) = &self.vertex {
+            let result =
+                unsafe { compile_shader(&self.gl, glow::VERTEX_SHADER, &vertex.shader_src) };
+            match result {
+                Ok(shader) => unsafe { self.gl.delete_shader(shader) },
+                Err(_) => return vertex,
+            }
+        }
+        &self.content
+    }
+
     /// Detected uniforms in the shader source
     pub fn find_shader_uniforms(&mut self, gl: &glow::Context) {
         if let Some(program) = self.program {
@@ -116,3 +278,128 @@ impl SkuggboxShader {
         }
     }
 }
+
+/// The vertex shader belonging to a shader, `<shader_id>.vert` in the same directory.
+/// Compute shaders don't have one.
+pub fn vertex_shader_path(shader_path: &Path) -> Option<PathBuf> {
+    let path = shader_path.with_extension("vert");
+    (path != shader_path && !is_compute_shader(shader_path) && path.is_file()).then_some(path)
+}
+
+/// Shader file name that reads the shader from stdin instead, `skuggbox -f -`
+pub const STDIN_SHADER: &str = "-";
+
+pub fn is_stdin_shader(path: &Path) -> bool {
+    path == Path::new(STDIN_SHADER)
+}
+
+/// Shader file name of the built-in `FALLBACK_SHADER`, which is compiled from memory
+pub const BUILTIN_SHADER: &str = "<built-in>";
+
+pub fn is_builtin_shader(path: &Path) -> bool {
+    path == Path::new(BUILTIN_SHADER)
+}
+
+/// Pre-process the main shader at `path`, which may also be stdin or the built-in shader
+pub fn load_shader(pre_processor: &PreProcessor, path: &PathBuf) -> ShaderContent {
+    if is_stdin_shader(path) {
+        pre_processor.load_source(path, read_stdin())
+    } else if is_builtin_shader(path) {
+        pre_processor.load_source(path, FALLBACK_SHADER.to_string())
+    } else {
+        pre_processor.load_file(path)
+    }
+}
+
+/// Read the whole of stdin, an empty shader when that fails. Read once and kept, since the
+/// window looks at the shader before it's compiled.
+fn read_stdin() -> String {
+    static STDIN: OnceCell<String> = OnceCell::new();
+    STDIN
+        .get_or_init(|| {
+            log::info!("Reading the shader from stdin");
+            std::io::read_to_string(std::io::stdin()).unwrap_or_else(|e| {
+                log::error!("Failed to read the shader from stdin: {}", e);
+                String::new()
+            })
+        })
+        .clone()
+}
+
+/// Extensions of the files that can be opened as the main shader
+pub const SHADER_EXTENSIONS: [&str; 3] = ["glsl", "frag", "comp"];
+
+pub fn is_shader_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| SHADER_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+pub fn is_compute_shader(shader_path: &Path) -> bool {
+    shader_path.extension().is_some_and(|ext| ext == "comp")
+}
+
+/// Local work group size, the dimensions that aren't declared are 1
+fn declared_local_size(source: &str) -> (u32, u32) {
+    let size = |name: &str| {
+        Regex::new(&format!(r"{}\s*=\s*(\d+)", name))
+            .unwrap()
+            .captures(source)
+            .and_then(|captures| captures[1].parse().ok())
+            .unwrap_or(1)
+    };
+    (size("local_size_x"), size("local_size_y"))
+}
+
+/// Vertex count from a `// vertices: N` comment
+fn declared_vertex_count(source: &str) -> Option<i32> {
+    let pattern = Regex::new(r"(?m)^\s*//\s*vertices:?\s*(\d+)").unwrap();
+    pattern
+        .captures(source)
+        .and_then(|captures| captures[1].parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{declared_local_size, declared_vertex_count, is_shader_file, vertex_shader_path};
+
+    #[test]
+    fn shader_files_by_extension() {
+        assert!(is_shader_file(Path::new("scene.glsl")));
+        assert!(is_shader_file(Path::new("particles.COMP")));
+        assert!(!is_shader_file(Path::new("scene.vert")));
+        assert!(!is_shader_file(Path::new("noise.png")));
+        assert!(!is_shader_file(Path::new("skybox")));
+    }
+
+    #[test]
+    fn find_vertex_shader_next_to_main_shader() {
+        assert_eq!(
+            vertex_shader_path(Path::new("tests/files/vertex_test.glsl")),
+            Some(PathBuf::from("tests/files/vertex_test.vert"))
+        );
+        assert_eq!(
+            vertex_shader_path(Path::new("tests/files/main_test.glsl")),
+            None
+        );
+    }
+
+    #[test]
+    fn vertex_count_from_comment() {
+        let source = std::fs::read_to_string("tests/files/vertex_test.vert").unwrap();
+        assert_eq!(declared_vertex_count(&source), Some(6));
+        assert_eq!(declared_vertex_count("void main() {}"), None);
+    }
+
+    #[test]
+    fn local_size_from_layout() {
+        let source = std::fs::read_to_string("tests/files/compute_test.comp").unwrap();
+        assert_eq!(declared_local_size(&source), (16, 8));
+        assert_eq!(
+            declared_local_size("layout(local_size_x = 64) in;"),
+            (64, 1)
+        );
+    }
+}
@@ -0,0 +1,73 @@
+use glow::HasContext;
+
+use crate::ShaderError;
+
+/// A shader storage buffer bound to the `binding` point a shader declares with
+/// `layout(std430, binding = N) buffer Name { ... };`
+pub struct StorageBuffer {
+    pub buffer: glow::Buffer,
+    pub binding: u32,
+    /// Size of the uploaded data in bytes
+    pub size: usize,
+}
+
+impl StorageBuffer {
+    pub fn new(gl: &glow::Context, binding: u32, data: &[u8]) -> Result<Self, ShaderError> {
+        if !supports_storage_buffers(gl) {
+            let version = gl.version();
+            return Err(ShaderError::StorageBufferError {
+                error: format!(
+                    "Storage buffers need OpenGL 4.3 or newer, this context is OpenGL {}.{}",
+                    version.major, version.minor
+                ),
+            });
+        }
+
+        let buffer = unsafe {
+            gl.create_buffer()
+                .map_err(|error| ShaderError::StorageBufferError { error })?
+        };
+        let mut storage_buffer = Self {
+            buffer,
+            binding,
+            size: 0,
+        };
+        storage_buffer.update(gl, data);
+
+        Ok(storage_buffer)
+    }
+
+    /// Upload new data, reallocating the buffer when the size changed
+    pub fn update(&mut self, gl: &glow::Context, data: &[u8]) {
+        unsafe {
+            gl.bind_buffer(glow::SHADER_STORAGE_BUFFER, Some(self.buffer));
+            if data.len() == self.size {
+                gl.buffer_sub_data_u8_slice(glow::SHADER_STORAGE_BUFFER, 0, data);
+            } else {
+                gl.buffer_data_u8_slice(glow::SHADER_STORAGE_BUFFER, data, glow::DYNAMIC_DRAW);
+                self.size = data.len();
+            }
+            gl.bind_buffer(glow::SHADER_STORAGE_BUFFER, None);
+            macros::check_for_gl_error!(gl, "storage_buffer_update");
+        }
+    }
+
+    pub fn bind(&self, gl: &glow::Context) {
+        unsafe {
+            gl.bind_buffer_base(glow::SHADER_STORAGE_BUFFER, self.binding, Some(self.buffer))
+        };
+    }
+
+    pub fn delete(&self, gl: &glow::Context) {
+        unsafe { gl.delete_buffer(self.buffer) };
+    }
+}
+
+/// OpenGL 4.3 or the `GL_ARB_shader_storage_buffer_object` extension
+pub fn supports_storage_buffers(gl: &glow::Context) -> bool {
+    let version = gl.version();
+    !version.is_embedded && (version.major, version.minor) >= (4, 3)
+        || gl
+            .supported_extensions()
+            .contains("GL_ARB_shader_storage_buffer_object")
+}
@@ -0,0 +1,203 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::CHANNEL_COUNT;
+
+const SHADERTOY_URL: &str = "https://www.shadertoy.com";
+
+/// Uniforms Shadertoy provides, declared with the types skuggbox uploads. The pre-processor
+/// adds the `main` calling `mainImage`.
+const SHADERTOY_HEADER: &str = "#version 330 core
+
+uniform vec3 iResolution;
+uniform float iTime;
+uniform float iTimeDelta;
+uniform int iFrame;
+uniform vec4 iMouse;
+uniform vec4 iDate;
+uniform vec3 iChannelResolution[4];
+uniform float iChannelTime[4];
+uniform sampler2D iChannel0;
+uniform sampler2D iChannel1;
+uniform sampler2D iChannel2;
+uniform sampler2D iChannel3;
+";
+
+#[derive(Debug, Deserialize)]
+struct Response {
+    #[serde(rename = "Shader")]
+    shader: Option<Shader>,
+    #[serde(rename = "Error")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Shader {
+    info: Info,
+    renderpass: Vec<RenderPass>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Info {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RenderPass {
+    #[serde(rename = "type")]
+    pass_type: String,
+    code: String,
+    #[serde(default)]
+    inputs: Vec<Input>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Input {
+    channel: usize,
+    ctype: String,
+    src: String,
+}
+
+/// The Image pass of a Shadertoy shader, converted to a stand-alone skuggbox shader
+#[derive(Debug)]
+pub struct ImportedShader {
+    pub name: String,
+    pub source: String,
+    /// Shadertoy path of the texture bound to each `iChannelN`
+    pub textures: [Option<String>; CHANNEL_COUNT],
+}
+
+/// Download a shader through the Shadertoy API, save it as `out` and download its textures
+/// next to it. Only the Image pass is imported.
+pub fn import_shader(id: &str, api_key: &str, out: &Path) -> Result<(), String> {
+    let client = reqwest::blocking::Client::new();
+    // errors leave out the URL, it contains the API key
+    let json = client
+        .get(format!("{}/api/v1/shaders/{}", SHADERTOY_URL, id))
+        .query(&[("key", api_key)])
+        .send()
+        .and_then(|response| response.error_for_status())
+        .and_then(|response| response.text())
+        .map_err(|e| format!("request failed: {}", e.without_url()))?;
+
+    let shader = parse_shader(&json)?;
+    fs::write(out, &shader.source).map_err(|e| format!("could not write {:?}: {}", out, e))?;
+    log::info!("Imported '{}' into {:?}", shader.name, out);
+
+    let base_dir = out.parent().unwrap_or_else(|| Path::new("."));
+    let mut channel_args: Vec<String> = vec![];
+    for (index, texture) in shader.textures.iter().enumerate() {
+        if let Some(texture) = texture {
+            let path = download_texture(&client, texture, base_dir)?;
+            channel_args.push(format!("--channel{} {}", index, path.display()));
+        }
+    }
+
+    log::info!(
+        "Run it with: skuggbox -f {} {}",
+        out.display(),
+        channel_args.join(" ")
+    );
+    Ok(())
+}
+
+/// Convert an API response into a skuggbox shader with the Shadertoy uniforms declared up
+/// front
+pub fn parse_shader(json: &str) -> Result<ImportedShader, String> {
+    let response: Response =
+        serde_json::from_str(json).map_err(|e| format!("unexpected response: {}", e))?;
+    if let Some(error) = response.error {
+        return Err(error);
+    }
+    let shader = response.shader.ok_or("response contains no shader")?;
+
+    if shader.renderpass.len() > 1 {
+        log::warn!("Only the Image pass is imported, other passes are ignored");
+    }
+    let image = shader
+        .renderpass
+        .iter()
+        .find(|pass| pass.pass_type == "image")
+        .ok_or("shader has no Image pass")?;
+
+    let mut textures: [Option<String>; CHANNEL_COUNT] = Default::default();
+    for input in &image.inputs {
+        match input.ctype.as_str() {
+            "texture" if input.channel < CHANNEL_COUNT => {
+                textures[input.channel] = Some(input.src.clone())
+            }
+            ctype => log::warn!(
+                "iChannel{} uses an unsupported input ({}), it's left unbound",
+                input.channel,
+                ctype
+            ),
+        }
+    }
+
+    Ok(ImportedShader {
+        name: shader.info.name,
+        source: format!("{}\n{}\n", SHADERTOY_HEADER, image.code),
+        textures,
+    })
+}
+
+fn download_texture(
+    client: &reqwest::blocking::Client,
+    src: &str,
+    base_dir: &Path,
+) -> Result<PathBuf, String> {
+    let file_name = Path::new(src)
+        .file_name()
+        .ok_or_else(|| format!("invalid texture path {}", src))?;
+    let path = base_dir.join(file_name);
+
+    let bytes = client
+        .get(format!("{}{}", SHADERTOY_URL, src))
+        .send()
+        .and_then(|response| response.error_for_status())
+        .and_then(|response| response.bytes())
+        .map_err(|e| format!("could not download {}: {}", src, e))?;
+
+    fs::write(&path, bytes).map_err(|e| format!("could not write {:?}: {}", path, e))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_shader;
+
+    #[test]
+    fn parse_image_pass() {
+        let json = r#"{
+            "Shader": {
+                "info": { "id": "abcdef", "name": "Test" },
+                "renderpass": [{
+                    "inputs": [
+                        { "channel": 1, "ctype": "texture", "src": "/media/a/noise.png" },
+                        { "channel": 0, "ctype": "music", "src": "/media/a/song.mp3" }
+                    ],
+                    "code": "void mainImage(out vec4 fragColor, in vec2 fragCoord) {}",
+                    "name": "Image",
+                    "type": "image"
+                }]
+            }
+        }"#;
+
+        let shader = parse_shader(json).unwrap();
+        assert_eq!(shader.name, "Test");
+        assert!(shader.source.starts_with("#version 330 core"));
+        assert!(shader.source.contains("void mainImage("));
+        // the pre-processor adds `main`
+        assert!(!shader.source.contains("void main("));
+        assert_eq!(shader.textures[0], None);
+        assert_eq!(shader.textures[1], Some("/media/a/noise.png".to_string()));
+    }
+
+    #[test]
+    fn parse_error_response() {
+        let json = r#"{ "Error": "Shader not found" }"#;
+        assert_eq!(parse_shader(json).unwrap_err(), "Shader not found");
+    }
+}
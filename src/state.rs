@@ -1,28 +1,147 @@
-use crate::camera::{CameraModel, OrbitCamera};
-use crate::{Mouse, ShaderError, Timer};
-use glam::Vec3;
+use crate::camera::{CameraKind, CameraModel, CameraSmoothing, SavedCamera};
+use crate::camera_path::{CameraPath, CameraPathMode};
+use crate::capture::timestamped_path;
+use crate::gif_recorder::{save_gif, GifRecorder, GifSettings};
+use crate::{
+    beat, load_uniform_values, number_key, save_uniform_values, spawn_guarded, uniform_values,
+    FrameTimes, GLSLValue, KeyboardState, MidiState, Mouse, Presets, ShaderError, TapTempo, Timer,
+    Uniform, UniformGraph, UniformValues, GRAPH_BUILTINS,
+};
+use glam::{Mat4, Vec2, Vec4};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use winit::event::VirtualKeyCode;
+
+/// How long a toast message stays on screen
+pub const TOAST_DURATION: Duration = Duration::from_secs(3);
 
 pub struct AppState {
+    /// Window size in physical pixels
     pub width: i32,
     pub height: i32,
+    /// Physical pixels per logical pixel of the window, above 1 on HiDPI displays
+    pub pixel_ratio: f32,
+    /// Render at `width * render_scale` x `height * render_scale`. Below 1.0 trades quality
+    /// for speed, above 1.0 supersamples.
+    pub render_scale: f32,
+    /// Render at exactly this size instead, letterboxed into the window. See `output_rect`
+    pub fixed_size: Option<(i32, i32)>,
+    /// Only render this part of the frame, the rest keeps the previous frame
+    pub render_region: Option<RenderRegion>,
+    pub draw_mode: DrawMode,
+    /// Dragging on the view selects the render region instead of moving the camera
+    pub selecting_region: bool,
     /// App state - is the application running?
     pub is_running: bool,
     pub timer: Timer,
+    /// Recent frame durations for the frame rate overlay
+    pub frame_times: FrameTimes,
+    /// Show the frame rate overlay in the top panel
+    pub show_stats: bool,
+    /// Show the window with the files and include tree of the active shader
+    pub show_shader_info: bool,
+    /// Started without shader files, show how to load one over the built-in shader
+    pub show_welcome: bool,
+    /// Short message confirming something happened, with the time it was shown
+    pub toast: Option<(String, Instant)>,
+    /// GPU time of a recent frame in nanoseconds, measured while the overlay is shown
+    pub gpu_time: Option<u32>,
+    /// Sync buffer swaps to the display refresh rate
+    pub vsync: bool,
+    /// Upper limit of frames per second
+    pub fps_cap: Option<f32>,
+    /// Frame delta scaled by `time_scale`, exposed as `iTimeDelta`
     pub delta_time: f32,
+    /// Upper limit of the unscaled frame delta, see `update_delta_time`
+    pub max_delta_time: f32,
     pub playback_time: f32,
+    /// Playback speed, negative values play in reverse
+    pub time_scale: f32,
+    /// Upper bound of the time scrubber, shaders have no duration of their own
+    pub max_time: f32,
+    /// In point of the loop, playback wraps back here from `loop_end`
+    pub loop_start: Option<f32>,
+    /// Out point of the loop, when `None` playback is unbounded
+    pub loop_end: Option<f32>,
+    /// Frames rendered since start, shader reload or rewind. Exposed as `iFrame`
+    pub frame_count: u64,
+    /// Show the average of successive frames, for path tracers. See `next_sample`
+    pub accumulate: bool,
+    /// Frames averaged into the accumulated image, including the current one. Exposed as
+    /// `iSampleCount`, 0 when not accumulating
+    pub sample_count: u32,
+    /// Start the accumulation over with the next frame, e.g. after a reload
+    pub reset_accumulation: bool,
+    /// What the accumulated image was rendered with, a change starts it over
+    pub accumulation_key: Option<AccumulationKey>,
+    /// Frame rate of `Action::StepFrame`
+    pub step_fps: f32,
+    /// Tempo the `iBeat` and `iBar` uniforms count in
+    pub bpm: f32,
+    /// Smoothed RMS level of the audio input, exposed as `iAudioLevel`
+    pub audio_level: f32,
+    pub tap_tempo: TapTempo,
     pub mouse: Mouse,
+    /// Keys held and pressed, uploaded to the keyboard texture
+    pub keyboard: KeyboardState,
+    /// Values the number keys set `key_value` to, `None` leaves them to their usual actions
+    pub key_bank: Option<[f32; 10]>,
+    /// The `iKey` uniform, set with the number keys when there's a key bank
+    pub key_value: f32,
+    /// The uniform plotted over time in the graph window, if any
+    pub uniform_graph: Option<UniformGraph>,
+    /// MIDI controllers mapped to custom uniforms
+    pub midi: MidiState,
     pub modifier: ActionModifier,
     /// Running or paused?
     pub play_mode: PlayMode,
     pub ui_visible: bool,
     pub is_fullscreen: bool,
     pub camera: Box<dyn CameraModel>,
-    // TODO(mathias): Move the camera pos into the camera model
-    pub camera_pos: Vec3,
+    /// Eases the dev camera towards where the input puts it, see `update_camera_smoothing`
+    pub camera_smoothing: CameraSmoothing,
+    pub camera_kind: CameraKind,
+    /// Sidecar file the dev camera is saved to and restored from, see `--restore-camera`
+    pub camera_file: Option<PathBuf>,
+    /// Recorded camera move, see `camera_transform`
+    pub camera_path: CameraPath,
+    /// File the camera path is saved to and loaded from, `--camera-path` or the sidecar file
+    /// of the shader
+    pub camera_path_file: Option<PathBuf>,
     pub shader_error: Option<ShaderError>,
+    /// Pause playback while there's a shader error, see `set_shader_error`
+    pub pause_on_error: bool,
+    /// Play mode to return to once the shader error is fixed
+    pub resume_play_mode: Option<PlayMode>,
+    /// Index of the shader rendered when running several shaders without passes
+    pub active_shader: usize,
+    /// Save the rendered frame, before the UI is painted, to this PNG
+    pub take_screenshot: Option<PathBuf>,
+    /// The last few seconds of frames, captured like screenshots, saved as a GIF with F8
+    pub gif_recorder: GifRecorder,
 
     pub scene_vars: SceneVars,
+    /// Shows where a shader doesn't cover the frame or writes alpha
+    pub clear_color: [f32; 4],
+    /// Index into `ShaderService::solo_textures` of the texture shown instead of the final shader
+    pub solo_channel: Option<usize>,
+    /// Uniforms declared in the shaders which are edited from the UI, keyed by name
+    pub custom_uniforms: BTreeMap<String, Uniform>,
+    /// Sidecar file the custom uniform values are saved to and restored from
+    pub uniforms_file: Option<PathBuf>,
+    /// Custom uniforms were edited since they were last saved or restored
+    pub uniforms_changed: bool,
+    /// Restore from `uniforms_file` once the shader compiles, after opening another shader
+    pub restore_uniforms_on_compile: bool,
+    /// Named snapshots of the custom uniform values
+    pub presets: Presets,
+    /// Sidecar file the presets are saved to and loaded from
+    pub presets_file: Option<PathBuf>,
+    /// Ask before closing the window with unsaved uniform edits, off with `--no-confirm-quit`
+    pub confirm_quit: bool,
+    pub show_quit_dialog: bool,
 }
 
 impl Default for AppState {
@@ -30,29 +149,546 @@ impl Default for AppState {
         Self {
             width: 1024,
             height: 768,
+            pixel_ratio: 1.0,
+            render_scale: 1.0,
+            fixed_size: None,
+            render_region: None,
+            draw_mode: DrawMode::default(),
+            selecting_region: false,
             is_running: true,
             timer: Timer::default(),
+            frame_times: FrameTimes::default(),
+            show_stats: false,
+            show_shader_info: false,
+            show_welcome: false,
+            toast: None,
+            gpu_time: None,
+            vsync: false,
+            fps_cap: None,
             delta_time: 0.0,
+            max_delta_time: 0.1,
             playback_time: 0.0,
+            time_scale: 1.0,
+            max_time: 60.0,
+            loop_start: None,
+            loop_end: None,
+            frame_count: 0,
+            step_fps: 60.0,
+            bpm: 120.0,
+            key_bank: None,
+            key_value: 0.0,
+            uniform_graph: None,
+            accumulate: false,
+            sample_count: 0,
+            reset_accumulation: false,
+            accumulation_key: None,
+            audio_level: 0.0,
+            tap_tempo: TapTempo::default(),
             mouse: Mouse::default(),
+            keyboard: KeyboardState::default(),
+            midi: MidiState::default(),
             modifier: ActionModifier::Normal,
             play_mode: PlayMode::Playing,
             ui_visible: true,
             is_fullscreen: false,
-            camera: Box::from(OrbitCamera::default()),
-            camera_pos: Vec3::default(),
+            camera: CameraKind::default().camera(),
+            camera_smoothing: CameraSmoothing::default(),
+            camera_kind: CameraKind::default(),
+            camera_file: None,
+            camera_path: CameraPath::default(),
+            camera_path_file: None,
             shader_error: None,
+            pause_on_error: false,
+            resume_play_mode: None,
+            active_shader: 0,
+            take_screenshot: None,
+            gif_recorder: GifRecorder::new(GifSettings::default()),
             scene_vars: Default::default(),
+            clear_color: [0.0, 0.0, 0.0, 1.0],
+            solo_channel: None,
+            custom_uniforms: BTreeMap::new(),
+            uniforms_file: None,
+            uniforms_changed: false,
+            restore_uniforms_on_compile: false,
+            presets: Presets::default(),
+            presets_file: None,
+            confirm_quit: true,
+            show_quit_dialog: false,
         }
     }
 }
 
+impl AppState {
+    /// Take the frame delta from the timer. A frame stalled by e.g. dragging the window only
+    /// advances playback by `max_delta_time`, so the time doesn't jump and physics style shaders
+    /// don't blow up.
+    pub fn update_delta_time(&mut self) {
+        // TODO(mathias): Remove this. Only use `app_state.timer.delta_time`
+        self.delta_time = self.timer.delta_time.min(self.max_delta_time) * self.time_scale;
+    }
+
+    /// Save the buffered frames and encode them on a background thread, palette quantization
+    /// takes a while
+    pub fn save_gif_recording(&mut self) {
+        let frames = self.gif_recorder.take_frames();
+        if frames.is_empty() {
+            self.show_toast("No frames recorded yet");
+            return;
+        }
+        let fps = self.gif_recorder.settings.fps;
+        let path = timestamped_path("recording", "gif");
+        self.show_toast(format!("Saving {}", path.display()));
+
+        let _ = spawn_guarded("GIF encoder", move || match save_gif(&path, frames, fps) {
+            Ok(_) => log::info!("GIF: Saved to {:?}", path),
+            Err(e) => log::error!("GIF: Failed to save {:?}: {}", path, e),
+        });
+    }
+
+    /// Show a short message in the UI for `TOAST_DURATION`
+    pub fn show_toast(&mut self, message: impl Into<String>) {
+        let message = message.into();
+        log::info!("{}", message);
+        self.toast = Some((message, Instant::now()));
+    }
+
+    pub fn toast_message(&self) -> Option<&str> {
+        match &self.toast {
+            Some((message, shown)) if shown.elapsed() < TOAST_DURATION => Some(message),
+            _ => None,
+        }
+    }
+
+    /// Update the shader error. With `pause_on_error` playback is paused when an error appears
+    /// and the previous play mode is restored when it's gone, so time doesn't jump ahead while
+    /// fixing the shader.
+    pub fn set_shader_error(&mut self, shader_error: Option<ShaderError>) {
+        let had_error = self.shader_error.is_some();
+        self.shader_error = shader_error;
+
+        if !self.pause_on_error {
+            return;
+        }
+
+        match (had_error, self.shader_error.is_some()) {
+            (false, true) => {
+                self.resume_play_mode = Some(self.play_mode);
+                self.play_mode = PlayMode::Paused;
+            }
+            (true, false) => {
+                if let Some(play_mode) = self.resume_play_mode.take() {
+                    if play_mode == PlayMode::Playing {
+                        // avoid counting the paused time as one long frame
+                        self.timer.start();
+                    }
+                    self.play_mode = play_mode;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Start playback at `time`, with `iFrame` set to the frame that time falls in when
+    /// stepping at `step_fps`, so a single frame looks like it does during playback
+    pub fn start_at(&mut self, time: f32, paused: bool) {
+        self.playback_time = time.max(0.0);
+        self.frame_count = step_frame(self.playback_time, self.step_fps, 0).1;
+        if paused {
+            self.play_mode = PlayMode::Paused;
+        }
+    }
+
+    /// The `iKey` value a key sets, if it's a number key and there's a key bank
+    pub fn key_bank_value(&self, key: VirtualKeyCode) -> Option<f32> {
+        Some(self.key_bank?[number_key(key)?])
+    }
+
+    /// Set a custom uniform, one value per component
+    pub fn set_uniform(&mut self, name: &str, values: &[f32]) -> Result<(), String> {
+        let value = self
+            .custom_uniforms
+            .get_mut(name)
+            .and_then(|uniform| uniform.value.as_mut())
+            .ok_or_else(|| format!("No custom uniform named {}", name))?;
+        let components = value.components_mut();
+        if components.len() != values.len() {
+            return Err(format!(
+                "{} takes {} values, got {}",
+                name,
+                components.len(),
+                values.len()
+            ));
+        }
+        components.copy_from_slice(values);
+        self.uniforms_changed = true;
+        Ok(())
+    }
+
+    /// Replace the custom uniforms with the ones found after a recompile. Uniforms that still
+    /// exist with the same type keep their value, matched by name since locations change
+    /// on every compile. Uniforms that disappeared are dropped.
+    pub fn set_custom_uniforms(&mut self, uniforms: Vec<Uniform>) {
+        let previous = std::mem::take(&mut self.custom_uniforms);
+        self.custom_uniforms = uniforms
+            .into_iter()
+            .map(|mut uniform| {
+                if let Some(old) = previous.get(&uniform.name) {
+                    if old.glsl_type == uniform.glsl_type {
+                        uniform.value = old.value.clone();
+                    }
+                }
+                (uniform.name.clone(), uniform)
+            })
+            .collect();
+    }
+
+    /// Count the sample rendered this frame into the accumulated image and return the number
+    /// of samples. Starts over at 1 after a reset or when the time, camera, mouse, size or
+    /// custom uniforms changed since the last sample.
+    pub fn next_sample(&mut self) -> u32 {
+        let key = AccumulationKey {
+            time: self.playback_time,
+            camera: self.current_camera_transform(),
+            mouse: self.mouse_uniform(),
+            size: self.render_size(),
+            uniforms: uniform_values(&self.custom_uniforms),
+        };
+        if self.reset_accumulation || self.accumulation_key.as_ref() != Some(&key) {
+            self.sample_count = 0;
+            self.reset_accumulation = false;
+        }
+        self.accumulation_key = Some(key);
+        self.sample_count += 1;
+        self.sample_count
+    }
+
+    /// Current value of a uniform that can be graphed, see `graph_uniforms`
+    pub fn graph_value(&self, name: &str) -> Option<f32> {
+        let value = match name {
+            "iTime" => self.playback_time,
+            "iTimeDelta" => self.delta_time,
+            "iFrame" => self.frame_count as f32,
+            "iBeat" => beat(self.playback_time, self.bpm),
+            "iAudioLevel" => self.audio_level,
+            "iKey" => self.key_value,
+            "iSampleCount" => self.sample_count as f32,
+            _ => match self.custom_uniforms.get(name)?.value {
+                Some(GLSLValue::Float(value)) => value,
+                _ => return None,
+            },
+        };
+        Some(value)
+    }
+
+    /// The built-in float uniforms followed by the custom float uniforms
+    pub fn graph_uniforms(&self) -> Vec<String> {
+        let custom = self
+            .custom_uniforms
+            .values()
+            .filter(|uniform| matches!(uniform.value, Some(GLSLValue::Float(_))))
+            .map(|uniform| uniform.name.clone());
+        GRAPH_BUILTINS
+            .iter()
+            .map(|name| name.to_string())
+            .chain(custom)
+            .collect()
+    }
+
+    /// Add the value of the graphed uniform, call once per frame
+    pub fn update_uniform_graph(&mut self, delta_time: f32) {
+        let name = match &self.uniform_graph {
+            Some(graph) => graph.uniform.clone(),
+            None => return,
+        };
+        let value = self.graph_value(&name);
+        if let (Some(graph), Some(value)) = (&mut self.uniform_graph, value) {
+            graph.push(delta_time, value);
+        }
+    }
+
+    /// Move the smoothed camera on by the frame time, call once per frame after the camera
+    /// has taken the input
+    pub fn update_camera_smoothing(&mut self, delta_time: f32) {
+        let target = self.camera.calculate_uniform_data();
+        self.camera_smoothing.update(&target, delta_time);
+    }
+
+    /// Camera transform of this frame. While recording it's added to the camera path at the
+    /// current playback time, during playback the path replaces the live camera.
+    pub fn camera_transform(&mut self) -> Mat4 {
+        let transform = self.current_camera_transform();
+        if self.camera_path.mode == CameraPathMode::Recording {
+            self.camera_path.record(self.playback_time, &transform);
+        }
+        transform
+    }
+
+    /// Camera transform of this frame without recording it, see `camera_transform`
+    pub fn current_camera_transform(&mut self) -> Mat4 {
+        if self.camera_path.mode == CameraPathMode::Playback {
+            if let Some(transform) = self.camera_path.sample(self.playback_time) {
+                return transform;
+            }
+        }
+
+        match self.camera_smoothing.transform() {
+            Some(transform) => transform,
+            None => self.camera.calculate_uniform_data(),
+        }
+    }
+
+    /// The values of the built-in uniforms and the custom uniforms as they're uploaded this
+    /// frame, one per line
+    pub fn dump_uniforms(&mut self) -> String {
+        let (width, height) = self.render_size();
+        let mouse = self.mouse_uniform();
+        let mouse_delta = self.mouse_delta_uniform();
+        // dumping must not record a keyframe into the camera path
+        let camera = self.current_camera_transform();
+
+        let mut lines = vec![
+            format!("iTime: {}", self.playback_time),
+            format!("iTimeDelta: {}", self.delta_time),
+            format!("iFrame: {}", self.frame_count),
+            format!("iResolution: {} {} 1", width, height),
+            format!("iMouse: {} {} {} {}", mouse.x, mouse.y, mouse.z, mouse.w),
+            format!("iMouseDelta: {} {}", mouse_delta.x, mouse_delta.y),
+            format!("iBeat: {}", beat(self.playback_time, self.bpm)),
+            format!("iSampleCount: {}", self.sample_count),
+            format!("iAudioLevel: {}", self.audio_level),
+            format!("iKey: {}", self.key_value),
+            format!("sbCameraTransform: {:?}", camera.to_cols_array_2d()),
+        ];
+        for uniform in self.custom_uniforms.values() {
+            match uniform.upload_value() {
+                Some(value) => lines.push(format!("{}: {:?}", uniform.name, value)),
+                None => lines.push(format!("{}: not set from skuggbox", uniform.name)),
+            }
+        }
+        lines.join("\n")
+    }
+
+    /// Save the custom uniform values to `uniforms_file`, if there is one
+    /// Returns `true` once the values are written
+    pub fn save_uniforms(&mut self) -> bool {
+        let file = match &self.uniforms_file {
+            Some(file) => file.clone(),
+            None => return false,
+        };
+        match save_uniform_values(&file, &self.custom_uniforms) {
+            Ok(()) => {
+                self.uniforms_changed = false;
+                self.show_toast(format!("Saved uniforms to {}", file.display()));
+                true
+            }
+            Err(e) => {
+                self.show_toast(format!("Failed to save uniforms: {}", e));
+                false
+            }
+        }
+    }
+
+    /// Restore the custom uniform values from `uniforms_file`, if they have been saved before
+    pub fn restore_uniforms(&mut self) {
+        let file = match &self.uniforms_file {
+            Some(file) if file.exists() => file,
+            _ => return,
+        };
+        match load_uniform_values(file, &mut self.custom_uniforms) {
+            Ok(()) => {
+                log::info!("Restored uniforms from {}", file.display());
+                self.uniforms_changed = false;
+            }
+            Err(e) => log::error!("Failed to restore uniforms: {}", e),
+        }
+    }
+
+    /// Write the presets to `presets_file` after they changed
+    pub fn save_presets(&self) {
+        if let Some(file) = &self.presets_file {
+            if let Err(e) = self.presets.save_to(file) {
+                log::error!("Failed to save presets: {}", e);
+            }
+        }
+    }
+
+    /// Load the presets of the current shader from `presets_file`, if there are any. The
+    /// transition settings are kept.
+    pub fn load_presets(&mut self) {
+        let transition = self.presets.transition;
+        self.presets = match &self.presets_file {
+            Some(file) if file.exists() => Presets::load(file).unwrap_or_else(|e| {
+                log::error!("Failed to load presets: {}", e);
+                Presets::default()
+            }),
+            _ => Presets::default(),
+        };
+        self.presets.transition = transition;
+    }
+
+    /// Save the dev camera to `camera_file`, if there is one
+    pub fn save_camera(&self) {
+        if let Some(file) = &self.camera_file {
+            match self.camera.save().save(file) {
+                Ok(()) => log::info!("Saved camera to {}", file.display()),
+                Err(e) => log::error!("Failed to save camera: {}", e),
+            }
+        }
+    }
+
+    /// Restore the dev camera from `camera_file`, if it has been saved before
+    pub fn restore_camera(&mut self) {
+        let file = match &self.camera_file {
+            Some(file) if file.exists() => file,
+            _ => return,
+        };
+        match SavedCamera::load(file) {
+            Ok(saved) => {
+                log::info!("Restored camera from {}", file.display());
+                self.camera_kind = saved.kind();
+                self.camera = saved.camera();
+            }
+            Err(e) => log::error!("Failed to restore camera: {}", e),
+        }
+    }
+
+    /// Size of the rendered image, the fixed size or the window size with the render scale
+    /// applied, never smaller than 1x1
+    pub fn render_size(&self) -> (i32, i32) {
+        if let Some(fixed_size) = self.fixed_size {
+            return fixed_size;
+        }
+        let scale = |size: i32| ((size as f32 * self.render_scale).round() as i32).max(1);
+        (scale(self.width), scale(self.height))
+    }
+
+    /// Where the rendered image is shown in the window as `(x, y, width, height)`, in pixels
+    /// from the bottom left. A fixed size keeps its aspect ratio with black bars around it.
+    pub fn output_rect(&self) -> (i32, i32, i32, i32) {
+        let (width, height) = match self.fixed_size {
+            Some(fixed_size) => fixed_size,
+            None => return (0, 0, self.width, self.height),
+        };
+        let scale = f32::min(
+            self.width as f32 / width as f32,
+            self.height as f32 / height as f32,
+        );
+        let fit = |size: i32| ((size as f32 * scale).round() as i32).max(1);
+        let (width, height) = (fit(width), fit(height));
+        (
+            (self.width - width) / 2,
+            (self.height - height) / 2,
+            width,
+            height,
+        )
+    }
+
+    /// The `iMouse` uniform, see `Mouse::shadertoy_uniform`, in pixels of the rendered image
+    pub fn mouse_uniform(&self) -> Vec4 {
+        let mouse = self.mouse.shadertoy_uniform(self.height as f32);
+        if mouse == Vec4::ZERO {
+            return mouse;
+        }
+
+        let (x, y, _, _) = self.output_rect();
+        let offset = Vec2::new(x as f32, y as f32);
+        let scale = self.output_scale();
+        let drag = (mouse.truncate().truncate() - offset) * scale;
+        // the signs of the click position carry the button state, only map the magnitude
+        let click = Vec2::new(mouse.z, mouse.w);
+        let click = click.signum() * (click.abs() - offset) * scale;
+
+        Vec4::new(drag.x, drag.y, click.x, click.y)
+    }
+
+    /// The `iMouseDelta` uniform, see `Mouse::drag_delta`, in pixels of the rendered image
+    pub fn mouse_delta_uniform(&self) -> Vec2 {
+        self.mouse.drag_delta() * self.output_scale()
+    }
+
+    /// Pixels of the rendered image per pixel of the window
+    fn output_scale(&self) -> Vec2 {
+        let (_, _, width, height) = self.output_rect();
+        let (render_width, render_height) = self.render_size();
+        Vec2::new(
+            render_width as f32 / width as f32,
+            render_height as f32 / height as f32,
+        )
+    }
+}
+
 /// Bad naming but these are the values we can set from within skuggbox like colors
 #[derive(Default)]
 pub struct SceneVars {
     pub color_a: [f32; 3],
 }
 
+/// Everything an accumulated image depends on besides the shader, see `AppState::next_sample`
+#[derive(Debug, PartialEq)]
+pub struct AccumulationKey {
+    time: f32,
+    camera: Mat4,
+    mouse: Vec4,
+    size: (i32, i32),
+    uniforms: UniformValues,
+}
+
+/// How the triangles of the shader are rasterized, wireframe and points help debugging the
+/// geometry of a vertex shader
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DrawMode {
+    #[default]
+    Filled,
+    Wireframe,
+    /// Only the vertices, sized by `gl_PointSize` in the vertex shader
+    Points,
+}
+
+impl DrawMode {
+    pub const ALL: [DrawMode; 3] = [DrawMode::Filled, DrawMode::Wireframe, DrawMode::Points];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            DrawMode::Filled => "filled",
+            DrawMode::Wireframe => "wireframe",
+            DrawMode::Points => "points",
+        }
+    }
+}
+
+/// Part of the rendered frame, in fractions of the render size from the bottom left so it
+/// stays put when the window is resized
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderRegion {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl RenderRegion {
+    /// The region between two corners, in any order, clamped to the frame
+    pub fn from_corners(a: (f32, f32), b: (f32, f32)) -> Self {
+        let (x0, x1) = (a.0.min(b.0).max(0.0), a.0.max(b.0).min(1.0));
+        let (y0, y1) = (a.1.min(b.1).max(0.0), a.1.max(b.1).min(1.0));
+        Self {
+            x: x0,
+            y: y0,
+            width: (x1 - x0).max(0.0),
+            height: (y1 - y0).max(0.0),
+        }
+    }
+
+    /// `(x, y, width, height)` in pixels of a `width` x `height` frame, at least one pixel
+    pub fn pixels(&self, width: i32, height: i32) -> (i32, i32, i32, i32) {
+        let x = ((self.x * width as f32) as i32).min(width - 1);
+        let y = ((self.y * height as f32) as i32).min(height - 1);
+        let right = ((self.x + self.width) * width as f32).ceil() as i32;
+        let top = ((self.y + self.height) * height as f32).ceil() as i32;
+        (x, y, (right - x).max(1), (top - y).max(1))
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ActionModifier {
     SuperSlow, // shift + ctrl
@@ -61,7 +697,7 @@ pub enum ActionModifier {
     Fast, // ctrl
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PlayMode {
     Playing,
     Paused,
@@ -76,6 +712,8 @@ impl Default for PlayMode {
 pub enum PlaybackControl {
     Forward(f32),
     Rewind(f32),
+    /// Jump to an absolute time, not affected by the modifier
+    Set(f32),
     Stop,
 }
 
@@ -94,6 +732,271 @@ pub fn seek(
     match playback_control {
         PlaybackControl::Forward(t) => playback_time + t * factor,
         PlaybackControl::Rewind(t) => f32::max(playback_time - t * factor, 0.0),
+        PlaybackControl::Set(t) => f32::max(t, 0.0),
         PlaybackControl::Stop => 0.0,
     }
 }
+
+/// Move `frames` frames at `fps` from the frame `playback_time` is in, snapping to the start
+/// of the frame. Returns the new time and its frame number, which stops at zero.
+pub fn step_frame(playback_time: f32, fps: f32, frames: i32) -> (f32, u64) {
+    // a little slack so times computed from a frame number land in that frame
+    let frame = (playback_time * fps + 0.001).floor() as i64 + frames as i64;
+    let frame = frame.max(0) as u64;
+    (frame as f32 / fps, frame)
+}
+
+/// Wrap the playback time around the loop once it passes the out point, or the in point when
+/// playing in reverse. Without a loop the time only stops at zero.
+pub fn wrap_loop(playback_time: f32, loop_start: Option<f32>, loop_end: Option<f32>) -> f32 {
+    let start = loop_start.unwrap_or(0.0);
+    match loop_end {
+        Some(end) if end > start && playback_time > end => {
+            start + (playback_time - end) % (end - start)
+        }
+        Some(end) if end > start && playback_time < start => {
+            end - (start - playback_time) % (end - start)
+        }
+        _ => f32::max(playback_time, 0.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        seek, step_frame, wrap_loop, ActionModifier, AppState, CameraPathMode, PlayMode,
+        PlaybackControl, RenderRegion,
+    };
+    use crate::{custom_uniforms, key_bank_values, GLSLValue, ShaderError, UniformGraph};
+    use glam::{Vec2, Vec4};
+    use winit::event::VirtualKeyCode;
+
+    #[test]
+    fn custom_uniform_values_survive_reload() {
+        let mut state = AppState::default();
+        state.set_custom_uniforms(custom_uniforms(
+            "uniform float speed;\nuniform float gone;\nuniform vec2 offset;",
+        ));
+        for uniform in state.custom_uniforms.values_mut() {
+            uniform.value.as_mut().unwrap().components_mut()[0] = 0.5;
+        }
+
+        // `offset` changed type so it's reset
+        state.set_custom_uniforms(custom_uniforms(
+            "uniform float speed;\nuniform vec3 offset;\nuniform float added;",
+        ));
+
+        let value = |name: &str| state.custom_uniforms[name].value.clone().unwrap();
+        assert_eq!(state.custom_uniforms.len(), 3);
+        assert_eq!(value("speed"), GLSLValue::Float(0.5));
+        assert_eq!(value("offset"), GLSLValue::Vec3([0.0; 3]));
+        assert_eq!(value("added"), GLSLValue::Float(0.0));
+    }
+
+    #[test]
+    fn start_paused_at_time() {
+        let mut state = AppState::default();
+        state.start_at(12.5, true);
+        assert_eq!(state.playback_time, 12.5);
+        assert_eq!(state.frame_count, 750);
+        assert!(matches!(state.play_mode, PlayMode::Paused));
+
+        let mut state = AppState::default();
+        state.start_at(-1.0, false);
+        assert_eq!(state.playback_time, 0.0);
+        assert!(matches!(state.play_mode, PlayMode::Playing));
+    }
+
+    #[test]
+    fn dump_builtin_and_custom_uniforms() {
+        let mut state = AppState {
+            playback_time: 1.5,
+            frame_count: 90,
+            ..Default::default()
+        };
+        state.set_custom_uniforms(custom_uniforms("uniform float speed;"));
+
+        let dump = state.dump_uniforms();
+        let lines: Vec<&str> = dump.lines().collect();
+        assert!(lines.contains(&"iTime: 1.5"));
+        assert!(lines.contains(&"iFrame: 90"));
+        assert!(lines.contains(&"iResolution: 1024 768 1"));
+        assert!(lines.contains(&"speed: Float(0.0)"));
+
+        state.camera_path.mode = CameraPathMode::Recording;
+        state.dump_uniforms();
+        assert!(state.camera_path.keyframes.is_empty());
+    }
+
+    #[test]
+    fn clamp_delta_time_after_stall() {
+        let mut state = AppState {
+            time_scale: 2.0,
+            ..Default::default()
+        };
+        state.timer.delta_time = 0.016;
+        state.update_delta_time();
+        assert_eq!(state.delta_time, 0.032);
+
+        state.timer.delta_time = 3.0;
+        state.update_delta_time();
+        assert_eq!(state.delta_time, 0.2);
+    }
+
+    #[test]
+    fn pause_on_error_restores_play_mode() {
+        let error = || Some(ShaderError::from("error".to_string()));
+        let mut state = AppState {
+            pause_on_error: true,
+            ..Default::default()
+        };
+
+        state.play_mode = PlayMode::Playing;
+        state.set_shader_error(error());
+        assert_eq!(state.play_mode, PlayMode::Paused);
+        state.set_shader_error(error());
+        state.set_shader_error(None);
+        assert_eq!(state.play_mode, PlayMode::Playing);
+
+        // paused before the error, stays paused after it
+        state.play_mode = PlayMode::Paused;
+        state.set_shader_error(error());
+        state.set_shader_error(None);
+        assert_eq!(state.play_mode, PlayMode::Paused);
+    }
+
+    #[test]
+    fn wrap_playback_time_in_loop() {
+        assert_eq!(wrap_loop(5.5, Some(2.0), Some(5.0)), 2.5);
+        assert_eq!(wrap_loop(4.0, Some(2.0), Some(5.0)), 4.0);
+        assert_eq!(wrap_loop(5.5, None, Some(5.0)), 0.5);
+        assert_eq!(wrap_loop(5.5, Some(2.0), None), 5.5);
+
+        // reverse playback
+        assert_eq!(wrap_loop(1.5, Some(2.0), Some(5.0)), 4.5);
+        assert_eq!(wrap_loop(-0.5, None, None), 0.0);
+    }
+
+    #[test]
+    fn letterbox_fixed_size() {
+        let mut state = AppState {
+            width: 1000,
+            height: 1000,
+            fixed_size: Some((1920, 1080)),
+            ..Default::default()
+        };
+        assert_eq!(state.render_size(), (1920, 1080));
+        assert_eq!(state.output_rect(), (0, 218, 1000, 563));
+
+        // a click in the center of the window is in the center of the image
+        state.mouse.is_lmb_down = true;
+        state.mouse.click_pos = Vec2::new(500.0, 500.0);
+        state.mouse.drag_pos = Vec2::new(500.0, 500.0);
+        let mouse = state.mouse_uniform();
+        assert!((mouse.x - 960.0).abs() < 2.0 && (mouse.y - 540.0).abs() < 2.0);
+        assert!((mouse.w + 540.0).abs() < 2.0);
+
+        state.fixed_size = None;
+        state.render_scale = 0.5;
+        assert_eq!(state.output_rect(), (0, 0, 1000, 1000));
+        assert_eq!(
+            state.mouse_uniform(),
+            Vec4::new(250.0, 250.0, 250.0, -250.0)
+        );
+    }
+
+    #[test]
+    fn accumulation_starts_over_on_changes() {
+        let mut state = AppState::default();
+        assert_eq!(state.next_sample(), 1);
+        assert_eq!(state.next_sample(), 2);
+        assert_eq!(state.next_sample(), 3);
+
+        state.playback_time = 1.0;
+        assert_eq!(state.next_sample(), 1);
+        state.width = 800;
+        assert_eq!(state.next_sample(), 1);
+        assert_eq!(state.next_sample(), 2);
+
+        state.reset_accumulation = true;
+        assert_eq!(state.next_sample(), 1);
+        assert!(!state.reset_accumulation);
+    }
+
+    #[test]
+    fn number_keys_set_key_value() {
+        let mut state = AppState::default();
+        assert_eq!(state.key_bank_value(VirtualKeyCode::Key3), None);
+
+        state.key_bank = Some(key_bank_values(Some(&[0.0, 0.5])));
+        assert_eq!(state.key_bank_value(VirtualKeyCode::Key1), Some(0.5));
+        assert_eq!(state.key_bank_value(VirtualKeyCode::Numpad7), Some(7.0));
+        assert_eq!(state.key_bank_value(VirtualKeyCode::A), None);
+    }
+
+    #[test]
+    fn graph_builtin_and_custom_uniforms() {
+        let mut state = AppState::default();
+        for uniform in custom_uniforms("uniform float speed;\nuniform vec2 offset;") {
+            state.custom_uniforms.insert(uniform.name.clone(), uniform);
+        }
+        let names = state.graph_uniforms();
+        assert!(names.contains(&"iAudioLevel".to_string()));
+        assert!(names.contains(&"speed".to_string()));
+        assert!(!names.contains(&"offset".to_string()));
+
+        state.audio_level = 0.5;
+        state.uniform_graph = Some(UniformGraph::new("iAudioLevel"));
+        state.update_uniform_graph(0.1);
+        state.audio_level = 0.25;
+        state.update_uniform_graph(0.1);
+        let graph = state.uniform_graph.as_ref().unwrap();
+        assert_eq!(graph.range(), Some((0.25, 0.5)));
+        assert_eq!(state.graph_value("offset"), None);
+    }
+
+    #[test]
+    fn render_region_pixels() {
+        let region = RenderRegion::from_corners((0.75, 1.5), (0.25, 0.5));
+        assert_eq!(
+            region,
+            RenderRegion {
+                x: 0.25,
+                y: 0.5,
+                width: 0.5,
+                height: 0.5
+            }
+        );
+        assert_eq!(region.pixels(100, 50), (25, 25, 50, 25));
+
+        // a click without dragging still renders a pixel
+        let region = RenderRegion::from_corners((1.0, 1.0), (1.0, 1.0));
+        assert_eq!(region.pixels(100, 50), (99, 49, 1, 1));
+    }
+
+    #[test]
+    fn seek_to_absolute_time() {
+        let fast = ActionModifier::Fast;
+        assert_eq!(seek(4.0, &fast, PlaybackControl::Set(2.5)), 2.5);
+        assert_eq!(seek(4.0, &fast, PlaybackControl::Set(-1.0)), 0.0);
+        assert_eq!(seek(4.0, &fast, PlaybackControl::Forward(1.0)), 12.0);
+    }
+
+    #[test]
+    fn step_frames() {
+        assert_eq!(step_frame(0.0, 30.0, 1), (1.0 / 30.0, 1));
+        assert_eq!(step_frame(1.0 / 30.0, 30.0, 1), (2.0 / 30.0, 2));
+        // in between frames snaps to the frame start
+        assert_eq!(step_frame(0.05, 30.0, 0), (1.0 / 30.0, 1));
+        assert_eq!(step_frame(0.05, 30.0, -1), (0.0, 0));
+        assert_eq!(step_frame(0.0, 30.0, -1), (0.0, 0));
+
+        let mut time = 0.0;
+        for frame in 1..=240 {
+            let (next, count) = step_frame(time, 24.0, 1);
+            assert_eq!(count, frame);
+            time = next;
+        }
+        assert_eq!(step_frame(time, 24.0, -240), (0.0, 0));
+    }
+}
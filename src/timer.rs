@@ -1,4 +1,6 @@
-use std::time::Instant;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
 
 pub struct Timer {
     /// current frame
@@ -35,3 +37,196 @@ impl Timer {
         self.last_time = self.time;
     }
 }
+
+/// Sleep for what's left of the frame interval of `fps`, counting from `frame_start`,
+/// so the time already spent on the frame is taken into account
+pub fn limit_frame_rate(frame_start: Instant, fps: f32) {
+    if fps <= 0.0 {
+        return;
+    }
+    let frame_interval = Duration::from_secs_f32(1.0 / fps);
+    if let Some(remaining) = frame_interval.checked_sub(frame_start.elapsed()) {
+        std::thread::sleep(remaining);
+    }
+}
+
+/// Durations of the most recent frames, used for frame rate statistics.
+/// Unlike `Timer` this keeps measuring while playback is paused.
+pub struct FrameTimes {
+    durations: VecDeque<f32>,
+    capacity: usize,
+    last_frame: Instant,
+}
+
+impl Default for FrameTimes {
+    fn default() -> Self {
+        Self::new(120)
+    }
+}
+
+impl FrameTimes {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            durations: VecDeque::with_capacity(capacity),
+            capacity,
+            last_frame: Instant::now(),
+        }
+    }
+
+    /// Call once per frame
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        self.push((now - self.last_frame).as_secs_f32());
+        self.last_frame = now;
+    }
+
+    /// Add a frame duration in seconds, dropping the oldest one when full
+    pub fn push(&mut self, duration: f32) {
+        if self.durations.len() == self.capacity {
+            self.durations.pop_front();
+        }
+        self.durations.push_back(duration);
+    }
+
+    /// Duration of the last frame in seconds
+    pub fn last(&self) -> f32 {
+        self.durations.back().copied().unwrap_or_default()
+    }
+
+    /// Average frame duration in seconds
+    pub fn average(&self) -> f32 {
+        if self.durations.is_empty() {
+            return 0.0;
+        }
+        self.durations.iter().sum::<f32>() / self.durations.len() as f32
+    }
+
+    /// Frames per second based on the last frame
+    pub fn fps(&self) -> f32 {
+        to_fps(self.last())
+    }
+
+    /// Frames per second based on the average frame duration
+    pub fn average_fps(&self) -> f32 {
+        to_fps(self.average())
+    }
+
+    /// Frame durations from oldest to newest
+    pub fn durations(&self) -> impl Iterator<Item = &f32> {
+        self.durations.iter()
+    }
+}
+
+fn to_fps(duration: f32) -> f32 {
+    if duration > 0.0 {
+        1.0 / duration
+    } else {
+        0.0
+    }
+}
+
+/// Beats in a bar, `iBar` counts bars of 4/4
+pub const BEATS_PER_BAR: f32 = 4.0;
+
+/// Beats since the start of playback at `bpm`, including the phase of the current beat
+pub fn beat(playback_time: f32, bpm: f32) -> f32 {
+    playback_time * bpm / 60.0
+}
+
+/// Sets the tempo from the interval between key presses
+#[derive(Default)]
+pub struct TapTempo {
+    taps: VecDeque<Instant>,
+}
+
+impl TapTempo {
+    /// Taps averaged into the tempo
+    const MAX_TAPS: usize = 8;
+    /// A pause longer than this starts counting again
+    const RESET_AFTER: Duration = Duration::from_secs(2);
+
+    /// Register a tap, returns the tempo in BPM from the average interval of the recent taps
+    /// once there are at least two
+    pub fn tap(&mut self, now: Instant) -> Option<f32> {
+        if let Some(last) = self.taps.back() {
+            if now.saturating_duration_since(*last) > Self::RESET_AFTER {
+                self.taps.clear();
+            }
+        }
+        if self.taps.len() == Self::MAX_TAPS {
+            self.taps.pop_front();
+        }
+        self.taps.push_back(now);
+
+        let first = self.taps.front()?;
+        let last = self.taps.back()?;
+        let intervals = self.taps.len() as u32 - 1;
+        if intervals == 0 {
+            return None;
+        }
+        let interval = (*last - *first) / intervals;
+        (interval > Duration::ZERO).then(|| 60.0 / interval.as_secs_f32())
+    }
+}
+
+/// Current local date as `(year, month - 1, day, seconds since midnight)`, matching Shadertoy's
+/// `iDate`. The seconds include the fractional part so second hands can move smoothly.
+pub fn local_date() -> [f32; 4] {
+    // `now_local` fails on some platforms when more than one thread is running, fall back to UTC
+    let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+    let seconds = now.hour() as f32 * 3600.0
+        + now.minute() as f32 * 60.0
+        + now.second() as f32
+        + now.nanosecond() as f32 / 1_000_000_000.0;
+
+    [
+        now.year() as f32,
+        u8::from(now.month()) as f32 - 1.0,
+        now.day() as f32,
+        seconds,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{beat, FrameTimes, TapTempo};
+
+    #[test]
+    fn frame_times_keep_the_latest_frames() {
+        let mut frame_times = FrameTimes::new(3);
+        assert_eq!(frame_times.average_fps(), 0.0);
+
+        for duration in [1.0, 0.5, 0.25, 0.25] {
+            frame_times.push(duration);
+        }
+
+        assert_eq!(frame_times.durations().count(), 3);
+        assert_eq!(frame_times.last(), 0.25);
+        assert_eq!(frame_times.fps(), 4.0);
+        assert_eq!(frame_times.average_fps(), 3.0);
+    }
+
+    #[test]
+    fn tap_tempo_averages_recent_taps() {
+        let mut tap_tempo = TapTempo::default();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        assert_eq!(tap_tempo.tap(at(0)), None);
+        assert_eq!(tap_tempo.tap(at(500)), Some(120.0));
+        assert_eq!(tap_tempo.tap(at(1250)), Some(96.0));
+
+        // a long pause starts over
+        assert_eq!(tap_tempo.tap(at(5000)), None);
+        assert_eq!(tap_tempo.tap(at(5400)), Some(150.0));
+    }
+
+    #[test]
+    fn beats_from_playback_time() {
+        assert_eq!(beat(0.0, 120.0), 0.0);
+        assert_eq!(beat(1.25, 120.0), 2.5);
+        assert_eq!(beat(3.0, 90.0), 4.5);
+    }
+}
@@ -1,10 +1,20 @@
-use crate::{Action, AppState, PlayMode, ShaderService};
+use std::path::Path;
+
+use crate::camera::CameraKind;
+use crate::camera_path::CameraPathMode;
+#[cfg(feature = "file-dialog")]
+use crate::SHADER_EXTENSIONS;
+use crate::{
+    beat, format_age, Action, AppState, DrawMode, Easing, FrameTimes, GLSLValue, GlInfo, MidiState,
+    PlayMode, Presets, ReloadStatus, RenderRegion, ShaderContent, ShaderService, UniformGraph,
+};
 
 pub fn top_bar(
     ui: &mut egui::Ui,
     app_state: &mut AppState,
     actions: &mut Vec<Action>,
     shader_service: &ShaderService,
+    gl_info: Option<&GlInfo>,
 ) {
     ui.horizontal(|ui| {
         // show the current time / beat
@@ -33,18 +43,841 @@ pub fn top_bar(
             actions.push(Action::TimeForward(1.0))
         }
 
+        // scrub anywhere in the timeline, playing or paused
+        let mut time = app_state.playback_time;
+        let scrubber = egui::Slider::new(&mut time, 0.0..=app_state.max_time)
+            .clamp_to_range(false)
+            .suffix(" s");
+        if ui.add(scrubber).changed() {
+            actions.push(Action::TimeSet(time))
+        }
+
+        loop_points(ui, app_state, actions);
+
+        // playback speed
+        let mut time_scale = app_state.time_scale;
+        let speed = egui::DragValue::new(&mut time_scale)
+            .speed(0.01)
+            .clamp_range(-8.0..=8.0)
+            .prefix("speed: ")
+            .suffix("x");
+        if ui.add(speed).changed() {
+            actions.push(Action::SetTimeScale(time_scale))
+        }
+
+        // tempo of the `iBeat` and `iBar` uniforms
+        let mut bpm = app_state.bpm;
+        let tempo = egui::DragValue::new(&mut bpm)
+            .speed(0.1)
+            .clamp_range(1.0..=999.0)
+            .max_decimals(1)
+            .suffix(" bpm");
+        if ui.add(tempo).changed() {
+            actions.push(Action::SetBpm(bpm))
+        }
+        if ui.button("tap").clicked() {
+            actions.push(Action::TapTempo)
+        }
+        ui.label(format!(
+            "beat: {:5.1}",
+            beat(app_state.playback_time, app_state.bpm)
+        ));
+
+        // render resolution relative to the window, unless it's fixed
+        if let Some((width, height)) = app_state.fixed_size {
+            ui.label(format!("{}x{}", width, height));
+        } else {
+            let mut render_scale = app_state.render_scale;
+            let scale = egui::Slider::new(&mut render_scale, 0.25..=2.0)
+                .step_by(0.05)
+                .text("scale");
+            if ui.add(scale).changed() {
+                actions.push(Action::SetRenderScale(render_scale))
+            }
+        }
+        render_region_buttons(ui, app_state, actions);
+        accumulation(ui, app_state, actions);
+        if shader_service
+            .shaders
+            .iter()
+            .any(|shader| shader.vertex.is_some())
+        {
+            draw_mode(ui, app_state, actions);
+        }
+
+        // switch between the loaded shaders, passes always render all of them
+        if shader_service.passes.is_empty() && shader_service.shaders.len() > 1 {
+            let mut active_shader = app_state.active_shader;
+            egui::ComboBox::from_id_source("active_shader")
+                .selected_text(shader_name(shader_service, active_shader))
+                .show_ui(ui, |ui| {
+                    for index in 0..shader_service.shaders.len() {
+                        ui.selectable_value(
+                            &mut active_shader,
+                            index,
+                            shader_name(shader_service, index),
+                        );
+                    }
+                });
+            if active_shader != app_state.active_shader {
+                actions.push(Action::SetActiveShader(active_shader))
+            }
+        }
+
+        solo_channel(ui, app_state, actions, shader_service);
+
+        reload_status(ui, shader_service);
+
         ui.spacing();
-        // show camera mode
+        // show camera mode, clicking switches it
         let cam_mode_str = match shader_service.use_camera_integration {
             true => "dev cam",
             false => "shader cam",
         };
-        ui.label(format!("Camera mode: {}", cam_mode_str));
+        ui.label("Camera mode:");
+        if ui
+            .button(cam_mode_str)
+            .on_hover_text("Switch between the dev camera and the shader's own camera")
+            .clicked()
+        {
+            actions.push(Action::ToggleCameraIntegration);
+        }
+        dev_camera(ui, app_state, actions);
+        ui.menu_button("Camera path", |ui| camera_path(ui, app_state, actions));
 
         ui.spacing();
 
         ui.with_layout(egui::Layout::left_to_right(egui::Align::LEFT), |ui| {
             ui.color_edit_button_rgb(&mut app_state.scene_vars.color_a);
+            ui.color_edit_button_rgba_unmultiplied(&mut app_state.clear_color)
+                .on_hover_text("Background");
         });
+
+        if app_state.show_stats {
+            ui.spacing();
+            frame_stats(ui, &app_state.frame_times);
+            if let Some(gpu_time) = app_state.gpu_time {
+                ui.label(format!("GPU: {:.2} ms", gpu_time as f32 / 1_000_000.0));
+            }
+
+            ui.menu_button("Graph", |ui| graph_menu(ui, app_state, actions));
+
+            let mut vsync = app_state.vsync;
+            if ui.checkbox(&mut vsync, "vsync").changed() {
+                actions.push(Action::ToggleVsync);
+            }
+
+            // a cap of 0 means no cap
+            let mut fps_cap = app_state.fps_cap.unwrap_or(0.0);
+            let cap = egui::DragValue::new(&mut fps_cap)
+                .clamp_range(0.0..=240.0)
+                .prefix("fps cap: ");
+            if ui.add(cap).changed() {
+                actions.push(Action::SetFpsCap((fps_cap > 0.0).then_some(fps_cap)));
+            }
+        }
+
+        open_file_button(ui, actions);
+        if let Some(shader) = shader_service.shaders.get(app_state.active_shader) {
+            save_flattened_button(ui, actions, &shader.content.shader_id);
+        }
+
+        if let Some(gl_info) = gl_info {
+            ui.menu_button("About", |ui| about(ui, gl_info, actions));
+        }
     });
 }
+
+/// A dot colored by the outcome of the latest reload and how long ago it was, so it's clear
+/// whether a save was picked up
+fn reload_status(ui: &mut egui::Ui, shader_service: &ShaderService) {
+    let color = match shader_service.reload_status() {
+        ReloadStatus::Idle => egui::Color32::GRAY,
+        ReloadStatus::Ok => egui::Color32::GREEN,
+        ReloadStatus::Error => egui::Color32::RED,
+    };
+    let watching = match shader_service.watched_file_count() {
+        Some(count) => format!("Watching {} files for changes", count),
+        None => "Not watching for changes".to_string(),
+    };
+    let reloaded = match shader_service.last_reload {
+        Some(last_reload) => format!("reloaded {} ago", format_age(last_reload.elapsed())),
+        None => "not loaded".to_string(),
+    };
+
+    ui.label(egui::RichText::new("●").color(color))
+        .on_hover_text(&watching);
+    ui.label(reloaded).on_hover_text(watching);
+}
+
+/// The OpenGL context skuggbox runs on, handy when a shader only fails on some machines
+fn about(ui: &mut egui::Ui, gl_info: &GlInfo, actions: &mut Vec<Action>) {
+    ui.label(format!("skuggbox {}", env!("CARGO_PKG_VERSION")));
+    ui.separator();
+    ui.label(format!("OpenGL: {}", gl_info.version));
+    ui.label(format!("GLSL: {}", gl_info.shading_language_version));
+    ui.label(format!("Renderer: {}", gl_info.renderer));
+    ui.label(format!("Vendor: {}", gl_info.vendor));
+    ui.separator();
+    if ui.button("Shader files").clicked() {
+        actions.push(Action::UIToggleShaderInfo);
+        ui.close_menu();
+    }
+}
+
+/// Shown over the built-in shader when no shader files were given
+pub fn welcome(ui: &mut egui::Ui, actions: &mut Vec<Action>) {
+    ui.label("skuggbox is running its built-in shader. Start it with a shader file:");
+    ui.monospace("skuggbox -f /path/to/shader.glsl");
+    ui.label("or create a new one:");
+    ui.monospace("skuggbox -n /path/to/shader.glsl");
+    open_file_button(ui, actions);
+}
+
+/// Pick a shader with the native file dialog
+#[cfg(feature = "file-dialog")]
+fn open_file_button(ui: &mut egui::Ui, actions: &mut Vec<Action>) {
+    if ui.button("Open…").clicked() {
+        let file = rfd::FileDialog::new()
+            .add_filter("Shaders", &SHADER_EXTENSIONS)
+            .pick_file();
+        if let Some(file) = file {
+            actions.push(Action::OpenFile(file));
+        }
+    }
+}
+
+#[cfg(not(feature = "file-dialog"))]
+fn open_file_button(_ui: &mut egui::Ui, _actions: &mut Vec<Action>) {}
+
+/// Pick where to save the active shader as a single self-contained file
+#[cfg(feature = "file-dialog")]
+fn save_flattened_button(ui: &mut egui::Ui, actions: &mut Vec<Action>, shader_id: &str) {
+    if ui.button("Save flattened…").clicked() {
+        let file = rfd::FileDialog::new()
+            .add_filter("Shaders", &SHADER_EXTENSIONS)
+            .set_file_name(format!("{}.flat.glsl", shader_id))
+            .save_file();
+        if let Some(file) = file {
+            actions.push(Action::SaveFlattened(file));
+        }
+    }
+}
+
+#[cfg(not(feature = "file-dialog"))]
+fn save_flattened_button(_ui: &mut egui::Ui, _actions: &mut Vec<Action>, _shader_id: &str) {}
+
+/// Render only part of a heavy shader to dial in a detail faster
+fn render_region_buttons(ui: &mut egui::Ui, app_state: &mut AppState, actions: &mut Vec<Action>) {
+    let select = ui
+        .selectable_label(app_state.selecting_region, "region")
+        .on_hover_text("Drag over the view to only render that part of the frame");
+    if select.clicked() {
+        app_state.selecting_region = !app_state.selecting_region;
+    }
+    if app_state.render_region.is_some() && ui.button("full frame").clicked() {
+        actions.push(Action::SetRenderRegion(None));
+    }
+}
+
+/// Progressive rendering for path tracers, with the number of samples averaged so far
+fn accumulation(ui: &mut egui::Ui, app_state: &AppState, actions: &mut Vec<Action>) {
+    let toggle = ui
+        .selectable_label(app_state.accumulate, "accumulate")
+        .on_hover_text("Average the frames until the time, camera or uniforms change");
+    if toggle.clicked() {
+        actions.push(Action::ToggleAccumulation);
+    }
+    if app_state.accumulate {
+        ui.label(format!("{} samples", app_state.sample_count));
+        if ui.button("reset").clicked() {
+            actions.push(Action::ResetAccumulation);
+        }
+    }
+}
+
+/// Filled, wireframe or points, to see the geometry of a vertex shader
+fn draw_mode(ui: &mut egui::Ui, app_state: &AppState, actions: &mut Vec<Action>) {
+    egui::ComboBox::from_id_source("draw_mode")
+        .selected_text(app_state.draw_mode.label())
+        .show_ui(ui, |ui| {
+            for draw_mode in DrawMode::ALL {
+                let selected = app_state.draw_mode == draw_mode;
+                if ui.selectable_label(selected, draw_mode.label()).clicked() {
+                    actions.push(Action::SetDrawMode(draw_mode));
+                }
+            }
+        });
+}
+
+/// Drag to select the render region while selecting, otherwise dim the frame around the
+/// render region. Shown after the panels so it only covers the free part of the window.
+pub fn render_region(ctx: &egui::Context, app_state: &AppState, actions: &mut Vec<Action>) {
+    let output = output_screen_rect(ctx, app_state);
+    // regions are measured from the bottom left, egui from the top left
+    let to_frame = |pos: egui::Pos2| {
+        (
+            (pos.x - output.left()) / output.width(),
+            (output.bottom() - pos.y) / output.height(),
+        )
+    };
+    let to_screen = |region: &RenderRegion| {
+        egui::Rect::from_min_max(
+            egui::pos2(
+                output.left() + region.x * output.width(),
+                output.bottom() - (region.y + region.height) * output.height(),
+            ),
+            egui::pos2(
+                output.left() + (region.x + region.width) * output.width(),
+                output.bottom() - region.y * output.height(),
+            ),
+        )
+    };
+    let stroke = egui::Stroke::new(1.0, egui::Color32::YELLOW);
+
+    if app_state.selecting_region {
+        // covers the view, so dragging doesn't move the camera meanwhile
+        egui::CentralPanel::default()
+            .frame(egui::Frame::none())
+            .show(ctx, |ui| {
+                let id = ui.id().with("render_region");
+                let response = ui.interact(ui.max_rect(), id, egui::Sense::drag());
+                ctx.set_cursor_icon(egui::CursorIcon::Crosshair);
+
+                let pointer = ui.input(|i| i.pointer.interact_pos());
+                if response.drag_started() {
+                    if let Some(pointer) = pointer {
+                        ui.data_mut(|data| data.insert_temp(id, pointer));
+                    }
+                }
+                let start: Option<egui::Pos2> = ui.data(|data| data.get_temp(id));
+                if let (Some(start), Some(pointer)) = (start, pointer) {
+                    let region = RenderRegion::from_corners(to_frame(start), to_frame(pointer));
+                    ui.painter().rect_stroke(to_screen(&region), 0.0, stroke);
+                    if response.drag_released() {
+                        ui.data_mut(|data| data.remove::<egui::Pos2>(id));
+                        actions.push(Action::SetRenderRegion(Some(region)));
+                    }
+                }
+            });
+        return;
+    }
+
+    if let Some(region) = &app_state.render_region {
+        let rect = to_screen(region);
+        let outside = [
+            egui::Rect::from_min_max(output.min, egui::pos2(output.max.x, rect.min.y)),
+            egui::Rect::from_min_max(egui::pos2(output.min.x, rect.max.y), output.max),
+            egui::Rect::from_min_max(
+                egui::pos2(output.min.x, rect.min.y),
+                egui::pos2(rect.min.x, rect.max.y),
+            ),
+            egui::Rect::from_min_max(
+                egui::pos2(rect.max.x, rect.min.y),
+                egui::pos2(output.max.x, rect.max.y),
+            ),
+        ];
+        let painter = ctx
+            .layer_painter(egui::LayerId::background())
+            .with_clip_rect(ctx.available_rect());
+        for outside in outside {
+            painter.rect_filled(outside, 0.0, egui::Color32::from_black_alpha(128));
+        }
+        painter.rect_stroke(rect, 0.0, stroke);
+    }
+}
+
+/// Where the rendered image is in the window in points, see `AppState::output_rect`
+fn output_screen_rect(ctx: &egui::Context, app_state: &AppState) -> egui::Rect {
+    let (x, y, width, height) = app_state.output_rect();
+    let points = |pixels: i32| pixels as f32 / ctx.pixels_per_point();
+    egui::Rect::from_min_size(
+        egui::pos2(points(x), points(app_state.height - y - height)),
+        egui::vec2(points(width), points(height)),
+    )
+}
+
+/// The message of `AppState::toast` near the bottom of the window while it lasts
+pub fn toast(ctx: &egui::Context, app_state: &AppState) {
+    if let Some(message) = app_state.toast_message() {
+        egui::Area::new("toast")
+            .anchor(egui::Align2::CENTER_BOTTOM, [0.0, -32.0])
+            .interactable(false)
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| ui.label(message));
+            });
+    }
+}
+
+/// What was loaded for the active shader: every file read while pre-processing and the
+/// include tree as it was inlined, to check that the expected files are watched
+pub fn shader_info(ui: &mut egui::Ui, shader_service: &ShaderService, active_shader: usize) {
+    let shader = match shader_service.shaders.get(active_shader) {
+        Some(shader) => shader,
+        None => {
+            ui.label("No shader loaded");
+            return;
+        }
+    };
+
+    egui::ScrollArea::vertical().show(ui, |ui| {
+        shader_content_info(ui, &shader.content);
+        if let Some(vertex) = &shader.vertex {
+            ui.separator();
+            shader_content_info(ui, vertex);
+        }
+    });
+}
+
+fn shader_content_info(ui: &mut egui::Ui, content: &ShaderContent) {
+    ui.heading(content.main_shader_path.display().to_string());
+
+    ui.label(format!("Files ({})", content.files.len()));
+    for file in &content.files {
+        ui.horizontal(|ui| {
+            ui.monospace(file.display().to_string());
+            ui.weak(modified_time(file));
+        });
+    }
+
+    ui.separator();
+    ui.label("Include tree");
+    for (depth, file) in content.include_tree() {
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        ui.horizontal(|ui| {
+            ui.add_space(depth as f32 * 16.0);
+            ui.monospace(name).on_hover_text(file.display().to_string());
+        });
+    }
+}
+
+/// Last modification of a file in local time, or why it's unknown
+fn modified_time(path: &Path) -> String {
+    let modified = match std::fs::metadata(path).and_then(|metadata| metadata.modified()) {
+        Ok(modified) => modified,
+        Err(e) => return e.to_string(),
+    };
+    let offset = time::UtcOffset::current_local_offset().unwrap_or(time::UtcOffset::UTC);
+    let modified = time::OffsetDateTime::from(modified).to_offset(offset);
+    format!(
+        "{}-{:02}-{:02} {:02}:{:02}:{:02}",
+        modified.year(),
+        modified.month() as u8,
+        modified.day(),
+        modified.hour(),
+        modified.minute(),
+        modified.second()
+    )
+}
+
+/// Pick the dev camera, save it and tune its speed
+fn dev_camera(ui: &mut egui::Ui, app_state: &mut AppState, actions: &mut Vec<Action>) {
+    let mut camera_kind = app_state.camera_kind;
+    egui::ComboBox::from_id_source("camera_kind")
+        .selected_text(format!("{:?}", camera_kind).to_lowercase())
+        .width(60.0)
+        .show_ui(ui, |ui| {
+            ui.selectable_value(&mut camera_kind, CameraKind::Orbit, "orbit");
+            ui.selectable_value(&mut camera_kind, CameraKind::Fly, "fly");
+        });
+    if camera_kind != app_state.camera_kind {
+        actions.push(Action::SetCameraKind(camera_kind));
+    }
+
+    if app_state.camera_file.is_some() && ui.button("save cam").clicked() {
+        actions.push(Action::SaveCamera);
+    }
+
+    if let Some(speed) = app_state.camera.speed_mut() {
+        let speed = egui::DragValue::new(speed)
+            .speed(0.05)
+            .clamp_range(0.01..=1000.0)
+            .prefix("cam speed: ");
+        ui.add(speed);
+    }
+
+    let smoothing = egui::DragValue::new(&mut app_state.camera_smoothing.time)
+        .speed(0.01)
+        .clamp_range(0.0..=2.0)
+        .prefix("smooth: ")
+        .suffix(" s");
+    ui.add(smoothing)
+        .on_hover_text("Seconds the camera takes to ease into place, 0 follows the input directly");
+}
+
+/// Record, replay, save and load the camera path
+fn camera_path(ui: &mut egui::Ui, app_state: &AppState, actions: &mut Vec<Action>) {
+    let mode = app_state.camera_path.mode;
+    ui.label(format!(
+        "{} keyframes",
+        app_state.camera_path.keyframes.len()
+    ));
+
+    let mut recording = mode == CameraPathMode::Recording;
+    if ui.checkbox(&mut recording, "record (R)").changed() {
+        actions.push(Action::CameraPathToggleRecording);
+    }
+    let mut playback = mode == CameraPathMode::Playback;
+    if ui.checkbox(&mut playback, "play (F6)").changed() {
+        actions.push(Action::CameraPathTogglePlayback);
+    }
+    if ui.button("clear").clicked() {
+        actions.push(Action::CameraPathClear);
+    }
+
+    ui.separator();
+    let file = match &app_state.camera_path_file {
+        Some(file) => file.display().to_string(),
+        None => "no camera path file".to_string(),
+    };
+    ui.label(file);
+    ui.horizontal(|ui| {
+        if ui.button("save").clicked() {
+            actions.push(Action::CameraPathSave);
+        }
+        if ui.button("load").clicked() {
+            actions.push(Action::CameraPathLoad);
+        }
+    });
+}
+
+/// Show one of the channels or buffers on its own to see what's in it
+fn solo_channel(
+    ui: &mut egui::Ui,
+    app_state: &AppState,
+    actions: &mut Vec<Action>,
+    shader_service: &ShaderService,
+) {
+    let textures = shader_service.solo_textures();
+    if textures.is_empty() {
+        return;
+    }
+
+    let name = |index: Option<usize>| {
+        index
+            .and_then(|index| textures.get(index))
+            .map_or("final", |texture| texture.name.as_str())
+    };
+    let mut solo_channel = app_state.solo_channel;
+    egui::ComboBox::from_id_source("solo_channel")
+        .selected_text(format!("show: {}", name(solo_channel)))
+        .show_ui(ui, |ui| {
+            ui.selectable_value(&mut solo_channel, None, name(None));
+            for index in 0..textures.len() {
+                ui.selectable_value(&mut solo_channel, Some(index), name(Some(index)));
+            }
+        });
+    if solo_channel != app_state.solo_channel {
+        actions.push(Action::SoloChannel(solo_channel));
+    }
+}
+
+fn shader_name(shader_service: &ShaderService, index: usize) -> &str {
+    shader_service
+        .shaders
+        .get(index)
+        .map_or("", |shader| shader.content.shader_id.as_str())
+}
+
+/// Toggle looping and edit the in and out points
+fn loop_points(ui: &mut egui::Ui, app_state: &AppState, actions: &mut Vec<Action>) {
+    let mut looping = app_state.loop_end.is_some();
+    let mut start = app_state.loop_start.unwrap_or(0.0);
+    let mut end = app_state.loop_end.unwrap_or(app_state.max_time);
+
+    if ui.checkbox(&mut looping, "loop").changed() {
+        match looping {
+            true => actions.push(Action::SetLoop(start, end)),
+            false => actions.push(Action::ClearLoop),
+        }
+    }
+
+    if looping {
+        let in_point = ui.add(egui::DragValue::new(&mut start).speed(0.1).suffix(" s"));
+        let out_point = ui.add(egui::DragValue::new(&mut end).speed(0.1).suffix(" s"));
+        if in_point.changed() || out_point.changed() {
+            actions.push(Action::SetLoop(start, end));
+        }
+    }
+}
+
+/// Frame rate readout followed by a sparkline of the recent frame times
+fn frame_stats(ui: &mut egui::Ui, frame_times: &FrameTimes) {
+    ui.label(format!(
+        "fps: {:5.1} ({:5.1} avg) {:5.2} ms",
+        frame_times.fps(),
+        frame_times.average_fps(),
+        frame_times.average() * 1000.0
+    ));
+
+    let size = egui::vec2(120.0, ui.spacing().interact_size.y);
+    let (rect, _response) = ui.allocate_exact_size(size, egui::Sense::hover());
+
+    let durations: Vec<f32> = frame_times.durations().copied().collect();
+    let max_duration = durations.iter().copied().fold(f32::EPSILON, f32::max);
+    let step = rect.width() / durations.len().max(2) as f32;
+
+    let points = durations
+        .iter()
+        .enumerate()
+        .map(|(i, duration)| {
+            egui::pos2(
+                rect.left() + i as f32 * step,
+                rect.bottom() - duration / max_duration * rect.height(),
+            )
+        })
+        .collect();
+
+    let stroke = egui::Stroke::new(1.0, ui.visuals().text_color());
+    ui.painter().add(egui::Shape::line(points, stroke));
+}
+
+/// Pick the uniform to plot over time
+fn graph_menu(ui: &mut egui::Ui, app_state: &AppState, actions: &mut Vec<Action>) {
+    let graphed = app_state.uniform_graph.as_ref().map(|graph| &graph.uniform);
+    for name in app_state.graph_uniforms() {
+        if ui.selectable_label(graphed == Some(&name), &name).clicked() {
+            actions.push(Action::GraphUniform(Some(name)));
+            ui.close_menu();
+        }
+    }
+}
+
+/// Window plotting the graphed uniform over the last seconds
+pub fn uniform_graph(ctx: &egui::Context, app_state: &mut AppState, actions: &mut Vec<Action>) {
+    let graph = match &mut app_state.uniform_graph {
+        Some(graph) => graph,
+        None => return,
+    };
+    let mut open = true;
+    egui::Window::new("Graph")
+        .open(&mut open)
+        .default_width(320.0)
+        .show(ctx, |ui| graph_plot(ui, graph));
+    if !open {
+        actions.push(Action::GraphUniform(None));
+    }
+}
+
+fn graph_plot(ui: &mut egui::Ui, graph: &mut UniformGraph) {
+    let (min, max) = graph.range().unwrap_or((0.0, 1.0));
+    ui.horizontal(|ui| {
+        ui.label(format!(
+            "{}: {:.3}",
+            graph.uniform,
+            graph.last().unwrap_or_default()
+        ));
+        let seconds = egui::DragValue::new(&mut graph.seconds)
+            .clamp_range(0.5..=60.0)
+            .speed(0.1)
+            .suffix(" s");
+        ui.add(seconds);
+    });
+
+    let size = egui::vec2(ui.available_width(), 100.0);
+    let (rect, _response) = ui.allocate_exact_size(size, egui::Sense::hover());
+    ui.painter()
+        .rect_filled(rect, 0.0, ui.visuals().extreme_bg_color);
+
+    // a flat line is drawn in the middle
+    let span = (max - min).max(f32::EPSILON);
+    let points = graph
+        .samples()
+        .map(|(age, value)| {
+            let y = match max > min {
+                true => (value - min) / span,
+                false => 0.5,
+            };
+            egui::pos2(
+                rect.right() - age / graph.seconds * rect.width(),
+                rect.bottom() - y * rect.height(),
+            )
+        })
+        .collect();
+    let stroke = egui::Stroke::new(1.0, ui.visuals().text_color());
+    ui.painter().add(egui::Shape::line(points, stroke));
+
+    ui.label(format!("min {:.3}  max {:.3}", min, max));
+}
+
+/// Sliders, or color pickers for colors, for the custom uniforms declared in the shaders
+pub fn uniforms_panel(ui: &mut egui::Ui, app_state: &mut AppState, actions: &mut Vec<Action>) {
+    ui.horizontal(|ui| {
+        ui.heading("Uniforms");
+        if app_state.uniforms_file.is_some() && ui.button("save").clicked() {
+            actions.push(Action::SaveUniforms);
+        }
+    });
+    egui::CollapsingHeader::new("Presets")
+        .show(ui, |ui| presets(ui, &mut app_state.presets, actions));
+
+    let midi = &mut app_state.midi;
+    if midi.enabled {
+        match midi.last_change {
+            Some(change) => ui.label(format!("MIDI: CC {} = {}", change.controller, change.value)),
+            None => ui.label("MIDI: waiting for input"),
+        };
+    }
+
+    let mut changed = false;
+    for (name, uniform) in app_state.custom_uniforms.iter_mut() {
+        let (min, max) = uniform.slider_range();
+        let is_color = uniform.color.is_some();
+        match &mut uniform.value {
+            Some(GLSLValue::Vec3(color)) if is_color => {
+                ui.horizontal(|ui| {
+                    changed |= ui.color_edit_button_rgb(color).changed();
+                    ui.label(name);
+                });
+            }
+            Some(GLSLValue::Vec4(color)) if is_color => {
+                ui.horizontal(|ui| {
+                    changed |= ui.color_edit_button_rgba_unmultiplied(color).changed();
+                    ui.label(name);
+                });
+            }
+            Some(value) => {
+                ui.label(name);
+                for (index, component) in value.components_mut().iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        changed |= ui.add(egui::Slider::new(component, min..=max)).changed();
+                        if midi.enabled {
+                            midi_learn_button(ui, midi, name, index);
+                        }
+                    });
+                }
+            }
+            None => {}
+        }
+    }
+    app_state.uniforms_changed |= changed;
+}
+
+/// Save, switch, rename and delete uniform presets and morph from the active one to another
+fn presets(ui: &mut egui::Ui, presets: &mut Presets, actions: &mut Vec<Action>) {
+    // the name typed in for saving or renaming, kept between frames
+    let name_id = ui.id().with("preset_name");
+    let mut name: String = ui
+        .data_mut(|data| data.get_temp(name_id))
+        .unwrap_or_default();
+    ui.horizontal(|ui| {
+        let name_edit = egui::TextEdit::singleline(&mut name)
+            .hint_text("name")
+            .desired_width(80.0);
+        ui.add(name_edit);
+        if ui
+            .add_enabled(!name.is_empty(), egui::Button::new("save"))
+            .clicked()
+        {
+            actions.push(Action::SavePreset(name.clone()));
+        }
+        if let Some(active) = &presets.active {
+            let rename = ui
+                .add_enabled(!name.is_empty(), egui::Button::new("rename"))
+                .on_hover_text(format!("Rename {}", active));
+            if rename.clicked() {
+                actions.push(Action::RenamePreset(active.clone(), name.clone()));
+            }
+        }
+    });
+    ui.data_mut(|data| data.insert_temp(name_id, name));
+
+    if presets.presets.is_empty() {
+        return;
+    }
+
+    // how applying a preset fades to it
+    ui.horizontal(|ui| {
+        let transition = &mut presets.transition;
+        let seconds = egui::DragValue::new(&mut transition.seconds)
+            .speed(0.05)
+            .clamp_range(0.0..=30.0)
+            .prefix("fade: ")
+            .suffix(" s");
+        ui.add(seconds);
+        egui::ComboBox::from_id_source("preset_easing")
+            .selected_text(format!("{:?}", transition.easing).to_lowercase())
+            .width(90.0)
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut transition.easing, Easing::Linear, "linear");
+                ui.selectable_value(&mut transition.easing, Easing::Smoothstep, "smoothstep");
+            });
+    });
+
+    ui.horizontal(|ui| {
+        let mut selected = presets.active.clone();
+        egui::ComboBox::from_id_source("preset")
+            .selected_text(selected.as_deref().unwrap_or("apply"))
+            .show_ui(ui, |ui| {
+                for name in presets.presets.keys() {
+                    ui.selectable_value(&mut selected, Some(name.clone()), name);
+                }
+            });
+        if let Some(name) = selected.filter(|name| presets.active.as_ref() != Some(name)) {
+            actions.push(Action::ApplyPreset(name));
+        }
+        if let Some(active) = &presets.active {
+            if ui.button("delete").clicked() {
+                actions.push(Action::DeletePreset(active.clone()));
+            }
+        }
+    });
+
+    let active = match &presets.active {
+        Some(active) => active,
+        None => return,
+    };
+    let (mut target, mut amount) = presets.morph.clone().unwrap_or_default();
+    ui.horizontal(|ui| {
+        egui::ComboBox::from_id_source("morph_target")
+            .selected_text(match target.is_empty() {
+                true => "morph to",
+                false => target.as_str(),
+            })
+            .show_ui(ui, |ui| {
+                for name in presets.presets.keys().filter(|name| *name != active) {
+                    ui.selectable_value(&mut target, name.clone(), name);
+                }
+            });
+        ui.add_enabled(
+            !target.is_empty(),
+            egui::Slider::new(&mut amount, 0.0..=1.0),
+        );
+    });
+    if !target.is_empty() && presets.morph != Some((target.clone(), amount)) {
+        actions.push(Action::MorphPreset(target, amount));
+    }
+}
+
+/// Asks to save the edited uniforms before the window closes
+pub fn quit_dialog(ui: &mut egui::Ui, uniforms_file: Option<&Path>, actions: &mut Vec<Action>) {
+    ui.label("The custom uniforms were edited since they were last saved.");
+    if let Some(file) = uniforms_file {
+        ui.label(format!("Saving writes them to {}", file.display()));
+    }
+    ui.horizontal(|ui| {
+        if uniforms_file.is_some() && ui.button("Save and quit").clicked() {
+            actions.push(Action::SaveUniformsAndExit);
+        }
+        if ui.button("Quit without saving").clicked() {
+            actions.push(Action::AppExit);
+        }
+        if ui.button("Cancel").clicked() {
+            actions.push(Action::CancelExit);
+        }
+    });
+}
+
+/// Shows the controller bound to a uniform component, click and move a knob to rebind it
+fn midi_learn_button(ui: &mut egui::Ui, midi: &mut MidiState, name: &str, component: usize) {
+    let target = (name.to_string(), component);
+    let learning = midi.learn.as_ref() == Some(&target);
+    let label = match (learning, midi.controller(name, component)) {
+        (true, _) => "move a knob".to_string(),
+        (false, Some(controller)) => format!("CC {}", controller),
+        (false, None) => "learn".to_string(),
+    };
+    if ui.selectable_label(learning, label).clicked() {
+        midi.learn = if learning { None } else { Some(target) };
+    }
+}
@@ -1,3 +1,8 @@
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
 pub fn string_between<'v>(value: &'v str, start: &str, end: &str) -> &'v str {
     if let Some(start_idx) = value.rfind(start) {
         if let Some(end_idx) = value.rfind(end) {
@@ -21,9 +26,66 @@ pub fn include_statement_from_string(shader_name: String) -> String {
     format!("#pragma include({});", shader_name)
 }
 
+/// Rounded down to the largest unit, e.g. "2s", "5m" or "1h"
+pub fn format_age(age: Duration) -> String {
+    match age.as_secs() {
+        seconds @ 0..=59 => format!("{}s", seconds),
+        seconds @ 60..=3599 => format!("{}m", seconds / 60),
+        seconds => format!("{}h", seconds / 3600),
+    }
+}
+
+/// Run `f` on a background thread named `name`. A panic is caught and logged, and the thread
+/// returns the panic message so its owner can report it instead of silently losing the thread.
+pub fn spawn_guarded<F>(name: &str, f: F) -> JoinHandle<Result<(), String>>
+where
+    F: FnOnce() + Send + 'static,
+{
+    let thread_name = name.to_string();
+    thread::Builder::new()
+        .name(name.to_string())
+        .spawn(move || {
+            panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+                let message = panic_message(payload.as_ref());
+                log::error!("The {} thread panicked: {}", thread_name, message);
+                message
+            })
+        })
+        .expect("failed to spawn thread")
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match (
+        payload.downcast_ref::<&str>(),
+        payload.downcast_ref::<String>(),
+    ) {
+        (Some(message), _) => message.to_string(),
+        (_, Some(message)) => message.clone(),
+        _ => "unknown panic".to_string(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{pragma_shader_name, string_between};
+    use std::time::Duration;
+
+    use super::{format_age, pragma_shader_name, spawn_guarded, string_between};
+
+    #[test]
+    fn panics_on_guarded_threads_are_returned() {
+        let thread = spawn_guarded("test", || panic!("lost {}", "file"));
+        assert_eq!(thread.join().unwrap(), Err("lost file".to_string()));
+
+        let thread = spawn_guarded("test", || {});
+        assert_eq!(thread.join().unwrap(), Ok(()));
+    }
+
+    #[test]
+    fn age_in_largest_unit() {
+        assert_eq!(format_age(Duration::from_millis(2500)), "2s");
+        assert_eq!(format_age(Duration::from_secs(125)), "2m");
+        assert_eq!(format_age(Duration::from_secs(7300)), "2h");
+    }
 
     #[test]
     fn is_string_between() {
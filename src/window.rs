@@ -1,21 +1,26 @@
 use glow::Context;
 use glutin::config::{Config, ConfigTemplateBuilder, GlConfig};
 use glutin::context::{
-    ContextApi, ContextAttributesBuilder, NotCurrentContext, NotCurrentGlContextSurfaceAccessor,
-    PossiblyCurrentContext, Version,
+    ContextApi, ContextAttributes, ContextAttributesBuilder, NotCurrentContext,
+    NotCurrentGlContextSurfaceAccessor, PossiblyCurrentContext, Version,
 };
 use glutin::display::{GetGlDisplay, GlDisplay};
-use glutin::surface::{GlSurface, Surface, WindowSurface};
+use glutin::surface::{GlSurface, Surface, SwapInterval, WindowSurface};
 use std::ffi::CString;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use glutin_winit::{DisplayBuilder, GlWindow};
 use raw_window_handle::HasRawWindowHandle;
-use winit::dpi::LogicalSize;
+use winit::dpi::{LogicalSize, PhysicalSize};
 use winit::event_loop::EventLoop;
-use winit::window::{Window, WindowBuilder};
+use winit::window::{Fullscreen, Window, WindowBuilder, WindowLevel};
 
-use crate::{AppConfig, AppState};
+use crate::{
+    load_shader, parse_pass_manifest, version_profile, AppConfig, AppState, GlInfo, GlProfile,
+    PreProcessor, BUILTIN_SHADER,
+};
 
 /// Encapsulates everything needed for setting up the window and gl_context
 pub struct AppWindow {
@@ -27,21 +32,42 @@ pub struct AppWindow {
 
     // winit
     pub window: Option<Window>,
+    /// Size to restore when leaving fullscreen
+    windowed_size: Option<PhysicalSize<u32>>,
+    /// Last applied vsync setting
+    vsync: Option<bool>,
+    /// Last applied surface size in physical pixels
+    surface_size: Option<(i32, i32)>,
+    /// Version and driver strings of the created context
+    pub gl_info: Option<GlInfo>,
 }
 
 impl AppWindow {
     /// Setup the required bits for a winit Window
     /// Returns Self and the winit event loop
-    pub fn new(_config: AppConfig, app_state: &AppState) -> (Self, EventLoop<()>) {
+    pub fn new(config: AppConfig, app_state: &AppState) -> (Self, EventLoop<()>) {
         // TODO: Move event loop out of AppWindow
         let event_loop = EventLoop::new();
 
         // Let winit create a window builder
         let window_builder = WindowBuilder::new()
             .with_title("Skuggbox")
-            .with_inner_size(LogicalSize::new(app_state.width, app_state.height));
+            .with_inner_size(LogicalSize::new(app_state.width, app_state.height))
+            .with_decorations(!config.borderless)
+            .with_transparent(config.transparent)
+            .with_window_level(match config.always_on_top {
+                true => WindowLevel::AlwaysOnTop,
+                false => WindowLevel::Normal,
+            })
+            // exporting, benchmarking and listing uniforms render offscreen so there is nothing
+            // to show
+            .with_visible(
+                config.export_frames.is_none()
+                    && config.benchmark.is_none()
+                    && config.list_uniforms.is_none(),
+            );
 
-        let template = ConfigTemplateBuilder::new();
+        let template = ConfigTemplateBuilder::new().with_transparency(config.transparent);
 
         let display_builder = DisplayBuilder::new().with_window_builder(Some(window_builder));
 
@@ -62,6 +88,12 @@ impl AppWindow {
             })
             .unwrap(); // TODO(mathias): ? operator instead
 
+        if let Some(window) = window.as_ref().filter(|_| config.click_through) {
+            if let Err(e) = window.set_cursor_hittest(false) {
+                log::warn!("Could not make the window click-through: {}", e);
+            }
+        }
+
         let raw_window_handle = window.as_ref().map(|window| window.raw_window_handle());
 
         let gl_display = gl_config.display();
@@ -78,8 +110,22 @@ impl AppWindow {
             .with_context_api(ContextApi::OpenGl(Some(Version::new(2, 1))))
             .build(raw_window_handle);
 
+        // the context asked for on the command line, if any
+        let requested_context = requested_context_attributes(&config, raw_window_handle)
+            .and_then(|attributes| {
+                let context = unsafe { gl_display.create_context(&gl_config, &attributes) };
+                context
+                    .map_err(|e| {
+                        log::warn!(
+                            "Could not create the requested OpenGL context, falling back to the default one: {}",
+                            e
+                        )
+                    })
+                    .ok()
+            });
+
         // This gl context has not been initialized yet. It will be set up in the Event::Resume handler
-        let not_current_context = Some(unsafe {
+        let not_current_context = Some(requested_context.unwrap_or_else(|| unsafe {
             gl_display
                 .create_context(&gl_config, &context_attributes)
                 .unwrap_or_else(|_| {
@@ -91,7 +137,7 @@ impl AppWindow {
                                 .expect("failed to create gl context")
                         })
                 })
-        });
+        }));
 
         (
             Self {
@@ -100,6 +146,10 @@ impl AppWindow {
                 gl_context: None,
                 gl_surface: None,
                 window,
+                windowed_size: None,
+                vsync: None,
+                surface_size: None,
+                gl_info: None,
             },
             event_loop,
         )
@@ -133,12 +183,85 @@ impl AppWindow {
                 gl_display.get_proc_address(symbol.as_c_str()).cast()
             })
         };
+        let gl_info = GlInfo::query(&gl);
+        gl_info.log();
+        self.gl_info = Some(gl_info);
+
         self.gl_context = Some(gl_context);
         // Return the gl context and the WindowSurface which is used to swap buffers
         self.gl_surface = Some(gl_surface);
         Arc::new(gl)
     }
 
+    pub fn is_fullscreen(&self) -> bool {
+        self.window
+            .as_ref()
+            .is_some_and(|window| window.fullscreen().is_some())
+    }
+
+    /// Switch between borderless fullscreen on the current monitor and windowed mode.
+    /// The resulting resize event updates the resolution.
+    pub fn set_fullscreen(&mut self, fullscreen: bool) {
+        let window = match &self.window {
+            Some(window) => window,
+            None => return,
+        };
+
+        if fullscreen {
+            self.windowed_size = Some(window.inner_size());
+            window.set_fullscreen(Some(Fullscreen::Borderless(None)));
+        } else {
+            window.set_fullscreen(None);
+            if let Some(size) = self.windowed_size.take() {
+                window.set_inner_size(size);
+            }
+        }
+    }
+
+    /// Wait for the vertical blank when swapping buffers. Does nothing if `vsync` is already
+    /// applied. Only call when the gl context is initialized.
+    pub fn set_vsync(&mut self, vsync: bool) {
+        if self.vsync == Some(vsync) {
+            return;
+        }
+        self.vsync = Some(vsync);
+
+        let surface = self.gl_surface.as_ref().unwrap();
+        let gl_context = self.gl_context.as_ref().unwrap();
+        let interval = match vsync {
+            true => SwapInterval::Wait(NonZeroU32::new(1).unwrap()),
+            false => SwapInterval::DontWait,
+        };
+        if let Err(err) = surface.set_swap_interval(gl_context, interval) {
+            log::error!("Failed to set swap interval {:?}", err);
+        }
+    }
+
+    /// Resize the gl surface to the window size from the resize events, some platforms
+    /// (e.g. Wayland) keep presenting the old size otherwise. Does nothing if the size is
+    /// already applied. Only call when the gl context is initialized.
+    ///
+    /// To check by hand: resize the window or drag it to a monitor with another scale factor,
+    /// the image must stay sharp and `iResolution` must follow the window.
+    pub fn resize_surface(&mut self, width: i32, height: i32) {
+        if self.surface_size == Some((width, height)) {
+            return;
+        }
+        let (width_px, height_px) = match (
+            NonZeroU32::new(width.max(0) as u32),
+            NonZeroU32::new(height.max(0) as u32),
+        ) {
+            (Some(width), Some(height)) => (width, height),
+            // minimized
+            _ => return,
+        };
+        self.surface_size = Some((width, height));
+
+        let surface = self.gl_surface.as_ref().unwrap();
+        let gl_context = self.gl_context.as_ref().unwrap();
+        surface.resize(gl_context, width_px, height_px);
+    }
+
     /// Only call when you know that the gl context is initialized or you'll have a panic
     pub fn swap_buffers(&self) {
         let surface = self.gl_surface.as_ref().unwrap();
@@ -148,3 +271,100 @@ impl AppWindow {
         };
     }
 }
+
+/// Attributes for the context version and profile from `--gl-version` and `--gl-profile`,
+/// or from shaders asking for the compatibility profile. `None` when none of them do.
+fn requested_context_attributes(
+    config: &AppConfig,
+    raw_window_handle: Option<raw_window_handle::RawWindowHandle>,
+) -> Option<ContextAttributes> {
+    let profile = config.gl_profile.or_else(|| shader_profile(config));
+    if config.gl_version.is_none() && profile.is_none() {
+        return None;
+    }
+
+    let version = config
+        .gl_version
+        .map(|version| Version::new(version.major, version.minor));
+    let mut builder = ContextAttributesBuilder::new().with_context_api(ContextApi::OpenGl(version));
+    if let Some(profile) = profile {
+        builder = builder.with_profile(match profile {
+            GlProfile::Core => glutin::context::GlProfile::Core,
+            GlProfile::Compatibility => glutin::context::GlProfile::Compatibility,
+        });
+    }
+    Some(builder.build(raw_window_handle))
+}
+
+/// The compatibility profile when a shader opts into it with e.g. `#version 330 compatibility`,
+/// for `gl_FragColor` and the built-in varyings of older shaders. The shaders are
+/// pre-processed the way they're compiled, so the `#version` may come from an include, the
+/// prelude or `--glsl-version`.
+fn shader_profile(config: &AppConfig) -> Option<GlProfile> {
+    let pre_processor = PreProcessor::new(config.pre_processor_config());
+    let profile = main_shader_files(config)
+        .iter()
+        .map(|path| version_profile(&load_shader(&pre_processor, path).shader_src))
+        .find(|profile| *profile == Some(GlProfile::Compatibility))??;
+
+    log::info!("A shader asks for the compatibility profile, requesting a compatibility context");
+    Some(profile)
+}
+
+/// The shader of every pass, or the shader files. A broken pass manifest is reported once the
+/// shaders are set up.
+fn main_shader_files(config: &AppConfig) -> Vec<PathBuf> {
+    match (&config.passes, &config.files) {
+        (Some(manifest), _) => {
+            let base_dir = manifest.parent().unwrap_or_else(|| Path::new("."));
+            std::fs::read_to_string(manifest)
+                .ok()
+                .and_then(|source| parse_pass_manifest(&source, base_dir).ok())
+                .map(|passes| passes.into_iter().map(|pass| pass.file).collect())
+                .unwrap_or_default()
+        }
+        (None, Some(files)) if !files.is_empty() => files.clone(),
+        _ => vec![PathBuf::from(BUILTIN_SHADER)],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::shader_profile;
+    use crate::{AppConfig, GlProfile};
+
+    #[test]
+    fn compatibility_version_from_an_include() {
+        let dir =
+            std::env::temp_dir().join(format!("skuggbox_profile_test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("version.glsl"), "#version 330 compatibility").unwrap();
+        let shader = dir.join("shader.glsl");
+        std::fs::write(
+            &shader,
+            "#pragma include(version.glsl)\nvoid main() { gl_FragColor = vec4(1.0); }",
+        )
+        .unwrap();
+        let plain = dir.join("plain.glsl");
+        std::fs::write(&plain, "void main() {}").unwrap();
+        let profile = |shader: &std::path::Path, args: &[&str]| {
+            let config = AppConfig::parse_from(
+                ["skuggbox", "--no-prelude", "-f", shader.to_str().unwrap()]
+                    .iter()
+                    .chain(args),
+            );
+            shader_profile(&config)
+        };
+
+        let included = profile(&shader, &[]);
+        let plain_default = profile(&plain, &[]);
+        let plain_compatibility = profile(&plain, &["--glsl-version", "330 compatibility"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(included, Some(GlProfile::Compatibility));
+        assert_eq!(plain_default, None);
+        assert_eq!(plain_compatibility, Some(GlProfile::Compatibility));
+    }
+}
@@ -0,0 +1,2 @@
+// start of a
+#pragma include(b.glsl)
@@ -0,0 +1,2 @@
+// start of b
+#pragma include(a.glsl)
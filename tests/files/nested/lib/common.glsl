@@ -0,0 +1,2 @@
+// content of common
+#pragma include(../shared.glsl)
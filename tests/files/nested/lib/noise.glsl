@@ -0,0 +1,3 @@
+// content of noise
+#pragma include(common.glsl)
+#pragma include(../shared.glsl)
@@ -0,0 +1,3 @@
+// start of main
+#pragma include(lib/noise.glsl)
+// end of main
@@ -0,0 +1,6 @@
+/*
+ * version after a comment
+ */
+
+#version 330 core
+out vec4 fragColor;
@@ -0,0 +1,16 @@
+#version 330 core
+// vertices: 6
+
+// two triangles making up a quad in the middle of the screen
+const vec2 corners[6] = vec2[6](
+    vec2(-0.5, -0.5), vec2(0.5, -0.5), vec2(0.5, 0.5),
+    vec2(-0.5, -0.5), vec2(0.5, 0.5), vec2(-0.5, 0.5)
+);
+
+out vec3 color;
+
+void main() {
+    vec2 corner = corners[gl_VertexID];
+    color = vec3(corner + 0.5, 0.0);
+    gl_Position = vec4(corner, 0.0, 1.0);
+}